@@ -13,7 +13,10 @@ exit $EXIT_DETACH_COMMENCE
 # The exit signal determines the continuation of the detachment-procedure. A
 # value of EXIT_DETACH_COMMENCE (0/success), causes the detachment procedure
 # to open the latch, while a value of EXIT_DETACH_ABORT (1, or any other
-# non-zero value) will cause the detachment-procedure to be aborted. On an
+# non-zero value) will cause the detachment-procedure to be aborted. A value
+# of EXIT_DETACH_DEFER (75) defers the detachment: this handler will be run
+# again after the configured defer_interval, or when a retry is requested via
+# D-Bus, until it either commences or aborts, or the timeout expires. On an
 # abort caused by this script, the detach_abort handler will _not_ be
 # executed. It is therefore the the responsibility of this handler-executable
 # to ensure the device state is properly reset to the state before its
@@ -6,8 +6,8 @@ Summary:    Surface Detachment System (DTX) Daemon
 License:    MIT
 URL:        https://github.com/linux-surface/surface-dtx-daemon
 
-Requires:       dbus libgcc
-BuildRequires:  rust cargo dbus-devel
+Requires:       dbus libgcc systemd-libs
+BuildRequires:  rust cargo dbus-devel systemd-devel
 
 %global debug_package %{nil}
 
@@ -25,12 +25,14 @@ export CARGO_INCREMENTAL=0
 cargo build --release --locked
 strip --strip-all "target/release/surface-dtx-daemon"
 strip --strip-all "target/release/surface-dtx-userd"
+strip --strip-all "target/release/surface-dtx-ctl"
 
 %install
 
 # binary files
 install -D -m755 "target/release/surface-dtx-daemon" "%{buildroot}/usr/bin/surface-dtx-daemon"
 install -D -m755 "target/release/surface-dtx-userd" "%{buildroot}/usr/bin/surface-dtx-userd"
+install -D -m755 "target/release/surface-dtx-ctl" "%{buildroot}/usr/bin/surface-dtx-ctl"
 
 # application files
 install -D -m644 "target/etc/dtx/surface-dtx-daemon.conf" "%{buildroot}/etc/surface-dtx/surface-dtx-daemon.conf"
@@ -40,15 +42,22 @@ install -D -m755 "target/etc/dtx/detach.sh" "%{buildroot}/etc/surface-dtx/detach
 install -D -m644 "target/etc/systemd/surface-dtx-daemon.service" "%{buildroot}/usr/lib/systemd/system/surface-dtx-daemon.service"
 install -D -m644 "target/etc/systemd/surface-dtx-userd.service" "%{buildroot}/usr/lib/systemd/user/surface-dtx-userd.service"
 install -D -m644 "target/etc/dbus/org.surface.dtx.conf" "%{buildroot}/etc/dbus-1/system.d/org.surface.dtx.conf"
+install -D -m644 "target/etc/dbus/org.surface.dtx.xml" "%{buildroot}/usr/share/dbus-1/interfaces/org.surface.dtx.xml"
 install -D -m644 "target/etc/udev/40-surface_dtx.rules" "%{buildroot}/etc/udev/rules.d/40-surface_dtx.rules"
 
 # completion files
 install -D -m644 "target/surface-dtx-daemon.bash" "%{buildroot}/usr/share/bash-completion/completions/surface-dtx-daemon"
 install -D -m644 "target/surface-dtx-userd.bash" "%{buildroot}/usr/share/bash-completion/completions/surface-dtx-userd"
+install -D -m644 "target/surface-dtx-ctl.bash" "%{buildroot}/usr/share/bash-completion/completions/surface-dtx-ctl"
 install -D -m644 "target/_surface-dtx-daemon" "%{buildroot}/usr/share/zsh/site-functions/_surface-dtx-daemon"
 install -D -m644 "target/_surface-dtx-userd" "%{buildroot}/usr/share/zsh/site-functions/_surface-dtx-userd"
+install -D -m644 "target/_surface-dtx-ctl" "%{buildroot}/usr/share/zsh/site-functions/_surface-dtx-ctl"
 install -D -m644 "target/surface-dtx-daemon.fish" "%{buildroot}/usr/share/fish/vendor_completions.d/surface-dtx-daemon.fish"
 install -D -m644 "target/surface-dtx-userd.fish" "%{buildroot}/usr/share/fish/vendor_completions.d/surface-dtx-userd.fish"
+install -D -m644 "target/surface-dtx-ctl.fish" "%{buildroot}/usr/share/fish/vendor_completions.d/surface-dtx-ctl.fish"
+
+# man pages
+install -D -m644 "target/surface-dtx-ctl.1" "%{buildroot}/usr/share/man/man1/surface-dtx-ctl.1"
 
 %files
 %config /etc/dbus-1/system.d/org.surface.dtx.conf
@@ -56,14 +65,20 @@ install -D -m644 "target/surface-dtx-userd.fish" "%{buildroot}/usr/share/fish/ve
 %config(noreplace) /etc/surface-dtx/*
 /usr/bin/surface-dtx-daemon
 /usr/bin/surface-dtx-userd
+/usr/bin/surface-dtx-ctl
 /usr/lib/systemd/system/surface-dtx-daemon.service
 /usr/lib/systemd/user/surface-dtx-userd.service
+/usr/share/dbus-1/interfaces/org.surface.dtx.xml
 /usr/share/bash-completion/completions/surface-dtx-daemon
 /usr/share/bash-completion/completions/surface-dtx-userd
+/usr/share/bash-completion/completions/surface-dtx-ctl
 /usr/share/zsh/site-functions/_surface-dtx-daemon
 /usr/share/zsh/site-functions/_surface-dtx-userd
+/usr/share/zsh/site-functions/_surface-dtx-ctl
 /usr/share/fish/vendor_completions.d/surface-dtx-daemon.fish
 /usr/share/fish/vendor_completions.d/surface-dtx-userd.fish
+/usr/share/fish/vendor_completions.d/surface-dtx-ctl.fish
+/usr/share/man/man1/surface-dtx-ctl.1*
 
 %changelog
 * Sat Sep 14 2024 Maximilian Luz <luzmaximilian@gmail.com> - 0.3.8-1
@@ -0,0 +1,149 @@
+//! Blocking variant of the client.
+
+use crate::{BaseBattery, BaseFirmware, BaseInfo, ConfigReport, DeviceMode, HandlerRecord, HandlerTest, Health, LatchStatus, Statistics};
+
+use std::ops::Deref;
+
+use anyhow::{Context, Result};
+
+use dbus::arg::PropMap;
+use dbus::blocking::{BlockingSender, Proxy};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+
+
+/// Blocking client for the system daemon.
+pub struct Client<C> {
+    proxy: Proxy<'static, C>,
+}
+
+impl<T: BlockingSender, C: Deref<Target = T>> Client<C> {
+    pub fn new(conn: C) -> Self {
+        Self { proxy: Proxy::new(crate::NAME, crate::PATH, crate::TIMEOUT, conn) }
+    }
+
+    pub fn device_mode(&self) -> Result<DeviceMode> {
+        let mode: String = self.proxy.get(crate::INTERFACE, "DeviceMode")
+            .context("Failed to query device mode")?;
+
+        mode.parse()
+    }
+
+    pub fn latch_status(&self) -> Result<LatchStatus> {
+        let status: String = self.proxy.get(crate::INTERFACE, "LatchStatus")
+            .context("Failed to query latch status")?;
+
+        status.parse()
+    }
+
+    /// Whether the latch has been locked explicitly via [`Client::lock`].
+    /// Temporary locks while handlers are running are not included.
+    pub fn latch_locked(&self) -> Result<bool> {
+        self.proxy.get(crate::INTERFACE, "LatchLocked")
+            .context("Failed to query latch lock state")
+    }
+
+    pub fn base(&self) -> Result<BaseInfo> {
+        let base = self.proxy.get(crate::INTERFACE, "Base")
+            .context("Failed to query base info")?;
+
+        let battery: PropMap = self.proxy.get(crate::INTERFACE, "BaseBattery")
+            .context("Failed to query base battery")?;
+
+        let firmware: PropMap = self.proxy.get(crate::INTERFACE, "BaseFirmware")
+            .context("Failed to query base firmware")?;
+
+        Ok(BaseInfo {
+            battery: BaseBattery::from_propmap(&battery)?,
+            firmware: BaseFirmware::from_propmap(&firmware),
+            ..BaseInfo::from_arg(base)?
+        })
+    }
+
+    pub fn dry_run(&self) -> Result<bool> {
+        self.proxy.get(crate::INTERFACE, "DryRun")
+            .context("Failed to query dry-run state")
+    }
+
+    /// Request detachment, or cancel an ongoing detachment.
+    pub fn request(&self) -> Result<()> {
+        self.call("Request")
+    }
+
+    /// Request detachment, skipping the detachment handler.
+    ///
+    /// The detachment is confirmed after the grace period configured in the
+    /// daemon. Intended for emergencies in which the handler is broken.
+    /// Only permitted for root by the default D-Bus policy.
+    pub fn request_force(&self) -> Result<()> {
+        self.call("RequestForce")
+    }
+
+    /// Lock the latch, preventing it from being opened.
+    ///
+    /// Only permitted for root by the default D-Bus policy.
+    pub fn lock(&self) -> Result<()> {
+        self.call("Lock")
+    }
+
+    /// Unlock the latch, allowing it to be opened again.
+    ///
+    /// Only permitted for root by the default D-Bus policy.
+    pub fn unlock(&self) -> Result<()> {
+        self.call("Unlock")
+    }
+
+    /// Re-run deferred detachment handlers immediately.
+    pub fn retry(&self) -> Result<()> {
+        self.call("Retry")
+    }
+
+    pub fn handler_records(&self) -> Result<Vec<HandlerRecord>> {
+        let (records,): (Vec<PropMap>,) = self.proxy
+            .method_call(crate::INTERFACE, "GetHandlerRecords", ())
+            .context("Failed to query handler records")?;
+
+        Ok(records.iter().map(HandlerRecord::from_propmap).collect())
+    }
+
+    /// Run the given handler (`detach`, `attach`, or `abort`) in dry-run mode
+    /// and return its output. Requires root privileges.
+    pub fn test_handler(&self, handler: &str) -> Result<HandlerTest> {
+        let proxy = Proxy::new(crate::NAME, crate::PATH, crate::HANDLER_TEST_TIMEOUT,
+                               &*self.proxy.connection);
+
+        let result = proxy.method_call(crate::INTERFACE, "TestHandler", (handler,))
+            .with_context(|| format!("Failed to test {handler} handler"))?;
+
+        Ok(HandlerTest::from_args(result))
+    }
+
+    pub fn statistics(&self) -> Result<Statistics> {
+        let (counters, cancels, handlers, buckets, histograms) = self.proxy
+            .method_call(crate::INTERFACE, "GetStatistics", ())
+            .context("Failed to query daemon statistics")?;
+
+        Ok(Statistics::from_args(counters, cancels, handlers, buckets, histograms))
+    }
+
+    /// Check that the daemon is still connected to the DTX device.
+    pub fn health(&self) -> Result<Health> {
+        let (uptime, available, last_event) = self.proxy
+            .method_call(crate::INTERFACE, "GetHealth", ())
+            .context("Failed to query daemon health")?;
+
+        Ok(Health::from_args(uptime, available, last_event))
+    }
+
+    pub fn config(&self) -> Result<ConfigReport> {
+        let (path, config, unknowns, problems) = self.proxy
+            .method_call(crate::INTERFACE, "GetConfig", ())
+            .context("Failed to query daemon configuration")?;
+
+        Ok(ConfigReport { path, config, unknowns, problems })
+    }
+
+    fn call(&self, method: &str) -> Result<()> {
+        self.proxy.method_call(crate::INTERFACE, method, ())
+            .with_context(|| format!("Failed to call daemon method '{method}'"))
+    }
+}
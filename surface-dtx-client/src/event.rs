@@ -0,0 +1,403 @@
+use crate::DeviceMode;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result};
+
+use dbus::{Message, MessageType};
+use dbus::arg::{Variant, RefArg};
+
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    DetachmentInhibited { reason: CancelReason },
+    DetachmentStart,
+    DetachmentReady,
+    DetachmentComplete,
+    DetachmentCancel { reason: CancelReason },
+    DetachmentCancelStart,
+    DetachmentCancelComplete,
+    DetachmentCancelTimeout,
+    DetachmentUnexpected,
+    DetachmentHandlerSlow,
+    DetachmentCountdown { remaining: u32 },
+    AttachmentStart,
+    AttachmentComplete,
+    AttachmentTimeout,
+    BaseFeasible,
+    DeviceModeMismatch { mode: DeviceMode, tablet_switch: bool },
+    HandlerStatus { message: String },
+    HandlerProgress { progress: u8 },
+    HandlerError { message: String },
+    HandlerCrash { handler: String, signal: i32 },
+}
+
+impl Event {
+    pub fn match_message(msg: &Message) -> bool {
+        msg.msg_type() == MessageType::Signal
+            && msg.path() == Some(crate::PATH.into())
+            && msg.interface() == Some(crate::INTERFACE.into())
+            && msg.member() == Some("Event".into())
+    }
+
+    pub fn try_from_message(msg: &Message) -> Result<Option<Self>> {
+        if Self::match_message(msg) {
+            Self::from_message(msg).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn from_message(msg: &Message) -> Result<Self> {
+        let (ty, args): (&str, HashMap<&str, Variant<Box<dyn RefArg>>>) = msg.read2()
+            .context("Protocol error")?;
+
+        let event = match ty {
+            "detachment:inhibited" => {
+                let reason = args.get("reason")
+                    .ok_or_else(|| anyhow::anyhow!("Missing argument: reason"))
+                    .and_then(CancelReason::try_from)
+                    .context("Protocol error")?;
+
+                Event::DetachmentInhibited { reason }
+            },
+            "detachment:start" => {
+                Event::DetachmentStart
+            },
+            "detachment:ready" => {
+                Event::DetachmentReady
+            },
+            "detachment:complete" => {
+                Event::DetachmentComplete
+            },
+            "detachment:cancel" => {
+                let reason = args.get("reason")
+                    .ok_or_else(|| anyhow::anyhow!("Missing argument: reason"))
+                    .and_then(CancelReason::try_from)
+                    .context("Protocol error")?;
+
+                Event::DetachmentCancel { reason }
+            },
+            "detachment:cancel:start" => {
+                Event::DetachmentCancelStart
+            },
+            "detachment:cancel:complete" => {
+                Event::DetachmentCancelComplete
+            },
+            "detachment:cancel:timeout" => {
+                Event::DetachmentCancelTimeout
+            },
+            "detachment:unexpected" => {
+                Event::DetachmentUnexpected
+            },
+            "detachment:handler:slow" => {
+                Event::DetachmentHandlerSlow
+            },
+            "detachment:countdown" => {
+                let remaining = args.get("remaining")
+                    .ok_or_else(|| anyhow::anyhow!("Missing argument: remaining"))
+                    .and_then(|v| v.as_u64().ok_or_else(|| anyhow::anyhow!("Invalid value type: {:?}", v)))
+                    .context("Protocol error")?;
+
+                Event::DetachmentCountdown { remaining: remaining as u32 }
+            },
+            "attachment:start" => {
+                Event::AttachmentStart
+            },
+            "attachment:complete" => {
+                Event::AttachmentComplete
+            },
+            "attachment:timeout" => {
+                Event::AttachmentTimeout
+            },
+            "base:feasible" => {
+                Event::BaseFeasible
+            },
+            "device-mode:mismatch" => {
+                let mode = get_str(&args, "mode")?.parse().context("Protocol error")?;
+                let tablet_switch = args.get("tablet-switch")
+                    .ok_or_else(|| anyhow::anyhow!("Missing argument: tablet-switch"))
+                    .and_then(|v| v.as_u64().ok_or_else(|| anyhow::anyhow!("Invalid value type: {:?}", v)))
+                    .context("Protocol error")?;
+
+                Event::DeviceModeMismatch { mode, tablet_switch: tablet_switch != 0 }
+            },
+            "handler:status" => {
+                let message = get_str(&args, "message")?;
+                Event::HandlerStatus { message }
+            },
+            "handler:progress" => {
+                let progress = args.get("progress")
+                    .ok_or_else(|| anyhow::anyhow!("Missing argument: progress"))
+                    .and_then(|v| v.as_u64().ok_or_else(|| anyhow::anyhow!("Invalid value type: {:?}", v)))
+                    .context("Protocol error")?;
+
+                Event::HandlerProgress { progress: progress.min(100) as u8 }
+            },
+            "handler:error" => {
+                let message = get_str(&args, "message")?;
+                Event::HandlerError { message }
+            },
+            "handler:crash" => {
+                let handler = get_str(&args, "handler")?;
+                let signal = args.get("signal")
+                    .ok_or_else(|| anyhow::anyhow!("Missing argument: signal"))
+                    .and_then(|v| v.as_i64().ok_or_else(|| anyhow::anyhow!("Invalid value type: {:?}", v)))
+                    .context("Protocol error")?;
+
+                Event::HandlerCrash { handler, signal: signal as i32 }
+            },
+            _ => {
+                Err(anyhow::anyhow!("Unsupported event type: {}", ty))
+                    .context("Protocol error")?
+            },
+        };
+
+        Ok(event)
+    }
+}
+
+fn get_str(args: &HashMap<&str, Variant<Box<dyn RefArg>>>, name: &str) -> Result<String> {
+    args.get(name)
+        .ok_or_else(|| anyhow::anyhow!("Missing argument: {}", name))
+        .and_then(|v| v.as_str().ok_or_else(|| anyhow::anyhow!("Invalid value type: {:?}", v)))
+        .map(|v| v.to_owned())
+        .context("Protocol error")
+}
+
+impl TryFrom<&Message> for Event {
+    type Error = Error;
+
+    fn try_from(msg: &Message) -> Result<Self> {
+        Self::from_message(msg)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    UserRequest,
+    HandlerTimeout,
+    HandlerSpawnFailed,
+    DisconnectTimeout,
+    Runtime(RuntimeError),
+    Hardware(HardwareError),
+    Unknown(u16),
+}
+
+impl FromStr for CancelReason {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "request"            => Ok(Self::UserRequest),
+            "timeout:handler"    => Ok(Self::HandlerTimeout),
+            "error:handler:spawn" => Ok(Self::HandlerSpawnFailed),
+            "timeout:disconnect" => Ok(Self::DisconnectTimeout),
+            _ if s.starts_with("error:runtime") => Ok(Self::Runtime(RuntimeError::from_str(s)?)),
+            _ if s.starts_with("error:hardware") => Ok(Self::Hardware(HardwareError::from_str(s)?)),
+            _ if s.starts_with("unknown:") => {
+                let value = s.strip_prefix("unknown:")
+                    .unwrap_or("")
+                    .parse()
+                    .context("Failed to parse unknown cancel reason")
+                    .context("Protocol error")?;
+
+                Ok(Self::Unknown(value))
+            },
+            _ => {
+                Err(anyhow::anyhow!("Unknown cancel reason: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for CancelReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UserRequest        => write!(f, "request"),
+            Self::HandlerTimeout     => write!(f, "timeout:handler"),
+            Self::HandlerSpawnFailed => write!(f, "error:handler:spawn"),
+            Self::DisconnectTimeout  => write!(f, "timeout:disconnect"),
+            Self::Runtime(rt)        => write!(f, "{rt}"),
+            Self::Hardware(hw)       => write!(f, "{hw}"),
+            Self::Unknown(x) => write!(f, "unknown:{x}"),
+        }
+    }
+}
+
+impl TryFrom<&Variant<Box<dyn RefArg>>> for CancelReason {
+    type Error = Error;
+
+    fn try_from(value: &Variant<Box<dyn RefArg>>) -> Result<Self> {
+        let value = value.as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid value type: {:?}", value))
+            .context("Protocol error")?;
+
+        Self::from_str(value)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeError {
+    NotAttached,
+    NotFeasible,
+    Timeout,
+    Unknown(u8),
+}
+
+impl FromStr for RuntimeError {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error:runtime:not-attached" => Ok(Self::NotAttached),
+            "error:runtime:not-feasible" => Ok(Self::NotFeasible),
+            "error:runtime:timeout"      => Ok(Self::Timeout),
+            _ if s.starts_with("error:runtime:unknown:") => {
+                let value = s.strip_prefix("error:runtime:unknown:")
+                    .unwrap_or("")
+                    .parse()
+                    .context("Failed to parse unknown runtime error value")
+                    .context("Protocol error")?;
+
+                Ok(Self::Unknown(value))
+            },
+            _ => {
+                Err(anyhow::anyhow!("Unknown runtime error value: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAttached => write!(f, "error:runtime:not-attached"),
+            Self::NotFeasible => write!(f, "error:runtime:not-feasible"),
+            Self::Timeout     => write!(f, "error:runtime:timeout"),
+            Self::Unknown(x) => write!(f, "error:runtime:unknown:{x}"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareError {
+    FailedToOpen,
+    FailedToRemainOpen,
+    FailedToClose,
+    Unknown(u8),
+}
+
+impl FromStr for HardwareError {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error:hardware:failed-to-open" => Ok(Self::FailedToOpen),
+            "error:hardware:failed-to-remain-open" => Ok(Self::FailedToRemainOpen),
+            "error:hardware:failed-to-close" => Ok(Self::FailedToClose),
+            _ if s.starts_with("error:hardware:unknown:") => {
+                let value = s.strip_prefix("error:hardware:unknown:")
+                    .unwrap_or("")
+                    .parse()
+                    .context("Failed to parse unknown hardware error value")
+                    .context("Protocol error")?;
+
+                Ok(Self::Unknown(value))
+            },
+            _ => {
+                Err(anyhow::anyhow!("Unknown hardware error value: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for HardwareError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailedToOpen       => write!(f, "error:hardware:failed-to-open"),
+            Self::FailedToRemainOpen => write!(f, "error:hardware:failed-to-remain-open"),
+            Self::FailedToClose      => write!(f, "error:hardware:failed-to-close"),
+            Self::Unknown(x) => write!(f, "error:hardware:unknown:{x}"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use dbus::arg::PropMap;
+
+    fn signal(ty: &str, args: Vec<(&str, Box<dyn RefArg>)>) -> Message {
+        let args: PropMap = args.into_iter()
+            .map(|(key, value)| (key.to_owned(), Variant(value)))
+            .collect();
+
+        Message::new_signal(crate::PATH, crate::INTERFACE, "Event").unwrap()
+            .append2(ty, args)
+    }
+
+    #[test]
+    fn decode() {
+        let msg = signal("detachment:start", vec![]);
+        assert!(matches!(Event::try_from_message(&msg).unwrap(), Some(Event::DetachmentStart)));
+
+        let msg = signal("detachment:cancel", vec![("reason", Box::new("error:runtime:timeout".to_owned()))]);
+        assert!(matches!(Event::from_message(&msg).unwrap(),
+                         Event::DetachmentCancel { reason: CancelReason::Runtime(RuntimeError::Timeout) }));
+
+        let msg = signal("detachment:countdown", vec![("remaining", Box::new(3u32))]);
+        assert!(matches!(Event::from_message(&msg).unwrap(), Event::DetachmentCountdown { remaining: 3 }));
+
+        let msg = signal("handler:progress", vec![("progress", Box::new(250u8))]);
+        assert!(matches!(Event::from_message(&msg).unwrap(), Event::HandlerProgress { progress: 100 }));
+
+        let msg = signal("device-mode:mismatch", vec![("mode", Box::new("tablet".to_owned())),
+                                                      ("tablet-switch", Box::new(1u8))]);
+        assert!(matches!(Event::from_message(&msg).unwrap(),
+                         Event::DeviceModeMismatch { mode: DeviceMode::Tablet, tablet_switch: true }));
+
+        let msg = signal("handler:crash", vec![("handler", Box::new("attach".to_owned())),
+                                               ("signal", Box::new(11i32))]);
+        match Event::from_message(&msg).unwrap() {
+            Event::HandlerCrash { handler, signal } => assert_eq!((handler.as_str(), signal), ("attach", 11)),
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn decode_invalid() {
+        // unknown event types and missing or mistyped arguments
+        assert!(Event::from_message(&signal("detachment:bogus", vec![])).is_err());
+        assert!(Event::from_message(&signal("detachment:cancel", vec![])).is_err());
+        assert!(Event::from_message(&signal("handler:status", vec![("message", Box::new(1u32))])).is_err());
+
+        // signals of other interfaces are ignored
+        let msg = Message::new_signal(crate::PATH, "org.example", "Event").unwrap()
+            .append2("detachment:start", PropMap::new());
+        assert!(Event::try_from_message(&msg).unwrap().is_none());
+    }
+
+    #[test]
+    fn cancel_reason_roundtrip() {
+        for s in ["request", "timeout:handler", "error:handler:spawn", "timeout:disconnect",
+                  "error:runtime:not-feasible", "error:runtime:unknown:9",
+                  "error:hardware:failed-to-close", "unknown:5"] {
+            assert_eq!(s.parse::<CancelReason>().unwrap().to_string(), s);
+        }
+
+        assert!("error:runtime:bogus".parse::<CancelReason>().is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Client library for the D-Bus interface of the Surface DTX system daemon
+//! (`org.surface.dtx`).
+//!
+//! The daemon exposes its state and events using a string-based protocol.
+//! This crate provides typed representations of those values, as well as
+//! async ([`Client`]) and blocking ([`blocking::Client`]) wrappers around the
+//! interface. Events are available as typed stream via [`Client::events`].
+
+mod event;
+pub use event::{CancelReason, Event, HardwareError, RuntimeError};
+
+mod types;
+pub use types::{
+    BaseBattery,
+    BaseFirmware,
+    BaseInfo,
+    BaseState,
+    BatteryStatus,
+    ConfigReport,
+    DeviceMode,
+    DeviceType,
+    HandlerHistogram,
+    HandlerRecord,
+    HandlerStatistics,
+    HandlerTest,
+    Health,
+    LatchStatus,
+    Statistics,
+};
+
+pub mod blocking;
+
+mod nonblock;
+pub use nonblock::Client;
+
+use std::time::Duration;
+
+
+/// Well-known bus name of the system daemon.
+pub const NAME: &str = "org.surface.dtx";
+
+/// Object path of the daemon service.
+pub const PATH: &str = "/org/surface/dtx";
+
+/// Interface of the daemon service.
+pub const INTERFACE: &str = "org.surface.dtx";
+
+/// Timeout used for method calls and property queries.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout used for handler test runs. The daemon enforces the timeout of the
+/// handler itself, this only guards against an unresponsive daemon.
+pub const HANDLER_TEST_TIMEOUT: Duration = Duration::from_secs(300);
@@ -0,0 +1,175 @@
+use crate::{BaseBattery, BaseFirmware, BaseInfo, ConfigReport, DeviceMode, Event, HandlerRecord, HandlerTest, Health, LatchStatus, Statistics};
+
+use std::ops::Deref;
+
+use anyhow::{Context, Result};
+
+use dbus::arg::PropMap;
+use dbus::message::MatchRule;
+use dbus::nonblock::{NonblockReply, Proxy, SyncConnection};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+
+use futures::prelude::*;
+
+
+/// Async client for the system daemon.
+pub struct Client<C> {
+    proxy: Proxy<'static, C>,
+}
+
+impl<T: NonblockReply, C: Deref<Target = T>> Client<C> {
+    pub fn new(conn: C) -> Self {
+        Self { proxy: Proxy::new(crate::NAME, crate::PATH, crate::TIMEOUT, conn) }
+    }
+
+    pub async fn device_mode(&self) -> Result<DeviceMode> {
+        let mode: String = self.proxy.get(crate::INTERFACE, "DeviceMode").await
+            .context("Failed to query device mode")?;
+
+        mode.parse()
+    }
+
+    pub async fn latch_status(&self) -> Result<LatchStatus> {
+        let status: String = self.proxy.get(crate::INTERFACE, "LatchStatus").await
+            .context("Failed to query latch status")?;
+
+        status.parse()
+    }
+
+    /// Whether the latch has been locked explicitly via [`Client::lock`].
+    /// Temporary locks while handlers are running are not included.
+    pub async fn latch_locked(&self) -> Result<bool> {
+        self.proxy.get(crate::INTERFACE, "LatchLocked").await
+            .context("Failed to query latch lock state")
+    }
+
+    pub async fn base(&self) -> Result<BaseInfo> {
+        let base = self.proxy.get(crate::INTERFACE, "Base").await
+            .context("Failed to query base info")?;
+
+        let battery: PropMap = self.proxy.get(crate::INTERFACE, "BaseBattery").await
+            .context("Failed to query base battery")?;
+
+        let firmware: PropMap = self.proxy.get(crate::INTERFACE, "BaseFirmware").await
+            .context("Failed to query base firmware")?;
+
+        Ok(BaseInfo {
+            battery: BaseBattery::from_propmap(&battery)?,
+            firmware: BaseFirmware::from_propmap(&firmware),
+            ..BaseInfo::from_arg(base)?
+        })
+    }
+
+    pub async fn dry_run(&self) -> Result<bool> {
+        self.proxy.get(crate::INTERFACE, "DryRun").await
+            .context("Failed to query dry-run state")
+    }
+
+    /// Request detachment, or cancel an ongoing detachment.
+    pub async fn request(&self) -> Result<()> {
+        self.call("Request").await
+    }
+
+    /// Request detachment, skipping the detachment handler.
+    ///
+    /// The detachment is confirmed after the grace period configured in the
+    /// daemon. Intended for emergencies in which the handler is broken.
+    /// Only permitted for root by the default D-Bus policy.
+    pub async fn request_force(&self) -> Result<()> {
+        self.call("RequestForce").await
+    }
+
+    /// Lock the latch, preventing it from being opened.
+    ///
+    /// Only permitted for root by the default D-Bus policy.
+    pub async fn lock(&self) -> Result<()> {
+        self.call("Lock").await
+    }
+
+    /// Unlock the latch, allowing it to be opened again.
+    ///
+    /// Only permitted for root by the default D-Bus policy.
+    pub async fn unlock(&self) -> Result<()> {
+        self.call("Unlock").await
+    }
+
+    /// Re-run deferred detachment handlers immediately.
+    pub async fn retry(&self) -> Result<()> {
+        self.call("Retry").await
+    }
+
+    pub async fn handler_records(&self) -> Result<Vec<HandlerRecord>> {
+        let (records,): (Vec<PropMap>,) = self.proxy
+            .method_call(crate::INTERFACE, "GetHandlerRecords", ()).await
+            .context("Failed to query handler records")?;
+
+        Ok(records.iter().map(HandlerRecord::from_propmap).collect())
+    }
+
+    /// Run the given handler (`detach`, `attach`, or `abort`) in dry-run mode
+    /// and return its output. Requires root privileges.
+    pub async fn test_handler(&self, handler: &str) -> Result<HandlerTest> {
+        let proxy = Proxy::new(crate::NAME, crate::PATH, crate::HANDLER_TEST_TIMEOUT,
+                               &*self.proxy.connection);
+
+        let result = proxy.method_call(crate::INTERFACE, "TestHandler", (handler,)).await
+            .with_context(|| format!("Failed to test {handler} handler"))?;
+
+        Ok(HandlerTest::from_args(result))
+    }
+
+    pub async fn statistics(&self) -> Result<Statistics> {
+        let (counters, cancels, handlers, buckets, histograms) = self.proxy
+            .method_call(crate::INTERFACE, "GetStatistics", ()).await
+            .context("Failed to query daemon statistics")?;
+
+        Ok(Statistics::from_args(counters, cancels, handlers, buckets, histograms))
+    }
+
+    /// Check that the daemon is still connected to the DTX device.
+    pub async fn health(&self) -> Result<Health> {
+        let (uptime, available, last_event) = self.proxy
+            .method_call(crate::INTERFACE, "GetHealth", ()).await
+            .context("Failed to query daemon health")?;
+
+        Ok(Health::from_args(uptime, available, last_event))
+    }
+
+    pub async fn config(&self) -> Result<ConfigReport> {
+        let (path, config, unknowns, problems) = self.proxy
+            .method_call(crate::INTERFACE, "GetConfig", ()).await
+            .context("Failed to query daemon configuration")?;
+
+        Ok(ConfigReport { path, config, unknowns, problems })
+    }
+
+    async fn call(&self, method: &str) -> Result<()> {
+        self.proxy.method_call(crate::INTERFACE, method, ()).await
+            .with_context(|| format!("Failed to call daemon method '{method}'"))
+    }
+}
+
+impl<C: Deref<Target = SyncConnection>> Client<C> {
+    /// Subscribe to the events emitted by the system daemon.
+    ///
+    /// The subscription is bound to the well-known name of the daemon, so it
+    /// remains valid across daemon restarts and does not need to be set up
+    /// again. Events that cannot be parsed are reported as errors in the
+    /// stream, which continues afterwards. The stream only ends once the
+    /// D-Bus connection is closed.
+    pub async fn events(&self) -> Result<impl Stream<Item = Result<Event>>> {
+        let mr = MatchRule::new_signal(crate::INTERFACE, "Event")
+            .with_sender(crate::NAME)
+            .with_path(crate::PATH);
+
+        let (msgs, stream) = self.proxy.connection.add_match(mr).await
+            .context("Failed to subscribe to daemon events")?
+            .msg_stream();
+
+        Ok(stream.map(move |msg| {
+            // keep the match alive for as long as the stream
+            let _ = &msgs;
+            Event::from_message(&msg)
+        }))
+    }
+}
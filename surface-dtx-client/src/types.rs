@@ -0,0 +1,563 @@
+use crate::event::HardwareError;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error, Result};
+
+use dbus::arg::{PropMap, RefArg};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceMode {
+    Tablet,
+    Laptop,
+    Studio,
+}
+
+impl FromStr for DeviceMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tablet" => Ok(Self::Tablet),
+            "laptop" => Ok(Self::Laptop),
+            "studio" => Ok(Self::Studio),
+            _ => {
+                Err(anyhow::anyhow!("Unknown device mode: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for DeviceMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tablet => write!(f, "tablet"),
+            Self::Laptop => write!(f, "laptop"),
+            Self::Studio => write!(f, "studio"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatchStatus {
+    Closed,
+    Opened,
+    Error(HardwareError),
+}
+
+impl FromStr for LatchStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "closed" => Ok(Self::Closed),
+            "opened" => Ok(Self::Opened),
+            _ if s.starts_with("error:hardware") => Ok(Self::Error(HardwareError::from_str(s)?)),
+            _ => {
+                Err(anyhow::anyhow!("Unknown latch status: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for LatchStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed"),
+            Self::Opened => write!(f, "opened"),
+            Self::Error(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseState {
+    Detached,
+    Attached,
+    NotFeasible,
+}
+
+impl FromStr for BaseState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "detached"     => Ok(Self::Detached),
+            "attached"     => Ok(Self::Attached),
+            "not-feasible" => Ok(Self::NotFeasible),
+            _ => {
+                Err(anyhow::anyhow!("Unknown base state: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for BaseState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Detached    => write!(f, "detached"),
+            Self::Attached    => write!(f, "attached"),
+            Self::NotFeasible => write!(f, "not-feasible"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Hid,
+    Ssh,
+    Unknown(u8),
+}
+
+impl FromStr for DeviceType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hid" => Ok(Self::Hid),
+            "ssh" => Ok(Self::Ssh),
+            _ if s.starts_with("unknown:") => {
+                let value = s.strip_prefix("unknown:")
+                    .unwrap_or("")
+                    .parse()
+                    .context("Failed to parse unknown device type")
+                    .context("Protocol error")?;
+
+                Ok(Self::Unknown(value))
+            },
+            _ => {
+                Err(anyhow::anyhow!("Unknown device type: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for DeviceType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hid => write!(f, "hid"),
+            Self::Ssh => write!(f, "ssh"),
+            Self::Unknown(x) => write!(f, "unknown:{x}"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseInfo {
+    pub state: BaseState,
+    pub device_type: DeviceType,
+    pub id: u8,
+
+    /// State of the base battery, if present.
+    pub battery: Option<BaseBattery>,
+
+    /// Firmware information of the base, if known.
+    pub firmware: Option<BaseFirmware>,
+}
+
+impl BaseInfo {
+    pub(crate) fn from_arg((state, device_type, id): (String, String, u8)) -> Result<Self> {
+        Ok(BaseInfo {
+            state: state.parse()?,
+            device_type: device_type.parse()?,
+            id,
+            battery: None,
+            firmware: None,
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryStatus {
+    Unknown,
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+}
+
+impl FromStr for BatteryStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unknown"      => Ok(Self::Unknown),
+            "charging"     => Ok(Self::Charging),
+            "discharging"  => Ok(Self::Discharging),
+            "not-charging" => Ok(Self::NotCharging),
+            "full"         => Ok(Self::Full),
+            _ => {
+                Err(anyhow::anyhow!("Unknown battery status: {}", s))
+                    .context("Protocol error")
+            },
+        }
+    }
+}
+
+impl Display for BatteryStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown     => write!(f, "unknown"),
+            Self::Charging    => write!(f, "charging"),
+            Self::Discharging => write!(f, "discharging"),
+            Self::NotCharging => write!(f, "not-charging"),
+            Self::Full        => write!(f, "full"),
+        }
+    }
+}
+
+
+/// Battery state of the base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaseBattery {
+    /// Capacity in percent, if reported.
+    pub capacity: Option<u8>,
+    pub status: BatteryStatus,
+}
+
+impl BaseBattery {
+    /// Parse the `BaseBattery` property, which is empty if there is no
+    /// battery.
+    pub(crate) fn from_propmap(battery: &PropMap) -> Result<Option<Self>> {
+        let status = match battery.get("status").and_then(|v| v.as_str()) {
+            Some(status) => status.parse()?,
+            None => return Ok(None),
+        };
+
+        let capacity = battery.get("capacity")
+            .and_then(|v| v.as_u64())
+            .map(|c| c.min(100) as u8);
+
+        Ok(Some(BaseBattery { capacity, status }))
+    }
+}
+
+
+/// Firmware information of the base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseFirmware {
+    pub version: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl BaseFirmware {
+    /// Parse the `BaseFirmware` property, which is empty if the firmware is
+    /// not known.
+    pub(crate) fn from_propmap(firmware: &PropMap) -> Option<Self> {
+        let version = firmware.get("version").and_then(|v| v.as_str()).map(ToOwned::to_owned);
+        let serial = firmware.get("serial").and_then(|v| v.as_str()).map(ToOwned::to_owned);
+
+        if version.is_none() && serial.is_none() {
+            return None;
+        }
+
+        Some(BaseFirmware { version, serial })
+    }
+}
+
+
+/// Result of a single handler run, as recorded by the daemon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandlerRecord {
+    pub handler: String,
+    pub time: SystemTime,
+    pub duration: Duration,
+    pub result: String,
+    pub session: Option<u64>,
+}
+
+impl HandlerRecord {
+    pub(crate) fn from_propmap(record: &PropMap) -> Self {
+        let time = record.get("time").and_then(|v| v.as_u64()).unwrap_or_default();
+        let duration = record.get("duration").and_then(|v| v.as_f64()).unwrap_or_default();
+
+        HandlerRecord {
+            handler: record.get("handler").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+            time: UNIX_EPOCH + Duration::from_secs(time),
+            duration: Duration::from_secs_f64(duration.max(0.0)),
+            result: record.get("result").and_then(|v| v.as_str()).unwrap_or_default().to_owned(),
+            session: record.get("session").and_then(|v| v.as_u64()),
+        }
+    }
+}
+
+
+/// Result and output of a handler test run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandlerTest {
+    /// Result of the run, e.g. `exit:0` or `timeout`.
+    pub result: String,
+
+    pub duration: Duration,
+    pub stdout: String,
+    pub stderr: String,
+
+    /// Status messages reported by the handler.
+    pub messages: Vec<String>,
+}
+
+impl HandlerTest {
+    pub(crate) fn from_args((result, duration, stdout, stderr, messages): (String, f64, String, String, Vec<String>))
+        -> Self
+    {
+        HandlerTest {
+            result,
+            duration: Duration::from_secs_f64(duration.max(0.0)),
+            stdout,
+            stderr,
+            messages,
+        }
+    }
+}
+
+
+/// Event counts and handler statistics since the daemon has been started.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Statistics {
+    /// Number of emitted events by type, e.g. `detachment:start`.
+    pub counters: BTreeMap<String, u64>,
+
+    /// Number of canceled detachments by reason, e.g. `timeout:handler`.
+    pub cancels: BTreeMap<String, u64>,
+
+    /// Accumulated statistics of each handler.
+    pub handlers: Vec<HandlerStatistics>,
+
+    /// Upper bounds of the histogram buckets, in seconds. Each histogram has
+    /// an additional last bucket for durations above the largest bound.
+    pub buckets: Vec<f64>,
+
+    /// Duration histograms of each handler stage.
+    pub histograms: Vec<HandlerHistogram>,
+}
+
+impl Statistics {
+    pub(crate) fn from_args(counters: HashMap<String, u64>, cancels: HashMap<String, u64>,
+                            handlers: Vec<(String, u64, u64, f64, f64)>, buckets: Vec<f64>,
+                            histograms: Vec<(String, String, Vec<u64>)>)
+        -> Self
+    {
+        let handlers = handlers.into_iter()
+            .map(|(handler, count, failures, total, max)| HandlerStatistics {
+                handler,
+                count,
+                failures,
+                total: Duration::from_secs_f64(total.max(0.0)),
+                max: Duration::from_secs_f64(max.max(0.0)),
+            })
+            .collect();
+
+        let histograms = histograms.into_iter()
+            .map(|(handler, stage, counts)| HandlerHistogram { handler, stage, counts })
+            .collect();
+
+        Statistics {
+            counters: counters.into_iter().collect(),
+            cancels: cancels.into_iter().collect(),
+            handlers,
+            buckets,
+            histograms,
+        }
+    }
+}
+
+/// Accumulated statistics of all runs of a single handler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandlerStatistics {
+    pub handler: String,
+    pub count: u64,
+    pub failures: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+/// Duration histogram of a single handler stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerHistogram {
+    pub handler: String,
+
+    /// Stage of the handler task, i.e. `delay` (until the handler has been
+    /// started), `exec` (running the handler), or `total`.
+    pub stage: String,
+
+    /// Number of durations per bucket, see [`Statistics::buckets`].
+    pub counts: Vec<u64>,
+}
+
+
+/// Health of the daemon and its connection to the DTX device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    /// Time since the daemon has been started.
+    pub uptime: Duration,
+
+    /// Whether the DTX device is still responding to requests.
+    pub available: bool,
+
+    /// Time at which the last event has been received from the DTX device,
+    /// if any.
+    pub last_event: Option<SystemTime>,
+}
+
+impl Health {
+    pub(crate) fn from_args(uptime: f64, available: bool, last_event: u64) -> Self {
+        Health {
+            uptime: Duration::from_secs_f64(uptime.max(0.0)),
+            available,
+            last_event: (last_event > 0).then(|| UNIX_EPOCH + Duration::from_secs(last_event)),
+        }
+    }
+}
+
+
+/// Effective configuration of the daemon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigReport {
+    /// Path of the loaded config file, empty if the defaults are used.
+    pub path: String,
+
+    /// Configuration with all defaults applied, serialized as JSON.
+    pub config: String,
+
+    /// Unknown config items.
+    pub unknowns: Vec<String>,
+
+    /// Problems with the configuration, e.g. missing handler executables.
+    pub problems: Vec<String>,
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use dbus::arg::Variant;
+
+    fn propmap(items: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        items.into_iter()
+            .map(|(key, value)| (key.to_owned(), Variant(value)))
+            .collect()
+    }
+
+    #[test]
+    fn parse_roundtrip() {
+        for s in ["tablet", "laptop", "studio"] {
+            assert_eq!(s.parse::<DeviceMode>().unwrap().to_string(), s);
+        }
+
+        for s in ["closed", "opened", "error:hardware:failed-to-open", "error:hardware:unknown:7"] {
+            assert_eq!(s.parse::<LatchStatus>().unwrap().to_string(), s);
+        }
+
+        for s in ["detached", "attached", "not-feasible"] {
+            assert_eq!(s.parse::<BaseState>().unwrap().to_string(), s);
+        }
+
+        for s in ["hid", "ssh", "unknown:42"] {
+            assert_eq!(s.parse::<DeviceType>().unwrap().to_string(), s);
+        }
+
+        for s in ["unknown", "charging", "discharging", "not-charging", "full"] {
+            assert_eq!(s.parse::<BatteryStatus>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!("desktop".parse::<DeviceMode>().is_err());
+        assert!("ajar".parse::<LatchStatus>().is_err());
+        assert!("error:hardware:bogus".parse::<LatchStatus>().is_err());
+        assert!("unknown:256".parse::<DeviceType>().is_err());
+        assert!("empty".parse::<BatteryStatus>().is_err());
+    }
+
+    #[test]
+    fn base_info() {
+        let info = BaseInfo::from_arg(("attached".into(), "ssh".into(), 3)).unwrap();
+        assert_eq!(info.state, BaseState::Attached);
+        assert_eq!(info.device_type, DeviceType::Ssh);
+        assert_eq!(info.id, 3);
+
+        assert!(BaseInfo::from_arg(("attached".into(), "usb".into(), 3)).is_err());
+    }
+
+    #[test]
+    fn base_battery() {
+        assert_eq!(BaseBattery::from_propmap(&PropMap::new()).unwrap(), None);
+
+        let battery = propmap(vec![("status", Box::new("charging".to_owned())), ("capacity", Box::new(87u8))]);
+        assert_eq!(BaseBattery::from_propmap(&battery).unwrap(), Some(BaseBattery {
+            capacity: Some(87),
+            status: BatteryStatus::Charging,
+        }));
+
+        // capacity is optional and clamped
+        let battery = propmap(vec![("status", Box::new("full".to_owned()))]);
+        assert_eq!(BaseBattery::from_propmap(&battery).unwrap().unwrap().capacity, None);
+
+        let battery = propmap(vec![("status", Box::new("full".to_owned())), ("capacity", Box::new(150u8))]);
+        assert_eq!(BaseBattery::from_propmap(&battery).unwrap().unwrap().capacity, Some(100));
+
+        let battery = propmap(vec![("status", Box::new("exploding".to_owned()))]);
+        assert!(BaseBattery::from_propmap(&battery).is_err());
+    }
+
+    #[test]
+    fn base_firmware() {
+        assert_eq!(BaseFirmware::from_propmap(&PropMap::new()), None);
+
+        let firmware = propmap(vec![("version", Box::new("1.2.3".to_owned()))]);
+        assert_eq!(BaseFirmware::from_propmap(&firmware), Some(BaseFirmware {
+            version: Some("1.2.3".into()),
+            serial: None,
+        }));
+    }
+
+    #[test]
+    fn handler_record() {
+        let record = propmap(vec![
+            ("handler", Box::new("detach".to_owned())),
+            ("time", Box::new(1_700_000_000u64)),
+            ("duration", Box::new(1.5f64)),
+            ("result", Box::new("exit:0".to_owned())),
+            ("session", Box::new(4u64)),
+        ]);
+
+        assert_eq!(HandlerRecord::from_propmap(&record), HandlerRecord {
+            handler: "detach".into(),
+            time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            duration: Duration::from_millis(1500),
+            result: "exit:0".into(),
+            session: Some(4),
+        });
+
+        // missing values fall back to defaults, the session is optional
+        let record = HandlerRecord::from_propmap(&propmap(vec![("duration", Box::new(-1.0f64))]));
+        assert_eq!(record.duration, Duration::ZERO);
+        assert_eq!(record.time, UNIX_EPOCH);
+        assert_eq!(record.session, None);
+    }
+
+    #[test]
+    fn health() {
+        let health = Health::from_args(12.5, true, 0);
+        assert_eq!(health.uptime, Duration::from_millis(12500));
+        assert_eq!(health.last_event, None);
+
+        let health = Health::from_args(1.0, false, 60);
+        assert_eq!(health.last_event, Some(UNIX_EPOCH + Duration::from_secs(60)));
+    }
+}
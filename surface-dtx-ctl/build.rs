@@ -0,0 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+use clap_complete::shells;
+
+include!("src/cli.rs");
+
+
+fn main() {
+    let outdir: PathBuf = env::var_os("CARGO_TARGET_DIR")
+        .or_else(|| env::var_os("OUT_DIR"))
+        .unwrap()
+        .into();
+
+    // generate shell completions
+    let mut app = app();
+    clap_complete::generate_to(shells::Bash, &mut app, "surface-dtx-ctl", &outdir).unwrap();
+    clap_complete::generate_to(shells::Zsh,  &mut app, "surface-dtx-ctl", &outdir).unwrap();
+    clap_complete::generate_to(shells::Fish, &mut app, "surface-dtx-ctl", &outdir).unwrap();
+
+    // generate man page
+    let mut man = std::fs::File::create(outdir.join("surface-dtx-ctl.1")).unwrap();
+    clap_mangen::Man::new(app).title("surface-dtx-ctl").render(&mut man).unwrap();
+}
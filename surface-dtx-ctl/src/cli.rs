@@ -0,0 +1,196 @@
+use clap::{Arg, ArgAction, Command};
+
+pub fn app() -> Command {
+    Command::new("Surface DTX Control")
+        .about(clap::crate_description!())
+        .version(clap::crate_version!())
+        .author(clap::crate_authors!())
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name("FORMAT")
+            .help("Output format")
+            .value_parser(["human", "json"])
+            .default_value("human")
+            .global(true))
+        .arg(Arg::new("socket")
+            .long("socket")
+            .value_name("PATH")
+            .help("Connect to the peer-to-peer socket of the daemon instead of the system bus")
+            .global(true))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .help("Only report errors, rely on the exit status otherwise")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("verbose")
+            .global(true))
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("Print confirmations and full error details")
+            .action(ArgAction::SetTrue)
+            .global(true))
+        .after_help("Exit status: 0 on success, 1 on general failure, 2 if not supported by the \
+                     daemon, 3 if the detachment has been inhibited, 4 on timeout, 5 if the \
+                     daemon or device is unavailable, 6 if permission has been denied. See the mode \
+                     subcommand for its specific exit status.")
+        .subcommand(Command::new("status")
+            .about("Show the current state of the device"))
+        .subcommand(Command::new("base")
+            .about("Show information about the base"))
+        .subcommand(Command::new("mode")
+            .about("Show the current device mode")
+            .after_help("Exit status: 10 in tablet mode, 11 in laptop mode, 12 in studio mode, \
+                         or one of the general error codes on failure."))
+        .subcommand(Command::new("request")
+            .about("Request detachment, or cancel an ongoing detachment")
+            .after_help("Exit status: 3 if the daemon inhibits the detachment, e.g. because it is \
+                         currently not feasible.")
+            .arg(Arg::new("force")
+                .long("force")
+                .help("Skip the detachment handler, e.g. if it is known to be broken (requires root)")
+                .action(ArgAction::SetTrue)))
+        .subcommand(Command::new("latch")
+            .about("Control the latch")
+            .subcommand_required(true)
+            .arg_required_else_help(true)
+            .subcommand(Command::new("lock")
+                .about("Lock the latch, disabling the detach button (requires root)"))
+            .subcommand(Command::new("unlock")
+                .about("Unlock the latch, re-enabling the detach button (requires root)"))
+            .subcommand(Command::new("status")
+                .about("Show the current latch status and whether it has been locked")))
+        .subcommand(Command::new("retry")
+            .about("Retry a deferred detachment handler immediately"))
+        .subcommand(Command::new("handlers")
+            .about("Show results of recent handler runs")
+            .subcommand(Command::new("test")
+                .about("Run a handler in dry-run mode and show its output (requires root)")
+                .arg(Arg::new("handler")
+                    .value_name("HANDLER")
+                    .help("Handler to run")
+                    .value_parser(["detach", "attach", "abort"])
+                    .required(true))))
+        .subcommand(Command::new("stats")
+            .about("Show detachment counts, cancellations, and handler durations since daemon start"))
+        .subcommand(Command::new("health")
+            .about("Check that the daemon is still connected to the DTX device"))
+        .subcommand(Command::new("config")
+            .about("Inspect the configuration of the daemon")
+            .subcommand_required(true)
+            .arg_required_else_help(true)
+            .subcommand(Command::new("check")
+                .about("Report unknown config items and missing handler executables"))
+            .subcommand(Command::new("show")
+                .about("Show the effective configuration, including defaults")))
+        .subcommand(Command::new("simulate")
+            .about("Inject a simulated device event (requires debug injection to be enabled)")
+            .arg(Arg::new("event")
+                .value_name("EVENT")
+                .help("Event to simulate")
+                .value_parser([
+                    "request",
+                    "cancel:not-feasible",
+                    "cancel:timeout",
+                    "base:attached",
+                    "base:detached",
+                    "base:not-feasible",
+                    "latch:closed",
+                    "latch:opened",
+                    "mode:tablet",
+                    "mode:laptop",
+                    "mode:studio",
+                ])
+                .required(true)))
+        .subcommand(Command::new("watch")
+            .about("Print a formatted status line whenever the device state changes")
+            .arg(Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("TEMPLATE")
+                .help("Line template, supports {mode}, {latch}, {base}, {base_type}, and {base_id}")
+                .default_value("{mode} {base}")))
+        .subcommand(Command::new("monitor")
+            .about("Print daemon events and property changes as they happen")
+            .arg(Arg::new("events")
+                .short('e')
+                .long("events")
+                .value_name("CATEGORIES")
+                .help("Only show the given comma-separated categories, e.g. 'detachment,mode'")
+                .value_delimiter(',')))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> clap::error::Result<clap::ArgMatches> {
+        app().try_get_matches_from(std::iter::once("surface-dtx-ctl").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn definition() {
+        app().debug_assert();
+    }
+
+    #[test]
+    fn global_options() {
+        let m = parse(&["status"]).unwrap();
+        assert_eq!(m.get_one::<String>("output").unwrap(), "human");
+        assert!(!m.get_flag("quiet") && !m.get_flag("verbose"));
+
+        // global options may also follow the subcommand
+        let m = parse(&["status", "-o", "json", "--socket", "/run/sdtx.sock", "-v"]).unwrap();
+        assert_eq!(m.get_one::<String>("output").unwrap(), "json");
+        assert_eq!(m.get_one::<String>("socket").unwrap(), "/run/sdtx.sock");
+        assert!(m.get_flag("verbose"));
+
+        assert!(parse(&["-o", "yaml", "status"]).is_err());
+        assert!(parse(&["-q", "-v", "status"]).is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["latch"]).is_err());
+        assert!(parse(&["config"]).is_err());
+
+        let m = parse(&["request", "--force"]).unwrap();
+        let (name, args) = m.subcommand().unwrap();
+        assert_eq!(name, "request");
+        assert!(args.get_flag("force"));
+
+        let m = parse(&["latch", "lock"]).unwrap();
+        assert_eq!(m.subcommand().unwrap().1.subcommand_name(), Some("lock"));
+
+        // handler records without subcommand, handler tests require a known handler
+        let m = parse(&["handlers"]).unwrap();
+        assert_eq!(m.subcommand().unwrap().1.subcommand_name(), None);
+
+        let m = parse(&["handlers", "test", "abort"]).unwrap();
+        let (_, args) = m.subcommand().unwrap().1.subcommand().unwrap();
+        assert_eq!(args.get_one::<String>("handler").unwrap(), "abort");
+
+        assert!(parse(&["handlers", "test"]).is_err());
+        assert!(parse(&["handlers", "test", "posture"]).is_err());
+
+        assert!(parse(&["simulate", "mode:studio"]).is_ok());
+        assert!(parse(&["simulate", "mode:desk"]).is_err());
+    }
+
+    #[test]
+    fn watch_and_monitor() {
+        let m = parse(&["watch"]).unwrap();
+        let args = m.subcommand().unwrap().1;
+        assert_eq!(args.get_one::<String>("format").unwrap(), "{mode} {base}");
+
+        let m = parse(&["monitor", "-e", "detachment,mode"]).unwrap();
+        let args = m.subcommand().unwrap().1;
+        let events: Vec<_> = args.get_many::<String>("events").unwrap().collect();
+        assert_eq!(events, ["detachment", "mode"]);
+    }
+}
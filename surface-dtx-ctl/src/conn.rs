@@ -0,0 +1,71 @@
+use std::ops::Deref;
+
+use anyhow::{Context, Result};
+
+use dbus::Message;
+use dbus::blocking::Connection;
+use dbus::channel::{Channel, MatchingReceiver, Token};
+use dbus::message::MatchRule;
+
+
+/// Connection to the daemon, either via the system bus or directly via a
+/// peer-to-peer socket.
+pub struct DaemonConnection {
+    conn: Connection,
+    p2p: bool,
+}
+
+impl DaemonConnection {
+    pub fn system() -> Result<Self> {
+        let conn = Connection::new_system()
+            .context("Failed to connect to D-Bus (system)")?;
+
+        Ok(Self { conn, p2p: false })
+    }
+
+    pub fn socket(path: &str) -> Result<Self> {
+        let address = format!("unix:path={path}");
+
+        // peer-to-peer connections must not be registered with a bus
+        let channel = Channel::open_private(&address)
+            .with_context(|| format!("Failed to connect to daemon socket '{path}'"))?;
+
+        Ok(Self { conn: Connection::from(channel), p2p: true })
+    }
+
+    /// Subscribe to signals matching the given rule.
+    ///
+    /// On peer-to-peer connections, signals are delivered without bus-side
+    /// match rules and without sender, so the rule is only applied locally
+    /// and any sender restriction is dropped.
+    pub fn subscribe<F>(&self, mut rule: MatchRule<'static>, mut callback: F) -> Result<Token>
+    where
+        F: FnMut(&Message) -> bool + Send + 'static,
+    {
+        if self.p2p {
+            rule.sender = None;
+            Ok(self.conn.start_receive(rule, Box::new(move |msg, _| callback(&msg))))
+        } else {
+            self.conn.add_match(rule, move |(), _, msg: &Message| callback(msg))
+                .context("Failed to subscribe to daemon signals")
+        }
+    }
+
+    pub fn unsubscribe(&self, token: Token) -> Result<()> {
+        if self.p2p {
+            self.conn.stop_receive(token);
+            Ok(())
+        } else {
+            self.conn.remove_match(token)
+                .context("Failed to unsubscribe from daemon signals")
+        }
+    }
+}
+
+impl Deref for DaemonConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
@@ -0,0 +1,124 @@
+use surface_dtx_client::CancelReason;
+
+
+/// Exit status of the utility on failure, zero is used for success. Also
+/// listed in the help text, keep both in sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Failure,
+    NotSupported,
+    Inhibited,
+    Timeout,
+    Unavailable,
+    PermissionDenied,
+}
+
+impl Code {
+    pub fn value(self) -> i32 {
+        match self {
+            Code::Failure          => 1,
+            Code::NotSupported     => 2,
+            Code::Inhibited        => 3,
+            Code::Timeout          => 4,
+            Code::Unavailable      => 5,
+            Code::PermissionDenied => 6,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::Failure          => "failure",
+            Code::NotSupported     => "not-supported",
+            Code::Inhibited        => "inhibited",
+            Code::Timeout          => "timeout",
+            Code::Unavailable      => "unavailable",
+            Code::PermissionDenied => "permission-denied",
+        }
+    }
+
+    /// Determine the exit status for the given error, based on the first
+    /// error in its chain that we know how to classify.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if cause.is::<Inhibited>() {
+                return Code::Inhibited;
+            }
+
+            if let Some(err) = cause.downcast_ref::<dbus::Error>() {
+                return Code::from_dbus_error(err);
+            }
+        }
+
+        Code::Failure
+    }
+
+    fn from_dbus_error(err: &dbus::Error) -> Self {
+        let name = err.name().unwrap_or_default();
+        let name = name.strip_prefix("org.freedesktop.DBus.Error.")
+            .or_else(|| name.strip_prefix("org.surface.dtx.Error."))
+            .unwrap_or(name);
+
+        match name {
+            "NoReply" | "Timeout" | "TimedOut"
+                => Code::Timeout,
+            "ServiceUnknown" | "NameHasNoOwner" | "NoServer" | "Disconnected" | "FileNotFound" | "Busy"
+                => Code::Unavailable,
+            "AccessDenied" | "AuthFailed" | "InteractiveAuthorizationRequired"
+                => Code::PermissionDenied,
+            "UnknownMethod" | "UnknownObject" | "UnknownInterface" | "UnknownProperty" | "NotSupported"
+                => Code::NotSupported,
+            _   => Code::Failure,
+        }
+    }
+}
+
+
+/// Error indicating that the daemon has refused to start a detachment.
+#[derive(Debug)]
+pub struct Inhibited {
+    pub reason: CancelReason,
+}
+
+impl std::fmt::Display for Inhibited {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Detachment inhibited by daemon (reason: {})", self.reason)
+    }
+}
+
+impl std::error::Error for Inhibited {}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::Context;
+
+    fn dbus_error(name: &str) -> anyhow::Error {
+        anyhow::Error::new(dbus::Error::new_custom(name, "failed")).context("Failed to call method")
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(Code::from_error(&anyhow::anyhow!("failed")), Code::Failure);
+
+        let cases = [
+            ("org.freedesktop.DBus.Error.NoReply", Code::Timeout),
+            ("org.freedesktop.DBus.Error.ServiceUnknown", Code::Unavailable),
+            ("org.freedesktop.DBus.Error.AccessDenied", Code::PermissionDenied),
+            ("org.freedesktop.DBus.Error.UnknownMethod", Code::NotSupported),
+            ("org.surface.dtx.Error.NotSupported", Code::NotSupported),
+            ("org.surface.dtx.Error.Timeout", Code::Timeout),
+            ("org.freedesktop.DBus.Error.Failed", Code::Failure),
+        ];
+
+        for (name, code) in cases {
+            assert_eq!(Code::from_error(&dbus_error(name)), code, "{}", name);
+        }
+
+        let err = Err::<(), _>(Inhibited { reason: CancelReason::UserRequest })
+            .context("Failed to request detachment")
+            .unwrap_err();
+        assert_eq!(Code::from_error(&err), Code::Inhibited);
+    }
+}
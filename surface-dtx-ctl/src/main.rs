@@ -0,0 +1,362 @@
+mod cli;
+mod conn;
+mod exit;
+mod monitor;
+mod output;
+mod watch;
+
+use crate::conn::DaemonConnection;
+use crate::output::{
+    Base,
+    Battery,
+    ConfigCheck,
+    ConfigShow,
+    Done,
+    Firmware,
+    Format,
+    HandlerRecord,
+    HandlerStats,
+    HandlerTest,
+    Health,
+    Latch,
+    Mode,
+    Output,
+    StageHistogram,
+    Stats,
+    Status,
+    Verbosity,
+};
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use clap::ArgMatches;
+
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+
+use surface_dtx_client::{DeviceMode, Event};
+use surface_dtx_client::blocking::Client;
+
+
+const DEBUG_PATH: &str = "/org/surface/dtx/debug";
+const DEBUG_INTERFACE: &str = "org.surface.dtx.Debug";
+
+/// Time to wait for the daemon to either start or inhibit a requested
+/// detachment.
+const REQUEST_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+fn status(client: &Client<&Connection>) -> Result<Status> {
+    Ok(Status {
+        device_mode: client.device_mode()?.to_string(),
+        latch_status: client.latch_status()?.to_string(),
+        base: base(client)?,
+        dry_run: client.dry_run()?,
+    })
+}
+
+fn base(client: &Client<&Connection>) -> Result<Base> {
+    let base = client.base()?;
+
+    Ok(Base {
+        state: base.state.to_string(),
+        ty: base.device_type.to_string(),
+        id: base.id,
+        battery: base.battery.map(|battery| Battery {
+            capacity: battery.capacity,
+            status: battery.status.to_string(),
+        }),
+        firmware: base.firmware.map(|firmware| Firmware {
+            version: firmware.version,
+            serial: firmware.serial,
+        }),
+    })
+}
+
+/// Print the device mode and exit with a status code specific to it.
+fn mode(out: Output, client: &Client<&Connection>) -> Result<()> {
+    let mode = client.device_mode()?;
+
+    output::print(out, &Mode { device_mode: mode.to_string() });
+
+    std::process::exit(match mode {
+        DeviceMode::Tablet => 10,
+        DeviceMode::Laptop => 11,
+        DeviceMode::Studio => 12,
+    })
+}
+
+fn latch_status(client: &Client<&Connection>) -> Result<Latch> {
+    Ok(Latch {
+        latch_status: client.latch_status()?.to_string(),
+        locked: client.latch_locked()?,
+    })
+}
+
+fn config_check(client: &Client<&Connection>) -> Result<ConfigCheck> {
+    let report = client.config()?;
+    let ok = report.unknowns.is_empty() && report.problems.is_empty();
+
+    Ok(ConfigCheck {
+        path: report.path,
+        unknowns: report.unknowns,
+        problems: report.problems,
+        ok,
+    })
+}
+
+fn health(client: &Client<&Connection>) -> Result<Health> {
+    let health = client.health()?;
+
+    let last_event = health.last_event
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|t| t.as_secs());
+
+    Ok(Health {
+        uptime: health.uptime.as_secs_f64(),
+        available: health.available,
+        last_event,
+    })
+}
+
+fn config_show(client: &Client<&Connection>) -> Result<ConfigShow> {
+    let report = client.config()?;
+
+    let config = serde_json::from_str(&report.config)
+        .context("Failed to parse daemon configuration")?;
+
+    Ok(ConfigShow { path: report.path, config })
+}
+
+/// Request detachment (or cancellation thereof) and check that the daemon
+/// does not inhibit it.
+fn request(conn: &DaemonConnection, client: &Client<&Connection>, force: bool) -> Result<Done> {
+    let rule = MatchRule::new_signal(surface_dtx_client::INTERFACE, "Event")
+        .with_path(surface_dtx_client::PATH);
+
+    // first detachment event emitted after our request, if any
+    let response = Arc::new(Mutex::new(None));
+    let sink = response.clone();
+
+    let token = conn.subscribe(rule, move |msg| {
+        match Event::from_message(msg) {
+            Ok(event @ Event::DetachmentInhibited { .. })
+            | Ok(event @ Event::DetachmentStart)
+            | Ok(event @ Event::DetachmentCancel { .. }) => {
+                sink.lock().unwrap().get_or_insert(event);
+            },
+            _ => {},
+        }
+        true
+    })?;
+
+    if force {
+        client.request_force()?;
+    } else {
+        client.request()?;
+    }
+
+    // no response within the timeout is not an error: the daemon may simply
+    // not run a handler (e.g. in dry-run mode)
+    let deadline = Instant::now() + REQUEST_RESPONSE_TIMEOUT;
+    while response.lock().unwrap().is_none() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        conn.process(deadline - now)
+            .context("D-Bus connection error (system)")?;
+    }
+
+    conn.unsubscribe(token)?;
+
+    let response = response.lock().unwrap().take();
+    if let Some(Event::DetachmentInhibited { reason }) = response {
+        return Err(exit::Inhibited { reason }.into());
+    }
+
+    Ok(Done { command: "request", success: true })
+}
+
+fn call(command: &'static str, result: Result<()>) -> Result<Done> {
+    result.map(|()| Done { command, success: true })
+}
+
+fn simulate(conn: &Connection, event: &str) -> Result<Done> {
+    conn.with_proxy(surface_dtx_client::NAME, DEBUG_PATH, surface_dtx_client::TIMEOUT)
+        .method_call::<(), _, _, _>(DEBUG_INTERFACE, "Inject", (event,))
+        .context("Failed to inject event (is debug injection enabled in the daemon config?)")?;
+
+    Ok(Done { command: "simulate", success: true })
+}
+
+fn handlers(client: &Client<&Connection>) -> Result<Vec<HandlerRecord>> {
+    let records = client.handler_records()?
+        .into_iter()
+        .map(|record| HandlerRecord {
+            handler: record.handler,
+            time: record.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            duration: record.duration.as_secs_f64(),
+            result: record.result,
+            session: record.session,
+        })
+        .collect();
+
+    Ok(records)
+}
+
+fn handler_test(client: &Client<&Connection>, handler: &str) -> Result<HandlerTest> {
+    let test = client.test_handler(handler)?;
+
+    // the exit status of the detachment handler determines how to proceed
+    let action = match (handler, test.result.as_str()) {
+        ("detach", "exit:0")  => Some("commence"),
+        ("detach", "exit:75") => Some("defer"),
+        ("detach", _)         => Some("abort"),
+        _                     => None,
+    };
+
+    Ok(HandlerTest {
+        handler: handler.to_owned(),
+        result: test.result,
+        action,
+        duration: test.duration.as_secs_f64(),
+        stdout: test.stdout,
+        stderr: test.stderr,
+        messages: test.messages,
+    })
+}
+
+fn stats(client: &Client<&Connection>) -> Result<Stats> {
+    let stats = client.statistics()?;
+
+    let handlers = stats.handlers.into_iter()
+        .map(|h| HandlerStats {
+            mean: if h.count > 0 { h.total.as_secs_f64() / h.count as f64 } else { 0.0 },
+            total: h.total.as_secs_f64(),
+            max: h.max.as_secs_f64(),
+            handler: h.handler,
+            count: h.count,
+            failures: h.failures,
+        })
+        .collect();
+
+    let histograms = stats.histograms.into_iter()
+        .map(|h| StageHistogram { handler: h.handler, stage: h.stage, counts: h.counts })
+        .collect();
+
+    Ok(Stats {
+        events: stats.counters,
+        cancels: stats.cancels,
+        handlers,
+        buckets: stats.buckets,
+        histograms,
+    })
+}
+
+fn run(out: Output, command: &str, args: &ArgMatches) -> Result<()> {
+    let conn = match args.get_one::<String>("socket") {
+        Some(path) => DaemonConnection::socket(path)?,
+        None       => DaemonConnection::system()?,
+    };
+
+    let client = Client::new(&*conn);
+
+    match command {
+        "monitor"  => {
+            let filter = args.get_many::<String>("events")
+                .map(|e| e.cloned().collect())
+                .unwrap_or_default();
+
+            monitor::run(&conn, out, filter)?
+        },
+        "watch"    => {
+            let template = args.get_one::<String>("format").unwrap().clone();
+            watch::run(&conn, out, template)?
+        },
+        "status"   => output::print(out, &status(&client)?),
+        "base"     => output::print(out, &base(&client)?),
+        "mode"     => mode(out, &client)?,
+        "request"  => output::print(out, &request(&conn, &client, args.get_flag("force"))?),
+        "latch"    => match args.subcommand().unwrap() {
+            ("lock", _)   => output::print(out, &call("latch lock", client.lock())?),
+            ("unlock", _) => output::print(out, &call("latch unlock", client.unlock())?),
+            ("status", _) => output::print(out, &latch_status(&client)?),
+            _             => unreachable!("unknown subcommand"),
+        },
+        "config"   => match args.subcommand().unwrap() {
+            ("check", _) => {
+                let check = config_check(&client)?;
+                output::print(out, &check);
+
+                if !check.ok {
+                    std::process::exit(exit::Code::Failure.value());
+                }
+            },
+            ("show", _)  => output::print(out, &config_show(&client)?),
+            _            => unreachable!("unknown subcommand"),
+        },
+        "retry"    => output::print(out, &call("retry", client.retry())?),
+        "handlers" => match args.subcommand() {
+            Some(("test", args)) => {
+                let handler = args.get_one::<String>("handler").unwrap();
+                output::print(out, &handler_test(&client, handler)?)
+            },
+            _ => output::print(out, &handlers(&client)?),
+        },
+        "stats"    => output::print(out, &stats(&client)?),
+        "health"   => {
+            let health = health(&client)?;
+            output::print(out, &health);
+
+            if !health.available {
+                std::process::exit(exit::Code::Failure.value());
+            }
+        },
+        "simulate" => {
+            let event = args.get_one::<String>("event").unwrap();
+            output::print(out, &simulate(&conn, event)?)
+        },
+        _          => unreachable!("unknown subcommand"),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let matches = cli::app().get_matches();
+
+    let verbosity = if matches.get_flag("quiet") {
+        Verbosity::Quiet
+    } else if matches.get_flag("verbose") {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+
+    let out = Output {
+        format: Format::from_arg(matches.get_one::<String>("output").unwrap()),
+        verbosity,
+    };
+
+    let (command, args) = matches.subcommand().unwrap();
+
+    if let Err(err) = run(out, command, args) {
+        let code = exit::Code::from_error(&err);
+
+        let error = output::Error {
+            error: format!("{err:#}"),
+            kind: code.name(),
+            exit_code: code.value(),
+            causes: err.chain().map(|e| e.to_string()).collect(),
+        };
+
+        // errors are reported even in quiet mode
+        output::print_always(out, &error);
+        std::process::exit(code.value());
+    }
+}
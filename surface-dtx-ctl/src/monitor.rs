@@ -0,0 +1,246 @@
+use crate::conn::DaemonConnection;
+use crate::output::{self, Human, Output};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::Message;
+use dbus::arg::{ArgType, RefArg};
+use dbus::message::MatchRule;
+
+use serde::Serialize;
+
+
+/// Single entry of the monitor stream.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Entry {
+    Event {
+        #[serde(rename = "type")]
+        ty: String,
+        values: BTreeMap<String, serde_json::Value>,
+    },
+    Property {
+        name: String,
+        value: serde_json::Value,
+    },
+}
+
+impl Human for Entry {
+    fn print_human(&self) {
+        match self {
+            Entry::Event { ty, values } => {
+                let values = values.iter()
+                    .map(|(k, v)| format!(" {k}={v}"))
+                    .collect::<String>();
+
+                println!("event     {ty}{values}");
+            },
+            Entry::Property { name, value } => {
+                println!("property  {name}={value}");
+            },
+        }
+    }
+}
+
+impl Entry {
+    /// Category used for filtering, e.g. "detachment" for detachment events
+    /// and "mode" for device-mode changes.
+    fn category(&self) -> &str {
+        match self {
+            Entry::Event { ty, .. } => ty.split(':').next().unwrap_or_default(),
+            Entry::Property { name, .. } => match name.as_str() {
+                "DeviceMode"   => "mode",
+                "LatchStatus"  => "latch",
+                "LatchLocked"  => "latch",
+                "Base"         => "base",
+                "BaseBattery"  => "base",
+                "BaseFirmware" => "base",
+                "DryRun"       => "dry-run",
+                _              => "",
+            },
+        }
+    }
+}
+
+
+/// Print daemon events and property changes until interrupted.
+pub fn run(conn: &DaemonConnection, out: Output, filter: Vec<String>) -> Result<()> {
+    let events = MatchRule::new_signal(surface_dtx_client::INTERFACE, "Event")
+        .with_path(surface_dtx_client::PATH);
+
+    let props = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+        .with_sender(surface_dtx_client::NAME)
+        .with_path(surface_dtx_client::PATH);
+
+    let print = move |entries: Vec<Entry>| {
+        for entry in entries {
+            if filter.is_empty() || filter.iter().any(|f| f == entry.category()) {
+                output::print(out, &entry);
+            }
+        }
+        true
+    };
+
+    let print_props = print.clone();
+
+    conn.subscribe(events, move |msg| print(parse_event(msg).into_iter().collect()))
+        .context("Failed to subscribe to daemon events")?;
+
+    conn.subscribe(props, move |msg| print_props(parse_properties(msg)))
+        .context("Failed to subscribe to daemon properties")?;
+
+    loop {
+        conn.process(Duration::from_secs(60))
+            .context("D-Bus connection error (system)")?;
+    }
+}
+
+fn parse_event(msg: &Message) -> Option<Entry> {
+    let mut iter = msg.iter_init();
+
+    let ty: String = iter.read().ok()?;
+    let values = dict(iter.get_refarg()?.as_ref());
+
+    Some(Entry::Event { ty, values })
+}
+
+fn parse_properties(msg: &Message) -> Vec<Entry> {
+    let mut iter = msg.iter_init();
+
+    let interface: String = match iter.read() {
+        Ok(interface) => interface,
+        Err(_) => return Vec::new(),
+    };
+
+    if interface != surface_dtx_client::INTERFACE {
+        return Vec::new();
+    }
+
+    let changed = match iter.get_refarg() {
+        Some(changed) => dict(changed.as_ref()),
+        None => return Vec::new(),
+    };
+
+    changed.into_iter()
+        .map(|(name, value)| Entry::Property { name, value })
+        .collect()
+}
+
+/// Convert a D-Bus dictionary with string keys to a map of JSON values.
+fn dict(arg: &dyn RefArg) -> BTreeMap<String, serde_json::Value> {
+    let mut map = BTreeMap::new();
+
+    if let Some(mut iter) = arg.as_iter() {
+        while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+            if let Some(key) = key.as_str() {
+                map.insert(key.to_owned(), json(value));
+            }
+        }
+    }
+
+    map
+}
+
+/// Convert a D-Bus value to JSON.
+fn json(arg: &dyn RefArg) -> serde_json::Value {
+    use serde_json::Value;
+
+    match arg.arg_type() {
+        ArgType::Variant => {
+            arg.as_iter()
+                .and_then(|mut i| i.next().map(json))
+                .unwrap_or(Value::Null)
+        },
+        ArgType::Boolean => Value::Bool(arg.as_i64() == Some(1)),
+        ArgType::String | ArgType::ObjectPath | ArgType::Signature => {
+            Value::String(arg.as_str().unwrap_or_default().to_owned())
+        },
+        ArgType::Double => {
+            arg.as_f64().map(Value::from).unwrap_or(Value::Null)
+        },
+        ArgType::Byte | ArgType::UInt16 | ArgType::UInt32 | ArgType::UInt64 => {
+            arg.as_u64().map(Value::from).unwrap_or(Value::Null)
+        },
+        ArgType::Int16 | ArgType::Int32 | ArgType::Int64 | ArgType::UnixFd => {
+            arg.as_i64().map(Value::from).unwrap_or(Value::Null)
+        },
+        ArgType::Array if arg.signature().starts_with("a{") => {
+            Value::Object(dict(arg).into_iter().collect())
+        },
+        ArgType::Array | ArgType::Struct | ArgType::DictEntry => {
+            arg.as_iter()
+                .map(|iter| Value::Array(iter.map(json).collect()))
+                .unwrap_or(Value::Null)
+        },
+        ArgType::Invalid => Value::Null,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use dbus::arg::{PropMap, Variant};
+
+    use serde_json::json;
+
+    #[test]
+    fn events() {
+        let mut args = PropMap::new();
+        args.insert("reason".into(), Variant(Box::new("timeout:handler".to_owned())));
+        args.insert("remaining".into(), Variant(Box::new(3u32)));
+
+        let msg = Message::new_signal(surface_dtx_client::PATH, surface_dtx_client::INTERFACE, "Event")
+            .unwrap()
+            .append2("detachment:cancel", args);
+
+        let entry = parse_event(&msg).unwrap();
+        assert_eq!(entry.category(), "detachment");
+        assert_eq!(serde_json::to_value(&entry).unwrap(), json!({
+            "kind": "event",
+            "type": "detachment:cancel",
+            "values": { "reason": "timeout:handler", "remaining": 3 },
+        }));
+    }
+
+    #[test]
+    fn properties() {
+        let mut battery = PropMap::new();
+        battery.insert("capacity".into(), Variant(Box::new(42u8)));
+
+        let mut changed = PropMap::new();
+        changed.insert("DeviceMode".into(), Variant(Box::new("studio".to_owned())));
+        changed.insert("LatchLocked".into(), Variant(Box::new(true)));
+        changed.insert("Base".into(), Variant(Box::new(("attached".to_owned(), "ssh".to_owned(), 7u8))));
+        changed.insert("BaseBattery".into(), Variant(Box::new(battery)));
+
+        let msg = Message::new_signal(surface_dtx_client::PATH, "org.freedesktop.DBus.Properties",
+                                      "PropertiesChanged")
+            .unwrap()
+            .append3(surface_dtx_client::INTERFACE, changed, Vec::<String>::new());
+
+        let entries: BTreeMap<_, _> = parse_properties(&msg).into_iter()
+            .map(|entry| match entry {
+                Entry::Property { ref name, ref value } => (name.clone(), (entry.category().to_owned(), value.clone())),
+                entry => panic!("unexpected entry: {:?}", entry),
+            })
+            .collect();
+
+        assert_eq!(entries["DeviceMode"], ("mode".into(), json!("studio")));
+        assert_eq!(entries["LatchLocked"], ("latch".into(), json!(true)));
+        assert_eq!(entries["Base"], ("base".into(), json!(["attached", "ssh", 7])));
+        assert_eq!(entries["BaseBattery"], ("base".into(), json!({ "capacity": 42 })));
+
+        // changes of other interfaces are ignored
+        let msg = Message::new_signal(surface_dtx_client::PATH, "org.freedesktop.DBus.Properties",
+                                      "PropertiesChanged")
+            .unwrap()
+            .append3("org.example", PropMap::new(), Vec::<String>::new());
+
+        assert!(parse_properties(&msg).is_empty());
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+
+/// Output format of the command results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+impl Format {
+    pub fn from_arg(arg: &str) -> Self {
+        match arg {
+            "json" => Format::Json,
+            _      => Format::Human,
+        }
+    }
+}
+
+/// Amount of output produced for successful commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Output settings, as specified on the command line.
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub format: Format,
+    pub verbosity: Verbosity,
+}
+
+
+/// Snapshot of the device state, as provided by the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub device_mode: String,
+    pub latch_status: String,
+    pub base: Base,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Base {
+    pub state: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub id: u8,
+    pub battery: Option<Battery>,
+    pub firmware: Option<Firmware>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Battery {
+    pub capacity: Option<u8>,
+    pub status: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Firmware {
+    pub version: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// Current device mode.
+#[derive(Debug, Clone, Serialize)]
+pub struct Mode {
+    pub device_mode: String,
+}
+
+/// Current status of the latch.
+#[derive(Debug, Clone, Serialize)]
+pub struct Latch {
+    pub latch_status: String,
+    pub locked: bool,
+}
+
+/// Result of a single handler run.
+#[derive(Debug, Clone, Serialize)]
+pub struct HandlerRecord {
+    pub handler: String,
+    pub time: u64,
+    pub duration: f64,
+    pub result: String,
+    pub session: Option<u64>,
+}
+
+/// Result and output of a handler test run.
+#[derive(Debug, Clone, Serialize)]
+pub struct HandlerTest {
+    pub handler: String,
+    pub result: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<&'static str>,
+    pub duration: f64,
+    pub stdout: String,
+    pub stderr: String,
+    pub messages: Vec<String>,
+}
+
+/// Event counts and handler statistics since the daemon has been started.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub events: BTreeMap<String, u64>,
+    pub cancels: BTreeMap<String, u64>,
+    pub handlers: Vec<HandlerStats>,
+    pub buckets: Vec<f64>,
+    pub histograms: Vec<StageHistogram>,
+}
+
+/// Accumulated statistics of a single handler.
+#[derive(Debug, Clone, Serialize)]
+pub struct HandlerStats {
+    pub handler: String,
+    pub count: u64,
+    pub failures: u64,
+    pub total: f64,
+    pub mean: f64,
+    pub max: f64,
+}
+
+/// Duration histogram of a single handler stage.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageHistogram {
+    pub handler: String,
+    pub stage: String,
+    pub counts: Vec<u64>,
+}
+
+/// Health of the daemon and its connection to the DTX device.
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub uptime: f64,
+    pub available: bool,
+    pub last_event: Option<u64>,
+}
+
+/// Problems found in the daemon configuration.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigCheck {
+    pub path: String,
+    pub unknowns: Vec<String>,
+    pub problems: Vec<String>,
+    pub ok: bool,
+}
+
+/// Effective configuration of the daemon.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigShow {
+    pub path: String,
+    pub config: serde_json::Value,
+}
+
+/// Result of a command without further output.
+#[derive(Debug, Clone, Serialize)]
+pub struct Done {
+    pub command: &'static str,
+    pub success: bool,
+}
+
+/// Error, emitted in place of the regular result.
+#[derive(Debug, Clone, Serialize)]
+pub struct Error {
+    pub error: String,
+    pub kind: &'static str,
+    pub exit_code: i32,
+    #[serde(skip)]
+    pub causes: Vec<String>,
+}
+
+
+pub trait Human {
+    fn print_human(&self);
+
+    fn print_verbose(&self) {
+        self.print_human()
+    }
+}
+
+impl Human for Status {
+    fn print_human(&self) {
+        println!("Device mode:  {}", self.device_mode);
+        println!("Latch status: {}", self.latch_status);
+        println!("Base:         {} (type: {}, id: {:#04x})", self.base.state, self.base.ty, self.base.id);
+        println!("Dry run:      {}", if self.dry_run { "yes" } else { "no" });
+    }
+}
+
+impl Human for Base {
+    fn print_human(&self) {
+        println!("State:    {}", self.state);
+        println!("Type:     {}", self.ty);
+        println!("ID:       {:#04x}", self.id);
+
+        match &self.battery {
+            Some(Battery { capacity: Some(capacity), status }) => {
+                println!("Battery:  {capacity}% ({status})");
+            },
+            Some(Battery { capacity: None, status }) => println!("Battery:  {status}"),
+            None => println!("Battery:  none"),
+        }
+
+        if let Some(firmware) = &self.firmware {
+            println!("Firmware: {}", firmware.version.as_deref().unwrap_or("unknown"));
+
+            if let Some(serial) = &firmware.serial {
+                println!("Serial:   {serial}");
+            }
+        }
+    }
+}
+
+impl Human for Mode {
+    fn print_human(&self) {
+        println!("{}", self.device_mode);
+    }
+}
+
+impl Human for Latch {
+    fn print_human(&self) {
+        println!("{}", self.latch_status);
+        println!("{}", if self.locked { "locked" } else { "unlocked" });
+    }
+}
+
+impl Human for Vec<HandlerRecord> {
+    fn print_human(&self) {
+        for record in self {
+            let session = record.session
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "-".into());
+
+            println!("{:>12}  {:<14} {:<8} {:>8.2}s  {}",
+                     record.time, record.handler, session, record.duration, record.result);
+        }
+    }
+}
+
+impl Human for HandlerTest {
+    fn print_human(&self) {
+        match self.action {
+            Some(action) => println!("Result:   {} ({})", self.result, action),
+            None         => println!("Result:   {}", self.result),
+        }
+        println!("Duration: {:.2}s", self.duration);
+
+        for message in &self.messages {
+            println!("Message:  {message}");
+        }
+
+        if !self.stdout.is_empty() {
+            println!("\nstdout:\n{}", self.stdout.trim_end());
+        }
+
+        if !self.stderr.is_empty() {
+            println!("\nstderr:\n{}", self.stderr.trim_end());
+        }
+    }
+}
+
+impl Human for Stats {
+    fn print_human(&self) {
+        println!("Events:");
+        if self.events.is_empty() {
+            println!("  none");
+        }
+        for (event, count) in &self.events {
+            println!("  {event:<28} {count:>6}");
+        }
+
+        println!();
+        println!("Cancellations:");
+        if self.cancels.is_empty() {
+            println!("  none");
+        }
+        for (reason, count) in &self.cancels {
+            println!("  {reason:<28} {count:>6}");
+        }
+
+        println!();
+        println!("Handlers:");
+        if self.handlers.is_empty() {
+            println!("  none");
+        } else {
+            println!("  {:<14} {:>6} {:>8} {:>9} {:>9}", "HANDLER", "RUNS", "FAILED", "MEAN", "MAX");
+        }
+        for h in &self.handlers {
+            println!("  {:<14} {:>6} {:>8} {:>8.2}s {:>8.2}s",
+                     h.handler, h.count, h.failures, h.mean, h.max);
+        }
+
+        println!();
+        println!("Durations:");
+        if self.histograms.is_empty() {
+            println!("  none");
+        } else {
+            let mut header = format!("  {:<14} {:<6}", "HANDLER", "STAGE");
+            for bound in &self.buckets {
+                header += &format!(" {:>6}", format!("{bound}s"));
+            }
+            if let Some(last) = self.buckets.last() {
+                header += &format!(" {:>6}", format!(">{last}s"));
+            }
+            println!("{header}");
+        }
+        for h in &self.histograms {
+            let mut line = format!("  {:<14} {:<6}", h.handler, h.stage);
+            for count in &h.counts {
+                line += &format!(" {count:>6}");
+            }
+            println!("{line}");
+        }
+    }
+}
+
+impl Human for Health {
+    fn print_human(&self) {
+        println!("Uptime:      {:.0}s", self.uptime);
+        println!("Device:      {}", if self.available { "available" } else { "unavailable" });
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        match self.last_event {
+            Some(time) => println!("Last event:  {}s ago", now.saturating_sub(time)),
+            None       => println!("Last event:  none"),
+        }
+    }
+}
+
+impl Human for ConfigCheck {
+    fn print_human(&self) {
+        if self.path.is_empty() {
+            println!("Config file: none (using defaults)");
+        } else {
+            println!("Config file: {}", self.path);
+        }
+
+        for item in &self.unknowns {
+            println!("warning: unknown config item: {item}");
+        }
+
+        for problem in &self.problems {
+            println!("error: {problem}");
+        }
+
+        if self.ok {
+            println!("No problems found");
+        }
+    }
+}
+
+impl Human for ConfigShow {
+    fn print_human(&self) {
+        if !self.path.is_empty() {
+            println!("# {}", self.path);
+        }
+
+        // serialization of JSON values can not fail
+        println!("{}", serde_json::to_string_pretty(&self.config).unwrap());
+    }
+}
+
+impl Human for Done {
+    fn print_human(&self) {}
+
+    fn print_verbose(&self) {
+        println!("{}: {}", self.command, if self.success { "ok" } else { "failed" });
+    }
+}
+
+impl Human for Error {
+    fn print_human(&self) {
+        eprintln!("Error: {}", self.error);
+    }
+
+    fn print_verbose(&self) {
+        eprintln!("Error: {}", self.causes.first().unwrap_or(&self.error));
+
+        for cause in self.causes.iter().skip(1) {
+            eprintln!("  caused by: {cause}");
+        }
+
+        eprintln!("Exit status: {} ({})", self.exit_code, self.kind);
+    }
+}
+
+
+/// Print the given value in the requested format. Nothing is printed in
+/// quiet mode.
+pub fn print<T: Serialize + Human>(out: Output, value: &T) {
+    if out.verbosity != Verbosity::Quiet {
+        print_always(out, value)
+    }
+}
+
+/// Print the given value in the requested format, regardless of verbosity.
+pub fn print_always<T: Serialize + Human>(out: Output, value: &T) {
+    match out.format {
+        Format::Human if out.verbosity == Verbosity::Verbose => value.print_verbose(),
+        Format::Human => value.print_human(),
+        Format::Json  => {
+            // serialization of these types can not fail
+            println!("{}", serde_json::to_string(value).unwrap());
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    fn base() -> Base {
+        Base {
+            state: "attached".into(),
+            ty: "ssh".into(),
+            id: 0x11,
+            battery: Some(Battery { capacity: Some(80), status: "charging".into() }),
+            firmware: None,
+        }
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(Format::from_arg("json"), Format::Json);
+        assert_eq!(Format::from_arg("human"), Format::Human);
+    }
+
+    #[test]
+    fn json_status() {
+        let status = Status {
+            device_mode: "laptop".into(),
+            latch_status: "closed".into(),
+            base: base(),
+            dry_run: false,
+        };
+
+        assert_eq!(serde_json::to_value(&status).unwrap(), json!({
+            "device_mode": "laptop",
+            "latch_status": "closed",
+            "base": {
+                "state": "attached",
+                "type": "ssh",
+                "id": 17,
+                "battery": { "capacity": 80, "status": "charging" },
+                "firmware": null,
+            },
+            "dry_run": false,
+        }));
+    }
+
+    #[test]
+    fn json_handler_test() {
+        let mut test = HandlerTest {
+            handler: "detach".into(),
+            result: "exit:75".into(),
+            action: Some("defer"),
+            duration: 0.5,
+            stdout: String::new(),
+            stderr: String::new(),
+            messages: vec!["waiting".into()],
+        };
+
+        let value = serde_json::to_value(&test).unwrap();
+        assert_eq!(value["action"], "defer");
+        assert_eq!(value["messages"], json!(["waiting"]));
+
+        // the action is only reported for detachment handlers
+        test.action = None;
+        let value = serde_json::to_value(&test).unwrap();
+        assert!(value.get("action").is_none());
+    }
+
+    #[test]
+    fn json_error() {
+        let error = Error {
+            error: "Failed: timeout".into(),
+            kind: "timeout",
+            exit_code: 4,
+            causes: vec!["Failed".into(), "timeout".into()],
+        };
+
+        // causes are only printed in verbose human-readable output
+        assert_eq!(serde_json::to_value(&error).unwrap(), json!({
+            "error": "Failed: timeout",
+            "kind": "timeout",
+            "exit_code": 4,
+        }));
+    }
+}
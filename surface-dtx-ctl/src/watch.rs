@@ -0,0 +1,101 @@
+use crate::conn::DaemonConnection;
+use crate::output::{self, Human, Output, Status};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::message::MatchRule;
+
+use serde::Serialize;
+
+use surface_dtx_client::blocking::Client;
+
+
+/// Single line of the watch stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Line {
+    pub text: String,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+impl Human for Line {
+    fn print_human(&self) {
+        println!("{}", self.text);
+    }
+}
+
+
+/// Print a formatted line whenever the state of the daemon changes, until
+/// interrupted.
+pub fn run(conn: &DaemonConnection, out: Output, template: String) -> Result<()> {
+    let props = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+        .with_sender(surface_dtx_client::NAME)
+        .with_path(surface_dtx_client::PATH);
+
+    // we only use the signal as trigger and re-query all properties, which
+    // keeps the output consistent with the actual state
+    let changed = Arc::new(AtomicBool::new(false));
+    let trigger = changed.clone();
+
+    conn.subscribe(props, move |_| { trigger.store(true, Ordering::Relaxed); true })
+        .context("Failed to subscribe to daemon properties")?;
+
+    let client = Client::new(&**conn);
+    let mut last = None;
+
+    loop {
+        let status = crate::status(&client)?;
+        let line = Line { text: expand(&template, &status), status };
+
+        if last.as_ref() != Some(&line) {
+            output::print(out, &line);
+            last = Some(line);
+        }
+
+        while !changed.swap(false, Ordering::Relaxed) {
+            conn.process(Duration::from_secs(60))
+                .context("D-Bus connection error (system)")?;
+        }
+    }
+}
+
+/// Expand the placeholders of the given template based on the status.
+fn expand(template: &str, status: &Status) -> String {
+    template
+        .replace("{mode}", &status.device_mode)
+        .replace("{latch}", &status.latch_status)
+        .replace("{base}", &status.base.state)
+        .replace("{base_type}", &status.base.ty)
+        .replace("{base_id}", &format!("{:#04x}", status.base.id))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::output::Base;
+
+    #[test]
+    fn expand_template() {
+        let status = Status {
+            device_mode: "tablet".into(),
+            latch_status: "opened".into(),
+            base: Base {
+                state: "detached".into(),
+                ty: "hid".into(),
+                id: 5,
+                battery: None,
+                firmware: None,
+            },
+            dry_run: false,
+        };
+
+        assert_eq!(expand("{mode} {base}", &status), "tablet detached");
+        assert_eq!(expand("{latch}/{base_type}/{base_id} {unknown}", &status), "opened/hid/0x05 {unknown}");
+    }
+}
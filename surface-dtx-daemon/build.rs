@@ -26,6 +26,7 @@ fn main() {
     // copy config files
     let files = [
         "etc/dbus/org.surface.dtx.conf",
+        "etc/dbus/org.surface.dtx.xml",
         "etc/dtx/attach.sh",
         "etc/dtx/detach.sh",
         "etc/dtx/surface-dtx-daemon.conf",
@@ -11,6 +11,22 @@ pub fn app() -> Command {
             .value_name("FILE")
             .help("Use the specified config file")
             .value_parser(clap::value_parser!(std::path::PathBuf)))
+        .arg(Arg::new("dry-run")
+            .long("dry-run")
+            .help("Run handlers but never open the latch")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("replay")
+            .long("replay")
+            .value_name("FILE")
+            .help("Replay recorded DTX events instead of using the hardware")
+            .value_parser(clap::value_parser!(std::path::PathBuf)))
+        .arg(Arg::new("replay-speed")
+            .long("replay-speed")
+            .value_name("FACTOR")
+            .help("Speed up (or slow down) the replay by the given factor")
+            .value_parser(clap::value_parser!(f64))
+            .default_value("1")
+            .requires("replay"))
         .arg(Arg::new("no-log-time")
             .long("no-log-time")
             .help("Do not emit timestamps in log")
@@ -1,7 +1,7 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
@@ -19,12 +19,134 @@ pub struct Config {
 
     #[serde(default)]
     pub handler: Handler,
+
+    #[serde(default)]
+    pub device: Device,
+
+    #[serde(default)]
+    pub latch: Latch,
+
+    #[serde(default)]
+    pub base: Base,
+
+    #[serde(default)]
+    pub tablet_switch: TabletSwitch,
+
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    #[serde(default)]
+    pub debug: Debug,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Device {
+    #[serde(default)]
+    pub wait_timeout: Option<f32>,
+
+    #[serde(default)]
+    pub syspath: Option<PathBuf>,
+
+    #[serde(default)]
+    pub instance: Option<String>,
+
+    #[serde(default)]
+    pub serial: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Latch {
+    #[serde(default="defaults::latch_open_timeout")]
+    pub open_timeout: f32,
+}
+
+impl Default for Latch {
+    fn default() -> Self {
+        Latch { open_timeout: defaults::latch_open_timeout() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Base {
+    #[serde(default)]
+    pub battery: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TabletSwitch {
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default="defaults::tablet_switch_studio")]
+    pub studio: bool,
+
+    #[serde(default)]
+    pub verify: bool,
+}
+
+impl Default for TabletSwitch {
+    fn default() -> Self {
+        TabletSwitch { enabled: false, studio: defaults::tablet_switch_studio(), verify: false }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Debug {
+    #[serde(default)]
+    pub inject: bool,
+
+    #[serde(default)]
+    pub raw_events: bool,
+
+    #[serde(default)]
+    pub mock_device: Option<PathBuf>,
+
+    #[serde(default)]
+    pub record: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Log {
     #[serde(default)]
     pub level: LogLevel,
+
+    #[serde(default)]
+    pub backend: LogBackend,
+
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+
+    #[serde(default="defaults::log_file_max_size")]
+    pub file_max_size: u64,
+
+    #[serde(default="defaults::log_file_rotations")]
+    pub file_rotations: u32,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            level: LogLevel::default(),
+            backend: LogBackend::default(),
+            file: None,
+            file_max_size: defaults::log_file_max_size(),
+            file_rotations: defaults::log_file_rotations(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all="lowercase")]
+pub enum LogBackend {
+    #[default]
+    Stdout,
+    Journal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
@@ -40,6 +162,9 @@ pub enum LogLevel {
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct Handler {
+    #[serde(default)]
+    pub output: HandlerOutput,
+
     #[serde(default)]
     pub detach: DetachHandler,
 
@@ -48,6 +173,20 @@ pub struct Handler {
 
     #[serde(default)]
     pub attach: AttachHandler,
+
+    #[serde(default)]
+    pub posture: PostureHandler,
+
+    #[serde(default)]
+    pub dgpu: DgpuStep,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all="lowercase")]
+pub enum HandlerOutput {
+    #[default]
+    Log,
+    Journal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -55,8 +194,95 @@ pub struct DetachHandler {
     #[serde(default)]
     pub exec: Option<PathBuf>,
 
+    #[serde(default)]
+    pub exec_script: Option<String>,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub pre_exec: Option<PathBuf>,
+
+    #[serde(default)]
+    pub post_exec: Option<PathBuf>,
+
     #[serde(default="defaults::task_timeout")]
     pub timeout: f32,
+
+    #[serde(default)]
+    pub max_timeout: Option<f32>,
+
+    #[serde(default="defaults::defer_interval")]
+    pub defer_interval: f32,
+
+    #[serde(default="defaults::slow_threshold")]
+    pub slow_threshold: f32,
+
+    #[serde(default="defaults::force_delay")]
+    pub force_delay: f32,
+
+    #[serde(default)]
+    pub spawn_failure: SpawnFailurePolicy,
+
+    #[serde(default)]
+    pub builtin: BuiltinDetach,
+
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+
+    #[serde(default)]
+    pub sandbox: Sandbox,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all="lowercase")]
+pub enum SpawnFailurePolicy {
+    #[default]
+    Abort,
+    Commence,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BuiltinDetach {
+    #[serde(default="defaults::builtin_enabled")]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub unbind: Vec<PathBuf>,
+}
+
+impl Default for BuiltinDetach {
+    fn default() -> Self {
+        BuiltinDetach {
+            enabled: defaults::builtin_enabled(),
+            unbind: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DgpuStep {
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub port: Option<String>,
+
+    #[serde(default="defaults::dgpu_drivers")]
+    pub drivers: Vec<String>,
+}
+
+impl Default for DgpuStep {
+    fn default() -> Self {
+        DgpuStep {
+            enabled: false,
+            port: None,
+            drivers: defaults::dgpu_drivers(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -64,8 +290,32 @@ pub struct DetachAbortHandler {
     #[serde(default)]
     pub exec: Option<PathBuf>,
 
+    #[serde(default)]
+    pub exec_script: Option<String>,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub pre_exec: Option<PathBuf>,
+
+    #[serde(default)]
+    pub post_exec: Option<PathBuf>,
+
     #[serde(default="defaults::task_timeout")]
     pub timeout: f32,
+
+    #[serde(default)]
+    pub lock_latch: bool,
+
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+
+    #[serde(default)]
+    pub sandbox: Sandbox,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -73,11 +323,111 @@ pub struct AttachHandler {
     #[serde(default)]
     pub exec: Option<PathBuf>,
 
+    #[serde(default)]
+    pub exec_script: Option<String>,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub pre_exec: Option<PathBuf>,
+
+    #[serde(default)]
+    pub post_exec: Option<PathBuf>,
+
     #[serde(default="defaults::task_timeout")]
     pub timeout: f32,
 
     #[serde(default="defaults::delay_attach")]
     pub delay: f32,
+
+    #[serde(default)]
+    pub udev_settle: bool,
+
+    #[serde(default="defaults::udev_settle_timeout")]
+    pub udev_settle_timeout: f32,
+
+    #[serde(default)]
+    pub lock_latch: bool,
+
+    #[serde(default)]
+    pub steps: BTreeMap<String, AttachStep>,
+
+    #[serde(default)]
+    pub reattach_window: f32,
+
+    #[serde(default)]
+    pub reattach_action: ReattachAction,
+
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+
+    #[serde(default)]
+    pub sandbox: Sandbox,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all="kebab-case")]
+pub enum ReattachAction {
+    #[default]
+    Skip,
+    NoDelay,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy)]
+pub struct Sandbox {
+    #[serde(default)]
+    pub clean_env: bool,
+
+    #[serde(default)]
+    pub private_tmp: bool,
+
+    #[serde(default)]
+    pub read_only_root: bool,
+
+    #[serde(default)]
+    pub no_new_privileges: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct AttachStep {
+    pub exec: PathBuf,
+
+    #[serde(default)]
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct PostureHandler {
+    #[serde(default)]
+    pub exec: Option<PathBuf>,
+
+    #[serde(default)]
+    pub exec_script: Option<String>,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub pre_exec: Option<PathBuf>,
+
+    #[serde(default)]
+    pub post_exec: Option<PathBuf>,
+
+    #[serde(default="defaults::task_timeout")]
+    pub timeout: f32,
+
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+
+    #[serde(default)]
+    pub sandbox: Sandbox,
 }
 
 
@@ -112,26 +462,139 @@ impl Config {
 
         config.dir = path.as_ref().parent().unwrap().into();
 
+        config.validate()
+            .with_context(|| format!("Invalid config file (path: {:?})", path.as_ref()))?;
+
         let diag = Diagnostics {
             path: path.as_ref().into(),
             unknowns,
+            problems: config.problems(),
         };
 
         Ok((config, diag))
     }
+
+    fn validate(&self) -> Result<()> {
+        let handlers = [
+            ("detach", &self.handler.detach.exec, &self.handler.detach.exec_script),
+            ("detach_abort", &self.handler.detach_abort.exec, &self.handler.detach_abort.exec_script),
+            ("attach", &self.handler.attach.exec, &self.handler.attach.exec_script),
+            ("posture", &self.handler.posture.exec, &self.handler.posture.exec_script),
+        ];
+
+        for (name, exec, script) in handlers {
+            if exec.is_some() && script.is_some() {
+                bail!("Handler '{}' specifies both 'exec' and 'exec_script'", name);
+            }
+        }
+
+        if self.handler.dgpu.enabled && self.handler.dgpu.port.is_none() {
+            bail!("Built-in dGPU step requires 'port' to be set");
+        }
+
+        self.handler.attach.validate()
+    }
+
+    /// Problems with the configuration that do not prevent the daemon from
+    /// running, i.e. handler executables that are missing or not executable.
+    pub fn problems(&self) -> Vec<String> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let h = &self.handler;
+        let handlers = [
+            ("detach", &h.detach.dir, &h.detach.exec, &h.detach.pre_exec, &h.detach.post_exec),
+            ("detach_abort", &h.detach_abort.dir, &h.detach_abort.exec, &h.detach_abort.pre_exec,
+             &h.detach_abort.post_exec),
+            ("attach", &h.attach.dir, &h.attach.exec, &h.attach.pre_exec, &h.attach.post_exec),
+            ("posture", &h.posture.dir, &h.posture.exec, &h.posture.pre_exec, &h.posture.post_exec),
+        ];
+
+        let mut execs = Vec::new();
+        for (name, dir, exec, pre, post) in handlers {
+            let dir = self.handler_dir(dir);
+
+            for (key, path) in [("exec", exec), ("pre_exec", pre), ("post_exec", post)] {
+                if let Some(path) = path {
+                    execs.push((format!("handler.{name}.{key}"), dir.join(path)));
+                }
+            }
+
+            if name == "attach" {
+                for (step, cfg) in &h.attach.steps {
+                    execs.push((format!("handler.attach.steps.{step}.exec"), dir.join(&cfg.exec)));
+                }
+            }
+        }
+
+        let mut problems = Vec::new();
+        for (key, path) in execs {
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => {},
+                Ok(_) => problems.push(format!("{key}: not an executable file: {path:?}")),
+                Err(_) => problems.push(format!("{key}: executable not found: {path:?}")),
+            }
+        }
+
+        problems
+    }
+
+    /// Working directory of a handler, i.e. the given directory resolved
+    /// relative to the config file or the directory of the config file
+    /// itself if unspecified.
+    pub fn handler_dir(&self, dir: &Option<PathBuf>) -> PathBuf {
+        match dir {
+            Some(dir) => self.dir.join(dir),
+            None => self.dir.clone(),
+        }
+    }
 }
 
+impl AttachHandler {
+    fn validate(&self) -> Result<()> {
+        // check that all dependencies refer to known steps
+        for (name, step) in &self.steps {
+            for dep in &step.after {
+                if !self.steps.contains_key(dep) {
+                    bail!("Attach step '{}' depends on unknown step '{}'", name, dep);
+                }
+            }
+        }
 
+        // check for dependency cycles by repeatedly resolving all steps
+        // whose dependencies have already been resolved
+        let mut resolved = BTreeSet::new();
+        while resolved.len() < self.steps.len() {
+            let ready: Vec<_> = self.steps.iter()
+                .filter(|(name, _)| !resolved.contains(*name))
+                .filter(|(_, step)| step.after.iter().all(|dep| resolved.contains(dep)))
+                .map(|(name, _)| name)
+                .collect();
+
+            if ready.is_empty() {
+                bail!("Attach steps contain a dependency cycle");
+            }
+
+            resolved.extend(ready);
+        }
+
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone)]
 pub struct Diagnostics {
     pub path: PathBuf,
     pub unknowns: BTreeSet<String>,
+    pub problems: Vec<String>,
 }
 
 impl Diagnostics {
     fn empty() -> Self {
         Diagnostics {
             path: PathBuf::new(),
-            unknowns: BTreeSet::new()
+            unknowns: BTreeSet::new(),
+            problems: Vec::new(),
         }
     }
 
@@ -143,21 +606,79 @@ impl Diagnostics {
         for item in &self.unknowns {
             warn!(target: "sdtxd::config", item = %item, "unknown config item")
         }
+        for problem in &self.problems {
+            warn!(target: "sdtxd::config", "{}", problem)
+        }
     }
 }
 
 
 mod defaults {
+    pub fn log_file_max_size() -> u64 {
+        1024
+    }
+
+    pub fn log_file_rotations() -> u32 {
+        3
+    }
+
+    pub fn latch_open_timeout() -> f32 {
+        10.0
+    }
+
+    pub fn tablet_switch_studio() -> bool {
+        true
+    }
+
     pub fn delay_attach() -> f32 {
         5.0
     }
 
+    pub fn udev_settle_timeout() -> f32 {
+        10.0
+    }
+
     pub fn task_timeout() -> f32 {
         60.0
     }
+
+    pub fn defer_interval() -> f32 {
+        5.0
+    }
+
+    pub fn slow_threshold() -> f32 {
+        0.8
+    }
+
+    pub fn force_delay() -> f32 {
+        3.0
+    }
+
+    pub fn builtin_enabled() -> bool {
+        true
+    }
+
+    pub fn dgpu_drivers() -> Vec<String> {
+        vec!["nvidia".into(), "amdgpu".into(), "nouveau".into()]
+    }
 }
 
 
+impl std::str::FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn"  => Ok(LogLevel::Warn),
+            "info"  => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _       => bail!("Invalid log level: '{}'", s),
+        }
+    }
+}
+
 impl From<LogLevel> for tracing::Level {
     fn from(level: LogLevel) -> Self {
         match level {
@@ -169,3 +690,79 @@ impl From<LogLevel> for tracing::Level {
         }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn validate(data: &str) -> Result<()> {
+        let config: Config = toml::from_str(data).unwrap();
+        config.validate()
+    }
+
+    #[test]
+    fn attach_steps_valid() {
+        let data = r#"
+            [handler.attach.steps.dgpu]
+            exec = "./dgpu.sh"
+
+            [handler.attach.steps.displays]
+            exec = "./displays.sh"
+            after = ["dgpu"]
+
+            [handler.attach.steps.audio]
+            exec = "./audio.sh"
+            after = ["dgpu", "displays"]
+        "#;
+
+        validate(data).unwrap();
+    }
+
+    #[test]
+    fn attach_steps_unknown_dependency() {
+        let data = r#"
+            [handler.attach.steps.displays]
+            exec = "./displays.sh"
+            after = ["dgpu"]
+        "#;
+
+        let err = validate(data).unwrap_err();
+        assert_eq!(err.to_string(), "Attach step 'displays' depends on unknown step 'dgpu'");
+    }
+
+    #[test]
+    fn attach_steps_self_dependency() {
+        let data = r#"
+            [handler.attach.steps.dgpu]
+            exec = "./dgpu.sh"
+            after = ["dgpu"]
+        "#;
+
+        let err = validate(data).unwrap_err();
+        assert_eq!(err.to_string(), "Attach steps contain a dependency cycle");
+    }
+
+    #[test]
+    fn attach_steps_cycle() {
+        let data = r#"
+            [handler.attach.steps.a]
+            exec = "./a.sh"
+            after = ["c"]
+
+            [handler.attach.steps.b]
+            exec = "./b.sh"
+            after = ["a"]
+
+            [handler.attach.steps.c]
+            exec = "./c.sh"
+            after = ["b"]
+
+            [handler.attach.steps.d]
+            exec = "./d.sh"
+        "#;
+
+        let err = validate(data).unwrap_err();
+        assert_eq!(err.to_string(), "Attach steps contain a dependency cycle");
+    }
+}
@@ -0,0 +1,172 @@
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use futures::future;
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use tracing::{trace, warn};
+
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Prefix of the SSAM device UIDs of batteries on the secondary target, i.e.
+/// in the base (category 0x02, target 0x02).
+const SSAM_BASE_BATTERY: &str = "ssam:01:02:02:";
+
+/// Interval in which the monitor thread checks whether it is still needed.
+const MONITOR_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Charging status of a battery, as reported by the power-supply class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryStatus {
+    Unknown,
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+}
+
+impl BatteryStatus {
+    fn from_sysfs(status: &str) -> Self {
+        match status {
+            "Charging"     => BatteryStatus::Charging,
+            "Discharging"  => BatteryStatus::Discharging,
+            "Not charging" => BatteryStatus::NotCharging,
+            "Full"         => BatteryStatus::Full,
+            _              => BatteryStatus::Unknown,
+        }
+    }
+}
+
+/// Battery state of the base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryInfo {
+    /// Capacity in percent, if reported.
+    pub capacity: Option<u8>,
+    pub status: BatteryStatus,
+}
+
+
+/// The battery in the base, read from its power supply in sysfs.
+///
+/// The power supply only exists while the base is attached, so it is looked
+/// up again on each read. Changes are signaled via power-supply uevents,
+/// received on a separate thread as the udev monitor cannot be shared with
+/// the runtime.
+pub struct BaseBattery {
+    name: Option<String>,
+    changes: UnboundedReceiver<()>,
+}
+
+impl BaseBattery {
+    /// Track the power supply with the given name, or the battery provided
+    /// by the Surface Aggregator Module for the base if no name is given.
+    pub fn new(name: Option<String>) -> Self {
+        let (tx, changes) = tokio::sync::mpsc::unbounded_channel();
+
+        let thread = std::thread::Builder::new()
+            .name("sdtxd-battery".into())
+            .spawn(move || {
+                if let Err(err) = monitor(tx) {
+                    warn!(target: "sdtxd", "failed to monitor base battery, changes will only be picked up on attachment: {:#}", err);
+                }
+            });
+
+        if let Err(err) = thread {
+            warn!(target: "sdtxd", "failed to monitor base battery, changes will only be picked up on attachment: {:#}", err);
+        }
+
+        Self { name, changes }
+    }
+
+    /// Read the current state of the battery, or `None` if it is not present
+    /// (e.g. because the base is detached).
+    pub fn read(&self) -> Option<BatteryInfo> {
+        let path = match &self.name {
+            Some(name) => Path::new(POWER_SUPPLY_DIR).join(name),
+            None => locate()?,
+        };
+
+        let status = std::fs::read_to_string(path.join("status")).ok()?;
+        let capacity = std::fs::read_to_string(path.join("capacity")).ok()
+            .and_then(|c| c.trim().parse().ok());
+
+        Some(BatteryInfo { capacity, status: BatteryStatus::from_sysfs(status.trim()) })
+    }
+
+    /// Wait until any power supply reports a change. Never completes if
+    /// uevents cannot be monitored.
+    pub async fn changed(&mut self) {
+        if self.changes.recv().await.is_none() {
+            return future::pending().await;
+        }
+
+        // coalesce changes that have piled up in the meantime
+        while self.changes.try_recv().is_ok() {}
+    }
+}
+
+
+/// Forward power-supply uevents until the receiving end has been dropped.
+fn monitor(tx: UnboundedSender<()>) -> Result<()> {
+    let socket = udev::MonitorBuilder::new()
+        .and_then(|b| b.match_subsystem("power_supply"))
+        .and_then(|b| b.listen())
+        .context("Failed to set up udev monitor")?;
+
+    let mut fds = [libc::pollfd { fd: socket.as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+    let timeout = MONITOR_POLL_INTERVAL.as_millis() as libc::c_int;
+
+    while !tx.is_closed() {
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(err).context("Failed to receive udev events");
+        }
+
+        for event in socket.iter() {
+            trace!(target: "sdtxd", supply=?event.sysname(), action=?event.event_type(), "power-supply uevent");
+
+            if tx.send(()).is_err() {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the power supply of the base battery provided by the Surface
+/// Aggregator Module, if present.
+fn locate() -> Option<PathBuf> {
+    let entries = std::fs::read_dir(POWER_SUPPLY_DIR).ok()?;
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+
+        let device = match std::fs::canonicalize(path.join("device")) {
+            Ok(device) => device,
+            Err(_) => continue,
+        };
+
+        let is_base = device.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(SSAM_BASE_BATTERY));
+
+        if is_base {
+            trace!(target: "sdtxd", supply=?entry.file_name(), ?device, "found base battery");
+            return Some(path);
+        }
+    }
+
+    None
+}
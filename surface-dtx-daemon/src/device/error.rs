@@ -0,0 +1,89 @@
+/// Known causes of failing DTX device requests, derived from the OS error code
+/// reported by the driver.
+///
+/// The description of each cause includes a hint on how to resolve it, so
+/// that it can be reported as-is to the user, either via logs or D-Bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    /// Access to the device node has been denied (EACCES, EPERM, EROFS).
+    PermissionDenied,
+
+    /// The device has been disconnected, e.g. due to the driver being
+    /// unloaded (ENODEV, ENXIO).
+    Disconnected,
+
+    /// The EC is currently busy with another request (EBUSY).
+    Busy,
+
+    /// The request is not supported by the driver (EOPNOTSUPP, ENOTTY).
+    Unsupported,
+
+    /// Latch control is unavailable because the device has been opened
+    /// read-only.
+    ReadOnly,
+}
+
+impl DeviceError {
+    /// Determine the cause of the given error, based on the first error in its
+    /// chain that we know how to classify.
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        err.chain().find_map(|cause| {
+            if let Some(err) = cause.downcast_ref::<DeviceError>() {
+                return Some(*err);
+            }
+
+            os_error(cause).and_then(Self::from_os_error)
+        })
+    }
+
+    fn from_os_error(code: i32) -> Option<Self> {
+        match code {
+            libc::EACCES | libc::EPERM | libc::EROFS => Some(Self::PermissionDenied),
+            libc::ENODEV | libc::ENXIO               => Some(Self::Disconnected),
+            libc::EBUSY                              => Some(Self::Busy),
+            libc::EOPNOTSUPP | libc::ENOTTY          => Some(Self::Unsupported),
+            _                                        => None,
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Self::PermissionDenied => {
+                "Access to DTX device denied, check the permissions of its device node"
+            },
+            Self::Disconnected => {
+                "DTX device disconnected, check that the surface_dtx driver is loaded"
+            },
+            Self::Busy => {
+                "DTX device busy, another request may still be in progress, try again later"
+            },
+            Self::Unsupported => {
+                "Request not supported by DTX device, the surface_dtx driver may be too old"
+            },
+            Self::ReadOnly => {
+                "Latch control unavailable, DTX device has been opened read-only"
+            },
+        };
+
+        write!(f, "{msg}")
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+
+/// The OS error codes of all I/O errors in the chain of the given error.
+pub fn os_errors(err: &anyhow::Error) -> impl Iterator<Item=i32> + '_ {
+    err.chain().filter_map(os_error)
+}
+
+fn os_error(cause: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    let io = match cause.downcast_ref::<sdtx::Error>() {
+        Some(sdtx::Error::IoError(err)) => Some(err),
+        _ => cause.downcast_ref::<std::io::Error>(),
+    };
+
+    io.and_then(|err| err.raw_os_error())
+}
@@ -0,0 +1,575 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsFd, AsRawFd};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use futures::prelude::*;
+
+use sdtx::{event, DeviceType, Event};
+
+use tokio::io::unix::AsyncFd;
+use tokio::sync::broadcast;
+
+use tracing::{trace, warn};
+
+
+/// Event format used by the DTX kernel driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    /// Events with header and variable-size payload, as provided by the
+    /// upstream driver via /dev/surface/dtx.
+    Current,
+
+    /// Fixed-size raw SSAM events, as provided by the legacy out-of-tree
+    /// driver via /dev/surface_dtx.
+    Legacy,
+}
+
+
+/// Event as received from the kernel, before translation.
+#[derive(Debug, Clone)]
+pub struct RawEvent {
+    /// Event code. For the legacy interface, this is the SSAM event ID.
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+
+/// `_IO(0xa5, 0x21)`, see include/uapi/linux/surface_aggregator/dtx.h.
+const SDTX_IOCTL_EVENTS_ENABLE: libc::c_ulong = 0xa521;
+
+const SDTX_EVENT_REQUEST: u16 = 1;
+const SDTX_EVENT_CANCEL: u16 = 2;
+const SDTX_EVENT_BASE_CONNECTION: u16 = 3;
+const SDTX_EVENT_LATCH_STATUS: u16 = 4;
+const SDTX_EVENT_DEVICE_MODE: u16 = 5;
+
+const SDTX_CATEGORY_MASK: u16 = 0xf000;
+const SDTX_CATEGORY_STATUS: u16 = 0x0000;
+const SDTX_CATEGORY_RUNTIME_ERROR: u16 = 0x1000;
+const SDTX_CATEGORY_HARDWARE_ERROR: u16 = 0x2000;
+
+const SDTX_DEVICE_TYPE_MASK: u16 = 0x0f00;
+const SDTX_DEVICE_TYPE_HID: u16 = 0x0100;
+const SDTX_DEVICE_TYPE_SSH: u16 = 0x0200;
+
+/// Target category and event IDs of the raw SSAM events forwarded by the
+/// legacy driver.
+const SSAM_SSH_TC_BAS: u8 = 0x11;
+const SAM_EVENT_CID_DTX_CONNECTION: u8 = 0x0c;
+const SAM_EVENT_CID_DTX_REQUEST: u8 = 0x0e;
+const SAM_EVENT_CID_DTX_CANCEL: u8 = 0x0f;
+const SAM_EVENT_CID_DTX_LATCH_STATUS: u8 = 0x11;
+
+/// Size of legacy events, i.e. type, code, and two arguments.
+const LEGACY_EVENT_LEN: usize = 4;
+
+/// Size of the event header, i.e. payload length and event code.
+const HEADER_LEN: usize = 4;
+
+/// Maximum accepted payload length. The events currently defined by the
+/// kernel carry at most four bytes, anything beyond this limit indicates that
+/// we have lost track of the frame boundaries.
+const MAX_PAYLOAD_LEN: usize = 256;
+
+/// Number of bytes requested per read.
+const READ_CHUNK: usize = 128;
+
+/// Time after which an incomplete frame is considered malformed. The kernel
+/// queues events atomically, so the remainder of a frame split across reads
+/// is available immediately.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+/// Reader for the event stream of the DTX kernel device.
+///
+/// Events are read into a buffer that grows as needed, so that events of any
+/// size and events split across multiple reads are handled correctly.
+/// Malformed frames are skipped by searching for the next plausible event
+/// header.
+pub struct EventReader {
+    abi: Abi,
+    raw: broadcast::Sender<RawEvent>,
+    file: AsyncFd<File>,
+    buffer: Vec<u8>,
+    skipped: Vec<u8>,
+}
+
+impl EventReader {
+    /// Enable events on the given device file and create a reader for them.
+    /// The reader uses its own non-blocking file descriptor, which shares the
+    /// event queue with the given one. All events are forwarded to the given
+    /// channel before translation.
+    pub fn new(file: &impl AsFd, abi: Abi, raw: broadcast::Sender<RawEvent>) -> Result<Self> {
+        let file = File::from(file.as_fd().try_clone_to_owned()
+            .context("Failed to duplicate DTX device file descriptor")?);
+
+        let fd = file.as_raw_fd();
+
+        // the legacy driver always provides events, without enabling them
+        if abi == Abi::Current {
+            let ret = unsafe { libc::ioctl(fd, SDTX_IOCTL_EVENTS_ENABLE) };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to enable DTX events");
+            }
+        }
+
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to set up DTX event reader");
+        }
+
+        let file = AsyncFd::new(file).context("Failed to set up DTX event reader")?;
+
+        Ok(Self { abi, raw, file, buffer: Vec::new(), skipped: Vec::new() })
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item=Result<Event>> + Unpin {
+        let stream = stream::unfold(self, |mut reader| async move {
+            match reader.next().await {
+                Ok(Some(event)) => Some((Ok(event), reader)),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), reader)),
+            }
+        });
+
+        Box::pin(stream)
+    }
+
+    /// Read the next event, or `None` if the device has been closed.
+    async fn next(&mut self) -> Result<Option<Event>> {
+        loop {
+            if let Some(event) = self.frame() {
+                return Ok(Some(event));
+            }
+
+            // don't wait forever for the remainder of a malformed frame
+            let read = if self.buffer.is_empty() {
+                self.read().await
+            } else {
+                match tokio::time::timeout(FRAME_TIMEOUT, self.read()).await {
+                    Ok(read) => read,
+                    Err(_) => {
+                        self.skip(self.buffer.len());
+                        self.report_skipped("incomplete event");
+                        continue;
+                    },
+                }
+            };
+
+            if read? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Read available data into the buffer, growing it as needed. Returns the
+    /// number of bytes read, zero indicating end of file.
+    async fn read(&mut self) -> Result<usize> {
+        let Self { file, buffer, .. } = self;
+
+        let len = buffer.len();
+        buffer.resize(len + READ_CHUNK, 0);
+
+        let result = loop {
+            let mut guard = match file.readable().await {
+                Ok(guard) => guard,
+                Err(err) => break Err(err),
+            };
+
+            match guard.try_io(|file| file.get_ref().read(&mut buffer[len..])) {
+                Ok(result) => break result,
+                Err(_would_block) => continue,
+            }
+        };
+
+        let n = result.as_ref().copied().unwrap_or(0);
+        buffer.truncate(len + n);
+
+        trace!(target: "sdtxd", bytes=n, buffered=buffer.len(), "read DTX event data");
+        result.context("Failed to read DTX events")
+    }
+
+    /// Take and decode the next complete frame from the buffer, skipping any
+    /// malformed data before it. Returns `None` if more data is needed.
+    fn frame(&mut self) -> Option<Event> {
+        loop {
+            let header = match self.abi {
+                Abi::Current => HEADER_LEN,
+                Abi::Legacy  => LEGACY_EVENT_LEN,
+            };
+
+            if self.buffer.len() < header {
+                return None;
+            }
+
+            let len = match self.abi {
+                Abi::Current => {
+                    let len = u16::from_ne_bytes([self.buffer[0], self.buffer[1]]) as usize;
+                    let code = u16::from_ne_bytes([self.buffer[2], self.buffer[3]]);
+
+                    // After malformed data, only accept known events: the
+                    // garbage is likely to contain a plausible header for
+                    // an unknown event, which would then swallow the
+                    // following events until the frame times out.
+                    let resync = !self.skipped.is_empty();
+
+                    is_valid_header(code, len, resync).then_some(HEADER_LEN + len)
+                },
+                Abi::Legacy => {
+                    (self.buffer[0] == SSAM_SSH_TC_BAS).then_some(LEGACY_EVENT_LEN)
+                },
+            };
+
+            let len = match len {
+                Some(len) => len,
+                None => {
+                    self.skip(1);
+                    continue;
+                },
+            };
+
+            if self.buffer.len() < len {
+                return None;
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..len).collect();
+            self.report_skipped("malformed event");
+            self.forward(&frame);
+
+            return match self.abi {
+                Abi::Current => Some(decode(&frame)),
+                Abi::Legacy  => Some(decode_legacy(&frame)),
+            };
+        }
+    }
+
+    fn forward(&self, frame: &[u8]) {
+        let raw = match self.abi {
+            Abi::Current => RawEvent {
+                code: u16::from_ne_bytes([frame[2], frame[3]]),
+                data: frame[HEADER_LEN..].to_vec(),
+            },
+            Abi::Legacy => RawEvent {
+                code: frame[1] as u16,
+                data: frame[2..].to_vec(),
+            },
+        };
+
+        trace!(target: "sdtxd", code=raw.code, data=?raw.data, "received raw DTX event");
+
+        // no receivers just means that nobody is interested
+        if self.raw.receiver_count() > 0 {
+            let _ = self.raw.send(raw);
+        }
+    }
+
+    fn skip(&mut self, n: usize) {
+        self.skipped.extend(self.buffer.drain(..n));
+    }
+
+    fn report_skipped(&mut self, what: &str) {
+        if self.skipped.is_empty() {
+            return;
+        }
+
+        let data: Vec<String> = self.skipped.iter().map(|b| format!("{b:02x}")).collect();
+        warn!(target: "sdtxd", bytes=self.skipped.len(), data=%data.join(" "),
+              "discarded data of {} from DTX device", what);
+
+        self.skipped.clear();
+    }
+}
+
+
+/// Check whether the given header describes a plausible event, i.e. known
+/// events carry the expected payload and unknown ones a reasonable amount.
+/// Unknown events are rejected while re-synchronizing after malformed data.
+fn is_valid_header(code: u16, len: usize, resync: bool) -> bool {
+    match code {
+        SDTX_EVENT_REQUEST         => len == 0,
+        SDTX_EVENT_CANCEL          => len == 2,
+        SDTX_EVENT_BASE_CONNECTION => len == 4,
+        SDTX_EVENT_LATCH_STATUS    => len == 2,
+        SDTX_EVENT_DEVICE_MODE     => len == 2,
+        0                          => false,
+        _                          => !resync && len <= MAX_PAYLOAD_LEN,
+    }
+}
+
+fn decode(frame: &[u8]) -> Event {
+    let code = u16::from_ne_bytes([frame[2], frame[3]]);
+    let data = &frame[HEADER_LEN..];
+
+    let word = |i: usize| u16::from_ne_bytes([data[2 * i], data[2 * i + 1]]);
+
+    match code {
+        SDTX_EVENT_REQUEST => Event::Request,
+        SDTX_EVENT_CANCEL => Event::Cancel { reason: cancel_reason(word(0)) },
+        SDTX_EVENT_BASE_CONNECTION => {
+            let state = match word(0) {
+                0x0000 => event::BaseState::Detached,
+                0x0001 => event::BaseState::Attached,
+                0x1001 => event::BaseState::NotFeasible,
+                state  => event::BaseState::Unknown(state as u8),
+            };
+
+            let base = word(1);
+            let device_type = match base & SDTX_DEVICE_TYPE_MASK {
+                SDTX_DEVICE_TYPE_HID => DeviceType::Hid,
+                SDTX_DEVICE_TYPE_SSH => DeviceType::Ssh,
+                ty                   => DeviceType::Unknown((ty >> 8) as u8),
+            };
+
+            Event::BaseConnection { state, device_type, id: base as u8 }
+        },
+        SDTX_EVENT_LATCH_STATUS => {
+            let status = word(0);
+            let status = match (status & SDTX_CATEGORY_MASK, status) {
+                (SDTX_CATEGORY_STATUS, 0x0000)    => event::LatchStatus::Closed,
+                (SDTX_CATEGORY_STATUS, 0x0001)    => event::LatchStatus::Opened,
+                (SDTX_CATEGORY_HARDWARE_ERROR, _) => event::LatchStatus::Error(hardware_error(status)),
+                _                                 => event::LatchStatus::Unknown(status as u8),
+            };
+
+            Event::LatchStatus { status }
+        },
+        SDTX_EVENT_DEVICE_MODE => {
+            let mode = match word(0) {
+                0 => event::DeviceMode::Tablet,
+                1 => event::DeviceMode::Laptop,
+                2 => event::DeviceMode::Studio,
+                m => event::DeviceMode::Unknown(m as u8),
+            };
+
+            Event::DeviceMode { mode }
+        },
+        _ => Event::Unknown { code, data: data.to_vec() },
+    }
+}
+
+fn cancel_reason(reason: u16) -> event::CancelReason {
+    match reason & SDTX_CATEGORY_MASK {
+        SDTX_CATEGORY_RUNTIME_ERROR => {
+            let err = match reason & 0xff {
+                0x01 => sdtx::RuntimeError::NotFeasible,
+                0x02 => sdtx::RuntimeError::Timeout,
+                code => sdtx::RuntimeError::Unknown(code as u8),
+            };
+
+            event::CancelReason::Runtime(err)
+        },
+        SDTX_CATEGORY_HARDWARE_ERROR => event::CancelReason::Hardware(hardware_error(reason)),
+        _ => event::CancelReason::Unknown(reason),
+    }
+}
+
+fn hardware_error(code: u16) -> sdtx::HardwareError {
+    match code & 0xff {
+        0x01 => sdtx::HardwareError::FailedToOpen,
+        0x02 => sdtx::HardwareError::FailedToRemainOpen,
+        0x03 => sdtx::HardwareError::FailedToClose,
+        code => sdtx::HardwareError::Unknown(code as u8),
+    }
+}
+
+/// Decode a raw SSAM event forwarded by the legacy driver, translating it in
+/// the same way as the upstream driver does.
+fn decode_legacy(frame: &[u8]) -> Event {
+    let (code, arg0, arg1) = (frame[1], frame[2], frame[3]);
+
+    match code {
+        SAM_EVENT_CID_DTX_CONNECTION => {
+            let state = match arg0 {
+                0x00  => event::BaseState::Detached,
+                0x01  => event::BaseState::Attached,
+                0x02  => event::BaseState::NotFeasible,
+                state => event::BaseState::Unknown(state),
+            };
+
+            Event::BaseConnection { state, device_type: DeviceType::Ssh, id: arg1 }
+        },
+        SAM_EVENT_CID_DTX_REQUEST => Event::Request,
+        SAM_EVENT_CID_DTX_CANCEL => {
+            let reason = match arg0 {
+                0x00   => event::CancelReason::Runtime(sdtx::RuntimeError::NotFeasible),
+                0x02   => event::CancelReason::Runtime(sdtx::RuntimeError::Timeout),
+                0x03   => event::CancelReason::Hardware(sdtx::HardwareError::FailedToOpen),
+                0x04   => event::CancelReason::Hardware(sdtx::HardwareError::FailedToRemainOpen),
+                0x05   => event::CancelReason::Hardware(sdtx::HardwareError::FailedToClose),
+                reason => event::CancelReason::Unknown(reason as u16),
+            };
+
+            Event::Cancel { reason }
+        },
+        SAM_EVENT_CID_DTX_LATCH_STATUS => {
+            let status = match arg0 {
+                0x00   => event::LatchStatus::Closed,
+                0x01   => event::LatchStatus::Opened,
+                0x02   => event::LatchStatus::Error(sdtx::HardwareError::FailedToOpen),
+                0x03   => event::LatchStatus::Error(sdtx::HardwareError::FailedToRemainOpen),
+                0x04   => event::LatchStatus::Error(sdtx::HardwareError::FailedToClose),
+                status => event::LatchStatus::Unknown(status),
+            };
+
+            Event::LatchStatus { status }
+        },
+        _ => Event::Unknown { code: code as u16, data: vec![arg0, arg1] },
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+
+    /// Reader for the given ABI, fed via the returned socket.
+    fn reader(abi: Abi) -> (EventReader, UnixStream) {
+        let (rx, tx) = UnixStream::pair().unwrap();
+        rx.set_nonblocking(true).unwrap();
+
+        let file = AsyncFd::new(File::from(OwnedFd::from(rx))).unwrap();
+        let (raw, _) = broadcast::channel(16);
+
+        (EventReader { abi, raw, file, buffer: Vec::new(), skipped: Vec::new() }, tx)
+    }
+
+    fn header(code: u16, len: usize) -> Vec<u8> {
+        let mut data = (len as u16).to_ne_bytes().to_vec();
+        data.extend_from_slice(&code.to_ne_bytes());
+        data
+    }
+
+    fn request() -> Vec<u8> {
+        header(SDTX_EVENT_REQUEST, 0)
+    }
+
+    #[tokio::test]
+    async fn header_split() {
+        let (mut reader, _tx) = reader(Abi::Current);
+        let frame = request();
+
+        reader.buffer.extend_from_slice(&frame[..2]);
+        assert!(reader.frame().is_none());
+
+        reader.buffer.extend_from_slice(&frame[2..]);
+        assert!(matches!(reader.frame(), Some(Event::Request)));
+        assert!(reader.buffer.is_empty());
+        assert!(reader.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn large_payload() {
+        let (mut reader, _tx) = reader(Abi::Current);
+
+        let len = READ_CHUNK + 72;
+        let mut frame = header(0x1234, len);
+        frame.extend((0..len).map(|i| i as u8));
+
+        reader.buffer.extend_from_slice(&frame[..READ_CHUNK]);
+        assert!(reader.frame().is_none());
+
+        reader.buffer.extend_from_slice(&frame[READ_CHUNK..]);
+        match reader.frame() {
+            Some(Event::Unknown { code, data }) => {
+                assert_eq!(code, 0x1234);
+                assert_eq!(data, frame[HEADER_LEN..]);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        assert!(reader.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_payload_resync() {
+        let (mut reader, _tx) = reader(Abi::Current);
+
+        reader.buffer.extend(header(0x1234, MAX_PAYLOAD_LEN + 1));
+        reader.buffer.extend(request());
+
+        assert!(matches!(reader.frame(), Some(Event::Request)));
+        assert!(reader.buffer.is_empty());
+        assert!(reader.skipped.is_empty());
+
+        // unknown events of acceptable size are still passed on
+        reader.buffer.extend(header(0x1234, 2));
+        reader.buffer.extend([0xab, 0xcd]);
+
+        assert!(matches!(reader.frame(), Some(Event::Unknown { code: 0x1234, .. })));
+        assert!(reader.buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trailing_garbage() {
+        let (mut reader, _tx) = reader(Abi::Current);
+
+        reader.buffer.extend(request());
+        reader.buffer.extend([0xff; 6]);
+
+        assert!(matches!(reader.frame(), Some(Event::Request)));
+        assert!(reader.frame().is_none());
+
+        // garbage is discarded up to the point where a header could start
+        assert!(reader.buffer.len() < HEADER_LEN);
+        assert_eq!(reader.skipped.len() + reader.buffer.len(), 6);
+
+        // and a following event is picked up again
+        reader.buffer.extend(request());
+        assert!(matches!(reader.frame(), Some(Event::Request)));
+        assert!(reader.buffer.is_empty());
+        assert!(reader.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_split_and_resync() {
+        let (mut reader, _tx) = reader(Abi::Legacy);
+
+        reader.buffer.extend([0x00, 0x42, SSAM_SSH_TC_BAS]);
+        assert!(reader.frame().is_none());
+
+        reader.buffer.extend([SAM_EVENT_CID_DTX_REQUEST, 0x00, 0x00]);
+        assert!(matches!(reader.frame(), Some(Event::Request)));
+        assert_eq!(reader.buffer.len(), 0);
+        assert!(reader.skipped.is_empty());
+
+        reader.buffer.extend([SSAM_SSH_TC_BAS, SAM_EVENT_CID_DTX_LATCH_STATUS]);
+        assert!(reader.frame().is_none());
+
+        reader.buffer.extend([0x01, 0x00, 0xff]);
+        assert!(matches!(reader.frame(),
+                         Some(Event::LatchStatus { status: event::LatchStatus::Opened })));
+        assert!(reader.frame().is_none());
+        assert_eq!(reader.buffer, [0xff]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn incomplete_frame_timeout() {
+        let (mut reader, mut tx) = reader(Abi::Current);
+
+        // incomplete header, the remainder never arrives
+        tx.write_all(&request()[..2]).unwrap();
+
+        // the next event is only sent well after the frame has timed out
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(FRAME_TIMEOUT * 10).await;
+            tx.write_all(&request()).unwrap();
+            tx
+        });
+
+        let event = reader.next().await.unwrap();
+        assert!(matches!(event, Some(Event::Request)));
+        assert!(reader.buffer.is_empty());
+        assert!(reader.skipped.is_empty());
+
+        // end of stream once the writer is gone
+        drop(writer.await.unwrap());
+        assert!(reader.next().await.unwrap().is_none());
+    }
+}
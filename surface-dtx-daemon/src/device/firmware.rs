@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use tracing::trace;
+
+
+const INPUT_DIR: &str = "/sys/class/input";
+
+/// Prefix of the SSAM device UIDs of HID devices on the secondary target, i.e.
+/// the keyboard and touchpad in the base (category 0x15, target 0x02).
+const SSAM_BASE_HID: &str = "ssam:01:15:02:";
+
+
+/// Firmware information of the base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseFirmware {
+    /// Firmware version, as reported by the HID devices of the base.
+    pub version: Option<String>,
+
+    /// Serial number, if reported.
+    pub serial: Option<String>,
+}
+
+impl BaseFirmware {
+    /// Read the firmware information of the currently attached base from
+    /// sysfs, or `None` if it is not present.
+    ///
+    /// The base does not have a dedicated firmware interface, so this is
+    /// taken from the input devices created for the HID devices provided by
+    /// the Surface Aggregator Module. These only exist while the base is
+    /// attached and their HID driver has been bound, so this should only be
+    /// read some time after attachment.
+    pub fn read() -> Option<Self> {
+        let entries = std::fs::read_dir(INPUT_DIR).ok()?;
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = match std::fs::canonicalize(entry.path()) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+
+            let is_base = path.components()
+                .filter_map(|c| c.as_os_str().to_str())
+                .any(|c| c.starts_with(SSAM_BASE_HID));
+
+            if !is_base {
+                continue;
+            }
+
+            trace!(target: "sdtxd", input=?entry.file_name(), device=?path, "found base input device");
+
+            let firmware = Self {
+                version: read_attr(&path.join("id/version")).map(|v| format_version(&v)),
+                serial: read_attr(&path.join("uniq")),
+            };
+
+            if firmware.version.is_some() || firmware.serial.is_some() {
+                return Some(firmware);
+            }
+        }
+
+        None
+    }
+}
+
+impl std::fmt::Display for BaseFirmware {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let version = self.version.as_deref().unwrap_or("unknown");
+        let serial = self.serial.as_deref().unwrap_or("unknown");
+
+        write!(f, "version {version}, serial {serial}")
+    }
+}
+
+
+/// Read the given attribute, ignoring it if it is empty or zero.
+fn read_attr(path: &Path) -> Option<String> {
+    let value = std::fs::read_to_string(path).ok()?;
+    let value = value.trim();
+
+    if value.is_empty() || value.chars().all(|c| c == '0') {
+        return None;
+    }
+
+    Some(value.to_owned())
+}
+
+/// Format a version number as reported by the input subsystem, i.e. major and
+/// minor byte in hexadecimal (e.g. "0204"), as dotted version (e.g. "2.4").
+fn format_version(version: &str) -> String {
+    match u16::from_str_radix(version, 16) {
+        Ok(v) => format!("{}.{}", v >> 8, v & 0xff),
+        Err(_) => version.to_owned(),
+    }
+}
@@ -0,0 +1,259 @@
+use crate::device::{DeviceError, DtxDevice};
+use crate::device::error::os_errors;
+use crate::device::events::{Abi, EventReader, RawEvent};
+use crate::logic::{DeviceMode, LatchStatus};
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+use futures::prelude::*;
+
+use sdtx::{BaseInfo, Event};
+
+use tokio::sync::broadcast;
+
+use tracing::{debug, info, warn};
+
+
+/// DTX device provided by the kernel driver.
+///
+/// Remembers the device node it has been opened from, so that it can be
+/// re-opened if the device disappears temporarily, e.g. due to the driver
+/// being re-bound or a firmware reset. Control operations failing due to this
+/// are transparently retried once on a fresh handle. Event streams end with an
+/// error in this case and need to be re-enabled after calling
+/// [`DtxDevice::reopen`].
+///
+/// If the device cannot be opened for writing (e.g. due to permissions), it
+/// is opened read-only instead. Events and state queries work as usual in
+/// this case, but latch commands fail with [`DeviceError::ReadOnly`].
+pub struct HardwareDevice {
+    path: PathBuf,
+    read_only: bool,
+    api: Api,
+    device: Mutex<sdtx_tokio::Device>,
+    raw: broadcast::Sender<RawEvent>,
+}
+
+/// Capabilities of the kernel DTX interface.
+///
+/// The driver does not report an interface version, so these are determined
+/// by probing the individual requests when the device is first opened.
+#[derive(Debug, Clone, Copy)]
+struct Api {
+    /// Whether the device mode can be queried. Older drivers only report it
+    /// via events.
+    device_mode: bool,
+}
+
+impl HardwareDevice {
+    pub fn open(path: &Path) -> Result<Self> {
+        let (device, read_only) = match open(path, false) {
+            Ok(device) => (device, false),
+            Err(err) if is_permission_denied(&err) => {
+                debug!(target: "sdtxd", device=?path, "cannot open DTX device for writing: {:#}", err);
+                (open(path, true).map_err(|_| err)?, true)
+            },
+            Err(err) => return Err(err),
+        };
+
+        let api = probe(path, &device)?;
+        let (raw, _) = broadcast::channel(64);
+
+        Ok(Self { path: path.to_owned(), read_only, api, device: Mutex::new(device), raw })
+    }
+
+    /// Whether the device has been opened read-only, i.e. without support
+    /// for latch commands.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Subscribe to the raw events received by this device or its clones.
+    pub fn raw_events(&self) -> broadcast::Receiver<RawEvent> {
+        self.raw.subscribe()
+    }
+
+    fn command<F>(&self, op: F) -> Result<()>
+    where
+        F: Fn(&sdtx_tokio::Device) -> sdtx::Result<()>,
+    {
+        if self.read_only {
+            return Err(DeviceError::ReadOnly.into());
+        }
+
+        self.call(op)
+    }
+
+    fn call<T, F>(&self, op: F) -> Result<T>
+    where
+        F: Fn(&sdtx_tokio::Device) -> sdtx::Result<T>,
+    {
+        let mut device = self.device.lock().unwrap();
+
+        let err = match op(&device) {
+            Ok(value) => return Ok(value),
+            Err(err) => anyhow::Error::from(err),
+        };
+
+        if !is_disconnected(&err) {
+            return Err(err);
+        }
+
+        warn!(target: "sdtxd", device=?self.path, "DTX device disconnected, re-opening: {:#}", err);
+
+        // report the original error if the device has not re-appeared (yet),
+        // so that callers can still tell that it has been disconnected
+        match open(&self.path, self.read_only) {
+            Ok(new) => *device = new,
+            Err(_) => return Err(err),
+        }
+
+        info!(target: "sdtxd", device=?self.path, "DTX device re-opened");
+        Ok(op(&device)?)
+    }
+}
+
+impl DtxDevice for HardwareDevice {
+    async fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            path: self.path.clone(),
+            read_only: self.read_only,
+            api: self.api,
+            device: Mutex::new(open(&self.path, self.read_only)?),
+            raw: self.raw.clone(),
+        })
+    }
+
+    fn reopen(&self) -> Result<()> {
+        *self.device.lock().unwrap() = open(&self.path, self.read_only)?;
+        Ok(())
+    }
+
+    fn events(&mut self) -> Result<impl Stream<Item=Result<Event>> + Unpin + '_> {
+        let reader = EventReader::new(self.device.get_mut().unwrap().file(), Abi::Current,
+                                      self.raw.clone())?;
+        Ok(reader.into_stream())
+    }
+
+    fn latch_lock(&self) -> Result<()> {
+        self.command(|d| d.latch_lock())
+    }
+
+    fn latch_unlock(&self) -> Result<()> {
+        self.command(|d| d.latch_unlock())
+    }
+
+    fn latch_request(&self) -> Result<()> {
+        self.command(|d| d.latch_request())
+    }
+
+    fn latch_confirm(&self) -> Result<()> {
+        self.command(|d| d.latch_confirm())
+    }
+
+    fn latch_heartbeat(&self) -> Result<()> {
+        self.command(|d| d.latch_heartbeat())
+    }
+
+    fn latch_cancel(&self) -> Result<()> {
+        self.command(|d| d.latch_cancel())
+    }
+
+    fn get_base_info(&self) -> Result<BaseInfo> {
+        self.call(|d| d.get_base_info())
+    }
+
+    fn get_latch_status(&self) -> Result<LatchStatus> {
+        self.call(|d| d.get_latch_status())
+    }
+
+    fn get_device_mode(&self) -> Result<DeviceMode> {
+        // without support for querying it, assume laptop mode until the
+        // first mode event tells us otherwise
+        if !self.api.device_mode {
+            return Ok(DeviceMode::Laptop);
+        }
+
+        self.call(|d| d.get_device_mode())
+    }
+}
+
+
+fn open(path: &Path, read_only: bool) -> Result<sdtx_tokio::Device> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(path)
+        .with_context(|| format!("Failed to access DTX device '{}'", path.display()))?;
+
+    Ok(sdtx_tokio::Device::from(tokio::fs::File::from_std(file)))
+}
+
+/// Determine the capabilities of the kernel interface, failing with a clear
+/// error if it is too old to be used at all.
+fn probe(path: &Path, device: &sdtx_tokio::Device) -> Result<Api> {
+    let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|release| release.trim().to_owned())
+        .unwrap_or_else(|_| "unknown".to_owned());
+
+    let driver = std::fs::read_to_string("/sys/module/surface_dtx/version")
+        .map(|version| version.trim().to_owned())
+        .ok();
+
+    // the base info request is required for any sensible operation and has
+    // been present since the first version of the driver
+    if let Err(err) = device.get_base_info() {
+        let err = anyhow::Error::from(err);
+
+        if is_unsupported(&err) {
+            return Err(err).context(format!(
+                "DTX device '{}' does not support the required requests, the surface_dtx driver \
+                 of kernel {} is too old", path.display(), kernel));
+        }
+
+        return Err(err).context(format!("Failed to query DTX device '{}'", path.display()));
+    }
+
+    let device_mode = match device.get_device_mode() {
+        Ok(_) => true,
+        Err(err) => {
+            let err = anyhow::Error::from(err);
+
+            if !is_unsupported(&err) {
+                return Err(err).context(format!("Failed to query DTX device '{}'", path.display()));
+            }
+
+            warn!(target: "sdtxd", device=?path, %kernel,
+                  "kernel does not support querying the device mode, relying on events only");
+            false
+        },
+    };
+
+    let api = Api { device_mode };
+    debug!(target: "sdtxd", device=?path, %kernel, ?driver, ?api, "probed kernel DTX interface");
+
+    Ok(api)
+}
+
+/// Check whether the given error indicates that access to the device has been
+/// denied, i.e. any I/O error in its chain failed with EACCES, EPERM, or EROFS.
+fn is_permission_denied(err: &anyhow::Error) -> bool {
+    os_errors(err).any(|code| code == libc::EACCES || code == libc::EPERM || code == libc::EROFS)
+}
+
+/// Check whether the given error indicates that the device has been
+/// disconnected, i.e. any I/O error in its chain failed with ENODEV or ENXIO.
+pub fn is_disconnected(err: &anyhow::Error) -> bool {
+    os_errors(err).any(|code| code == libc::ENODEV || code == libc::ENXIO)
+}
+
+/// Check whether the given error indicates that the request is not supported
+/// by the kernel driver, i.e. any I/O error in its chain failed with ENOTTY
+/// or EINVAL.
+fn is_unsupported(err: &anyhow::Error) -> bool {
+    os_errors(err).any(|code| code == libc::ENOTTY || code == libc::EINVAL)
+}
+
@@ -0,0 +1,114 @@
+use crate::device::{is_dtx_device, Selection};
+
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use futures::future;
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use tracing::{debug, trace, warn};
+
+
+/// Interval in which the monitor thread checks whether it is still needed.
+const MONITOR_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Addition or removal of a DTX device node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotplugEvent {
+    Added(PathBuf),
+    Removed(PathBuf),
+}
+
+
+/// Monitor for DTX devices matching a selection being added or removed, e.g.
+/// due to the driver being (re-)loaded.
+///
+/// Uevents are received on a separate thread as the udev monitor cannot be
+/// shared with the runtime. Removals are reported for all misc devices, as
+/// the driver may already be unbound at that point. It is up to the receiver
+/// to ignore devices it does not know about.
+pub struct Hotplug {
+    events: UnboundedReceiver<HotplugEvent>,
+}
+
+impl Hotplug {
+    pub fn new(selection: Selection) -> Self {
+        let (tx, events) = tokio::sync::mpsc::unbounded_channel();
+
+        let thread = std::thread::Builder::new()
+            .name("sdtxd-hotplug".into())
+            .spawn(move || {
+                if let Err(err) = monitor(tx, &selection) {
+                    warn!(target: "sdtxd", "failed to monitor DTX devices, hotplug is unavailable: {:#}", err);
+                }
+            });
+
+        if let Err(err) = thread {
+            warn!(target: "sdtxd", "failed to monitor DTX devices, hotplug is unavailable: {:#}", err);
+        }
+
+        Self { events }
+    }
+
+    /// Wait for the next device to be added or removed. Never completes if
+    /// uevents cannot be monitored.
+    pub async fn next(&mut self) -> HotplugEvent {
+        match self.events.recv().await {
+            Some(event) => event,
+            None => future::pending().await,
+        }
+    }
+}
+
+
+/// Forward DTX device uevents until the receiving end has been dropped.
+fn monitor(tx: UnboundedSender<HotplugEvent>, selection: &Selection) -> Result<()> {
+    let socket = udev::MonitorBuilder::new()
+        .and_then(|b| b.match_subsystem("misc"))
+        .and_then(|b| b.listen())
+        .context("Failed to set up udev monitor")?;
+
+    let mut fds = [libc::pollfd { fd: socket.as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+    let timeout = MONITOR_POLL_INTERVAL.as_millis() as libc::c_int;
+
+    while !tx.is_closed() {
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(err).context("Failed to receive udev events");
+        }
+
+        for event in socket.iter() {
+            trace!(target: "sdtxd", device=?event.sysname(), action=?event.event_type(), "misc uevent");
+
+            let node = match event.devnode() {
+                Some(node) => node.to_owned(),
+                None => continue,
+            };
+
+            let event = match event.event_type() {
+                udev::EventType::Add if is_dtx_device(&event) && selection.matches(&event) => {
+                    debug!(target: "sdtxd", devnode=?node, "DTX device added");
+                    HotplugEvent::Added(node)
+                },
+                udev::EventType::Remove => HotplugEvent::Removed(node),
+                _ => continue,
+            };
+
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
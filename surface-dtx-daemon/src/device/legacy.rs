@@ -0,0 +1,207 @@
+use crate::device::DtxDevice;
+use crate::device::events::{Abi, EventReader, RawEvent};
+use crate::logic::{BaseState, DeviceMode, DeviceType, LatchStatus};
+
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, bail};
+
+use futures::prelude::*;
+
+use sdtx::{event, BaseInfo, Event};
+
+use tokio::sync::broadcast;
+
+use tracing::trace;
+
+
+/// `_IO(0x11, 0x01)` to `_IO(0x11, 0x04)`, see the legacy surface_dtx driver.
+const DTX_CMD_LATCH_LOCK: libc::c_ulong = 0x1101;
+const DTX_CMD_LATCH_UNLOCK: libc::c_ulong = 0x1102;
+const DTX_CMD_LATCH_REQUEST: libc::c_ulong = 0x1103;
+const DTX_CMD_LATCH_OPEN: libc::c_ulong = 0x1104;
+
+/// `_IOR(0x11, 0x05, int)`, see the legacy surface_dtx driver.
+const DTX_CMD_GET_OPMODE: libc::c_ulong = 0x8004_1105;
+
+
+/// DTX device provided by the legacy out-of-tree driver of older
+/// linux-surface kernels.
+///
+/// The legacy driver forwards raw EC events and only supports the basic latch
+/// commands. In particular, it can neither report the base and latch state,
+/// nor send heartbeats or cancel a detachment. The base and latch state are
+/// therefore tracked from events, assuming an attached base and closed latch
+/// initially. Canceling is done by issuing another request, which the EC
+/// treats as abort while a detachment is in progress. Device mode changes are
+/// only reported via the tablet-mode input switch of the legacy driver, so
+/// the device mode is only picked up when (re-)synchronizing the state.
+#[derive(Clone)]
+pub struct LegacyDevice {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    file: Mutex<File>,
+    state: Mutex<State>,
+    raw: broadcast::Sender<RawEvent>,
+}
+
+struct State {
+    base: BaseInfo,
+    latch: LatchStatus,
+}
+
+impl LegacyDevice {
+    pub fn open(path: &Path) -> Result<Self> {
+        let state = State {
+            base: BaseInfo { state: BaseState::Attached, device_type: DeviceType::Ssh, id: 0 },
+            latch: LatchStatus::Closed,
+        };
+
+        let (raw, _) = broadcast::channel(64);
+
+        let shared = Shared {
+            path: path.to_owned(),
+            file: Mutex::new(open(path)?),
+            state: Mutex::new(state),
+            raw,
+        };
+
+        Ok(Self { inner: Arc::new(shared) })
+    }
+
+    /// Subscribe to the raw events received by this device.
+    pub fn raw_events(&self) -> broadcast::Receiver<RawEvent> {
+        self.inner.raw.subscribe()
+    }
+
+    fn command(&self, cmd: libc::c_ulong) -> Result<()> {
+        let file = self.inner.file.lock().unwrap();
+
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), cmd) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to execute DTX command");
+        }
+
+        Ok(())
+    }
+}
+
+impl DtxDevice for LegacyDevice {
+    async fn try_clone(&self) -> Result<Self> {
+        // events are read via a separate file descriptor anyway, sharing the
+        // device allows us to track its state
+        Ok(self.clone())
+    }
+
+    fn reopen(&self) -> Result<()> {
+        *self.inner.file.lock().unwrap() = open(&self.inner.path)?;
+        Ok(())
+    }
+
+    fn events(&mut self) -> Result<impl Stream<Item=Result<Event>> + Unpin + '_> {
+        let file = self.inner.file.lock().unwrap();
+        let reader = EventReader::new(&*file, Abi::Legacy, self.inner.raw.clone())?;
+        let inner = self.inner.clone();
+
+        Ok(reader.into_stream().inspect_ok(move |event| inner.update(event)))
+    }
+
+    fn latch_lock(&self) -> Result<()> {
+        self.command(DTX_CMD_LATCH_LOCK)
+    }
+
+    fn latch_unlock(&self) -> Result<()> {
+        self.command(DTX_CMD_LATCH_UNLOCK)
+    }
+
+    fn latch_request(&self) -> Result<()> {
+        self.command(DTX_CMD_LATCH_REQUEST)
+    }
+
+    fn latch_confirm(&self) -> Result<()> {
+        self.command(DTX_CMD_LATCH_OPEN)
+    }
+
+    fn latch_heartbeat(&self) -> Result<()> {
+        // not supported, the EC just uses its default timeout
+        Ok(())
+    }
+
+    fn latch_cancel(&self) -> Result<()> {
+        self.command(DTX_CMD_LATCH_REQUEST)
+    }
+
+    fn get_base_info(&self) -> Result<BaseInfo> {
+        Ok(self.inner.state.lock().unwrap().base)
+    }
+
+    fn get_latch_status(&self) -> Result<LatchStatus> {
+        Ok(self.inner.state.lock().unwrap().latch)
+    }
+
+    fn get_device_mode(&self) -> Result<DeviceMode> {
+        let file = self.inner.file.lock().unwrap();
+        let mut mode: libc::c_int = 0;
+
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), DTX_CMD_GET_OPMODE, &mut mode) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to get device mode");
+        }
+
+        match mode {
+            0 => Ok(DeviceMode::Tablet),
+            1 => Ok(DeviceMode::Laptop),
+            2 => Ok(DeviceMode::Studio),
+            _ => bail!("Unknown device mode: {}", mode),
+        }
+    }
+}
+
+impl Shared {
+    /// Update the tracked state based on the given event.
+    fn update(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+
+        match *event {
+            Event::BaseConnection { state: base, device_type, id } => {
+                let base = match base {
+                    event::BaseState::Attached    => BaseState::Attached,
+                    event::BaseState::Detached    => BaseState::Detached,
+                    event::BaseState::NotFeasible => BaseState::NotFeasible,
+                    event::BaseState::Unknown(_)  => return,
+                };
+
+                state.base = BaseInfo { state: base, device_type, id };
+            },
+            Event::LatchStatus { status } => match status {
+                event::LatchStatus::Closed     => state.latch = LatchStatus::Closed,
+                event::LatchStatus::Opened     => state.latch = LatchStatus::Opened,
+                event::LatchStatus::Error(err) => state.latch = LatchStatus::Error(err),
+                event::LatchStatus::Unknown(_) => return,
+            },
+            _ => return,
+        }
+
+        trace!(target: "sdtxd", base=?state.base, latch=?state.latch, "updated legacy DTX device state");
+    }
+}
+
+
+fn open(path: &Path) -> Result<File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to access DTX device '{}'", path.display()))
+}
+
+/// Check whether the given device node is provided by the legacy driver.
+pub fn is_legacy(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "surface_dtx")
+}
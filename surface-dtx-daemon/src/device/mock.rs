@@ -0,0 +1,441 @@
+use crate::device::{DtxDevice, Record, RecordKind};
+use crate::logic::{BaseState, DeviceMode, DeviceType, LatchStatus};
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+
+use futures::prelude::*;
+
+use sdtx::{event, BaseInfo, Event};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use tokio::time::Instant;
+
+use tracing::{debug, info, trace, warn};
+
+
+/// Simulated DTX device for development and testing without Surface hardware.
+///
+/// Device events are read line by line from clients connected to a unix
+/// socket, using the same names as for debug event injection (see
+/// [`parse_event`]). Latch commands issued by the daemon are written back to
+/// all connected clients, e.g. `latch:confirm`. Confirming and canceling a
+/// detachment opens and closes the simulated latch, base removal has to be
+/// simulated by the client.
+///
+/// Alternatively, events can be replayed from a recording (see
+/// [`MockDevice::replay`]).
+#[derive(Clone)]
+pub struct MockDevice {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    events: broadcast::Sender<Event>,
+    commands: broadcast::Sender<&'static str>,
+}
+
+struct State {
+    base: BaseInfo,
+    latch: LatchStatus,
+    mode: DeviceMode,
+}
+
+impl MockDevice {
+    /// Create a new mock device, listening for clients on the given socket
+    /// path. A stale socket at that path is replaced.
+    pub fn bind(path: &Path) -> Result<Self> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).context("Failed to remove stale mock device socket");
+            },
+            _ => {},
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind mock device socket '{}'", path.display()))?;
+
+        let device = MockDevice::new();
+
+        tokio::spawn(device.clone().serve(listener));
+        Ok(device)
+    }
+
+    /// Create a new mock device, replaying the given recorded events. State
+    /// records at the start of the recording determine the initial state,
+    /// events are emitted at their recorded time divided by `speed`, starting
+    /// once events have been enabled.
+    pub fn replay(records: Vec<Record>, speed: f64) -> Self {
+        let device = MockDevice::new();
+
+        let start = records.iter()
+            .position(|r| r.kind == RecordKind::Event)
+            .unwrap_or(records.len());
+
+        for record in &records[..start] {
+            device.update(&record.event);
+        }
+
+        tokio::spawn(device.clone().play(records.into_iter().skip(start).collect(), speed));
+        device
+    }
+
+    fn new() -> Self {
+        let state = State {
+            base: BaseInfo { state: BaseState::Attached, device_type: DeviceType::Ssh, id: 0 },
+            latch: LatchStatus::Closed,
+            mode: DeviceMode::Laptop,
+        };
+
+        let (events, _) = broadcast::channel(64);
+        let (commands, _) = broadcast::channel(64);
+
+        MockDevice {
+            inner: Arc::new(Shared { state: Mutex::new(state), events, commands }),
+        }
+    }
+
+    async fn play(self, records: Vec<Record>, speed: f64) {
+        // wait for the core to enable events, otherwise they would get lost
+        while self.inner.events.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        info!(target: "sdtxd::mock", events=records.len(), speed, "starting replay");
+
+        let start = Instant::now();
+        let offset = records.first().map(|r| r.time).unwrap_or_default();
+
+        for record in records {
+            let time = (record.time - offset).div_f64(speed);
+            tokio::time::sleep_until(start + time).await;
+
+            match record.kind {
+                RecordKind::Event => self.emit(record.event),
+                RecordKind::State => self.update(&record.event),
+            }
+        }
+
+        info!(target: "sdtxd::mock", "replay complete");
+    }
+
+    async fn serve(self, listener: UnixListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    debug!(target: "sdtxd::mock", "client connected");
+
+                    let device = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = device.client(stream).await {
+                            warn!(target: "sdtxd::mock", "client error: {:#}", err);
+                        }
+                    });
+                },
+                Err(err) => {
+                    warn!(target: "sdtxd::mock", error=%err, "failed to accept client");
+                },
+            }
+        }
+    }
+
+    async fn client(self, stream: UnixStream) -> Result<()> {
+        let (rd, mut wr) = stream.into_split();
+        let mut lines = BufReader::new(rd).lines();
+        let mut commands = self.inner.commands.subscribe();
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let line = match line? {
+                        Some(line) => line,
+                        None => break,
+                    };
+
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+
+                    match parse_event(line) {
+                        Ok(event) => self.emit(event),
+                        Err(err) => wr.write_all(format!("error: {err}\n").as_bytes()).await?,
+                    }
+                },
+                command = commands.recv() => match command {
+                    Ok(command) => wr.write_all(format!("{command}\n").as_bytes()).await?,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+
+        debug!(target: "sdtxd::mock", "client disconnected");
+        Ok(())
+    }
+
+    /// Update the simulated state based on the given event.
+    fn update(&self, event: &Event) {
+        let mut state = self.inner.state.lock().unwrap();
+
+        match *event {
+            Event::BaseConnection { state: base, device_type, id } => {
+                let base = match base {
+                    event::BaseState::Attached    => BaseState::Attached,
+                    event::BaseState::Detached    => BaseState::Detached,
+                    event::BaseState::NotFeasible => BaseState::NotFeasible,
+                    event::BaseState::Unknown(_)  => state.base.state,
+                };
+
+                state.base = BaseInfo { state: base, device_type, id };
+            },
+            Event::LatchStatus { status } => match status {
+                event::LatchStatus::Closed     => state.latch = LatchStatus::Closed,
+                event::LatchStatus::Opened     => state.latch = LatchStatus::Opened,
+                event::LatchStatus::Error(err) => state.latch = LatchStatus::Error(err),
+                event::LatchStatus::Unknown(_) => {},
+            },
+            Event::DeviceMode { mode } => {
+                if let Ok(mode) = DeviceMode::try_from(mode) {
+                    state.mode = mode;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Update the simulated state based on the given event and forward it.
+    fn emit(&self, event: Event) {
+        self.update(&event);
+
+        debug!(target: "sdtxd::mock", ?event, "emitting simulated event");
+
+        // no receivers just means that events are not enabled yet
+        let _ = self.inner.events.send(event);
+    }
+
+    fn command(&self, command: &'static str) {
+        debug!(target: "sdtxd::mock", command, "received latch command");
+
+        // no receivers just means that no client is connected
+        let _ = self.inner.commands.send(command);
+    }
+
+    fn latch(&self) -> LatchStatus {
+        self.inner.state.lock().unwrap().latch
+    }
+}
+
+impl DtxDevice for MockDevice {
+    async fn try_clone(&self) -> Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn reopen(&self) -> Result<()> {
+        // the simulated device never disappears
+        Ok(())
+    }
+
+    fn events(&mut self) -> Result<impl Stream<Item=Result<Event>> + Unpin + '_> {
+        let events = self.inner.events.subscribe();
+
+        let stream = stream::unfold(events, |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((Ok(event), events)),
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(target: "sdtxd::mock", skipped=n, "event receiver lagging behind");
+                    },
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    fn latch_lock(&self) -> Result<()> {
+        self.command("latch:lock");
+        Ok(())
+    }
+
+    fn latch_unlock(&self) -> Result<()> {
+        self.command("latch:unlock");
+        Ok(())
+    }
+
+    fn latch_request(&self) -> Result<()> {
+        // behaves like pressing the detach button
+        self.command("latch:request");
+        self.emit(Event::Request);
+        Ok(())
+    }
+
+    fn latch_confirm(&self) -> Result<()> {
+        self.command("latch:confirm");
+
+        if self.latch() == LatchStatus::Closed {
+            self.emit(Event::LatchStatus { status: event::LatchStatus::Opened });
+        }
+        Ok(())
+    }
+
+    fn latch_heartbeat(&self) -> Result<()> {
+        trace!(target: "sdtxd::mock", "received latch heartbeat");
+        Ok(())
+    }
+
+    fn latch_cancel(&self) -> Result<()> {
+        self.command("latch:cancel");
+
+        if self.latch() == LatchStatus::Opened {
+            self.emit(Event::LatchStatus { status: event::LatchStatus::Closed });
+        }
+        Ok(())
+    }
+
+    fn get_base_info(&self) -> Result<BaseInfo> {
+        Ok(self.inner.state.lock().unwrap().base)
+    }
+
+    fn get_latch_status(&self) -> Result<LatchStatus> {
+        Ok(self.latch())
+    }
+
+    fn get_device_mode(&self) -> Result<DeviceMode> {
+        Ok(self.inner.state.lock().unwrap().mode)
+    }
+}
+
+
+/// Parse a simulated event, e.g. "request", "base:detached", or "mode:tablet".
+///
+/// Base events may optionally specify the device type and ID, e.g.
+/// "base:attached:ssh:2", otherwise an SSH base with ID 0 is assumed.
+pub fn parse_event(name: &str) -> Result<Event> {
+    let event = match name {
+        "request"                         => Event::Request,
+        "cancel:not-feasible"             => Event::Cancel { reason: runtime(sdtx::RuntimeError::NotFeasible) },
+        "cancel:timeout"                  => Event::Cancel { reason: runtime(sdtx::RuntimeError::Timeout) },
+        "cancel:failed-to-open"           => Event::Cancel { reason: hardware(sdtx::HardwareError::FailedToOpen) },
+        "cancel:failed-to-remain-open"    => Event::Cancel { reason: hardware(sdtx::HardwareError::FailedToRemainOpen) },
+        "cancel:failed-to-close"          => Event::Cancel { reason: hardware(sdtx::HardwareError::FailedToClose) },
+        "latch:closed"                    => Event::LatchStatus { status: event::LatchStatus::Closed },
+        "latch:opened"                    => Event::LatchStatus { status: event::LatchStatus::Opened },
+        "latch:error:failed-to-open"      => latch_error(sdtx::HardwareError::FailedToOpen),
+        "latch:error:failed-to-remain-open" => latch_error(sdtx::HardwareError::FailedToRemainOpen),
+        "latch:error:failed-to-close"     => latch_error(sdtx::HardwareError::FailedToClose),
+        "mode:tablet"                     => Event::DeviceMode { mode: event::DeviceMode::Tablet },
+        "mode:laptop"                     => Event::DeviceMode { mode: event::DeviceMode::Laptop },
+        "mode:studio"                     => Event::DeviceMode { mode: event::DeviceMode::Studio },
+        _ => match name.strip_prefix("base:").and_then(base) {
+            Some(event) => event,
+            None => bail!("Unknown event '{}'", name),
+        },
+    };
+
+    Ok(event)
+}
+
+/// Format an event using the names accepted by [`parse_event`]. Returns `None`
+/// for events that cannot be represented this way, i.e. unknown ones.
+pub fn format_event(event: &Event) -> Option<String> {
+    let name = match event {
+        Event::Request => "request".to_owned(),
+        Event::Cancel { reason } => match reason {
+            event::CancelReason::Runtime(sdtx::RuntimeError::NotFeasible) => "cancel:not-feasible".to_owned(),
+            event::CancelReason::Runtime(sdtx::RuntimeError::Timeout) => "cancel:timeout".to_owned(),
+            event::CancelReason::Hardware(err) => format!("cancel:{}", hardware_error(err)?),
+            _ => return None,
+        },
+        Event::BaseConnection { state, device_type, id } => {
+            let state = match state {
+                event::BaseState::Attached    => "attached",
+                event::BaseState::Detached    => "detached",
+                event::BaseState::NotFeasible => "not-feasible",
+                event::BaseState::Unknown(_)  => return None,
+            };
+
+            let device_type = match device_type {
+                DeviceType::Hid        => "hid".to_owned(),
+                DeviceType::Ssh        => "ssh".to_owned(),
+                DeviceType::Unknown(t) => t.to_string(),
+            };
+
+            format!("base:{state}:{device_type}:{id}")
+        },
+        Event::LatchStatus { status } => match status {
+            event::LatchStatus::Closed     => "latch:closed".to_owned(),
+            event::LatchStatus::Opened     => "latch:opened".to_owned(),
+            event::LatchStatus::Error(err) => format!("latch:error:{}", hardware_error(err)?),
+            event::LatchStatus::Unknown(_) => return None,
+        },
+        Event::DeviceMode { mode } => match mode {
+            event::DeviceMode::Tablet     => "mode:tablet".to_owned(),
+            event::DeviceMode::Laptop     => "mode:laptop".to_owned(),
+            event::DeviceMode::Studio     => "mode:studio".to_owned(),
+            event::DeviceMode::Unknown(_) => return None,
+        },
+        Event::Unknown { .. } => return None,
+    };
+
+    Some(name)
+}
+
+fn runtime(err: sdtx::RuntimeError) -> event::CancelReason {
+    event::CancelReason::Runtime(err)
+}
+
+fn hardware(err: sdtx::HardwareError) -> event::CancelReason {
+    event::CancelReason::Hardware(err)
+}
+
+fn latch_error(err: sdtx::HardwareError) -> Event {
+    Event::LatchStatus { status: event::LatchStatus::Error(err) }
+}
+
+fn hardware_error(err: &sdtx::HardwareError) -> Option<&'static str> {
+    match err {
+        sdtx::HardwareError::FailedToOpen       => Some("failed-to-open"),
+        sdtx::HardwareError::FailedToRemainOpen => Some("failed-to-remain-open"),
+        sdtx::HardwareError::FailedToClose      => Some("failed-to-close"),
+        sdtx::HardwareError::Unknown(_)         => None,
+    }
+}
+
+/// Parse a base event without prefix, i.e. "<state>" or
+/// "<state>:<type>:<id>".
+fn base(name: &str) -> Option<Event> {
+    let mut parts = name.split(':');
+
+    let state = match parts.next()? {
+        "attached"     => event::BaseState::Attached,
+        "detached"     => event::BaseState::Detached,
+        "not-feasible" => event::BaseState::NotFeasible,
+        _ => return None,
+    };
+
+    let (device_type, id) = match (parts.next(), parts.next(), parts.next()) {
+        (None, _, _) => (DeviceType::Ssh, 0),
+        (Some(device_type), Some(id), None) => {
+            let device_type = match device_type {
+                "hid" => DeviceType::Hid,
+                "ssh" => DeviceType::Ssh,
+                other => DeviceType::Unknown(other.parse().ok()?),
+            };
+
+            (device_type, id.parse().ok()?)
+        },
+        _ => return None,
+    };
+
+    Some(Event::BaseConnection { state, device_type, id })
+}
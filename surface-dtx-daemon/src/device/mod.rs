@@ -0,0 +1,441 @@
+mod battery;
+
+mod error;
+pub use error::DeviceError;
+
+mod events;
+pub use events::RawEvent;
+pub use battery::{BaseBattery, BatteryInfo, BatteryStatus};
+
+mod firmware;
+pub use firmware::BaseFirmware;
+
+mod hardware;
+pub use hardware::{HardwareDevice, is_disconnected};
+
+mod hotplug;
+pub use hotplug::{Hotplug, HotplugEvent};
+
+mod legacy;
+pub use legacy::{LegacyDevice, is_legacy};
+
+mod mock;
+pub use mock::{MockDevice, format_event, parse_event};
+
+mod record;
+pub use record::{EventRecorder, Record, RecordKind};
+
+use crate::logic::{DeviceMode, LatchStatus};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use futures::future::Either;
+use futures::prelude::*;
+
+use tokio::sync::broadcast;
+
+use tracing::{debug, info, warn};
+
+use sdtx::{BaseInfo, Event};
+
+
+/// Abstraction over the DTX device used by the logic core.
+///
+/// This allows the core state machine to be driven by something else than
+/// the actual kernel device, e.g. by mock devices for testing or alternative
+/// backends.
+pub trait DtxDevice: Send + Sync {
+    /// Create a new, independent handle to the same device.
+    async fn try_clone(&self) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Re-open the device after it has been disconnected. Event streams
+    /// obtained before need to be re-enabled.
+    fn reopen(&self) -> Result<()>;
+
+    /// Enable and return the stream of events emitted by this device.
+    fn events(&mut self) -> Result<impl Stream<Item=Result<Event>> + Unpin + '_>
+    where
+        Self: Sized;
+
+    fn latch_lock(&self) -> Result<()>;
+    fn latch_unlock(&self) -> Result<()>;
+    fn latch_request(&self) -> Result<()>;
+    fn latch_confirm(&self) -> Result<()>;
+    fn latch_heartbeat(&self) -> Result<()>;
+    fn latch_cancel(&self) -> Result<()>;
+
+    fn get_base_info(&self) -> Result<BaseInfo>;
+    fn get_latch_status(&self) -> Result<LatchStatus>;
+    fn get_device_mode(&self) -> Result<DeviceMode>;
+
+    /// Read base info, latch status, and device mode as one consistent
+    /// snapshot. Used to initialize the state at startup and after re-opening
+    /// the device, and to re-sync it after resuming from sleep.
+    ///
+    /// The device does not provide a single request for this, so an event
+    /// arriving between the individual requests could leave us with a torn
+    /// state, e.g. the base state from before and the latch status from after
+    /// a detachment. Detect this by reading the state again and retry until
+    /// two consecutive reads agree.
+    fn snapshot(&self) -> Result<Snapshot> {
+        let read = || -> Result<Snapshot> {
+            Ok(Snapshot {
+                base: self.get_base_info()?,
+                latch: self.get_latch_status()?,
+                mode: self.get_device_mode()?,
+            })
+        };
+
+        let mut snapshot = read()?;
+
+        for attempt in 1..=SNAPSHOT_ATTEMPTS {
+            let current = read()?;
+            if current == snapshot {
+                return Ok(snapshot);
+            }
+
+            debug!(target: "sdtxd", attempt, previous=?snapshot, ?current,
+                   "DTX device state changed while reading, retrying");
+
+            snapshot = current;
+        }
+
+        bail!("DTX device state did not settle after {} attempts", SNAPSHOT_ATTEMPTS)
+    }
+}
+
+
+/// Device state as read by [`DtxDevice::snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub base: BaseInfo,
+    pub latch: LatchStatus,
+    pub mode: DeviceMode,
+}
+
+
+/// Directory containing the DTX device files, used if udev is unavailable.
+pub const DEVICE_DIR: &str = "/dev/surface";
+
+/// Device file of the legacy driver, used if udev is unavailable.
+const LEGACY_DEVICE_PATH: &str = "/dev/surface_dtx";
+
+/// Name of the kernel driver providing DTX devices.
+const DRIVER: &str = "surface_dtx";
+
+/// Initial and maximum interval between attempts to find DTX devices while
+/// waiting for them to appear.
+const WAIT_INTERVAL_MIN: Duration = Duration::from_millis(250);
+const WAIT_INTERVAL_MAX: Duration = Duration::from_secs(5);
+
+/// Maximum number of attempts to read a consistent device state snapshot.
+const SNAPSHOT_ATTEMPTS: u32 = 5;
+
+
+/// Criteria for selecting specific DTX devices, resolved via udev. All given
+/// criteria need to match, no criteria select all devices.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Sysfs path of the DTX device or its parent device.
+    pub syspath: Option<PathBuf>,
+
+    /// Name of the driver instance, i.e. the parent device of the DTX
+    /// device (e.g. "MSHW0133:00").
+    pub instance: Option<String>,
+
+    /// Serial number of the DTX device or its parent device, as reported by
+    /// udev.
+    pub serial: Option<String>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.syspath.is_none() && self.instance.is_none() && self.serial.is_none()
+    }
+
+    /// Check whether the given DTX device matches all criteria.
+    fn matches(&self, device: &udev::Device) -> bool {
+        let parent = device.parent();
+        let candidates = std::iter::once(device).chain(parent.as_ref());
+
+        let syspath = self.syspath.as_ref().is_none_or(|path| {
+            // allow paths via symlinks, e.g. in /sys/class/misc/
+            let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            candidates.clone().any(|d| d.syspath() == path)
+        });
+
+        let instance = self.instance.as_ref().is_none_or(|name| {
+            parent.as_ref().is_some_and(|p| p.sysname() == name.as_str())
+        });
+
+        let serial = self.serial.as_ref().is_none_or(|serial| {
+            candidates.clone().any(|d| {
+                let value = d.property_value("ID_SERIAL").or_else(|| d.attribute_value("serial"));
+                value.is_some_and(|value| value == serial.as_str())
+            })
+        });
+
+        syspath && instance && serial
+    }
+}
+
+
+/// Find the device nodes of all DTX devices matching the given selection,
+/// ordered by path.
+///
+/// Devices are looked up via udev, so that the actual device node is used
+/// regardless of its naming. If udev is unavailable or does not know about
+/// any DTX device (e.g. in some initramfs setups), fall back to scanning
+/// /dev/surface/. Specific devices can only be selected via udev, so there is
+/// no fallback in that case.
+pub fn enumerate(selection: &Selection) -> Result<Vec<PathBuf>> {
+    if !selection.is_empty() {
+        return enumerate_udev(selection)
+            .context("Failed to look up selected DTX device via udev");
+    }
+
+    match enumerate_udev(selection) {
+        Ok(paths) if !paths.is_empty() => return Ok(paths),
+        Ok(_) => {
+            debug!(target: "sdtxd", dir=DEVICE_DIR, "no DTX device found via udev, scanning device directory");
+        },
+        Err(err) => {
+            warn!(target: "sdtxd", dir=DEVICE_DIR, "failed to enumerate DTX devices via udev, scanning device directory: {:#}", err);
+        },
+    }
+
+    enumerate_dir()
+}
+
+/// Find the device nodes of all DTX devices matching the given selection,
+/// waiting for at least one to appear if there is none yet, e.g. because the
+/// driver has not been loaded. Retries with increasing interval and gives up
+/// after the given timeout, if any.
+pub async fn wait(timeout: Option<Duration>, selection: &Selection) -> Result<Vec<PathBuf>> {
+    let start = tokio::time::Instant::now();
+    let mut interval = WAIT_INTERVAL_MIN;
+
+    loop {
+        let paths = enumerate(selection)?;
+        if !paths.is_empty() {
+            return Ok(paths);
+        }
+
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            if !selection.is_empty() {
+                bail!("No DTX device matching {:?} found", selection);
+            }
+
+            bail!("No DTX device found in '{}'", DEVICE_DIR);
+        }
+
+        if interval == WAIT_INTERVAL_MIN {
+            info!(target: "sdtxd", "no DTX device found, waiting for it to appear");
+        }
+
+        debug!(target: "sdtxd", ?interval, "retrying DTX device discovery");
+
+        tokio::time::sleep(interval).await;
+        interval = (interval * 2).min(WAIT_INTERVAL_MAX);
+    }
+}
+
+fn enumerate_udev(selection: &Selection) -> Result<Vec<PathBuf>> {
+    let mut enumerator = udev::Enumerator::new()
+        .context("Failed to set up udev enumeration")?;
+
+    enumerator.match_subsystem("misc")
+        .context("Failed to set up udev enumeration")?;
+
+    let devices = enumerator.scan_devices()
+        .context("Failed to enumerate udev devices")?;
+
+    let mut paths = Vec::new();
+    for device in devices.filter(is_dtx_device).filter(|d| selection.matches(d)) {
+        match device.devnode() {
+            Some(node) => {
+                info!(target: "sdtxd", syspath=?device.syspath(), devnode=?node, "found DTX device via udev");
+                paths.push(node.to_owned());
+            },
+            None => {
+                warn!(target: "sdtxd", syspath=?device.syspath(), "ignoring DTX device without device node");
+            },
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+/// Check whether the given misc device is provided by the DTX driver, either
+/// directly or via its parent (platform) device.
+fn is_dtx_device(device: &udev::Device) -> bool {
+    let has_driver = |device: &udev::Device| device.driver().is_some_and(|d| d == DRIVER);
+
+    device.sysname() == DRIVER || has_driver(device) || device.parent().is_some_and(|p| has_driver(&p))
+}
+
+/// Find all device files in /dev/surface/ with names starting with "dtx", or
+/// the device file of the legacy driver if there are none.
+fn enumerate_dir() -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(DEVICE_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(enumerate_legacy()),
+        Err(e) => return Err(e).context("Failed to enumerate DTX devices"),
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("dtx"))
+        })
+        .collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        return Ok(enumerate_legacy());
+    }
+
+    for path in &paths {
+        info!(target: "sdtxd", devnode=?path, "found DTX device in device directory");
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+fn enumerate_legacy() -> Vec<PathBuf> {
+    let path = PathBuf::from(LEGACY_DEVICE_PATH);
+    if !path.exists() {
+        return Vec::new();
+    }
+
+    info!(target: "sdtxd", devnode=?path, "found legacy DTX device");
+    vec![path]
+}
+
+
+/// DTX device used by the daemon, either the actual hardware (using the
+/// current or legacy kernel interface) or a mock.
+pub enum Device {
+    Hardware(HardwareDevice),
+    Legacy(LegacyDevice),
+    Mock(MockDevice),
+}
+
+impl Device {
+    /// Subscribe to the raw events received by this device before they are
+    /// translated, if it is backed by the kernel driver.
+    pub fn raw_events(&self) -> Option<broadcast::Receiver<RawEvent>> {
+        match self {
+            Self::Hardware(dev) => Some(dev.raw_events()),
+            Self::Legacy(dev)   => Some(dev.raw_events()),
+            Self::Mock(_)       => None,
+        }
+    }
+}
+
+impl DtxDevice for Device {
+    async fn try_clone(&self) -> Result<Self> {
+        match self {
+            Self::Hardware(dev) => Ok(Self::Hardware(DtxDevice::try_clone(dev).await?)),
+            Self::Legacy(dev)   => Ok(Self::Legacy(DtxDevice::try_clone(dev).await?)),
+            Self::Mock(dev)     => Ok(Self::Mock(DtxDevice::try_clone(dev).await?)),
+        }
+    }
+
+    fn reopen(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.reopen(),
+            Self::Legacy(dev)   => dev.reopen(),
+            Self::Mock(dev)     => dev.reopen(),
+        }
+    }
+
+    fn events(&mut self) -> Result<impl Stream<Item=Result<Event>> + Unpin + '_> {
+        match self {
+            Self::Hardware(dev) => dev.events().map(Either::Left),
+            Self::Legacy(dev)   => dev.events().map(|s| Either::Right(Either::Left(s))),
+            Self::Mock(dev)     => dev.events().map(|s| Either::Right(Either::Right(s))),
+        }
+    }
+
+    fn latch_lock(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.latch_lock(),
+            Self::Legacy(dev)   => dev.latch_lock(),
+            Self::Mock(dev)     => dev.latch_lock(),
+        }
+    }
+
+    fn latch_unlock(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.latch_unlock(),
+            Self::Legacy(dev)   => dev.latch_unlock(),
+            Self::Mock(dev)     => dev.latch_unlock(),
+        }
+    }
+
+    fn latch_request(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.latch_request(),
+            Self::Legacy(dev)   => dev.latch_request(),
+            Self::Mock(dev)     => dev.latch_request(),
+        }
+    }
+
+    fn latch_confirm(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.latch_confirm(),
+            Self::Legacy(dev)   => dev.latch_confirm(),
+            Self::Mock(dev)     => dev.latch_confirm(),
+        }
+    }
+
+    fn latch_heartbeat(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.latch_heartbeat(),
+            Self::Legacy(dev)   => dev.latch_heartbeat(),
+            Self::Mock(dev)     => dev.latch_heartbeat(),
+        }
+    }
+
+    fn latch_cancel(&self) -> Result<()> {
+        match self {
+            Self::Hardware(dev) => dev.latch_cancel(),
+            Self::Legacy(dev)   => dev.latch_cancel(),
+            Self::Mock(dev)     => dev.latch_cancel(),
+        }
+    }
+
+    fn get_base_info(&self) -> Result<BaseInfo> {
+        match self {
+            Self::Hardware(dev) => dev.get_base_info(),
+            Self::Legacy(dev)   => dev.get_base_info(),
+            Self::Mock(dev)     => dev.get_base_info(),
+        }
+    }
+
+    fn get_latch_status(&self) -> Result<LatchStatus> {
+        match self {
+            Self::Hardware(dev) => dev.get_latch_status(),
+            Self::Legacy(dev)   => dev.get_latch_status(),
+            Self::Mock(dev)     => dev.get_latch_status(),
+        }
+    }
+
+    fn get_device_mode(&self) -> Result<DeviceMode> {
+        match self {
+            Self::Hardware(dev) => dev.get_device_mode(),
+            Self::Legacy(dev)   => dev.get_device_mode(),
+            Self::Mock(dev)     => dev.get_device_mode(),
+        }
+    }
+}
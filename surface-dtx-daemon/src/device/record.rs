@@ -0,0 +1,160 @@
+use crate::device::{format_event, parse_event};
+use crate::logic::{BaseState, DeviceMode, LatchStatus};
+
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use sdtx::{event, BaseInfo, Event};
+
+use tracing::{debug, info, warn};
+
+
+/// Kind of a recorded entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// A raw event received from the device.
+    Event,
+
+    /// Device state read after enabling events, expressed as event.
+    State,
+}
+
+/// A single entry of an event recording.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Time relative to the start of the recording.
+    pub time: Duration,
+    pub kind: RecordKind,
+    pub event: Event,
+}
+
+impl Record {
+    /// Load an event recording created by [`EventRecorder`].
+    pub fn load(path: &Path) -> Result<Vec<Record>> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read event recording '{}'", path.display()))?;
+
+        let mut records = Vec::new();
+
+        for (n, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let record = Record::parse(line)
+                .with_context(|| format!("Invalid event recording '{}', line {}", path.display(), n + 1))?;
+
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let mut parts = line.split_whitespace();
+
+        let (time, kind, event) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(time), Some(kind), Some(event), None) => (time, kind, event),
+            _ => bail!("Expected '<time> <kind> <event>'"),
+        };
+
+        let time = time.parse::<f64>().ok()
+            .and_then(|t| Duration::try_from_secs_f64(t).ok())
+            .ok_or_else(|| anyhow!("Invalid time '{}'", time))?;
+
+        let kind = match kind {
+            "event" => RecordKind::Event,
+            "state" => RecordKind::State,
+            _ => bail!("Unknown record kind '{}'", kind),
+        };
+
+        Ok(Record { time, kind, event: parse_event(event)? })
+    }
+}
+
+
+/// Records raw DTX events with timestamps to a file.
+///
+/// Each line has the form `<seconds> <kind> <event>`, where the event uses
+/// the names accepted by [`parse_event`]. Lines starting with `#` are
+/// comments. Whenever events are (re-)enabled, the device state is recorded
+/// as well, so that a replay can start from the same state.
+pub struct EventRecorder {
+    file: LineWriter<File>,
+    start: Instant,
+}
+
+impl EventRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create event recording '{}'", path.display()))?;
+
+        let mut file = LineWriter::new(file);
+        writeln!(file, "# surface-dtx-daemon event recording")
+            .with_context(|| format!("Failed to write event recording '{}'", path.display()))?;
+
+        info!(target: "sdtxd", file=?path, "recording DTX events");
+        Ok(Self { file, start: Instant::now() })
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        self.write(RecordKind::Event, event);
+    }
+
+    pub fn record_state(&mut self, base: BaseInfo, latch: LatchStatus, mode: DeviceMode) {
+        let state = match base.state {
+            BaseState::Attached    => event::BaseState::Attached,
+            BaseState::Detached    => event::BaseState::Detached,
+            BaseState::NotFeasible => event::BaseState::NotFeasible,
+        };
+
+        let latch = match latch {
+            LatchStatus::Closed     => event::LatchStatus::Closed,
+            LatchStatus::Opened     => event::LatchStatus::Opened,
+            LatchStatus::Error(err) => event::LatchStatus::Error(err),
+        };
+
+        let mode = match mode {
+            DeviceMode::Tablet => event::DeviceMode::Tablet,
+            DeviceMode::Laptop => event::DeviceMode::Laptop,
+            DeviceMode::Studio => event::DeviceMode::Studio,
+        };
+
+        let events = [
+            Event::BaseConnection { state, device_type: base.device_type, id: base.id },
+            Event::LatchStatus { status: latch },
+            Event::DeviceMode { mode },
+        ];
+
+        for event in &events {
+            self.write(RecordKind::State, event);
+        }
+    }
+
+    fn write(&mut self, kind: RecordKind, event: &Event) {
+        let name = match format_event(event) {
+            Some(name) => name,
+            None => {
+                debug!(target: "sdtxd", ?event, "cannot record unknown event");
+                return;
+            },
+        };
+
+        let kind = match kind {
+            RecordKind::Event => "event",
+            RecordKind::State => "state",
+        };
+
+        let time = self.start.elapsed().as_secs_f64();
+
+        // failing to record should not affect event handling, so only warn
+        if let Err(err) = writeln!(self.file, "{time:.3} {kind} {name}") {
+            warn!(target: "sdtxd", error=%err, "failed to record DTX event");
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::logic::{
+    Adapter,
+    AtHandle,
+    BaseInfo,
+    BaseState,
+    CancelReason,
+    DeviceMode,
+    DryRun,
+    DtHandle,
+    DtcHandle,
+    HandlerRecords,
+    LatchState,
+    SessionId,
+};
+
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use serde_json::{json, Value};
+
+use tracing::warn;
+
+
+/// File to which detachment and attachment sessions are recorded.
+const AUDIT_LOG: &str = "/var/lib/surface-dtx/audit.log";
+
+
+/// Adapter recording each step of detachment and attachment sessions to the
+/// audit log, independent of the log level.
+///
+/// Each step is appended as a single JSON line, together with the results of
+/// all handlers of the session that have completed since the previous step.
+/// Does nothing if disabled, e.g. for simulated devices.
+pub struct AuditAdapter {
+    enabled: bool,
+    records: HandlerRecords,
+    dry_run: DryRun,
+    base: Option<u8>,
+    session: Option<Session>,
+    failed: bool,
+}
+
+struct Session {
+    id: SessionId,
+    base: Option<u8>,
+    handlers: usize,
+}
+
+impl AuditAdapter {
+    pub fn new(enabled: bool, records: HandlerRecords, dry_run: DryRun) -> Self {
+        Self { enabled, records, dry_run, base: None, session: None, failed: false }
+    }
+
+    fn record(&mut self, session: SessionId, step: &str, reason: Option<CancelReason>) {
+        if !self.enabled {
+            return;
+        }
+
+        // steps of a new session always start with the current base, if any
+        if self.session.as_ref().map(|s| s.id) != Some(session) {
+            self.session = Some(Session { id: session, base: self.base, handlers: 0 });
+        }
+
+        let current = self.session.as_mut().unwrap();
+
+        let handlers: Vec<Value> = self.records.get().into_iter()
+            .filter(|r| r.session == Some(session))
+            .skip(current.handlers)
+            .map(|r| json!({
+                "handler": r.handler,
+                "result": r.result.to_string(),
+                "duration": r.duration.as_secs_f64(),
+            }))
+            .collect();
+
+        current.handlers += handlers.len();
+
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut entry = json!({
+            "time": time.as_secs_f64(),
+            "session": session.value(),
+            "step": step,
+        });
+
+        if let Some(base) = current.base {
+            entry["base"] = json!(base);
+        }
+        if let Some(reason) = reason {
+            entry["reason"] = json!(reason.to_string());
+        }
+        if !handlers.is_empty() {
+            entry["handlers"] = Value::Array(handlers);
+        }
+        if self.dry_run.get() {
+            entry["dry_run"] = json!(true);
+        }
+
+        // the audit log is not essential, so only warn, and only once
+        match append(Path::new(AUDIT_LOG), &entry) {
+            Ok(()) => self.failed = false,
+            Err(err) if !self.failed => {
+                warn!(target: "sdtxd", "failed to write audit log: {:#}", err);
+                self.failed = true;
+            },
+            Err(_) => {},
+        }
+    }
+}
+
+impl Adapter for AuditAdapter {
+    fn set_state(&mut self, _mode: DeviceMode, base: BaseInfo, _latch: LatchState) {
+        self.base = attached_base(&base);
+    }
+
+    fn request_inhibited(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
+        self.record(session, "detach:inhibited", Some(reason));
+        Ok(())
+    }
+
+    fn detachment_start(&mut self, session: SessionId, _handle: DtHandle) -> Result<()> {
+        self.record(session, "detach:start", None);
+        Ok(())
+    }
+
+    fn detachment_ready(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "detach:ready", None);
+        Ok(())
+    }
+
+    fn detachment_complete(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "detach:complete", None);
+        Ok(())
+    }
+
+    fn detachment_cancel(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
+        self.record(session, "detach:cancel", Some(reason));
+        Ok(())
+    }
+
+    fn detachment_cancel_start(&mut self, session: SessionId, _handle: DtcHandle) -> Result<()> {
+        self.record(session, "detach:cancel:start", None);
+        Ok(())
+    }
+
+    fn detachment_cancel_complete(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "detach:cancel:complete", None);
+        Ok(())
+    }
+
+    fn detachment_cancel_timeout(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "detach:cancel:timeout", None);
+        Ok(())
+    }
+
+    fn detachment_unexpected(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "detach:unexpected", None);
+        Ok(())
+    }
+
+    fn attachment_start(&mut self, session: SessionId, _handle: AtHandle) -> Result<()> {
+        self.record(session, "attach:start", None);
+        Ok(())
+    }
+
+    fn attachment_complete(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "attach:complete", None);
+        Ok(())
+    }
+
+    fn attachment_timeout(&mut self, session: SessionId) -> Result<()> {
+        self.record(session, "attach:timeout", None);
+        Ok(())
+    }
+
+    fn on_base_state(&mut self, info: BaseInfo) -> Result<()> {
+        self.base = attached_base(&info);
+        Ok(())
+    }
+}
+
+
+fn attached_base(info: &BaseInfo) -> Option<u8> {
+    (info.state == BaseState::Attached).then_some(info.id)
+}
+
+
+/// Append a single entry to the audit log. The file is opened for each entry,
+/// so that it may be moved or removed at any time.
+fn append(path: &Path, entry: &Value) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory '{}'", dir.display()))?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path)
+        .with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+    writeln!(file, "{entry}")
+        .with_context(|| format!("Failed to write to '{}'", path.display()))
+}
@@ -0,0 +1,535 @@
+use crate::config::{BuiltinDetach, DgpuStep};
+use crate::logic::SessionId;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use tracing::{debug, trace, warn};
+
+
+const USB_DISK_DIR: &str = "disk/by-id";
+const USB_DISK_PREFIX: &str = "usb-";
+
+const PCI_DEVICE_DIR: &str = "bus/pci/devices";
+const PCI_RESCAN: &str = "bus/pci/rescan";
+const PCI_CLASS_DISPLAY: &str = "0x03";
+
+
+/// Mount points of the pseudo file systems used by the built-in steps.
+#[derive(Debug, Clone)]
+struct Roots {
+    sys: PathBuf,
+    dev: PathBuf,
+    proc: PathBuf,
+}
+
+impl Default for Roots {
+    fn default() -> Self {
+        Roots {
+            sys: "/sys".into(),
+            dev: "/dev".into(),
+            proc: "/proc".into(),
+        }
+    }
+}
+
+impl Roots {
+    /// Resolve an absolute sysfs path, e.g. from the config, below our sysfs
+    /// root.
+    fn sys_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix("/sys") {
+            Ok(rel) => self.sys.join(rel),
+            Err(_) => path.to_owned(),
+        }
+    }
+}
+
+
+/// Built-in detachment actions, run when no detachment handler is specified.
+///
+/// Synchronizes all file systems, flushes the buffer caches of USB block
+/// devices, and unbinds the configured devices from their drivers.
+pub async fn detach(session: SessionId, config: &BuiltinDetach) -> Result<()> {
+    let config = config.clone();
+
+    tokio::task::spawn_blocking(move || {
+        run_detach(&Roots::default(), session, &config)
+    }).await.context("Built-in detachment handler failed")?
+}
+
+fn run_detach(root: &Roots, session: SessionId, config: &BuiltinDetach) -> Result<()> {
+    debug!(target: "sdtxd::proc", %session, "built-in: synchronizing file systems");
+    sync();
+
+    debug!(target: "sdtxd::proc", %session, "built-in: flushing USB block device caches");
+    flush_usb_disks(root, session);
+
+    for path in &config.unbind {
+        debug!(target: "sdtxd::proc", %session, ?path, "built-in: unbinding device");
+        unbind(&root.sys_path(path))
+            .with_context(|| format!("Failed to unbind device (path: {path:?})"))?;
+    }
+
+    Ok(())
+}
+
+fn sync() {
+    // SAFETY: sync() has no preconditions and cannot fail.
+    unsafe { libc::sync() }
+}
+
+fn flush_usb_disks(root: &Roots, session: SessionId) {
+    let disks = match usb_disks(root) {
+        Ok(disks) => disks,
+        Err(err) => {
+            trace!(target: "sdtxd::proc", %session, error=%err, "built-in: no disks found");
+            return;
+        },
+    };
+
+    for path in disks {
+        // fsync() on a block device flushes its buffer cache
+        let result = std::fs::File::open(&path).and_then(|f| f.sync_all());
+
+        if let Err(err) = result {
+            warn!(target: "sdtxd::proc", %session, ?path, error=%err,
+                  "built-in: failed to flush block device");
+        }
+    }
+}
+
+/// Links to all USB block devices (including partitions), sorted by name.
+fn usb_disks(root: &Roots) -> std::io::Result<Vec<PathBuf>> {
+    let mut disks: Vec<_> = std::fs::read_dir(root.dev.join(USB_DISK_DIR))?
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(USB_DISK_PREFIX))
+        .map(|entry| entry.path())
+        .collect();
+
+    disks.sort();
+    Ok(disks)
+}
+
+fn unbind(path: &Path) -> Result<()> {
+    let name = path.file_name()
+        .context("Invalid device path")?;
+
+    let path = path.join("driver").join("unbind");
+    if !path.exists() {
+        // no driver bound, nothing to do
+        return Ok(());
+    }
+
+    std::fs::write(&path, name.to_string_lossy().as_bytes())?;
+    Ok(())
+}
+
+
+/// A discrete GPU bound to one of the configured drivers.
+#[derive(Debug)]
+struct Dgpu {
+    path: PathBuf,
+    driver: String,
+}
+
+/// Unbind and remove the dGPU(s) in the base before detaching it.
+///
+/// Fails without changing anything if the dGPU is still in use, i.e. if any
+/// process holds one of its device nodes open.
+pub async fn dgpu_detach(session: SessionId, config: &DgpuStep) -> Result<()> {
+    let config = config.clone();
+
+    tokio::task::spawn_blocking(move || {
+        remove_dgpus(&Roots::default(), session, &config)
+    }).await.context("Built-in dGPU detachment step failed")?
+}
+
+/// Rescan the PCI bus after attaching the base, so that the dGPU is
+/// re-discovered and bound to its driver.
+pub async fn dgpu_attach(session: SessionId) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        debug!(target: "sdtxd::proc", %session, "built-in: rescanning PCI bus for dGPU");
+
+        std::fs::write(Roots::default().sys.join(PCI_RESCAN), "1")
+            .context("Failed to rescan PCI bus")
+    }).await.context("Built-in dGPU attachment step failed")?
+}
+
+fn remove_dgpus(root: &Roots, session: SessionId, config: &DgpuStep) -> Result<()> {
+    let port = config.port.as_deref()
+        .context("No PCIe port of the dGPU configured")?;
+
+    let gpus = find_dgpus(root, port, &config.drivers)?;
+
+    if gpus.is_empty() {
+        debug!(target: "sdtxd::proc", %session, %port, "built-in: no dGPU found");
+        return Ok(());
+    }
+
+    // check for users first, so we don't end up with a partially removed set
+    for gpu in &gpus {
+        if let Some((pid, comm)) = find_user(root, gpu)? {
+            bail!("dGPU {} is in use by {} (pid {})", gpu.name(), comm, pid);
+        }
+    }
+
+    for gpu in &gpus {
+        debug!(target: "sdtxd::proc", %session, device=%gpu.name(), driver=%gpu.driver,
+               "built-in: removing dGPU");
+
+        std::fs::write(gpu.path.join("driver").join("unbind"), gpu.name())
+            .with_context(|| format!("Failed to unbind dGPU {}", gpu.name()))?;
+
+        std::fs::write(gpu.path.join("remove"), "1")
+            .with_context(|| format!("Failed to remove dGPU {}", gpu.name()))?;
+    }
+
+    Ok(())
+}
+
+impl Dgpu {
+    fn name(&self) -> String {
+        self.path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    /// Device nodes provided by this GPU.
+    fn nodes(&self, root: &Roots) -> Vec<PathBuf> {
+        let mut nodes = Vec::new();
+
+        // DRM nodes, e.g. /dev/dri/card1 and /dev/dri/renderD129
+        if let Ok(entries) = std::fs::read_dir(self.path.join("drm")) {
+            for entry in entries.flatten() {
+                nodes.push(root.dev.join("dri").join(entry.file_name()));
+            }
+        }
+
+        // the proprietary nvidia driver provides global /dev/nvidia* nodes
+        if self.driver == "nvidia" {
+            if let Ok(entries) = std::fs::read_dir(&root.dev) {
+                for entry in entries.flatten() {
+                    if entry.file_name().to_string_lossy().starts_with("nvidia") {
+                        nodes.push(entry.path());
+                    }
+                }
+            }
+        }
+
+        nodes
+    }
+}
+
+/// Find all display devices bound to one of the given drivers that are
+/// located behind the given PCIe port.
+///
+/// Only considering devices behind the port of the base ensures that we never
+/// pick up an integrated GPU, which may be bound to the same driver (e.g. on
+/// AMD-based devices).
+fn find_dgpus(root: &Roots, port: &str, drivers: &[String]) -> Result<Vec<Dgpu>> {
+    let entries = std::fs::read_dir(root.sys.join(PCI_DEVICE_DIR))
+        .context("Failed to enumerate PCI devices")?;
+
+    let mut gpus = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        // entries link to the device in the PCI hierarchy, e.g.
+        // /sys/devices/pci0000:00/0000:00:1c.0/0000:02:00.0
+        let real = match std::fs::canonicalize(&path) {
+            Ok(real) => real,
+            Err(_) => continue,
+        };
+
+        if !real.ancestors().any(|p| p.file_name().map(|n| n == port).unwrap_or(false)) {
+            continue;
+        }
+
+        let class = std::fs::read_to_string(path.join("class")).unwrap_or_default();
+        if !class.starts_with(PCI_CLASS_DISPLAY) {
+            continue;
+        }
+
+        let driver = match std::fs::read_link(path.join("driver")) {
+            Ok(link) => link.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            Err(_) => continue,     // no driver bound
+        };
+
+        if drivers.contains(&driver) {
+            gpus.push(Dgpu { path, driver });
+        }
+    }
+
+    Ok(gpus)
+}
+
+/// Find a process holding one of the device nodes of the given GPU open.
+fn find_user(root: &Roots, gpu: &Dgpu) -> Result<Option<(u32, String)>> {
+    let nodes = gpu.nodes(root);
+    if nodes.is_empty() {
+        return Ok(None);
+    }
+
+    let own = std::process::id();
+    let procs = std::fs::read_dir(&root.proc).context("Failed to enumerate processes")?;
+
+    for proc in procs.flatten() {
+        let pid = match proc.file_name().to_string_lossy().parse::<u32>() {
+            Ok(pid) if pid != own => pid,
+            _ => continue,
+        };
+
+        // processes may exit or deny access while we look at them
+        let fds = match std::fs::read_dir(proc.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+
+        for fd in fds.flatten() {
+            let target = match std::fs::read_link(fd.path()) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+
+            if nodes.contains(&target) {
+                let comm = std::fs::read_to_string(proc.path().join("comm")).unwrap_or_default();
+                return Ok(Some((pid, comm.trim().to_owned())));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::os::unix::fs::symlink;
+
+    const PORT: &str = "0000:00:1c.0";
+
+    fn roots(dir: &Path) -> Roots {
+        Roots {
+            sys: dir.join("sys"),
+            dev: dir.join("dev"),
+            proc: dir.join("proc"),
+        }
+    }
+
+    struct Fake {
+        _dir: tempfile::TempDir,
+        root: Roots,
+    }
+
+    impl Fake {
+        /// Set up a fake sysfs with an AMD iGPU on the root bus and a dGPU
+        /// (plus its audio function) behind the PCIe port of the base.
+        fn new(dgpu_driver: &str) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let root = roots(dir.path());
+
+            std::fs::create_dir_all(root.sys.join(PCI_DEVICE_DIR)).unwrap();
+            std::fs::create_dir_all(root.dev.join("dri")).unwrap();
+            std::fs::create_dir_all(&root.proc).unwrap();
+
+            let fake = Fake { _dir: dir, root };
+            fake.device("pci0000:00/0000:00:02.0", "0x030000", Some("amdgpu"), &["card0"]);
+            fake.device("pci0000:00/0000:00:1c.0", "0x060400", Some("pcieport"), &[]);
+            fake.device("pci0000:00/0000:00:1c.0/0000:02:00.0", "0x030200", Some(dgpu_driver),
+                        &["card1", "renderD129"]);
+            fake.device("pci0000:00/0000:00:1c.0/0000:02:00.1", "0x040300", Some("snd_hda_intel"),
+                        &[]);
+            fake
+        }
+
+        fn device(&self, path: &str, class: &str, driver: Option<&str>, drm: &[&str]) {
+            let real = self.root.sys.join("devices").join(path);
+            std::fs::create_dir_all(&real).unwrap();
+            std::fs::write(real.join("class"), format!("{}\n", class)).unwrap();
+
+            if let Some(driver) = driver {
+                let drv = self.root.sys.join("bus/pci/drivers").join(driver);
+                std::fs::create_dir_all(&drv).unwrap();
+                symlink(&drv, real.join("driver")).unwrap();
+            }
+
+            for node in drm {
+                std::fs::create_dir_all(real.join("drm").join(node)).unwrap();
+            }
+
+            let name = real.file_name().unwrap();
+            symlink(&real, self.root.sys.join(PCI_DEVICE_DIR).join(name)).unwrap();
+        }
+
+        fn open(&self, pid: u32, comm: &str, node: &Path) {
+            let proc = self.root.proc.join(pid.to_string());
+            std::fs::create_dir_all(proc.join("fd")).unwrap();
+            std::fs::write(proc.join("comm"), format!("{}\n", comm)).unwrap();
+            symlink(node, proc.join("fd").join("3")).unwrap();
+        }
+
+        fn config(&self, port: &str, drivers: &[&str]) -> DgpuStep {
+            DgpuStep {
+                enabled: true,
+                port: Some(port.into()),
+                drivers: drivers.iter().map(|d| d.to_string()).collect(),
+            }
+        }
+
+        fn read(&self, path: &str) -> String {
+            std::fs::read_to_string(self.root.sys.join(path)).unwrap_or_default()
+        }
+    }
+
+    fn names(gpus: &[Dgpu]) -> Vec<String> {
+        gpus.iter().map(Dgpu::name).collect()
+    }
+
+    #[test]
+    fn discovery_ignores_igpu() {
+        let fake = Fake::new("amdgpu");
+        let drivers = defaults();
+
+        let gpus = find_dgpus(&fake.root, PORT, &drivers).unwrap();
+        assert_eq!(names(&gpus), ["0000:02:00.0"]);
+        assert_eq!(gpus[0].driver, "amdgpu");
+
+        // the dGPU can also be specified directly
+        let gpus = find_dgpus(&fake.root, "0000:02:00.0", &drivers).unwrap();
+        assert_eq!(names(&gpus), ["0000:02:00.0"]);
+
+        // nothing behind an unrelated port
+        let gpus = find_dgpus(&fake.root, "0000:00:1d.0", &drivers).unwrap();
+        assert!(gpus.is_empty());
+
+        // only devices bound to the configured drivers
+        let gpus = find_dgpus(&fake.root, PORT, &["nvidia".to_string()]).unwrap();
+        assert!(gpus.is_empty());
+    }
+
+    #[test]
+    fn in_use_drm() {
+        let fake = Fake::new("amdgpu");
+        let gpus = find_dgpus(&fake.root, PORT, &defaults()).unwrap();
+
+        // users of the iGPU do not matter
+        fake.open(100, "gnome-shell", &fake.root.dev.join("dri/card0"));
+        assert_eq!(find_user(&fake.root, &gpus[0]).unwrap(), None);
+
+        fake.open(200, "glxgears", &fake.root.dev.join("dri/renderD129"));
+        assert_eq!(find_user(&fake.root, &gpus[0]).unwrap(), Some((200, "glxgears".into())));
+    }
+
+    #[test]
+    fn in_use_nvidia() {
+        let fake = Fake::new("nvidia");
+        std::fs::write(fake.root.dev.join("nvidiactl"), "").unwrap();
+
+        let gpus = find_dgpus(&fake.root, PORT, &defaults()).unwrap();
+        assert_eq!(find_user(&fake.root, &gpus[0]).unwrap(), None);
+
+        fake.open(300, "nvidia-smi", &fake.root.dev.join("nvidiactl"));
+        assert_eq!(find_user(&fake.root, &gpus[0]).unwrap(), Some((300, "nvidia-smi".into())));
+    }
+
+    #[test]
+    fn remove() {
+        let fake = Fake::new("amdgpu");
+        let config = fake.config(PORT, &["amdgpu"]);
+
+        // in use: nothing is changed
+        fake.open(200, "glxgears", &fake.root.dev.join("dri/card1"));
+        let err = remove_dgpus(&fake.root, SessionId::default(), &config).unwrap_err();
+        assert_eq!(err.to_string(), "dGPU 0000:02:00.0 is in use by glxgears (pid 200)");
+        assert_eq!(fake.read("bus/pci/drivers/amdgpu/unbind"), "");
+        assert_eq!(fake.read("devices/pci0000:00/0000:00:1c.0/0000:02:00.0/remove"), "");
+
+        // not in use: only the dGPU is unbound and removed
+        std::fs::remove_dir_all(fake.root.proc.join("200")).unwrap();
+        remove_dgpus(&fake.root, SessionId::default(), &config).unwrap();
+        assert_eq!(fake.read("bus/pci/drivers/amdgpu/unbind"), "0000:02:00.0");
+        assert_eq!(fake.read("devices/pci0000:00/0000:00:1c.0/0000:02:00.0/remove"), "1");
+        assert_eq!(fake.read("devices/pci0000:00/0000:00:02.0/remove"), "");
+    }
+
+    #[test]
+    fn remove_requires_port() {
+        let fake = Fake::new("amdgpu");
+        let config = DgpuStep { port: None, ..fake.config(PORT, &["amdgpu"]) };
+
+        assert!(remove_dgpus(&fake.root, SessionId::default(), &config).is_err());
+        assert_eq!(fake.read("bus/pci/drivers/amdgpu/unbind"), "");
+    }
+
+    #[test]
+    fn usb_disk_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = roots(dir.path());
+
+        // no disks at all
+        assert!(usb_disks(&root).is_err());
+
+        let ids = root.dev.join(USB_DISK_DIR);
+        std::fs::create_dir_all(&ids).unwrap();
+
+        for (link, dev) in [("usb-Generic_Flash-0:0", "sdb"), ("usb-Generic_Flash-0:0-part1", "sdb1"),
+                            ("ata-Samsung_SSD", "sda"), ("nvme-eui.0025385", "nvme0n1")] {
+            std::fs::write(root.dev.join(dev), "").unwrap();
+            symlink(Path::new("../..").join(dev), ids.join(link)).unwrap();
+        }
+
+        assert_eq!(usb_disks(&root).unwrap(), [
+            ids.join("usb-Generic_Flash-0:0"),
+            ids.join("usb-Generic_Flash-0:0-part1"),
+        ]);
+    }
+
+    #[test]
+    fn unbind_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = roots(dir.path());
+
+        let devices = root.sys.join("bus/usb/devices");
+        let drivers = root.sys.join("bus/usb/drivers");
+
+        for (dev, driver) in [("1-1", Some("usb")), ("1-2", None), ("2-1", Some("broken")), ("3-1", Some("uas"))] {
+            std::fs::create_dir_all(devices.join(dev)).unwrap();
+
+            if let Some(driver) = driver {
+                std::fs::create_dir_all(drivers.join(driver)).unwrap();
+                symlink(drivers.join(driver), devices.join(dev).join("driver")).unwrap();
+            }
+        }
+
+        std::fs::write(drivers.join("usb/unbind"), "").unwrap();
+        std::fs::write(drivers.join("uas/unbind"), "").unwrap();
+
+        // writing to the unbind attribute of this driver fails
+        std::fs::create_dir_all(drivers.join("broken/unbind")).unwrap();
+
+        let config = BuiltinDetach {
+            enabled: true,
+            unbind: ["1-1", "1-2", "2-1", "3-1"].iter()
+                .map(|dev| Path::new("/sys/bus/usb/devices").join(dev))
+                .collect(),
+        };
+
+        // devices are unbound in order, stopping at the first failure
+        let err = run_detach(&root, SessionId::default(), &config).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to unbind device (path: \"/sys/bus/usb/devices/2-1\")");
+        assert_eq!(std::fs::read_to_string(drivers.join("usb/unbind")).unwrap(), "1-1");
+        assert_eq!(std::fs::read_to_string(drivers.join("uas/unbind")).unwrap(), "");
+
+        let config = BuiltinDetach {
+            unbind: config.unbind.into_iter().filter(|p| !p.ends_with("2-1")).collect(),
+            ..config
+        };
+
+        run_detach(&root, SessionId::default(), &config).unwrap();
+        assert_eq!(std::fs::read_to_string(drivers.join("uas/unbind")).unwrap(), "3-1");
+    }
+
+    fn defaults() -> Vec<String> {
+        DgpuStep::default().drivers
+    }
+}
@@ -0,0 +1,183 @@
+use crate::logic::{
+    BaseInfo,
+    BaseState,
+    CancelReason,
+    DeviceMode,
+    DeviceType,
+    HardwareError,
+    RuntimeError,
+    SessionId,
+};
+
+use serde::Serialize;
+
+
+/// Context information passed to handlers as JSON document via stdin.
+#[derive(Debug, Clone, Serialize)]
+pub struct HandlerContext {
+    pub event: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<u64>,
+
+    pub base: BaseContext,
+    pub device_mode: &'static str,
+    pub cancel_reason: Option<String>,
+    pub timeout: f32,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BaseContext {
+    pub state: &'static str,
+
+    #[serde(rename = "type")]
+    pub device_type: String,
+
+    pub id: u8,
+}
+
+impl HandlerContext {
+    pub fn new(event: &'static str, session: Option<SessionId>, base: BaseInfo,
+               mode: DeviceMode, reason: Option<CancelReason>, timeout: f32, dry_run: bool)
+        -> Self
+    {
+        HandlerContext {
+            event,
+            session: session.map(SessionId::value),
+            base: BaseContext {
+                state: base_state_str(base.state),
+                device_type: device_type_str(base.device_type),
+                id: base.id,
+            },
+            device_mode: device_mode_str(mode),
+            cancel_reason: reason.map(cancel_reason_str),
+            timeout,
+            dry_run,
+        }
+    }
+
+    /// Environment variables describing this context.
+    ///
+    /// Variables that do not apply to the current event (e.g. the cancel
+    /// reason for attachment handlers) are set to an empty string.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("SDTX_EVENT", self.event.into()),
+            ("SDTX_SESSION_ID", self.session.map(|s| s.to_string()).unwrap_or_default()),
+            ("SDTX_BASE_STATE", self.base.state.into()),
+            ("SDTX_BASE_TYPE", self.base.device_type.clone()),
+            ("SDTX_BASE_ID", self.base.id.to_string()),
+            ("SDTX_DEVICE_MODE", self.device_mode.into()),
+            ("SDTX_CANCEL_REASON", self.cancel_reason.clone().unwrap_or_default()),
+            ("SDTX_DRY_RUN", if self.dry_run { "1" } else { "0" }.into()),
+        ]
+    }
+
+    /// Expand placeholders of the form `{name}` in the given template,
+    /// e.g. `{base_id}`. Unknown placeholders are kept as-is, `{{` and `}}`
+    /// can be used to insert literal braces.
+    pub fn expand(&self, template: &str) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(pos) = rest.find(['{', '}']) {
+            out.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                out.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+
+            let value = rest.strip_prefix('{')
+                .and_then(|r| r.find('}').map(|end| &r[..end]))
+                .and_then(|name| self.var(name).map(|value| (name, value)));
+
+            match value {
+                Some((name, value)) => {
+                    out.push_str(&value);
+                    rest = &rest[name.len() + 2..];
+                },
+                None => {
+                    out.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                },
+            }
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "event"       => self.event.into(),
+            "session"     => self.session.map(|s| s.to_string()).unwrap_or_default(),
+            "base_state"  => self.base.state.into(),
+            "base_type"   => self.base.device_type.clone(),
+            "base_id"     => self.base.id.to_string(),
+            "device_mode" => self.device_mode.into(),
+            "reason"      => self.cancel_reason.clone().unwrap_or_default(),
+            "timeout"     => self.timeout.to_string(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        // serialization of this struct cannot fail
+        let mut data = serde_json::to_vec(self).unwrap();
+        data.push(b'\n');
+        data
+    }
+}
+
+
+pub fn base_state_str(state: BaseState) -> &'static str {
+    match state {
+        BaseState::Detached    => "detached",
+        BaseState::Attached    => "attached",
+        BaseState::NotFeasible => "not-feasible",
+    }
+}
+
+pub fn device_type_str(ty: DeviceType) -> String {
+    match ty {
+        DeviceType::Hid => "hid".into(),
+        DeviceType::Ssh => "ssh".into(),
+        DeviceType::Unknown(x) => format!("unknown:{x}"),
+    }
+}
+
+pub fn device_mode_str(mode: DeviceMode) -> &'static str {
+    match mode {
+        DeviceMode::Tablet => "tablet",
+        DeviceMode::Laptop => "laptop",
+        DeviceMode::Studio => "studio",
+    }
+}
+
+pub fn cancel_reason_str(reason: CancelReason) -> String {
+    match reason {
+        CancelReason::UserRequest             => "request".into(),
+        CancelReason::HandlerTimeout          => "timeout:handler".into(),
+        CancelReason::HandlerSpawnFailed      => "error:handler:spawn".into(),
+        CancelReason::DisconnectTimeout       => "timeout:disconnect".into(),
+        CancelReason::Runtime(rt) => match rt {
+            RuntimeError::NotAttached         => "error:runtime:not-attached".into(),
+            RuntimeError::NotFeasible         => "error:runtime:not-feasible".into(),
+            RuntimeError::Timeout             => "error:runtime:timeout".into(),
+            RuntimeError::Unknown(x)  => format!("error:runtime:unknown:{x}"),
+        },
+        CancelReason::Hardware(hw) => match hw {
+            HardwareError::FailedToOpen       => "error:hardware:failed-to-open".into(),
+            HardwareError::FailedToRemainOpen => "error:hardware:failed-to-remain-open".into(),
+            HardwareError::FailedToClose      => "error:hardware:failed-to-close".into(),
+            HardwareError::Unknown(x) => format!("error:hardware:unknown:{x}"),
+        },
+        CancelReason::Unknown(x) => format!("unknown:{x}"),
+    }
+}
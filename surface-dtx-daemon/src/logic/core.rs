@@ -1,28 +1,54 @@
+use crate::device::{self, BaseBattery, BaseFirmware, BatteryInfo, DeviceError, DtxDevice, EventRecorder, Snapshot};
 use crate::logic::{
     BaseInfo,
     BaseState,
     CancelReason,
     DeviceMode,
     DeviceType,
+    DryRun,
+    ForceRequest,
+    HandlerStatus,
     HardwareError,
     LatchState,
+    LastEvent,
     LatchStatus,
     RuntimeError,
+    SessionId,
 };
+use crate::utils::ratelimit::RateLimit;
 
 use std::convert::TryFrom;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use futures::prelude::*;
 
 use sdtx::event;
-use sdtx_tokio::Device;
 
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument, Level, Span};
+use tracing::span::EnteredSpan;
+
+
+/// Maximum time to wait for a latch status event after a cancellation request
+/// has been received for an already confirmed detachment.
+const CANCEL_SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Interval and number of attempts for re-opening the DTX device after it has
+/// been disconnected, e.g. due to the driver being re-bound.
+const REOPEN_INTERVAL: Duration = Duration::from_millis(500);
+const REOPEN_ATTEMPTS: u32 = 20;
+
+/// Delay after attachment before reading the firmware information of the
+/// base, giving its HID devices time to be set up.
+const FIRMWARE_READ_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval in which repetitions of the same hardware error are only logged
+/// once.
+const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(60);
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -32,17 +58,52 @@ enum Event {
     DetachConfirm,
     DetachCancel,
     DetachTimeout,
+    DetachSpawnFailed,
+    DetachSlow {
+        session: SessionId,
+    },
+
+    AttachComplete {
+        session: SessionId,
+    },
+    AttachTimeout {
+        session: SessionId,
+    },
+
+    CancelComplete {
+        session: SessionId,
+    },
+    CancelTimeout {
+        session: SessionId,
+    },
+
+    CancelSyncTimeout {
+        seq: u32,
+    },
+
+    PrepareForSleep {
+        active: bool,
+    },
 
-    AttachComplete,
-    AttachTimeout,
+    HandlerStatus {
+        session: Option<SessionId>,
+        status: HandlerStatus,
+    },
 
-    CancelComplete,
-    CancelTimeout,
+    PostureComplete {
+        mode: DeviceMode,
+    },
+    PostureTimeout {
+        mode: DeviceMode,
+    },
 
     Cancel {
         reason: event::CancelReason,
     },
 
+    BaseBattery,
+    BaseFirmware,
+
     BaseConnection {
         state: event::BaseState,
         device_type: DeviceType,
@@ -107,56 +168,139 @@ enum RuntimeState {
 #[derive(Debug)]
 struct CoreState {
     base:  Trace<BaseState>,
+    battery: Trace<Option<BatteryInfo>>,
+    firmware: Trace<Option<BaseFirmware>>,
     latch: Trace<LatchState>,
     mode:  Trace<DeviceMode>,
     ec:    Trace<EcState>,
     rt:    Trace<RuntimeState>,
     needs_attachment: Trace<bool>,
+    cancel_sync: Trace<Option<u32>>,
+    session: Trace<SessionId>,
+    suspended: Trace<bool>,
+    attach_on_resume: Trace<bool>,
 }
 
-pub struct Core<A> {
-    device: Arc<Device>,
+pub struct Core<D, A> {
+    device: Arc<D>,
+    battery: Option<BaseBattery>,
+    recorder: Option<EventRecorder>,
     inject_rx: UnboundedReceiver<Event>,
     inject_tx: UnboundedSender<Event>,
     state: CoreState,
     adapter: A,
+    cancel_sync_seq: u32,
+    dry_run: DryRun,
+    errors: RateLimit,
+    base_id: (DeviceType, u8),
+    span: Span,
+    last_event: LastEvent,
+    force: ForceRequest,
 }
 
-impl<A: Adapter> Core<A> {
-    pub fn new(device: Device, adapter: A) -> Self {
+impl<D: DtxDevice + 'static, A: Adapter> Core<D, A> {
+    pub fn new(device: D, battery: Option<BaseBattery>, recorder: Option<EventRecorder>, adapter: A,
+               dry_run: DryRun, last_event: LastEvent, force: ForceRequest) -> Self {
         let state = CoreState {
             base:  Trace::new("state.base", BaseState::Attached),
+            battery: Trace::new("state.battery", None),
+            firmware: Trace::new("state.firmware", None),
             latch: Trace::new("state.latch", LatchState::Closed),
             mode:  Trace::new("state.mode", DeviceMode::Laptop),
             ec:    Trace::new("state.ec", EcState::Ready),
             rt:    Trace::new("state.rt", RuntimeState::Ready),
             needs_attachment: Trace::new("state.needs_attachment", false),
+            cancel_sync: Trace::new("state.cancel_sync", None),
+            session: Trace::new("state.session", SessionId::default()),
+            suspended: Trace::new("state.suspended", false),
+            attach_on_resume: Trace::new("state.attach_on_resume", false),
         };
 
         let device = Arc::new(device);
         let (inject_tx, inject_rx) = tokio::sync::mpsc::unbounded_channel();
 
-        Self { device, inject_rx, inject_tx, state, adapter }
+        Self {
+            device,
+            battery,
+            recorder,
+            inject_rx,
+            inject_tx,
+            state,
+            adapter,
+            cancel_sync_seq: 0,
+            dry_run,
+            errors: RateLimit::new(ERROR_LOG_INTERVAL),
+            base_id: (DeviceType::Unknown(0), 0),
+            span: Span::none(),
+            last_event,
+            force,
+        }
+    }
+
+    pub fn sleep_handle(&self) -> SleepHandle {
+        SleepHandle {
+            inject: self.inject_tx.clone(),
+        }
+    }
+
+    pub fn inject_handle(&self) -> InjectHandle {
+        InjectHandle {
+            inject: self.inject_tx.clone(),
+        }
     }
 
     pub async fn run(&mut self) -> Result<()> {
-        let mut evdev = Device::from(self.device.file().try_clone().await?);
+        let mut evdev = self.device.try_clone().await?;
+
+        // If the device gets disconnected, wait for it to re-appear, then
+        // re-enable events and re-synchronize our state. Any detachment or
+        // attachment in progress is lost at that point.
+        loop {
+            match self.run_events(&mut evdev).await {
+                Err(err) if device::is_disconnected(&err) => {
+                    warn!(target: "sdtxd::core", "DTX device disconnected: {:#}", err);
+                    self.reopen(&evdev).await?;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    async fn reopen(&self, evdev: &D) -> Result<()> {
+        for attempt in 1..=REOPEN_ATTEMPTS {
+            tokio::time::sleep(REOPEN_INTERVAL).await;
 
+            match evdev.reopen().and_then(|_| self.device.reopen()) {
+                Ok(()) => {
+                    info!(target: "sdtxd::core", "DTX device re-opened");
+                    return Ok(());
+                },
+                Err(err) => {
+                    debug!(target: "sdtxd::core", attempt, "failed to re-open DTX device: {:#}", err);
+                },
+            }
+        }
+
+        bail!("DTX device did not re-appear after being disconnected")
+    }
+
+    async fn run_events(&mut self, evdev: &mut D) -> Result<()> {
         // enable events
         trace!(target: "sdtxd::core", "enabling events");
 
-        let mut events = evdev.events_async()
-            .context("DTX device error")?
-            .map(|r| r.map(Event::from));
+        let mut events = evdev.events()
+            .map_err(device_error)?;
 
         // Update our state before we start handling events but after we've
         // enabled them. This way, we can ensure that we don't miss any
         // events/changes and accidentally set a stale state.
         trace!(target: "sdtxd::core", "updating state");
 
-        let base = self.device.get_base_info().context("DTX device error")?;
-        let latch = self.device.get_latch_status().context("DTX device error")?;
-        let mode = self.device.get_device_mode().context("DTX device error")?;
+        let Snapshot { base, latch, mode } = self.device.snapshot().map_err(device_error)?;
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_state(base, latch, mode);
+        }
 
         let latch = match latch {
             LatchStatus::Closed => LatchState::Closed,
@@ -169,11 +313,26 @@ impl<A: Adapter> Core<A> {
             LatchState::Opened => EcState::Confirmed,
         };
 
+        let battery = self.read_battery();
+        let firmware = match base.state {
+            BaseState::Attached => BaseFirmware::read(),
+            _ => None,
+        };
+
+        if let Some(fw) = &firmware {
+            info!(target: "sdtxd::core", version=?fw.version, serial=?fw.serial, "base: firmware {}", fw);
+        }
+
+        let base = BaseInfo { battery, firmware: firmware.clone(), ..BaseInfo::from(base) };
+
         self.state.base.set(base.state);
+        self.state.battery.set(battery);
+        self.state.firmware.set(firmware);
         self.state.latch.set(latch);
         self.state.mode.set(mode);
         self.state.ec.set(ec);
         self.state.rt.set(RuntimeState::Ready);
+        self.base_id = (base.device_type, base.id);
 
         self.adapter.set_state(mode, base, latch);
 
@@ -183,13 +342,33 @@ impl<A: Adapter> Core<A> {
             let event = tokio::select! {
                 event = self.inject_rx.recv() => event,
                 event = events.next() => {
-                    event.map_or(Ok(None), |r| r.map(Some))
-                        .context("DTX device error")?
+                    let event = event.map_or(Ok(None), |r| r.map(Some))
+                        .map_err(device_error)?;
+
+                    // record raw events only, i.e. not injected ones
+                    if let (Some(recorder), Some(event)) = (&mut self.recorder, &event) {
+                        recorder.record(event);
+                    }
+
+                    if event.is_some() {
+                        self.last_event.update();
+                    }
+
+                    event.map(Event::from)
+                },
+                _ = battery_changed(&mut self.battery) => {
+                    Some(Event::BaseBattery)
                 },
             };
 
+            // events during a detachment or attachment belong to its session
+            let span = match *self.state.rt {
+                RuntimeState::Ready => Span::none(),
+                _ => self.span.clone(),
+            };
+
             if let Some(event) = event {
-                self.handle(event).await?;
+                self.handle(event).instrument(span).await?;
             } else {
                 break;
             }
@@ -198,12 +377,16 @@ impl<A: Adapter> Core<A> {
         Ok(())
     }
 
+    fn read_battery(&self) -> Option<BatteryInfo> {
+        self.battery.as_ref().and_then(BaseBattery::read)
+    }
+
     async fn handle(&mut self, event: Event) -> Result<()> {
         trace!(target: "sdtxd::core", ?event, "received event");
 
         match event {
             Event::Request => {
-                self.on_request().await
+                self.on_request()
             },
             Event::DetachConfirm => {
                 self.on_detach_confirm()
@@ -214,21 +397,48 @@ impl<A: Adapter> Core<A> {
             Event::DetachTimeout => {
                 self.on_detach_timeout()
             },
-            Event::AttachComplete => {
-                self.on_attach_complete()
+            Event::DetachSpawnFailed => {
+                self.on_detach_spawn_failed()
+            },
+            Event::DetachSlow { session } => {
+                self.on_detach_slow(session)
+            },
+            Event::AttachComplete { session } => {
+                self.on_attach_complete(session)
+            },
+            Event::AttachTimeout { session } => {
+                self.on_attach_timeout(session)
             },
-            Event::AttachTimeout => {
-                self.on_attach_timeout()
+            Event::CancelComplete { session } => {
+                self.on_cancel_complete(session)
             },
-            Event::CancelComplete => {
-                self.on_cancel_complete()
+            Event::CancelTimeout { session } => {
+                self.on_cancel_timeout(session)
             },
-            Event::CancelTimeout => {
-                self.on_cancel_timeout()
+            Event::CancelSyncTimeout { seq } => {
+                self.on_cancel_sync_timeout(seq)
+            },
+            Event::PrepareForSleep { active } => {
+                self.on_prepare_for_sleep(active).await
+            },
+            Event::HandlerStatus { session, status } => {
+                self.on_handler_status(session, status)
+            },
+            Event::PostureComplete { mode } => {
+                self.on_posture_complete(mode)
+            },
+            Event::PostureTimeout { mode } => {
+                self.on_posture_timeout(mode)
             },
             Event::Cancel { reason } => {
                 self.on_cancel(reason)
             },
+            Event::BaseBattery => {
+                self.on_base_battery()
+            },
+            Event::BaseFirmware => {
+                self.on_base_firmware()
+            },
             Event::BaseConnection { state, device_type, id } => {
                 self.on_base_state(state, device_type, id)
             },
@@ -239,57 +449,56 @@ impl<A: Adapter> Core<A> {
                 self.on_device_mode(mode)
             },
             Event::Unknown { code, data } => {
-                warn!(target: "sdtxd::core", code, ?data, "unhandled event");
+                event_limited!(self.errors, format!("unhandled event {code}"),
+                               target: "sdtxd::core", Level::WARN, code, ?data, "unhandled event");
                 Ok(())
             },
         }
     }
 
-    async fn on_request(&mut self) -> Result<()> {
+    fn on_request(&mut self) -> Result<()> {
+        // A forced request only applies to the request it has been issued
+        // with. Consume it here so that it does not carry over to a later
+        // request if this one does not start a detachment.
+        let force = self.force.take();
+
         // handle cancellation signals
         if *self.state.ec != EcState::Ready {
             if *self.state.latch == LatchState::Opened {
                 // if latch is open, defer cancellation until latch is closed
                 // again
                 debug!(target: "sdtxd::core", "request: deferring cancellation until latch closes");
-                return self.adapter.detachment_cancel(CancelReason::UserRequest);
+                return self.adapter.detachment_cancel(*self.state.session, CancelReason::UserRequest);
 
             } else if *self.state.ec == EcState::Confirmed {
                 // If we have requested the EC to open the latch, we have two
                 // possibilities: Either, the latch will open momentarily, or
                 // we already had a cancel request event queued before we sent
                 // the confirm signal, and the latch will never open. To
-                // determine which case we are in, wait 2 seconds and check if
-                // the latch is closed. Note that this shouldn't cause any
-                // issues with lost-update problems as we are essentially
-                // blocking the event handler.
+                // determine which case we are in, wait for the next latch
+                // status event. If none arrives within a bounded time, check
+                // the latch status directly. We do not block the event loop
+                // while waiting, the result is handled either in
+                // on_latch_status() or on_cancel_sync_timeout().
 
-                self.adapter.detachment_cancel(CancelReason::UserRequest)?;
+                self.adapter.detachment_cancel(*self.state.session, CancelReason::UserRequest)?;
 
-                debug!(target: "sdtxd::core", "request: sleeping 2s to prevent synchronization issues");
-                tokio::time::sleep(std::time::Duration::new(2, 0)).await;
+                debug!(target: "sdtxd::core", "request: waiting for latch status to prevent synchronization issues");
 
-                let status = self.device.get_latch_status().context("DTX device error")?;
-                if status != LatchStatus::Closed {
-                    debug!(target: "sdtxd::core", "request: deferring cancellation until latch closes");
-                    return Ok(());
-                }
-            }
+                self.cancel_sync_seq = self.cancel_sync_seq.wrapping_add(1);
+                self.state.cancel_sync.set(Some(self.cancel_sync_seq));
 
-            debug!(target: "sdtxd::core", "request: canceling current request");
-
-            self.state.ec.set(EcState::Ready);
+                let seq = self.cancel_sync_seq;
+                let inject = self.inject_tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(CANCEL_SYNC_TIMEOUT).await;
+                    let _ = inject.send(Event::CancelSyncTimeout { seq });
+                });
 
-            if *self.state.rt == RuntimeState::Detaching {
-                self.state.rt.set(RuntimeState::Canceling);
-
-                self.adapter.detachment_cancel(CancelReason::UserRequest)?;
-
-                let handle = DtcHandle { inject: self.inject_tx.clone() };
-                self.adapter.detachment_cancel_start(handle)?;
+                return Ok(());
             }
 
-            return Ok(());
+            return self.cancel_request();
         }
 
         // if this request is not for cancellation, mark us as in-progress
@@ -297,7 +506,8 @@ impl<A: Adapter> Core<A> {
 
         // if no base is attached (or not-feasible), cancel
         if *self.state.base != BaseState::Attached {
-            self.device.latch_cancel().context("DTX device error")?;
+            command(self.device.latch_cancel())?;
+            let _span = self.session_begin();
 
             let reason = match *self.state.base {
                 BaseState::NotFeasible => {
@@ -312,22 +522,201 @@ impl<A: Adapter> Core<A> {
             };
 
             // notify adapter
-            return self.adapter.request_inhibited(reason);
+            return self.adapter.request_inhibited(*self.state.session, reason);
         }
 
         // if there is already a detachment in progress, cancel
         if *self.state.rt != RuntimeState::Ready {
             debug!(target: "sdtxd::core", "request: already processing, canceling this request");
-            return self.device.latch_cancel().context("DTX device error")
+            return command(self.device.latch_cancel())
         }
 
         self.state.rt.set(RuntimeState::Detaching);
+        let _span = self.session_begin();
 
         // commence detachment
-        debug!(target: "sdtxd::core", "detachment requested");
+        debug!(target: "sdtxd::core", session=%*self.state.session, force, "detachment requested");
+
+        let handle = self.dt_handle(force);
+        self.adapter.detachment_start(*self.state.session, handle)
+    }
+
+    fn dt_handle(&self, force: bool) -> DtHandle {
+        DtHandle {
+            session: *self.state.session,
+            force,
+            device: self.device.clone(),
+            inject: self.inject_tx.clone(),
+        }
+    }
 
-        let handle = DtHandle { device: self.device.clone(), inject: self.inject_tx.clone() };
-        self.adapter.detachment_start(handle)
+    fn dtc_handle(&self) -> DtcHandle {
+        DtcHandle {
+            session: *self.state.session,
+            device: self.device.clone(),
+            inject: self.inject_tx.clone(),
+        }
+    }
+
+    fn at_handle(&self) -> AtHandle {
+        AtHandle {
+            session: *self.state.session,
+            device: self.device.clone(),
+            inject: self.inject_tx.clone(),
+        }
+    }
+
+    fn pc_handle(&self, mode: DeviceMode) -> PcHandle {
+        PcHandle {
+            mode,
+            inject: self.inject_tx.clone(),
+        }
+    }
+
+    /// Start a new detachment or attachment session. Returns the entered
+    /// span of the session, which is also used for all events handled until
+    /// the session has ended.
+    fn session_begin(&mut self) -> EnteredSpan {
+        let session = self.state.session.next();
+        self.state.session.set(session);
+
+        let (ty, id) = self.base_id;
+        self.span = info_span!(target: "sdtxd::core", parent: None, "session", session=%session,
+                               base.device_type=?ty, base.id=id);
+
+        self.span.clone().entered()
+    }
+
+    fn attachment_begin(&mut self) -> Result<()> {
+        self.state.rt.set(RuntimeState::Attaching);
+
+        // Don't run the attachment process into a system that is about to be
+        // suspended. Instead, keep it pending until the system has resumed.
+        if *self.state.suspended {
+            debug!(target: "sdtxd::core", "system is suspending, deferring attachment until resume");
+
+            self.state.attach_on_resume.set(true);
+            return Ok(());
+        }
+
+        let _span = self.session_begin();
+
+        debug!(target: "sdtxd::core", session=%*self.state.session, "starting attachment process");
+
+        let handle = self.at_handle();
+        self.adapter.attachment_start(*self.state.session, handle)
+    }
+
+    fn cancel_request(&mut self) -> Result<()> {
+        debug!(target: "sdtxd::core", session=%*self.state.session, "request: canceling current request");
+
+        self.state.ec.set(EcState::Ready);
+
+        if *self.state.rt == RuntimeState::Detaching {
+            self.state.rt.set(RuntimeState::Canceling);
+
+            self.adapter.detachment_cancel(*self.state.session, CancelReason::UserRequest)?;
+
+            let handle = self.dtc_handle();
+            self.adapter.detachment_cancel_start(*self.state.session, handle)?;
+        }
+
+        Ok(())
+    }
+
+    fn on_cancel_sync_timeout(&mut self, seq: u32) -> Result<()> {
+        // internal event, sent when no latch status event has been received
+        // after a cancellation request for a confirmed detachment
+        if *self.state.cancel_sync != Some(seq) {
+            trace!(target: "sdtxd::core", seq, "request: stale synchronization timeout, ignoring");
+            return Ok(());
+        }
+        self.state.cancel_sync.set(None);
+
+        let status = self.device.get_latch_status().map_err(device_error)?;
+        if status != LatchStatus::Closed {
+            debug!(target: "sdtxd::core", "request: deferring cancellation until latch closes");
+            return Ok(());
+        }
+
+        self.cancel_request()
+    }
+
+    async fn on_prepare_for_sleep(&mut self, active: bool) -> Result<()> {
+        // internal event, sent when the system is about to suspend or has
+        // resumed from suspend
+        if *self.state.suspended == active {
+            return Ok(());
+        }
+        self.state.suspended.set(active);
+
+        if active {
+            debug!(target: "sdtxd::core", "system is preparing for sleep");
+            return Ok(());
+        }
+
+        debug!(target: "sdtxd::core", "system has resumed from sleep");
+
+        // Events may get lost while the system is suspended, e.g. if the base
+        // has been detached during sleep. Re-sync before running any
+        // deferred attachment, which is dropped if the base is gone.
+        self.resync().await?;
+
+        if !*self.state.attach_on_resume {
+            return Ok(());
+        }
+        self.state.attach_on_resume.set(false);
+
+        debug!(target: "sdtxd::core", "running attachment process deferred by sleep now");
+        self.attachment_begin()
+    }
+
+    /// Read the current device state and handle any differences to our state
+    /// as if they had been reported via events.
+    ///
+    /// Only actual changes are forwarded: the handlers also act on repeated
+    /// values, e.g. a closed latch resets the EC state, which would break an
+    /// ongoing detachment that has not been confirmed yet.
+    async fn resync(&mut self) -> Result<()> {
+        trace!(target: "sdtxd::core", "re-syncing state");
+
+        let Snapshot { base, latch, mode } = self.device.snapshot().map_err(device_error)?;
+
+        if base.state != *self.state.base {
+            let state = match base.state {
+                BaseState::Attached    => event::BaseState::Attached,
+                BaseState::Detached    => event::BaseState::Detached,
+                BaseState::NotFeasible => event::BaseState::NotFeasible,
+            };
+
+            debug!(target: "sdtxd::core", ?state, "resync: base state changed while suspended");
+            self.on_base_state(state, base.device_type, base.id)?;
+        }
+
+        // errors are reported via events only, there is nothing to re-sync
+        let status = match (latch, *self.state.latch) {
+            (LatchStatus::Closed, LatchState::Opened) => Some(event::LatchStatus::Closed),
+            (LatchStatus::Opened, LatchState::Closed) => Some(event::LatchStatus::Opened),
+            (_, _) => None,
+        };
+
+        if let Some(status) = status {
+            debug!(target: "sdtxd::core", ?status, "resync: latch status changed while suspended");
+            self.on_latch_status(status).await?;
+        }
+
+        if mode != *self.state.mode {
+            let mode = match mode {
+                DeviceMode::Tablet => event::DeviceMode::Tablet,
+                DeviceMode::Laptop => event::DeviceMode::Laptop,
+                DeviceMode::Studio => event::DeviceMode::Studio,
+            };
+
+            debug!(target: "sdtxd::core", ?mode, "resync: device mode changed while suspended");
+            self.on_device_mode(mode)?;
+        }
+
+        Ok(())
     }
 
     fn on_detach_confirm(&mut self) -> Result<()> {
@@ -343,10 +732,15 @@ impl<A: Adapter> Core<A> {
             return Ok(());
         }
 
-        debug!(target: "sdtxd::core", "confirming detachment");
+        if self.dry_run.get() {
+            info!(target: "sdtxd::core", session=%*self.state.session, "dry-run: canceling instead of confirming detachment");
+            return command(self.device.latch_cancel());
+        }
+
+        debug!(target: "sdtxd::core", session=%*self.state.session, "confirming detachment");
         self.state.ec.set(EcState::Confirmed);
 
-        self.device.latch_confirm().context("DTX device error")
+        command(self.device.latch_confirm())
     }
 
     fn on_detach_cancel(&mut self) -> Result<()> {
@@ -362,16 +756,25 @@ impl<A: Adapter> Core<A> {
             return Ok(());
         }
 
-        debug!(target: "sdtxd::core", "canceling detachment");
-        self.device.latch_cancel().context("DTX device error")
+        debug!(target: "sdtxd::core", session=%*self.state.session, "canceling detachment");
+        command(self.device.latch_cancel())
     }
 
     fn on_detach_timeout(&mut self) -> Result<()> {
         // internal event, sent by adapter when latch open process times out
         debug!(target: "sdtxd::core", "detachment timed out");
+        self.cancel_detachment(CancelReason::HandlerTimeout)
+    }
 
+    fn on_detach_spawn_failed(&mut self) -> Result<()> {
+        // internal event, sent by adapter when the handler could not be started
+        debug!(target: "sdtxd::core", "detachment handler failed to start");
+        self.cancel_detachment(CancelReason::HandlerSpawnFailed)
+    }
+
+    fn cancel_detachment(&mut self, reason: CancelReason) -> Result<()> {
         if *self.state.ec != EcState::InProgress {
-            debug!(target: "sdtxd::core", "timeout sent while no detachment in progress");
+            debug!(target: "sdtxd::core", "cancellation requested while no detachment in progress");
             return Ok(());
         }
 
@@ -380,38 +783,68 @@ impl<A: Adapter> Core<A> {
             return Ok(());
         }
 
-        debug!(target: "sdtxd::core", "canceling detachment");
-        self.device.latch_cancel().context("DTX device error")?;
+        debug!(target: "sdtxd::core", session=%*self.state.session, "canceling detachment");
+        command(self.device.latch_cancel())?;
 
-        self.adapter.detachment_cancel(CancelReason::HandlerTimeout)
+        self.adapter.detachment_cancel(*self.state.session, reason)
     }
 
-    fn on_attach_complete(&mut self) -> Result<()> {
+    fn on_detach_slow(&mut self, session: SessionId) -> Result<()> {
+        // internal event, sent by adapter when the handler is about to time out
+        debug!(target: "sdtxd::core", %session, "detachment handler is slow");
+
+        if *self.state.rt != RuntimeState::Detaching || *self.state.session != session {
+            debug!(target: "sdtxd::core", "detachment no longer in progress, ignoring");
+            return Ok(());
+        }
+
+        self.adapter.detachment_slow(session)
+    }
+
+    fn on_attach_complete(&mut self, session: SessionId) -> Result<()> {
         // internal event, sent by adapter when attachment is completed
-        debug!(target: "sdtxd::core", "attachment complete");
+        debug!(target: "sdtxd::core", %session, "attachment complete");
         self.state.rt.set(RuntimeState::Ready);
-        self.adapter.attachment_complete()
+        self.adapter.attachment_complete(session)
     }
 
-    fn on_attach_timeout(&mut self) -> Result<()> {
+    fn on_attach_timeout(&mut self, session: SessionId) -> Result<()> {
         // internal event, sent by adapter when attachment is completed
-        debug!(target: "sdtxd::core", "attachment timed out");
+        debug!(target: "sdtxd::core", %session, "attachment timed out");
         self.state.rt.set(RuntimeState::Ready);
-        self.adapter.attachment_timeout()
+        self.adapter.attachment_timeout(session)
     }
 
-    fn on_cancel_complete(&mut self) -> Result<()> {
+    fn on_cancel_complete(&mut self, session: SessionId) -> Result<()> {
         // internal event, sent by adapter when detach-abort is completed
-        debug!(target: "sdtxd::core", "detachment cancellation complete");
+        debug!(target: "sdtxd::core", %session, "detachment cancellation complete");
         self.state.rt.set(RuntimeState::Ready);
-        self.adapter.detachment_cancel_complete()
+        self.adapter.detachment_cancel_complete(session)
     }
 
-    fn on_cancel_timeout(&mut self) -> Result<()> {
+    fn on_cancel_timeout(&mut self, session: SessionId) -> Result<()> {
         // internal event, sent by adapter when detach-abort is completed
-        debug!(target: "sdtxd::core", "detachment cancellation timed out");
+        debug!(target: "sdtxd::core", %session, "detachment cancellation timed out");
         self.state.rt.set(RuntimeState::Ready);
-        self.adapter.detachment_cancel_timeout()
+        self.adapter.detachment_cancel_timeout(session)
+    }
+
+    fn on_handler_status(&mut self, session: Option<SessionId>, status: HandlerStatus) -> Result<()> {
+        // internal event, sent by adapter when a handler reports its status
+        debug!(target: "sdtxd::core", session=session.map(SessionId::value), ?status, "handler status");
+        self.adapter.handler_status(session, status)
+    }
+
+    fn on_posture_complete(&mut self, mode: DeviceMode) -> Result<()> {
+        // internal event, sent by adapter when posture change is completed
+        debug!(target: "sdtxd::core", ?mode, "posture change complete");
+        self.adapter.posture_change_complete(mode)
+    }
+
+    fn on_posture_timeout(&mut self, mode: DeviceMode) -> Result<()> {
+        // internal event, sent by adapter when posture change timed out
+        debug!(target: "sdtxd::core", ?mode, "posture change timed out");
+        self.adapter.posture_change_timeout(mode)
     }
 
     fn on_cancel(&mut self, reason: event::CancelReason) -> Result<()> {
@@ -419,13 +852,17 @@ impl<A: Adapter> Core<A> {
 
         match *self.state.ec {
             EcState::Ready => {                             // no detachment in progress
-                debug!(target: "sdtxd::core", %reason, "cancel: detachment prevented");
+                let _span = self.session_begin();
+
+                debug!(target: "sdtxd::core", session=%*self.state.session, %reason,
+                       "cancel: detachment prevented");
 
                 // forward to adapter
-                self.adapter.request_inhibited(reason)
+                self.adapter.request_inhibited(*self.state.session, reason)
             },
             EcState::InProgress | EcState::Confirmed => {   // detachment in progress
-                debug!(target: "sdtxd::core", %reason, "cancel: detachment canceled");
+                debug!(target: "sdtxd::core", session=%*self.state.session, %reason,
+                       "cancel: detachment canceled");
 
                 // reset EC state
                 self.state.ec.set(EcState::Ready);
@@ -434,10 +871,10 @@ impl<A: Adapter> Core<A> {
                 if *self.state.rt == RuntimeState::Detaching {
                     self.state.rt.set(RuntimeState::Canceling);
 
-                    self.adapter.detachment_cancel(reason)?;
+                    self.adapter.detachment_cancel(*self.state.session, reason)?;
 
-                    let handle = DtcHandle { inject: self.inject_tx.clone() };
-                    self.adapter.detachment_cancel_start(handle)?;
+                    let handle = self.dtc_handle();
+                    self.adapter.detachment_cancel_start(*self.state.session, handle)?;
                 }
 
                 Ok(())
@@ -452,7 +889,8 @@ impl<A: Adapter> Core<A> {
             event::BaseState::Detached    => BaseState::Detached,
             event::BaseState::NotFeasible => BaseState::NotFeasible,
             event::BaseState::Unknown(state) => {
-                error!(target: "sdtxd::core", state, "base: unknown base state");
+                event_limited!(self.errors, format!("base: unknown base state {state}"),
+                               target: "sdtxd::core", Level::ERROR, state, "base: unknown base state");
                 return Ok(());
             },
         };
@@ -465,19 +903,43 @@ impl<A: Adapter> Core<A> {
 
         debug!(target: "sdtxd::core", ?state, ?ty, id, "base: state changed");
 
+        // the base battery is only present while the base is attached, so
+        // refresh it on any change
+        let battery = self.read_battery();
+        self.state.battery.set(battery);
+
+        // the firmware information is only available some time after
+        // attachment, so read it later
+        self.state.firmware.set(None);
+        if state == BaseState::Attached {
+            self.schedule_firmware_read();
+        }
+
+        self.base_id = (ty, id);
+
         // fowrard to adapter
-        self.adapter.on_base_state(BaseInfo { state, device_type: ty, id })?;
+        self.adapter.on_base_state(BaseInfo { state, device_type: ty, id, battery, firmware: None })?;
 
         // handle actual transition
         match (old, state) {
             (_, BaseState::Detached) => {       // disconnected
-                if *self.state.latch == LatchState::Closed {
+                if *self.state.attach_on_resume {
+                    // The base has been attached and detached again while
+                    // the system was suspending. Drop the pending attachment.
+                    debug!(target: "sdtxd::core", "base detached before deferred attachment could run");
+
+                    self.state.attach_on_resume.set(false);
+                    self.state.rt.set(RuntimeState::Ready);
+                    Ok(())
+
+                } else if *self.state.latch == LatchState::Closed {
                     // If the latch is closed, we don't expect any disconnect.
                     // This is either the user forcefully removing the
                     // clipboard, or incorrect reporting from the EC.
                     error!(target: "sdtxd::core", "unexpected disconnect: latch is closed");
 
-                    self.adapter.detachment_unexpected()
+                    let _span = self.session_begin();
+                    self.adapter.detachment_unexpected(*self.state.session)
 
                 } else if *self.state.ec == EcState::Ready {
                     // If the latch is open, we expect the EC state to be
@@ -486,7 +948,8 @@ impl<A: Adapter> Core<A> {
                     error!(target: "sdtxd::core", "unexpected disconnect: detachment not \
                            in-progress but latch is open");
 
-                    self.adapter.detachment_unexpected()
+                    let _span = self.session_begin();
+                    self.adapter.detachment_unexpected(*self.state.session)
                 } else {
                     Ok(())
                 }
@@ -499,10 +962,7 @@ impl<A: Adapter> Core<A> {
                         debug!(target: "sdtxd::core", "base attached, starting attachment process");
 
                         self.state.needs_attachment.set(false);
-                        self.state.rt.set(RuntimeState::Attaching);
-
-                        let handle = AtHandle { inject: self.inject_tx.clone() };
-                        self.adapter.attachment_start(handle)
+                        self.attachment_begin()
                     },
                     LatchState::Opened => {
                         debug!(target: "sdtxd::core", "base attached, deferring attachment");
@@ -512,8 +972,59 @@ impl<A: Adapter> Core<A> {
                     },
                 }
             },
-            (_, _) => Ok(()),                   // other (attached <-> feasible)
+            (BaseState::NotFeasible, BaseState::Attached) => {  // feasible again
+                debug!(target: "sdtxd::core", "base: detachment feasible again");
+                self.adapter.base_feasible()
+            },
+            (_, _) => Ok(()),                   // other (attached -> not feasible)
+        }
+    }
+
+    fn on_base_battery(&mut self) -> Result<()> {
+        let battery = self.read_battery();
+
+        // update state, return if it hasn't changed
+        if *self.state.battery == battery {
+            return Ok(());
+        }
+        self.state.battery.set(battery);
+
+        debug!(target: "sdtxd::core", ?battery, "base: battery changed");
+
+        self.adapter.on_base_battery(battery)
+    }
+
+    fn on_base_firmware(&mut self) -> Result<()> {
+        // internal event, sent some time after the base has been attached
+        if *self.state.base != BaseState::Attached {
+            return Ok(());
+        }
+
+        let firmware = BaseFirmware::read();
+
+        // update state, return if it hasn't changed
+        if *self.state.firmware == firmware {
+            return Ok(());
+        }
+        self.state.firmware.set(firmware.clone());
+
+        match &firmware {
+            Some(fw) => info!(target: "sdtxd::core", version=?fw.version, serial=?fw.serial, "base: firmware {}", fw),
+            None => debug!(target: "sdtxd::core", "base: firmware information unavailable"),
         }
+
+        self.adapter.on_base_firmware(firmware)
+    }
+
+    /// Read the firmware information of the base after giving it some time
+    /// to be set up after attachment.
+    fn schedule_firmware_read(&self) {
+        let inject = self.inject_tx.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(FIRMWARE_READ_DELAY).await;
+            let _ = inject.send(Event::BaseFirmware);
+        });
     }
 
     async fn on_latch_status(&mut self, status: event::LatchStatus) -> Result<()> {
@@ -524,11 +1035,12 @@ impl<A: Adapter> Core<A> {
             event::LatchStatus::Error(error) => {
                 use HardwareError as HwErr;
 
-                error!(target: "sdtxd::core", %error, "latch: status error");
+                event_limited!(self.errors, format!("latch: status error: {error}"),
+                               target: "sdtxd::core", Level::ERROR, %error, "latch: status error");
 
                 // try to read latch status via ioctl, maybe we get an updated non-error state;
                 // otherwise try to infer actual state
-                let status = self.device.get_latch_status().context("DTX device error")?;
+                let status = self.device.get_latch_status().map_err(device_error)?;
                 let status = match status {
                     LatchStatus::Closed                           => LatchState::Closed,
                     LatchStatus::Opened                           => LatchState::Opened,
@@ -546,11 +1058,25 @@ impl<A: Adapter> Core<A> {
                 status
             },
             event::LatchStatus::Unknown(status) => {
-                error!(target: "sdtxd::core", status, "latch: unknown latch status");
+                event_limited!(self.errors, format!("latch: unknown latch status {status}"),
+                               target: "sdtxd::core", Level::ERROR, status, "latch: unknown latch status");
                 return Ok(());
             },
         };
 
+        // resolve pending cancellation request: If the latch has been
+        // opened, cancellation is deferred until it closes again. If it
+        // is still closed, the latch will never open and we can cancel now.
+        if self.state.cancel_sync.is_some() {
+            self.state.cancel_sync.set(None);
+
+            if state == LatchState::Closed && *self.state.latch == LatchState::Closed {
+                self.cancel_request()?;
+            } else {
+                debug!(target: "sdtxd::core", "request: deferring cancellation until latch closes");
+            }
+        }
+
         // reset EC state if closed
         let ec = *self.state.ec;
         if state == LatchState::Closed {
@@ -574,7 +1100,7 @@ impl<A: Adapter> Core<A> {
             // will get two events, one with an error and one with an attempt
             // at correcting this error.
             self.adapter.on_latch_status(LatchStatus::Opened)?;
-            self.adapter.detachment_ready()?;
+            self.adapter.detachment_ready(*self.state.session)?;
             return Ok(());
         }
 
@@ -584,13 +1110,17 @@ impl<A: Adapter> Core<A> {
         // mode. Sleep 1s and then update those things ourselves.
         tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
 
-        let base = self.device.get_base_info().context("DTX device error")?;
+        let base = self.device.get_base_info().map_err(device_error)?;
         if *self.state.base != base.state {
             trace!(target: "sdtxd::core", state=?base.state,
                    "updating base info for closed latch detachment quirk");
 
+            let battery = self.read_battery();
+            self.state.battery.set(battery);
+
             *self.state.base = base.state;
-            self.adapter.on_base_state(base)?;
+            let firmware = self.state.firmware.clone();
+            self.adapter.on_base_state(BaseInfo { battery, firmware, ..BaseInfo::from(base) })?;
         }
 
         let device = self.device.clone();
@@ -620,10 +1150,10 @@ impl<A: Adapter> Core<A> {
         if *self.state.base == BaseState::Detached {
             // The latch has been closed and the base is detached. This is what
             // we normally expect the detachment procedure to end with.
-            debug!(target: "sdtxd::core", "detachment completed via latch close");
+            debug!(target: "sdtxd::core", session=%*self.state.session, "detachment completed via latch close");
 
             self.state.rt.set(RuntimeState::Ready);
-            self.adapter.detachment_complete()
+            self.adapter.detachment_complete(*self.state.session)
 
         } else if !*self.state.needs_attachment {
             // The latch has been opened and closed without the tablet being
@@ -634,16 +1164,16 @@ impl<A: Adapter> Core<A> {
             // detachment procedure via a cancel event. Only tell the adapter
             // if we haven't done so yet.
             if ec != EcState::Ready {
-                debug!(target: "sdtxd::core", "detachment canceled via latch close");
+                debug!(target: "sdtxd::core", session=%*self.state.session, "detachment canceled via latch close");
 
                 // cancel current detachment procedure, if in progress
                 if *self.state.rt == RuntimeState::Detaching {
                     self.state.rt.set(RuntimeState::Canceling);
 
-                    self.adapter.detachment_cancel(CancelReason::DisconnectTimeout)?;
+                    self.adapter.detachment_cancel(*self.state.session, CancelReason::DisconnectTimeout)?;
 
-                    let handle = DtcHandle { inject: self.inject_tx.clone() };
-                    self.adapter.detachment_cancel_start(handle)?;
+                    let handle = self.dtc_handle();
+                    self.adapter.detachment_cancel_start(*self.state.session, handle)?;
                 }
             } else {
                 debug!(target: "sdtxd::core", "detachment already canceled before latch closed");
@@ -656,22 +1186,20 @@ impl<A: Adapter> Core<A> {
             // (signalled by this event), the tablet has been detached and
             // re-attached. Complete the detachment procedure and notify the
             // adapter that an attachmend has occured.
-            debug!(target: "sdtxd::core", "detachment completed via latch close");
+            debug!(target: "sdtxd::core", session=%*self.state.session, "detachment completed via latch close");
             self.state.rt.set(RuntimeState::Ready);
-            self.adapter.detachment_complete()?;
+            self.adapter.detachment_complete(*self.state.session)?;
 
             debug!(target: "sdtxd::core", "running deferred attachment process now");
             self.state.needs_attachment.set(false);
-            self.state.rt.set(RuntimeState::Attaching);
-
-            let handle = AtHandle { inject: self.inject_tx.clone() };
-            self.adapter.attachment_start(handle)
+            self.attachment_begin()
         }
     }
 
     fn on_device_mode(&mut self, mode: event::DeviceMode) -> Result<()> {
         if let event::DeviceMode::Unknown(mode) = mode {
-            error!(target: "sdtxd::core", mode, "mode: unknown device mode");
+            event_limited!(self.errors, format!("mode: unknown device mode {mode}"),
+                           target: "sdtxd::core", Level::ERROR, mode, "mode: unknown device mode");
             return Ok(());
         }
         let mode = DeviceMode::try_from(mode).unwrap();
@@ -679,22 +1207,46 @@ impl<A: Adapter> Core<A> {
         if *self.state.mode == mode {
             return Ok(());
         }
-        self.state.mode.set(mode);
+        let old = self.state.mode.replace(mode);
 
         debug!(target: "sdtxd::core", ?mode, "mode: device mode changed");
 
-        self.adapter.on_device_mode(mode)
+        self.adapter.on_device_mode(mode)?;
+
+        // Device mode changes while the base is attached and no detachment
+        // or attachment is in progress are pure posture changes, e.g. on the
+        // Surface Laptop Studio or when the base is attached in reverse.
+        if *self.state.rt != RuntimeState::Ready || *self.state.base != BaseState::Attached {
+            return Ok(());
+        }
+
+        debug!(target: "sdtxd::core", from=?old, to=?mode, "mode: starting posture change process");
+
+        let handle = self.pc_handle(mode);
+        self.adapter.posture_change_start(old, mode, handle)
     }
 }
 
 
 #[derive(Clone)]
 pub struct DtHandle {
-    device: Arc<Device>,
+    session: SessionId,
+    force: bool,
+    device: Arc<dyn DtxDevice>,
     inject: UnboundedSender<Event>,
 }
 
 impl DtHandle {
+    /// Whether the detachment has been requested via a forced request,
+    /// i.e. should skip the detachment handler.
+    pub fn is_forced(&self) -> bool {
+        self.force
+    }
+
+    pub fn status(&self, status: HandlerStatus) {
+        let _ = self.inject.send(Event::HandlerStatus { session: Some(self.session), status });
+    }
+
     pub fn confirm(&self) {
         let _ = self.inject.send(Event::DetachConfirm);
     }
@@ -707,41 +1259,128 @@ impl DtHandle {
         let _ = self.inject.send(Event::DetachTimeout);
     }
 
+    pub fn spawn_failed(&self) {
+        let _ = self.inject.send(Event::DetachSpawnFailed);
+    }
+
+    pub fn slow(&self) {
+        let _ = self.inject.send(Event::DetachSlow { session: self.session });
+    }
+
     pub fn heartbeat(&self) -> Result<()> {
         debug!(target: "sdtxd::core", "sending heartbeat");
-        self.device.latch_heartbeat().context("DTX device error")
+        command(self.device.latch_heartbeat())
     }
 }
 
 
 #[derive(Clone)]
 pub struct DtcHandle {
+    session: SessionId,
+    device: Arc<dyn DtxDevice>,
     inject: UnboundedSender<Event>,
 }
 
 impl DtcHandle {
+    pub fn status(&self, status: HandlerStatus) {
+        let _ = self.inject.send(Event::HandlerStatus { session: Some(self.session), status });
+    }
+
+    pub fn latch_lock(&self) -> Result<()> {
+        debug!(target: "sdtxd::core", session=%self.session, "locking latch");
+        command(self.device.latch_lock())
+    }
+
+    pub fn latch_unlock(&self) -> Result<()> {
+        debug!(target: "sdtxd::core", session=%self.session, "unlocking latch");
+        command(self.device.latch_unlock())
+    }
+
     pub fn complete(&self) {
-        let _ = self.inject.send(Event::CancelComplete);
+        let _ = self.inject.send(Event::CancelComplete { session: self.session });
     }
 
     pub fn timeout(&self) {
-        let _ = self.inject.send(Event::CancelTimeout);
+        let _ = self.inject.send(Event::CancelTimeout { session: self.session });
     }
 }
 
 
 #[derive(Clone)]
 pub struct AtHandle {
+    session: SessionId,
+    device: Arc<dyn DtxDevice>,
     inject: UnboundedSender<Event>,
 }
 
 impl AtHandle {
+    pub fn status(&self, status: HandlerStatus) {
+        let _ = self.inject.send(Event::HandlerStatus { session: Some(self.session), status });
+    }
+
+    pub fn latch_lock(&self) -> Result<()> {
+        debug!(target: "sdtxd::core", session=%self.session, "locking latch");
+        command(self.device.latch_lock())
+    }
+
+    pub fn latch_unlock(&self) -> Result<()> {
+        debug!(target: "sdtxd::core", session=%self.session, "unlocking latch");
+        command(self.device.latch_unlock())
+    }
+
     pub fn complete(&self) {
-        let _ = self.inject.send(Event::AttachComplete);
+        let _ = self.inject.send(Event::AttachComplete { session: self.session });
     }
 
     pub fn timeout(&self) {
-        let _ = self.inject.send(Event::AttachTimeout);
+        let _ = self.inject.send(Event::AttachTimeout { session: self.session });
+    }
+}
+
+
+#[derive(Clone)]
+pub struct PcHandle {
+    mode: DeviceMode,
+    inject: UnboundedSender<Event>,
+}
+
+impl PcHandle {
+    pub fn status(&self, status: HandlerStatus) {
+        let _ = self.inject.send(Event::HandlerStatus { session: None, status });
+    }
+
+    pub fn complete(&self) {
+        let _ = self.inject.send(Event::PostureComplete { mode: self.mode });
+    }
+
+    pub fn timeout(&self) {
+        let _ = self.inject.send(Event::PostureTimeout { mode: self.mode });
+    }
+}
+
+
+#[derive(Clone)]
+pub struct SleepHandle {
+    inject: UnboundedSender<Event>,
+}
+
+impl SleepHandle {
+    pub fn prepare_for_sleep(&self, active: bool) {
+        let _ = self.inject.send(Event::PrepareForSleep { active });
+    }
+}
+
+
+/// Handle for injecting simulated device events, handled as if they had been
+/// emitted by the DTX device.
+#[derive(Clone)]
+pub struct InjectHandle {
+    inject: UnboundedSender<Event>,
+}
+
+impl InjectHandle {
+    pub fn inject(&self, event: sdtx::Event) {
+        let _ = self.inject.send(Event::from(event));
     }
 }
 
@@ -750,51 +1389,75 @@ impl AtHandle {
 pub trait Adapter {
     fn set_state(&mut self, mode: DeviceMode, base: BaseInfo, latch: LatchState) { }
 
-    fn request_inhibited(&mut self, reason: CancelReason) -> Result<()> {
+    fn request_inhibited(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_start(&mut self, handle: DtHandle) -> Result<()> {
+    fn detachment_start(&mut self, session: SessionId, handle: DtHandle) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_ready(&mut self) -> Result<()> {
+    fn detachment_ready(&mut self, session: SessionId) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_complete(&mut self) -> Result<()> {
+    fn detachment_complete(&mut self, session: SessionId) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_cancel(&mut self, reason: CancelReason) -> Result<()> {
+    fn detachment_cancel(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_cancel_start(&mut self, handle: DtcHandle) -> Result<()> {
+    fn detachment_cancel_start(&mut self, session: SessionId, handle: DtcHandle) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_cancel_complete(&mut self) -> Result<()> {
+    fn detachment_cancel_complete(&mut self, session: SessionId) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_cancel_timeout(&mut self) -> Result<()> {
+    fn detachment_cancel_timeout(&mut self, session: SessionId) -> Result<()> {
         Ok(())
     }
 
-    fn detachment_unexpected(&mut self) -> Result<()> {
+    fn detachment_unexpected(&mut self, session: SessionId) -> Result<()> {
         Ok(())
     }
 
-    fn attachment_start(&mut self, handle: AtHandle) -> Result<()> {
+    fn detachment_slow(&mut self, session: SessionId) -> Result<()> {
         Ok(())
     }
 
-    fn attachment_complete(&mut self) -> Result<()> {
+    fn attachment_start(&mut self, session: SessionId, handle: AtHandle) -> Result<()> {
         Ok(())
     }
 
-    fn attachment_timeout(&mut self) -> Result<()> {
+    fn attachment_complete(&mut self, session: SessionId) -> Result<()> {
+        Ok(())
+    }
+
+    fn attachment_timeout(&mut self, session: SessionId) -> Result<()> {
+        Ok(())
+    }
+
+    fn base_feasible(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn posture_change_start(&mut self, from: DeviceMode, to: DeviceMode, handle: PcHandle) -> Result<()> {
+        Ok(())
+    }
+
+    fn handler_status(&mut self, session: Option<SessionId>, status: HandlerStatus) -> Result<()> {
+        Ok(())
+    }
+
+    fn posture_change_complete(&mut self, mode: DeviceMode) -> Result<()> {
+        Ok(())
+    }
+
+    fn posture_change_timeout(&mut self, mode: DeviceMode) -> Result<()> {
         Ok(())
     }
 
@@ -802,6 +1465,14 @@ pub trait Adapter {
         Ok(())
     }
 
+    fn on_base_battery(&mut self, battery: Option<BatteryInfo>) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_base_firmware(&mut self, firmware: Option<BaseFirmware>) -> Result<()> {
+        Ok(())
+    }
+
     fn on_latch_status(&mut self, status: LatchStatus) -> Result<()> {
         Ok(())
     }
@@ -818,84 +1489,132 @@ macro_rules! impl_adapter_for_tuple {
         {
             fn set_state(&mut self, mode: DeviceMode, base: BaseInfo, latch: LatchState) {
                 let ($($name,)+) = self;
-                ($($name.set_state(mode, base, latch),)+);
+                ($($name.set_state(mode, base.clone(), latch),)+);
             }
 
-            fn request_inhibited(&mut self, reason: CancelReason) -> Result<()> {
+            fn request_inhibited(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.request_inhibited(reason)?,)+);
+                ($($name.request_inhibited(session, reason)?,)+);
                 Ok(())
             }
 
-            fn detachment_start(&mut self, handle: DtHandle) -> Result<()> {
+            fn detachment_start(&mut self, session: SessionId, handle: DtHandle) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_start(handle.clone())?,)+);
+                ($($name.detachment_start(session, handle.clone())?,)+);
                 Ok(())
             }
 
-            fn detachment_ready(&mut self) -> Result<()> {
+            fn detachment_ready(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_ready()?,)+);
+                ($($name.detachment_ready(session)?,)+);
                 Ok(())
             }
 
-            fn detachment_complete(&mut self) -> Result<()> {
+            fn detachment_complete(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_complete()?,)+);
+                ($($name.detachment_complete(session)?,)+);
                 Ok(())
             }
 
-            fn detachment_cancel(&mut self, reason: CancelReason) -> Result<()> {
+            fn detachment_cancel(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_cancel(reason)?,)+);
+                ($($name.detachment_cancel(session, reason)?,)+);
                 Ok(())
             }
 
-            fn detachment_cancel_start(&mut self, handle: DtcHandle) -> Result<()> {
+            fn detachment_cancel_start(&mut self, session: SessionId, handle: DtcHandle) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_cancel_start(handle.clone())?,)+);
+                ($($name.detachment_cancel_start(session, handle.clone())?,)+);
                 Ok(())
             }
 
-            fn detachment_cancel_complete(&mut self) -> Result<()> {
+            fn detachment_cancel_complete(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_cancel_complete()?,)+);
+                ($($name.detachment_cancel_complete(session)?,)+);
                 Ok(())
             }
 
-            fn detachment_cancel_timeout(&mut self) -> Result<()> {
+            fn detachment_cancel_timeout(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_cancel_timeout()?,)+);
+                ($($name.detachment_cancel_timeout(session)?,)+);
                 Ok(())
             }
 
-            fn detachment_unexpected(&mut self) -> Result<()> {
+            fn detachment_unexpected(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.detachment_unexpected()?,)+);
+                ($($name.detachment_unexpected(session)?,)+);
                 Ok(())
             }
 
-            fn attachment_start(&mut self, handle: AtHandle) -> Result<()> {
+            fn detachment_slow(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.attachment_start(handle.clone())?,)+);
+                ($($name.detachment_slow(session)?,)+);
                 Ok(())
             }
 
-            fn attachment_complete(&mut self) -> Result<()> {
+            fn attachment_start(&mut self, session: SessionId, handle: AtHandle) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.attachment_complete()?,)+);
+                ($($name.attachment_start(session, handle.clone())?,)+);
                 Ok(())
             }
 
-            fn attachment_timeout(&mut self) -> Result<()> {
+            fn attachment_complete(&mut self, session: SessionId) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.attachment_timeout()?,)+);
+                ($($name.attachment_complete(session)?,)+);
+                Ok(())
+            }
+
+            fn attachment_timeout(&mut self, session: SessionId) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.attachment_timeout(session)?,)+);
+                Ok(())
+            }
+
+            fn base_feasible(&mut self) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.base_feasible()?,)+);
+                Ok(())
+            }
+
+            fn posture_change_start(&mut self, from: DeviceMode, to: DeviceMode, handle: PcHandle) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.posture_change_start(from, to, handle.clone())?,)+);
+                Ok(())
+            }
+
+            fn handler_status(&mut self, session: Option<SessionId>, status: HandlerStatus) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.handler_status(session, status.clone())?,)+);
+                Ok(())
+            }
+
+            fn posture_change_complete(&mut self, mode: DeviceMode) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.posture_change_complete(mode)?,)+);
+                Ok(())
+            }
+
+            fn posture_change_timeout(&mut self, mode: DeviceMode) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.posture_change_timeout(mode)?,)+);
                 Ok(())
             }
 
             fn on_base_state(&mut self, info: BaseInfo) -> Result<()> {
                 let ($($name,)+) = self;
-                ($($name.on_base_state(info)?,)+);
+                ($($name.on_base_state(info.clone())?,)+);
+                Ok(())
+            }
+
+            fn on_base_battery(&mut self, battery: Option<BatteryInfo>) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.on_base_battery(battery)?,)+);
+                Ok(())
+            }
+
+            fn on_base_firmware(&mut self, firmware: Option<BaseFirmware>) -> Result<()> {
+                let ($($name,)+) = self;
+                ($($name.on_base_firmware(firmware.clone())?,)+);
                 Ok(())
             }
 
@@ -917,6 +1636,38 @@ macro_rules! impl_adapter_for_tuple {
 impl_adapter_for_tuple! { A1 }
 impl_adapter_for_tuple! { A1 A2 }
 impl_adapter_for_tuple! { A1 A2 A3 }
+impl_adapter_for_tuple! { A1 A2 A3 A4 }
+impl_adapter_for_tuple! { A1 A2 A3 A4 A5 }
+impl_adapter_for_tuple! { A1 A2 A3 A4 A5 A6 }
+
+
+/// Send a latch command, ignoring it if latch control is unavailable because
+/// the device has been opened read-only.
+fn command(result: Result<()>) -> Result<()> {
+    match result {
+        Err(err) if DeviceError::of(&err) == Some(DeviceError::ReadOnly) => {
+            debug!(target: "sdtxd::core", "read-only: ignoring latch command");
+            Ok(())
+        },
+        result => result.map_err(device_error),
+    }
+}
+
+/// Describe the given device error by its cause, including a hint on how to
+/// resolve it, if known.
+fn device_error(err: anyhow::Error) -> anyhow::Error {
+    match DeviceError::of(&err) {
+        Some(cause) => err.context(cause),
+        None => err.context("DTX device error"),
+    }
+}
+
+async fn battery_changed(battery: &mut Option<BaseBattery>) {
+    match battery {
+        Some(battery) => battery.changed().await,
+        None => future::pending().await,
+    }
+}
 
 
 #[derive(Debug)]
@@ -954,3 +1705,412 @@ impl<T> std::ops::DerefMut for Trace<T> {
         &mut self.value
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// DTX device driven by the test, recording all latch commands.
+    #[derive(Clone)]
+    struct FakeDevice {
+        inner: Arc<Mutex<FakeState>>,
+    }
+
+    struct FakeState {
+        base: BaseState,
+        latch: LatchStatus,
+        commands: Vec<&'static str>,
+        streams: VecDeque<Vec<Result<sdtx::Event>>>,
+        reopen: bool,
+        reopened: u32,
+    }
+
+    impl FakeDevice {
+        fn new() -> Self {
+            let state = FakeState {
+                base: BaseState::Attached,
+                latch: LatchStatus::Closed,
+                commands: Vec::new(),
+                streams: VecDeque::new(),
+                reopen: true,
+                reopened: 0,
+            };
+
+            Self { inner: Arc::new(Mutex::new(state)) }
+        }
+
+        fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+            self.inner.lock().unwrap()
+        }
+
+        fn command(&self, command: &'static str) -> Result<()> {
+            self.state().commands.push(command);
+            Ok(())
+        }
+    }
+
+    impl DtxDevice for FakeDevice {
+        async fn try_clone(&self) -> Result<Self> {
+            Ok(self.clone())
+        }
+
+        fn reopen(&self) -> Result<()> {
+            let mut state = self.state();
+            state.reopened += 1;
+
+            if state.reopen {
+                Ok(())
+            } else {
+                Err(disconnected())
+            }
+        }
+
+        fn events(&mut self) -> Result<impl Stream<Item=Result<sdtx::Event>> + Unpin + '_> {
+            let events = self.state().streams.pop_front().unwrap_or_default();
+            Ok(stream::iter(events))
+        }
+
+        fn latch_lock(&self) -> Result<()> { self.command("lock") }
+        fn latch_unlock(&self) -> Result<()> { self.command("unlock") }
+        fn latch_request(&self) -> Result<()> { self.command("request") }
+        fn latch_confirm(&self) -> Result<()> { self.command("confirm") }
+        fn latch_heartbeat(&self) -> Result<()> { Ok(()) }
+        fn latch_cancel(&self) -> Result<()> { self.command("cancel") }
+
+        fn get_base_info(&self) -> Result<sdtx::BaseInfo> {
+            Ok(sdtx::BaseInfo { state: self.state().base, device_type: DeviceType::Ssh, id: 0 })
+        }
+
+        fn get_latch_status(&self) -> Result<LatchStatus> {
+            Ok(self.state().latch)
+        }
+
+        fn get_device_mode(&self) -> Result<DeviceMode> {
+            Ok(DeviceMode::Laptop)
+        }
+    }
+
+    /// Adapter recording the processes started and finished by the core.
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<&'static str>,
+        dt: Option<DtHandle>,
+        dtc: Option<DtcHandle>,
+        at: Option<AtHandle>,
+    }
+
+    impl Adapter for Recorder {
+        fn request_inhibited(&mut self, _session: SessionId, _reason: CancelReason) -> Result<()> {
+            self.calls.push("request_inhibited");
+            Ok(())
+        }
+
+        fn detachment_start(&mut self, _session: SessionId, handle: DtHandle) -> Result<()> {
+            self.calls.push("detachment_start");
+            self.dt = Some(handle);
+            Ok(())
+        }
+
+        fn detachment_ready(&mut self, _session: SessionId) -> Result<()> {
+            self.calls.push("detachment_ready");
+            Ok(())
+        }
+
+        fn detachment_complete(&mut self, _session: SessionId) -> Result<()> {
+            self.calls.push("detachment_complete");
+            Ok(())
+        }
+
+        fn detachment_cancel(&mut self, _session: SessionId, _reason: CancelReason) -> Result<()> {
+            self.calls.push("detachment_cancel");
+            Ok(())
+        }
+
+        fn detachment_cancel_start(&mut self, _session: SessionId, handle: DtcHandle) -> Result<()> {
+            self.calls.push("detachment_cancel_start");
+            self.dtc = Some(handle);
+            Ok(())
+        }
+
+        fn detachment_cancel_complete(&mut self, _session: SessionId) -> Result<()> {
+            self.calls.push("detachment_cancel_complete");
+            Ok(())
+        }
+
+        fn attachment_start(&mut self, _session: SessionId, handle: AtHandle) -> Result<()> {
+            self.calls.push("attachment_start");
+            self.at = Some(handle);
+            Ok(())
+        }
+
+        fn attachment_complete(&mut self, _session: SessionId) -> Result<()> {
+            self.calls.push("attachment_complete");
+            Ok(())
+        }
+    }
+
+    fn core(device: &FakeDevice) -> Core<FakeDevice, Recorder> {
+        Core::new(device.clone(), None, None, Recorder::default(), DryRun::new(false),
+                  LastEvent::default(), ForceRequest::default())
+    }
+
+    /// Handle all internal events sent by the adapter so far.
+    async fn pump(core: &mut Core<FakeDevice, Recorder>) {
+        while let Ok(event) = core.inject_rx.try_recv() {
+            core.handle(event).await.unwrap();
+        }
+    }
+
+    fn disconnected() -> anyhow::Error {
+        std::io::Error::from_raw_os_error(libc::ENODEV).into()
+    }
+
+    fn latch(status: event::LatchStatus) -> Event {
+        Event::LatchStatus { status }
+    }
+
+    fn base(state: event::BaseState) -> Event {
+        Event::BaseConnection { state, device_type: DeviceType::Ssh, id: 0 }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_confirm_detach() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        core.handle(Event::Request).await.unwrap();
+        assert_eq!(*core.state.ec, EcState::InProgress);
+        assert_eq!(*core.state.rt, RuntimeState::Detaching);
+
+        core.adapter.dt.as_ref().unwrap().confirm();
+        pump(&mut core).await;
+        assert_eq!(*core.state.ec, EcState::Confirmed);
+        assert_eq!(device.state().commands, ["confirm"]);
+
+        device.state().latch = LatchStatus::Opened;
+        core.handle(latch(event::LatchStatus::Opened)).await.unwrap();
+
+        device.state().base = BaseState::Detached;
+        core.handle(base(event::BaseState::Detached)).await.unwrap();
+
+        device.state().latch = LatchStatus::Closed;
+        core.handle(latch(event::LatchStatus::Closed)).await.unwrap();
+
+        assert_eq!(*core.state.ec, EcState::Ready);
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+        assert_eq!(core.adapter.calls, ["detachment_start", "detachment_ready", "detachment_complete"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_while_in_progress() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        core.handle(Event::Request).await.unwrap();
+
+        // pressing the button again cancels the detachment
+        core.handle(Event::Request).await.unwrap();
+        assert_eq!(*core.state.ec, EcState::Ready);
+        assert_eq!(*core.state.rt, RuntimeState::Canceling);
+
+        // late confirmation by the handler must not open the latch
+        core.adapter.dt.as_ref().unwrap().confirm();
+        pump(&mut core).await;
+        assert!(device.state().commands.is_empty());
+
+        core.adapter.dtc.as_ref().unwrap().complete();
+        pump(&mut core).await;
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+
+        assert_eq!(core.adapter.calls, [
+            "detachment_start",
+            "detachment_cancel",
+            "detachment_cancel_start",
+            "detachment_cancel_complete",
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_after_confirm_latch_remains_closed() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        core.handle(Event::Request).await.unwrap();
+        core.adapter.dt.as_ref().unwrap().confirm();
+        pump(&mut core).await;
+
+        // cancellation needs to wait for the latch status
+        core.handle(Event::Request).await.unwrap();
+        assert!(core.state.cancel_sync.is_some());
+        assert_eq!(*core.state.rt, RuntimeState::Detaching);
+
+        core.handle(latch(event::LatchStatus::Closed)).await.unwrap();
+        assert!(core.state.cancel_sync.is_none());
+        assert_eq!(*core.state.ec, EcState::Ready);
+        assert_eq!(*core.state.rt, RuntimeState::Canceling);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_while_attaching() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        device.state().base = BaseState::Detached;
+        core.handle(base(event::BaseState::Detached)).await.unwrap();
+
+        device.state().base = BaseState::Attached;
+        core.handle(base(event::BaseState::Attached)).await.unwrap();
+        assert_eq!(*core.state.rt, RuntimeState::Attaching);
+
+        core.handle(Event::Request).await.unwrap();
+        assert_eq!(device.state().commands, ["cancel"]);
+
+        core.adapter.at.as_ref().unwrap().complete();
+        pump(&mut core).await;
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+
+        assert_eq!(core.adapter.calls, ["attachment_start", "attachment_complete"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn force_request() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        core.force.set(true);
+        core.handle(Event::Request).await.unwrap();
+
+        assert_eq!(*core.state.rt, RuntimeState::Detaching);
+        assert!(core.adapter.dt.as_ref().unwrap().is_forced());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn force_request_while_busy() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        device.state().base = BaseState::Detached;
+        core.handle(base(event::BaseState::Detached)).await.unwrap();
+
+        // a forced request without base is inhibited and must not carry over
+        core.force.set(true);
+        core.handle(Event::Request).await.unwrap();
+
+        device.state().base = BaseState::Attached;
+        core.handle(base(event::BaseState::Attached)).await.unwrap();
+
+        // same for a forced request while attaching
+        core.force.set(true);
+        core.handle(Event::Request).await.unwrap();
+
+        core.adapter.at.as_ref().unwrap().complete();
+        pump(&mut core).await;
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+
+        // the next regular request starts a regular detachment
+        core.handle(Event::Request).await.unwrap();
+        assert_eq!(*core.state.rt, RuntimeState::Detaching);
+        assert!(!core.adapter.dt.as_ref().unwrap().is_forced());
+
+        // cancelling a detachment via a forced request drops the flag as well
+        core.force.set(true);
+        core.handle(Event::Request).await.unwrap();
+        assert!(!core.force.take());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_without_base() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        device.state().base = BaseState::Detached;
+        core.handle(base(event::BaseState::Detached)).await.unwrap();
+        core.handle(Event::Request).await.unwrap();
+
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+        assert_eq!(device.state().commands, ["cancel"]);
+        assert_eq!(core.adapter.calls, ["request_inhibited"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resync_after_resume() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        core.handle(Event::PrepareForSleep { active: true }).await.unwrap();
+
+        // base re-attached while suspending, attachment is deferred
+        device.state().base = BaseState::Detached;
+        core.handle(base(event::BaseState::Detached)).await.unwrap();
+
+        device.state().base = BaseState::Attached;
+        core.handle(base(event::BaseState::Attached)).await.unwrap();
+        assert!(*core.state.attach_on_resume);
+
+        // base removed during sleep, without any event
+        device.state().base = BaseState::Detached;
+
+        core.handle(Event::PrepareForSleep { active: false }).await.unwrap();
+
+        assert_eq!(*core.state.base, BaseState::Detached);
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+        assert!(!*core.state.attach_on_resume);
+        assert!(!core.adapter.calls.contains(&"attachment_start"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resume_during_unconfirmed_request() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        core.handle(Event::Request).await.unwrap();
+        assert_eq!(*core.state.ec, EcState::InProgress);
+
+        // sleep and resume before the detachment has been confirmed, the
+        // latch is still closed
+        core.handle(Event::PrepareForSleep { active: true }).await.unwrap();
+        core.handle(Event::PrepareForSleep { active: false }).await.unwrap();
+
+        assert_eq!(*core.state.ec, EcState::InProgress);
+        assert_eq!(*core.state.rt, RuntimeState::Detaching);
+
+        core.adapter.dt.as_ref().unwrap().confirm();
+        pump(&mut core).await;
+
+        assert_eq!(*core.state.ec, EcState::Confirmed);
+        assert_eq!(device.state().commands, ["confirm"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reopen_after_disconnect() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        // disconnect in the middle of a detachment, which is then lost
+        device.state().streams.push_back(vec![Ok(sdtx::Event::Request), Err(disconnected())]);
+
+        core.run().await.unwrap();
+
+        // both the event and the control handle have been re-opened
+        assert_eq!(device.state().reopened, 2);
+        assert_eq!(*core.state.ec, EcState::Ready);
+        assert_eq!(*core.state.rt, RuntimeState::Ready);
+        assert_eq!(core.adapter.calls, ["detachment_start"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reopen_gives_up() {
+        let device = FakeDevice::new();
+        let mut core = core(&device);
+
+        device.state().reopen = false;
+        device.state().streams.push_back(vec![Err(disconnected())]);
+
+        assert!(core.run().await.is_err());
+        assert_eq!(device.state().reopened, REOPEN_ATTEMPTS);
+    }
+}
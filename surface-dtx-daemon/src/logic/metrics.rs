@@ -0,0 +1,48 @@
+use crate::logic::{Adapter, CancelReason, DtHandle, SessionId};
+use crate::metrics::{Counter, Metrics};
+
+use anyhow::Result;
+
+
+/// Maintains the metrics of the detachment and attachment processes.
+pub struct MetricsAdapter {
+    metrics: Metrics,
+}
+
+impl MetricsAdapter {
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics }
+    }
+}
+
+impl Adapter for MetricsAdapter {
+    fn request_inhibited(&mut self, _session: SessionId, _reason: CancelReason) -> Result<()> {
+        self.metrics.inc(Counter::DetachmentInhibited);
+        Ok(())
+    }
+
+    fn detachment_start(&mut self, _session: SessionId, _handle: DtHandle) -> Result<()> {
+        self.metrics.inc(Counter::DetachmentAttempts);
+        Ok(())
+    }
+
+    fn detachment_complete(&mut self, _session: SessionId) -> Result<()> {
+        self.metrics.inc(Counter::DetachmentComplete);
+        Ok(())
+    }
+
+    fn detachment_cancel(&mut self, _session: SessionId, reason: CancelReason) -> Result<()> {
+        self.metrics.cancel(reason);
+        Ok(())
+    }
+
+    fn detachment_unexpected(&mut self, _session: SessionId) -> Result<()> {
+        self.metrics.inc(Counter::DetachmentUnexpected);
+        Ok(())
+    }
+
+    fn attachment_complete(&mut self, _session: SessionId) -> Result<()> {
+        self.metrics.inc(Counter::AttachmentComplete);
+        Ok(())
+    }
+}
@@ -1,15 +1,73 @@
+mod audit;
+pub use self::audit::AuditAdapter;
+
+mod builtin;
+
+mod context;
+
 mod core;
-pub use self::core::{Adapter, AtHandle, Core, DtHandle, DtcHandle};
+pub use self::core::{Adapter, AtHandle, Core, DtHandle, DtcHandle, InjectHandle, PcHandle, SleepHandle};
+
+mod metrics;
+pub use self::metrics::MetricsAdapter;
 
 mod proc;
-pub use self::proc::ProcessAdapter;
+pub use self::proc::{ProcessAdapter, test_handler};
+
+mod sandbox;
 
 mod srvc;
 pub use self::srvc::ServiceAdapter;
 
+mod switch;
+pub use self::switch::{TabletSwitchAdapter, TabletSwitchCheckAdapter};
+
+mod stats;
+pub use self::stats::{HandlerRecord, HandlerRecords, HandlerResult};
+
+
+use crate::device::{BaseFirmware, BatteryInfo};
+use crate::metrics::Metrics;
+use crate::utils::loglevel::LogControl;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use tokio::sync::Notify;
 
 use sdtx::event;
-pub use sdtx::{BaseInfo, BaseState, DeviceMode, DeviceType, HardwareError, LatchStatus};
+pub use sdtx::{BaseState, DeviceMode, DeviceType, HardwareError, LatchStatus};
+
+
+/// Information about the base, as reported by the DTX device and augmented
+/// with the state of its battery and its firmware information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseInfo {
+    pub state: BaseState,
+    pub device_type: DeviceType,
+    pub id: u8,
+
+    /// State of the base battery, if present.
+    pub battery: Option<BatteryInfo>,
+
+    /// Firmware information of the base, if known.
+    pub firmware: Option<BaseFirmware>,
+}
+
+impl From<sdtx::BaseInfo> for BaseInfo {
+    fn from(info: sdtx::BaseInfo) -> Self {
+        Self {
+            state: info.state,
+            device_type: info.device_type,
+            id: info.id,
+            battery: None,
+            firmware: None,
+        }
+    }
+}
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,6 +104,7 @@ impl std::fmt::Display for RuntimeError {
 pub enum CancelReason {
     UserRequest,    // user or higher layer requested cancelation, or user did not act
     HandlerTimeout,
+    HandlerSpawnFailed,
     DisconnectTimeout,
     Runtime(RuntimeError),
     Hardware(HardwareError),
@@ -67,6 +126,7 @@ impl std::fmt::Display for CancelReason {
         match self {
             Self::UserRequest       => write!(f, "user request"),
             Self::HandlerTimeout    => write!(f, "timed out waiting for detachment handler"),
+            Self::HandlerSpawnFailed => write!(f, "failed to start detachment handler"),
             Self::DisconnectTimeout => write!(f, "timed out waiting for user to disconnect base"),
             Self::Runtime(err)      => write!(f, "runtime error: {err}"),
             Self::Hardware(err)     => write!(f, "hardware error: {err}"),
@@ -76,6 +136,226 @@ impl std::fmt::Display for CancelReason {
 }
 
 
+/// Identifier for a single detachment or attachment sequence.
+///
+/// Session IDs are increasing monotonically and can be used to correlate
+/// log records, handler output, and events belonging to the same sequence.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SessionId(u64);
+
+impl SessionId {
+    pub fn next(self) -> Self {
+        Self(self.0.wrapping_add(1))
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+/// Shared flag for the dry-run mode.
+///
+/// In dry-run mode, handlers are run as usual (but are told so via their
+/// context) while the latch is never actually opened. Instead, detachment is
+/// canceled once it would have been confirmed.
+#[derive(Debug, Clone, Default)]
+pub struct DryRun(Arc<AtomicBool>);
+
+impl DryRun {
+    pub fn new(enabled: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed)
+    }
+}
+
+
+/// Time at which the last event has been received from the DTX device, shared
+/// between the core and the D-Bus service for health checks.
+#[derive(Debug, Clone, Default)]
+pub struct LastEvent(Arc<AtomicU64>);
+
+impl LastEvent {
+    pub fn update(&self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.0.store(now.as_secs(), Ordering::Relaxed)
+    }
+
+    /// Time of the last event in seconds since the Unix epoch, or zero if no
+    /// event has been received yet.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+
+/// Shared flag for forced detachment requests.
+///
+/// The flag is consumed by the next request. If that request starts a
+/// detachment, the detachment handler and all built-in steps are skipped and
+/// the detachment is confirmed after a grace period. Otherwise, e.g. if the
+/// request cancels an ongoing detachment, the flag is simply dropped.
+#[derive(Debug, Clone, Default)]
+pub struct ForceRequest(Arc<AtomicBool>);
+
+impl ForceRequest {
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed)
+    }
+
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+
+/// Lock state of the latch, shared between the D-Bus service and the process
+/// adapter.
+///
+/// The latch can be locked explicitly via D-Bus, e.g. to disable the detach
+/// button in kiosk setups, and by the daemon itself while handlers are
+/// running. Releasing the latter must not release the former, so both are
+/// tracked separately and the latch is only unlocked once neither holds it.
+#[derive(Debug, Clone, Default)]
+pub struct LatchLock(Arc<Mutex<LockHolders>>);
+
+#[derive(Debug, Default)]
+struct LockHolders {
+    explicit: bool,
+    handler: bool,
+}
+
+impl LatchLock {
+    /// Whether the latch has been locked explicitly.
+    pub fn is_explicit(&self) -> bool {
+        self.0.lock().unwrap().explicit
+    }
+
+    /// Lock the latch explicitly using the given command.
+    pub fn lock(&self, command: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut holders = self.0.lock().unwrap();
+
+        command()?;
+        holders.explicit = true;
+        Ok(())
+    }
+
+    /// Release an explicit lock using the given command. The latch stays
+    /// locked while a handler holds it.
+    pub fn unlock(&self, command: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut holders = self.0.lock().unwrap();
+
+        if !holders.handler {
+            command()?;
+        }
+        holders.explicit = false;
+        Ok(())
+    }
+
+    /// Lock the latch for a handler using the given command. Returns whether
+    /// the lock has been taken, i.e. whether it needs to be released via
+    /// [`LatchLock::release`]. Does nothing if the latch is already locked.
+    pub fn acquire(&self, command: impl FnOnce() -> Result<()>) -> Result<bool> {
+        let mut holders = self.0.lock().unwrap();
+
+        if holders.explicit || holders.handler {
+            return Ok(false);
+        }
+
+        command()?;
+        holders.handler = true;
+        Ok(true)
+    }
+
+    /// Release the lock taken for a handler using the given command. The
+    /// latch stays locked if it has been locked explicitly in the meantime.
+    pub fn release(&self, command: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut holders = self.0.lock().unwrap();
+
+        if !holders.handler {
+            return Ok(());
+        }
+        holders.handler = false;
+
+        if holders.explicit {
+            return Ok(());
+        }
+        command()
+    }
+}
+
+
+/// State shared between the event handling, the handler adapters, and the
+/// D-Bus service of a single device.
+#[derive(Clone)]
+pub struct DeviceContext {
+    /// Trigger for retrying a deferred detachment.
+    pub retry: Arc<Notify>,
+    pub records: HandlerRecords,
+    pub force: ForceRequest,
+    pub lock: LatchLock,
+    pub last_event: LastEvent,
+
+    // daemon-wide state, shared by all devices
+    pub dry_run: DryRun,
+    pub log: LogControl,
+    pub metrics: Metrics,
+    pub started: Instant,
+}
+
+impl DeviceContext {
+    pub fn new(dry_run: DryRun, log: LogControl, metrics: Metrics) -> Self {
+        Self {
+            retry: Arc::new(Notify::new()),
+            records: HandlerRecords::new(metrics.clone()),
+            force: ForceRequest::default(),
+            lock: LatchLock::default(),
+            last_event: LastEvent::default(),
+            dry_run,
+            log,
+            metrics,
+            started: Instant::now(),
+        }
+    }
+
+    /// Context for another device, sharing the daemon-wide state with this
+    /// one but with its own per-device state.
+    pub fn for_device(&self) -> Self {
+        Self {
+            retry: Arc::new(Notify::new()),
+            records: HandlerRecords::new(self.metrics.clone()),
+            force: ForceRequest::default(),
+            lock: LatchLock::default(),
+            last_event: LastEvent::default(),
+            ..self.clone()
+        }
+    }
+}
+
+
+/// Status report emitted by a handler via its standard output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandlerStatus {
+    Status(String),
+    Progress(u8),
+    Error(String),
+    Crash(String, i32),     // handler name, signal
+}
+
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LatchState {
     Closed,
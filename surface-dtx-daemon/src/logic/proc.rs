@@ -1,17 +1,42 @@
-use crate::config::Config;
+use crate::config::{AttachStep, Config, HandlerOutput, LogLevel, ReattachAction, Sandbox, SpawnFailurePolicy};
+use crate::device::{BaseFirmware, BatteryInfo};
 use crate::logic::{
     Adapter,
     AtHandle,
+    BaseInfo,
+    BaseState,
+    CancelReason,
+    DeviceMode,
+    DeviceType,
     DtHandle,
     DtcHandle,
+    DryRun,
+    HandlerRecords,
+    HandlerResult,
+    HandlerStatus,
+    LatchLock,
+    LatchState,
+    PcHandle,
+    SessionId,
 };
+use crate::logic::{builtin, sandbox};
+use crate::logic::context::{HandlerContext, device_mode_str};
 use crate::utils::taskq::TaskSender;
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Error, Result, bail};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
-use tracing::{Level, debug, trace};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::{Level, debug, error, trace, warn};
 
 
 const HEARTBEAT_PERIOD_MS: u64 = 2500;
@@ -19,8 +44,10 @@ const HEARTBEAT_PERIOD_MS: u64 = 2500;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ExitStatus {
-    Commence = 0,
-    Abort    = 1,
+    Commence,
+    Abort,
+    Defer,
+    Crash(i32),
 }
 
 impl ExitStatus {
@@ -28,17 +55,23 @@ impl ExitStatus {
         match self {
             Self::Commence => "0",
             Self::Abort    => "1",
+            Self::Defer    => "75",     // EX_TEMPFAIL
+            Self::Crash(_) => unreachable!("crashes do not have an exit code"),
         }
     }
 }
 
 impl From<std::process::ExitStatus> for ExitStatus {
     fn from(status: std::process::ExitStatus) -> Self {
-        status.code().map(|s| if s == 0 {
-            ExitStatus::Commence
-        } else {
-            ExitStatus::Abort
-        }).unwrap_or(ExitStatus::Abort)
+        if let Some(signal) = status.signal() {
+            return ExitStatus::Crash(signal);
+        }
+
+        match status.code() {
+            Some(0)  => ExitStatus::Commence,
+            Some(75) => ExitStatus::Defer,
+            _        => ExitStatus::Abort,
+        }
     }
 }
 
@@ -46,19 +79,148 @@ impl From<std::process::ExitStatus> for ExitStatus {
 pub struct ProcessAdapter {
     config: Config,
     queue: TaskSender<Error>,
+    base: BaseInfo,
+    mode: DeviceMode,
+    reason: Option<CancelReason>,
+    detached: Option<(DeviceType, u8, Instant)>,
+    retry: Arc<Notify>,
+    records: HandlerRecords,
+    dry_run: DryRun,
+    lock: LatchLock,
 }
 
 impl ProcessAdapter {
-    pub fn new(config: Config, queue: TaskSender<Error>) -> Self {
+    pub fn new(config: Config, queue: TaskSender<Error>, retry: Arc<Notify>, records: HandlerRecords,
+               dry_run: DryRun, lock: LatchLock)
+        -> Self
+    {
         Self {
             config,
             queue,
+            base: BaseInfo { state: BaseState::Attached, device_type: DeviceType::Hid, id: 0, battery: None, firmware: None },
+            mode: DeviceMode::Laptop,
+            reason: None,
+            detached: None,
+            retry,
+            records,
+            dry_run,
+            lock,
+        }
+    }
+
+    /// Confirm a forced detachment after the configured grace period,
+    /// skipping the handler and all built-in steps.
+    fn detachment_force(&mut self, session: SessionId, handle: DtHandle) -> Result<()> {
+        let delay = Duration::from_secs_f32(self.config.handler.detach.force_delay.max(0.0));
+
+        warn!(target: "sdtxd::proc", %session, ?delay, "forced detachment, skipping handler");
+
+        let h = handle.clone();
+        let heartbeat = async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(HEARTBEAT_PERIOD_MS)).await;
+                h.heartbeat()?;
+            }
+        };
+
+        let confirm = async move {
+            tokio::time::sleep(delay).await;
+
+            debug!(target: "sdtxd::proc", %session, "forced detachment commencing");
+            handle.confirm();
+            Ok(())
+        };
+
+        let task = async move {
+            tokio::select! {
+                r = confirm   => r,
+                r = heartbeat => r,
+            }
+        };
+
+        trace!(target: "sdtxd::proc", %session, "scheduling forced detachment task");
+        if self.queue.submit(task).is_err() {
+            unreachable!("receiver dropped");
         }
+
+        Ok(())
+    }
+
+    fn context(&self, event: &'static str, session: Option<SessionId>, reason: Option<CancelReason>,
+               timeout: f32) -> HandlerContext
+    {
+        HandlerContext::new(event, session, self.base.clone(), self.mode, reason, timeout, self.dry_run.get())
+    }
+
+    fn output(&self, ident: &'static str, level: Option<LogLevel>) -> OutputLog {
+        let journal = match self.config.handler.output {
+            HandlerOutput::Log => None,
+            HandlerOutput::Journal => Some(ident),
+        };
+
+        OutputLog { level: level.map(Level::from), journal }
+    }
+
+    /// Build the pre- and post-exec hooks of a handler, bounded by the
+    /// timeout of its context.
+    fn hooks(&self, pre: &Option<PathBuf>, post: &Option<PathBuf>, dir: &Path, ctx: &HandlerContext,
+             log: OutputLog, sandbox: Sandbox) -> (Hook, Hook)
+    {
+        let hook = |path: &Option<PathBuf>| Hook {
+            path: path.as_ref().map(|path| dir.join(path)),
+            dir: dir.into(),
+            ctx: ctx.clone(),
+            timeout: Duration::from_secs_f32(ctx.timeout),
+            log,
+            sandbox,
+        };
+
+        (hook(pre), hook(post))
     }
 }
 
 impl Adapter for ProcessAdapter {
-    fn detachment_start(&mut self, handle: DtHandle) -> Result<()> {
+    fn set_state(&mut self, mode: DeviceMode, base: BaseInfo, _latch: LatchState) {
+        self.base = base;
+        self.mode = mode;
+    }
+
+    fn on_base_state(&mut self, info: BaseInfo) -> Result<()> {
+        // remember which base has been detached when, to detect quick
+        // re-attachments of the same base
+        if info.state == BaseState::Detached && self.base.state != BaseState::Detached {
+            self.detached = Some((self.base.device_type, self.base.id, Instant::now()));
+        }
+
+        self.base = info;
+        Ok(())
+    }
+
+    fn on_base_battery(&mut self, battery: Option<BatteryInfo>) -> Result<()> {
+        self.base.battery = battery;
+        Ok(())
+    }
+
+    fn on_base_firmware(&mut self, firmware: Option<BaseFirmware>) -> Result<()> {
+        self.base.firmware = firmware;
+        Ok(())
+    }
+
+    fn on_device_mode(&mut self, mode: DeviceMode) -> Result<()> {
+        self.mode = mode;
+        Ok(())
+    }
+
+    fn detachment_cancel(&mut self, _session: SessionId, reason: CancelReason) -> Result<()> {
+        self.reason = Some(reason);
+        Ok(())
+    }
+
+    fn detachment_start(&mut self, session: SessionId, handle: DtHandle) -> Result<()> {
+        if handle.is_forced() {
+            return self.detachment_force(session, handle);
+        }
+
         // build heartbeat task
         let h = handle.clone();
         let heartbeat = async move {
@@ -68,72 +230,215 @@ impl Adapter for ProcessAdapter {
             }
         };
 
-        // build timeout task
+        // build timeout task, allowing the handler to extend the timeout up
+        // to the configured maximum
+        let (extend_tx, mut extend_rx) = tokio::sync::mpsc::unbounded_channel::<Duration>();
+
         let h = handle.clone();
-        let timeout = self.config.handler.detach.timeout * 1000.0;
+        let records = self.records.clone();
+        let start = Instant::now();
+        let timeout = self.config.handler.detach.timeout;
+        let max_timeout = self.config.handler.detach.max_timeout.unwrap_or(timeout).max(timeout);
+        let slow_threshold = self.config.handler.detach.slow_threshold;
         let timeout = async move {
-            tokio::time::sleep(Duration::from_millis(timeout as _)).await;
+            let mut deadline = start + Duration::from_secs_f32(timeout);
+            let limit = start + Duration::from_secs_f32(max_timeout);
 
-            trace!(target: "sdtxd::proc", "detachment process timed out, canceling");
+            // warn clients once the given fraction of the timeout has elapsed,
+            // re-armed whenever the timeout gets extended
+            let slow_at = |deadline: Instant| {
+                (0.0..1.0).contains(&slow_threshold)
+                    .then(|| start + (deadline - start).mul_f32(slow_threshold))
+            };
+            let mut slow = slow_at(deadline);
+
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = tokio::time::sleep_until(slow.unwrap_or(deadline)), if slow.is_some() => {
+                        trace!(target: "sdtxd::proc", %session, "detachment handler is slow");
+                        h.slow();
+                        slow = None;
+                    },
+                    Some(ext) = extend_rx.recv() => {
+                        let requested = Instant::now() + ext;
+                        let new = requested.min(limit);
+
+                        if new > deadline {
+                            deadline = new;
+                            slow = slow_at(deadline);
+                            debug!(target: "sdtxd::proc", %session, timeout=?(deadline - start),
+                                   "detachment timeout extended by handler");
+                        }
+
+                        if requested > limit {
+                            warn!(target: "sdtxd::proc", %session, max_timeout,
+                                  "detachment timeout extension exceeds limit");
+                        }
+                    },
+                }
+            }
+
+            trace!(target: "sdtxd::proc", %session, "detachment process timed out, canceling");
+            records.push("detach", Some(session), start, HandlerResult::Timeout);
             h.timeout();
 
             Ok(())
         };
 
         // build process task
-        let dir = self.config.dir.clone();
-        let handler = self.config.handler.detach.exec.clone();
+        let dir = self.config.handler_dir(&self.config.handler.detach.dir);
+        let handler = HandlerExec::from_config(&dir, &self.config.handler.detach.exec,
+                                               &self.config.handler.detach.exec_script,
+                                               &self.config.handler.detach.args);
+        let builtin = self.config.handler.detach.builtin.clone();
+        let dgpu = self.config.handler.dgpu.clone();
+        let spawn_failure = self.config.handler.detach.spawn_failure;
+        let defer_interval = Duration::from_secs_f32(self.config.handler.detach.defer_interval);
+        let retry = self.retry.clone();
+        let ctx = self.context("detachment", Some(session), None, self.config.handler.detach.timeout);
+        let sandbox = self.config.handler.detach.sandbox;
+        let timer = self.records.timer("detach", Some(session));
+        let timings = timer.clone();
+        let log = self.output("sdtx-handler-detach", self.config.handler.detach.log_level);
+        let (pre, post) = self.hooks(&self.config.handler.detach.pre_exec,
+                                     &self.config.handler.detach.post_exec,
+                                     &dir, &ctx, log, sandbox);
         let proc = async move {
-            trace!(target: "sdtxd::proc", "detachment process started");
+            trace!(target: "sdtxd::proc", %session, "detachment process started");
 
-            // run handler if specified
-            let status = if let Some(ref path) = handler {
-                debug!(target: "sdtxd::proc", ?path, ?dir, "running detachment handler");
+            pre.run(Some(session), "detachment pre-exec hook").await;
 
-                // run handler
-                let output = Command::new(path)
-                    .current_dir(dir)
-                    .env("EXIT_DETACH_COMMENCE", ExitStatus::Commence.as_str())
-                    .env("EXIT_DETACH_ABORT", ExitStatus::Abort.as_str())
-                    .kill_on_drop(true)
-                    .output().await
-                    .context("Subprocess error (detachment)")?;
+            let status = loop {
+                // run handler if specified
+                let status = if let Some(ref exec) = handler {
+                    debug!(target: "sdtxd::proc", %session, ?exec, ?dir, "running detachment handler");
 
-                // log output
-                output.log("detachment handler");
+                    // run handler
+                    let mut cmd = exec.command(&ctx, &sandbox);
+                    cmd.current_dir(&dir)
+                        .env("EXIT_DETACH_COMMENCE", ExitStatus::Commence.as_str())
+                        .env("EXIT_DETACH_ABORT", ExitStatus::Abort.as_str())
+                        .env("EXIT_DETACH_DEFER", ExitStatus::Defer.as_str())
+                        .kill_on_drop(true);
+
+                    let extend = |time| { let _ = extend_tx.send(time); };
+                    timer.begin();
+                    let output = run_handler(&mut cmd, &ctx, |s| handle.status(s), extend).await;
+                    timer.end(HandlerResult::from(&output));
+                    let output = match output {
+                        Ok(output) => output,
+                        Err(err) => {
+                            error!(target: "sdtxd::proc", %session, ?exec, "failed to run detachment handler: {}", err);
+                            handle.status(HandlerStatus::Error(format!("Failed to run detachment handler: {err}")));
+
+                            match spawn_failure {
+                                SpawnFailurePolicy::Abort => {
+                                    handle.spawn_failed();
+                                    return Ok(());
+                                },
+                                SpawnFailurePolicy::Commence => break ExitStatus::Commence,
+                            }
+                        },
+                    };
+
+                    // log output
+                    output.log(Some(session), "detachment handler", log);
+
+                    // report crashes separately from ordinary failures
+                    let status = ExitStatus::from(output.status);
+                    if let ExitStatus::Crash(signal) = status {
+                        handle.status(HandlerStatus::Crash("detach".into(), signal));
+                    }
+
+                    // confirm latch open/detach commence based on return status
+                    status
+
+                } else if builtin.enabled && ctx.dry_run {
+                    debug!(target: "sdtxd::proc", %session, "dry-run: skipping built-in detachment actions");
+                    ExitStatus::Commence
 
-                // confirm latch open/detach commence based on return status
-                ExitStatus::from(output.status)
+                } else if builtin.enabled {
+                    debug!(target: "sdtxd::proc", %session, "no detachment handler specified, running built-in actions");
 
+                    match builtin::detach(session, &builtin).await {
+                        Ok(()) => ExitStatus::Commence,
+                        Err(err) => {
+                            warn!(target: "sdtxd::proc", %session, "built-in detachment actions failed: {:#}", err);
+                            handle.status(HandlerStatus::Error(format!("{err:#}")));
+                            ExitStatus::Abort
+                        },
+                    }
+
+                } else {
+                    debug!(target: "sdtxd::proc", %session, "no detachment handler specified, skipping");
+                    ExitStatus::Commence
+                };
+
+                if status != ExitStatus::Defer {
+                    break status;
+                }
+
+                // handler requested to defer detachment: retry after the
+                // configured interval or when triggered via D-Bus
+                debug!(target: "sdtxd::proc", %session, interval=?defer_interval,
+                       "detachment deferred by handler, retrying later");
+
+                tokio::select! {
+                    _ = tokio::time::sleep(defer_interval) => {},
+                    _ = retry.notified() => {
+                        debug!(target: "sdtxd::proc", %session, "detachment retry triggered");
+                    },
+                }
+            };
+
+            // remove dGPU once the handler agreed to commence
+            let status = if status == ExitStatus::Commence && dgpu.enabled && ctx.dry_run {
+                debug!(target: "sdtxd::proc", %session, "dry-run: skipping built-in dGPU detachment step");
+                status
+            } else if status == ExitStatus::Commence && dgpu.enabled {
+                match builtin::dgpu_detach(session, &dgpu).await {
+                    Ok(()) => ExitStatus::Commence,
+                    Err(err) => {
+                        warn!(target: "sdtxd::proc", %session, "built-in dGPU detachment step failed: {:#}", err);
+                        handle.status(HandlerStatus::Error(format!("{err:#}")));
+                        ExitStatus::Abort
+                    },
+                }
             } else {
-                debug!(target: "sdtxd::proc", "no detachment handler specified, skipping");
-                ExitStatus::Commence
+                status
             };
 
             // send response, will be ignored if already canceled
             if status == ExitStatus::Commence {
-                debug!(target: "sdtxd::proc", "detachment commencing based on handler response");
+                debug!(target: "sdtxd::proc", %session, "detachment commencing based on handler response");
                 handle.confirm();
             } else {
-                debug!(target: "sdtxd::proc", "detachment canceled based on handler response");
+                debug!(target: "sdtxd::proc", %session, "detachment canceled based on handler response");
                 handle.cancel();
             }
 
-            trace!(target: "sdtxd::proc", "detachment process completed");
+            trace!(target: "sdtxd::proc", %session, "detachment process completed");
             Ok(())
         };
 
         // build task
         let task = async move {
-            tokio::select! {
+            let result = tokio::select! {
                 r = proc      => r,
                 r = heartbeat => r,
                 r = timeout   => r,
-            }
+            };
+
+            // run post-exec hook, regardless of success
+            post.run(Some(session), "detachment post-exec hook").await;
+
+            timings.finish();
+            result
         };
 
         // submit task
-        trace!(target: "sdtxd::proc", "scheduling detachment task");
+        trace!(target: "sdtxd::proc", %session, "scheduling detachment task");
         if self.queue.submit(task).is_err() {
             unreachable!("receiver dropped");
         }
@@ -141,59 +446,100 @@ impl Adapter for ProcessAdapter {
         Ok(())
     }
 
-    fn detachment_cancel_start(&mut self, handle: DtcHandle) -> Result<()> {
+    fn detachment_cancel_start(&mut self, session: SessionId, handle: DtcHandle) -> Result<()> {
         // build timeout task
         let h = handle.clone();
+        let records = self.records.clone();
         let timeout = self.config.handler.detach_abort.timeout * 1000.0;
         let timeout = async move {
+            let start = Instant::now();
             tokio::time::sleep(Duration::from_millis(timeout as _)).await;
 
-            trace!(target: "sdtxd::proc", "detachment-abort timed out, canceling");
+            trace!(target: "sdtxd::proc", %session, "detachment-abort timed out, canceling");
+            records.push("detach-abort", Some(session), start, HandlerResult::Timeout);
             h.timeout();
 
             Ok(())
         };
 
+        // keep handle for latch locking
+        let latch = handle.clone();
+
         // build process task
-        let dir = self.config.dir.clone();
-        let handler = self.config.handler.detach_abort.exec.clone();
+        let dir = self.config.handler_dir(&self.config.handler.detach_abort.dir);
+        let handler = HandlerExec::from_config(&dir, &self.config.handler.detach_abort.exec,
+                                               &self.config.handler.detach_abort.exec_script,
+                                               &self.config.handler.detach_abort.args);
+        let reason = self.reason.take();
+        let ctx = self.context("detachment-abort", Some(session), reason,
+                               self.config.handler.detach_abort.timeout);
+        let sandbox = self.config.handler.detach_abort.sandbox;
+        let timer = self.records.timer("detach-abort", Some(session));
+        let timings = timer.clone();
+        let log = self.output("sdtx-handler-detach-abort", self.config.handler.detach_abort.log_level);
+        let (pre, post) = self.hooks(&self.config.handler.detach_abort.pre_exec,
+                                     &self.config.handler.detach_abort.post_exec,
+                                     &dir, &ctx, log, sandbox);
         let proc = async move {
-            trace!(target: "sdtxd::proc", "detachment-abort process started");
+            trace!(target: "sdtxd::proc", %session, "detachment-abort process started");
+
+            pre.run(Some(session), "detachment-abort pre-exec hook").await;
 
             // run handler if specified
-            if let Some(ref path) = handler {
-                debug!(target: "sdtxd::proc", ?path, ?dir, "running detachment-abort handler");
+            if let Some(ref exec) = handler {
+                debug!(target: "sdtxd::proc", %session, ?exec, ?dir, "running detachment-abort handler");
 
                 // run handler
-                let output = Command::new(path)
-                    .current_dir(dir)
-                    .kill_on_drop(true)
-                    .output().await
-                    .context("Subprocess error (detachment-abort)")?;
+                let mut cmd = exec.command(&ctx, &sandbox);
+                cmd.current_dir(dir)
+                    .kill_on_drop(true);
+
+                timer.begin();
+                let output = run_handler(&mut cmd, &ctx, |s| handle.status(s), ignore_extend).await;
+                timer.end(HandlerResult::from(&output));
+                let output = output.context("Subprocess error (detachment-abort)")?;
 
                 // log output
-                output.log("detachment-abort handler");
+                output.log(Some(session), "detachment-abort handler", log);
+
+                if let Some(signal) = output.status.signal() {
+                    handle.status(HandlerStatus::Crash("detach-abort".into(), signal));
+                }
 
             } else {
-                debug!(target: "sdtxd::proc", "no detachment-abort handler specified, skipping");
+                debug!(target: "sdtxd::proc", %session, "no detachment-abort handler specified, skipping");
             };
 
-            trace!(target: "sdtxd::proc", "detachment-abort process completed");
+            trace!(target: "sdtxd::proc", %session, "detachment-abort process completed");
             handle.complete();
 
             Ok(())
         };
 
         // build task
+        let lock = self.config.handler.detach_abort.lock_latch.then(|| self.lock.clone());
         let task = async move {
-            tokio::select! {
+            // lock latch while handler is running, if requested
+            let locked = lock.filter(|lock| lock_latch(lock, session, || latch.latch_lock()));
+
+            let result = tokio::select! {
                 r = proc      => r,
                 r = timeout   => r,
+            };
+
+            if let Some(lock) = locked {
+                unlock_latch(&lock, session, || latch.latch_unlock());
             }
+
+            // run post-exec hook, regardless of success
+            post.run(Some(session), "detachment-abort post-exec hook").await;
+
+            timings.finish();
+            result
         };
 
         // submit task
-        trace!(target: "sdtxd::proc", "scheduling detachment-abort task");
+        trace!(target: "sdtxd::proc", %session, "scheduling detachment-abort task");
         if self.queue.submit(task).is_err() {
             unreachable!("receiver dropped");
         }
@@ -201,65 +547,257 @@ impl Adapter for ProcessAdapter {
         Ok(())
     }
 
-    fn attachment_start(&mut self, handle: AtHandle) -> Result<()> {
+    fn attachment_start(&mut self, session: SessionId, handle: AtHandle) -> Result<()> {
+        // check if the same base has been re-attached shortly after detaching it
+        let window = Duration::from_secs_f32(self.config.handler.attach.reattach_window.max(0.0));
+        let reattach = match self.detached.take() {
+            Some((ty, id, time)) => {
+                ty == self.base.device_type && id == self.base.id && time.elapsed() < window
+            },
+            None => false,
+        };
+
+        let action = self.config.handler.attach.reattach_action;
+        if reattach && action == ReattachAction::Skip {
+            debug!(target: "sdtxd::proc", %session, "same base re-attached quickly, skipping attachment handler");
+            handle.complete();
+            return Ok(());
+        }
+
         // build timeout task
         let h = handle.clone();
+        let records = self.records.clone();
         let timeout = self.config.handler.attach.timeout * 1000.0;
         let timeout = async move {
+            let start = Instant::now();
             tokio::time::sleep(Duration::from_millis(timeout as _)).await;
 
-            trace!(target: "sdtxd::proc", "detachment-abort timed out, canceling");
+            trace!(target: "sdtxd::proc", %session, "attachment timed out, canceling");
+            records.push("attach", Some(session), start, HandlerResult::Timeout);
             h.timeout();
 
             Ok(())
         };
 
+        // keep handle for latch locking
+        let latch = handle.clone();
+
         // build process task
-        let dir = self.config.dir.clone();
-        let handler = self.config.handler.attach.exec.clone();
+        let dir = self.config.handler_dir(&self.config.handler.attach.dir);
+        let handler = HandlerExec::from_config(&dir, &self.config.handler.attach.exec,
+                                               &self.config.handler.attach.exec_script,
+                                               &self.config.handler.attach.args);
+        let steps = self.config.handler.attach.steps.clone();
+        let dgpu = self.config.handler.dgpu.enabled;
+        let ctx = self.context("attachment", Some(session), None, self.config.handler.attach.timeout);
+        let sandbox = self.config.handler.attach.sandbox;
+        let timer = self.records.timer("attach", Some(session));
+        let timings = timer.clone();
+        let log = self.output("sdtx-handler-attach", self.config.handler.attach.log_level);
+        let (pre, post) = self.hooks(&self.config.handler.attach.pre_exec,
+                                     &self.config.handler.attach.post_exec,
+                                     &dir, &ctx, log, sandbox);
         let proc = async move {
-            trace!(target: "sdtxd::proc", "attachment process started");
+            trace!(target: "sdtxd::proc", %session, "attachment process started");
+
+            pre.run(Some(session), "attachment pre-exec hook").await;
+
+            // re-discover dGPU before running the handler, so it can use it
+            if dgpu {
+                if let Err(err) = builtin::dgpu_attach(session).await {
+                    warn!(target: "sdtxd::proc", %session, "built-in dGPU attachment step failed: {:#}", err);
+                    handle.status(HandlerStatus::Error(format!("{err:#}")));
+                }
+            }
 
             // run handler if specified
-            if let Some(ref path) = handler {
-                debug!(target: "sdtxd::proc", ?path, ?dir, "running attachment handler");
+            if let Some(ref exec) = handler {
+                debug!(target: "sdtxd::proc", %session, ?exec, ?dir, "running attachment handler");
 
                 // run handler
-                let output = Command::new(path)
-                    .current_dir(dir)
-                    .kill_on_drop(true)
-                    .output().await
-                    .context("Subprocess error (attachment)")?;
+                let mut cmd = exec.command(&ctx, &sandbox);
+                cmd.current_dir(&dir)
+                    .kill_on_drop(true);
+
+                timer.begin();
+                let output = run_handler(&mut cmd, &ctx, |s| handle.status(s), ignore_extend).await;
+                timer.end(HandlerResult::from(&output));
+                let output = output.context("Subprocess error (attachment)")?;
 
                 // log output
-                output.log("attachment handler");
+                output.log(Some(session), "attachment handler", log);
+
+                if let Some(signal) = output.status.signal() {
+                    handle.status(HandlerStatus::Crash("attach".into(), signal));
+                }
 
             } else {
-                debug!(target: "sdtxd::proc", "no attachment handler specified, skipping");
+                debug!(target: "sdtxd::proc", %session, "no attachment handler specified, skipping");
             };
 
-            trace!(target: "sdtxd::proc", "attachment process completed");
+            // run additional steps, if any
+            if !steps.is_empty() {
+                run_attach_steps(session, steps, &dir, &ctx, |s| handle.status(s), log, sandbox).await;
+            }
+
+            trace!(target: "sdtxd::proc", %session, "attachment process completed");
             handle.complete();
 
             Ok(())
         };
 
         // build task
-        let delay = Duration::from_millis((self.config.handler.attach.delay * 1000.0) as _);
+        let no_delay = reattach && action == ReattachAction::NoDelay;
+        let delay = if no_delay {
+            debug!(target: "sdtxd::proc", %session, "same base re-attached quickly, skipping attachment delay");
+            Duration::ZERO
+        } else {
+            Duration::from_millis((self.config.handler.attach.delay * 1000.0) as _)
+        };
+        let settle = if self.config.handler.attach.udev_settle && !no_delay {
+            Some(Duration::from_secs_f32(self.config.handler.attach.udev_settle_timeout.max(0.0)))
+        } else {
+            None
+        };
+        let lock = self.config.handler.attach.lock_latch.then(|| self.lock.clone());
         let task = async move {
+            // Lock latch while handler is running, if requested. Do this
+            // before the delay so that the latch cannot be opened while
+            // devices in the base are still being set up.
+            let locked = lock.filter(|lock| lock_latch(lock, session, || latch.latch_lock()));
+
+            // wait for udev to process all events of the new devices, fall
+            // back to the fixed delay if that's not possible
+            let settled = match settle {
+                Some(timeout) => {
+                    debug!(target: "sdtxd::proc", %session, "waiting for udev to settle");
+
+                    match udev_settle(timeout).await {
+                        Ok(()) => true,
+                        Err(err) => {
+                            warn!(target: "sdtxd::proc", %session, "failed to wait for udev: {:#}", err);
+                            false
+                        },
+                    }
+                },
+                None => false,
+            };
+
             // delay to ensure all devices are set up
-            debug!(target: "sdtxd::proc", "delaying attachment process by {}ms", delay.as_millis());
-            tokio::time::sleep(delay).await;
+            if !settled {
+                debug!(target: "sdtxd::proc", %session, "delaying attachment process by {}ms", delay.as_millis());
+                tokio::time::sleep(delay).await;
+            }
 
             // drive main tasks
-            tokio::select! {
+            let result = tokio::select! {
                 r = proc      => r,
                 r = timeout   => r,
+            };
+
+            if let Some(lock) = locked {
+                unlock_latch(&lock, session, || latch.latch_unlock());
             }
+
+            // run post-exec hook, regardless of success
+            post.run(Some(session), "attachment post-exec hook").await;
+
+            timings.finish();
+            result
+        };
+
+        // submit task
+        trace!(target: "sdtxd::proc", %session, "scheduling attachment task");
+        if self.queue.submit(task).is_err() {
+            unreachable!("receiver dropped");
+        }
+
+        Ok(())
+    }
+
+    fn posture_change_start(&mut self, from: DeviceMode, to: DeviceMode, handle: PcHandle) -> Result<()> {
+        // build timeout task
+        let h = handle.clone();
+        let records = self.records.clone();
+        let timeout = self.config.handler.posture.timeout * 1000.0;
+        let timeout = async move {
+            let start = Instant::now();
+            tokio::time::sleep(Duration::from_millis(timeout as _)).await;
+
+            trace!(target: "sdtxd::proc", ?from, ?to, "posture-change process timed out, canceling");
+            records.push("posture", None, start, HandlerResult::Timeout);
+            h.timeout();
+
+            Ok(())
+        };
+
+        // build process task
+        let dir = self.config.handler_dir(&self.config.handler.posture.dir);
+        let handler = HandlerExec::from_config(&dir, &self.config.handler.posture.exec,
+                                               &self.config.handler.posture.exec_script,
+                                               &self.config.handler.posture.args);
+        let ctx = self.context("posture-change", None, None, self.config.handler.posture.timeout);
+        let sandbox = self.config.handler.posture.sandbox;
+        let timer = self.records.timer("posture", None);
+        let timings = timer.clone();
+        let log = self.output("sdtx-handler-posture", self.config.handler.posture.log_level);
+        let (pre, post) = self.hooks(&self.config.handler.posture.pre_exec,
+                                     &self.config.handler.posture.post_exec,
+                                     &dir, &ctx, log, sandbox);
+        let proc = async move {
+            trace!(target: "sdtxd::proc", ?from, ?to, "posture-change process started");
+
+            pre.run(None, "posture-change pre-exec hook").await;
+
+            // run handler if specified
+            if let Some(ref exec) = handler {
+                debug!(target: "sdtxd::proc", ?from, ?to, ?exec, ?dir, "running posture-change handler");
+
+                // run handler
+                let mut cmd = exec.command(&ctx, &sandbox);
+                cmd.current_dir(dir)
+                    .env("SDTX_POSTURE_FROM", device_mode_str(from))
+                    .env("SDTX_POSTURE_TO", device_mode_str(to))
+                    .kill_on_drop(true);
+
+                timer.begin();
+                let output = run_handler(&mut cmd, &ctx, |s| handle.status(s), ignore_extend).await;
+                timer.end(HandlerResult::from(&output));
+                let output = output.context("Subprocess error (posture-change)")?;
+
+                // log output
+                output.log(None, "posture-change handler", log);
+
+                if let Some(signal) = output.status.signal() {
+                    handle.status(HandlerStatus::Crash("posture".into(), signal));
+                }
+
+            } else {
+                debug!(target: "sdtxd::proc", ?from, ?to, "no posture-change handler specified, skipping");
+            };
+
+            trace!(target: "sdtxd::proc", ?from, ?to, "posture-change process completed");
+            handle.complete();
+
+            Ok(())
+        };
+
+        // build task
+        let task = async move {
+            let result = tokio::select! {
+                r = proc      => r,
+                r = timeout   => r,
+            };
+
+            // run post-exec hook, regardless of success
+            post.run(None, "posture-change post-exec hook").await;
+
+            timings.finish();
+            result
         };
 
         // submit task
-        trace!(target: "sdtxd::proc", "scheduling attachment task");
+        trace!(target: "sdtxd::proc", ?from, ?to, "scheduling posture-change task");
         if self.queue.submit(task).is_err() {
             unreachable!("receiver dropped");
         }
@@ -269,12 +807,429 @@ impl Adapter for ProcessAdapter {
 }
 
 
+/// Executable or inline script to run as handler.
+#[derive(Debug, Clone)]
+enum HandlerExec {
+    Path(PathBuf, Vec<String>),
+    Script(String, Vec<String>),
+}
+
+impl HandlerExec {
+    /// Build the handler from its config, resolving a relative executable
+    /// path against the working directory of the handler.
+    fn from_config(dir: &Path, exec: &Option<PathBuf>, script: &Option<String>, args: &[String])
+        -> Option<Self>
+    {
+        exec.as_ref().map(|exec| Self::Path(dir.join(exec), args.to_vec()))
+            .or_else(|| script.clone().map(|script| Self::Script(script, args.to_vec())))
+    }
+
+    /// Build the command for this handler, expanding placeholders in its
+    /// arguments based on the given context.
+    fn command(&self, ctx: &HandlerContext, sandbox: &Sandbox) -> Command {
+        let (mut cmd, args) = match self {
+            Self::Path(path, args) => (Command::new(path), args),
+            Self::Script(script, args) => {
+                // arguments are passed as positional parameters ($1, ...)
+                let mut cmd = Command::new("/bin/sh");
+                cmd.arg("-c").arg(script).arg("sh");
+                (cmd, args)
+            },
+        };
+
+        cmd.args(args.iter().map(|arg| ctx.expand(arg)));
+
+        sandbox::apply(&mut cmd, sandbox);
+        cmd
+    }
+}
+
+
+/// Handler that can be run on request for testing, see [`test_handler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestHandler {
+    Detach,
+    Attach,
+    Abort,
+}
+
+impl std::str::FromStr for TestHandler {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "detach" => Ok(Self::Detach),
+            "attach" => Ok(Self::Attach),
+            "abort"  => Ok(Self::Abort),
+            _        => bail!("unknown handler '{}', expected detach, attach, or abort", s),
+        }
+    }
+}
+
+/// Result and output of a handler test run.
+#[derive(Debug, Clone)]
+pub struct TestOutput {
+    pub result: HandlerResult,
+    pub duration: Duration,
+    pub stdout: String,
+    pub stderr: String,
+    pub messages: Vec<String>,
+}
+
+/// Run the configured handler outside of any detachment or attachment
+/// procedure, with a synthetic dry-run context based on the given device
+/// state. Hooks and built-in steps are not run and the result is not recorded.
+pub async fn test_handler(config: &Config, handler: TestHandler, base: BaseInfo, mode: DeviceMode)
+    -> Result<TestOutput>
+{
+    let h = &config.handler;
+    let (event, dir, exec, script, args, timeout, sandbox, reason) = match handler {
+        TestHandler::Detach => {
+            let c = &h.detach;
+            ("detachment", &c.dir, &c.exec, &c.exec_script, &c.args, c.timeout, c.sandbox, None)
+        },
+        TestHandler::Attach => {
+            let c = &h.attach;
+            ("attachment", &c.dir, &c.exec, &c.exec_script, &c.args, c.timeout, c.sandbox, None)
+        },
+        TestHandler::Abort => {
+            let c = &h.detach_abort;
+            let reason = Some(CancelReason::UserRequest);
+            ("detachment-abort", &c.dir, &c.exec, &c.exec_script, &c.args, c.timeout, c.sandbox, reason)
+        },
+    };
+
+    let dir = config.handler_dir(dir);
+    let exec = HandlerExec::from_config(&dir, exec, script, args)
+        .with_context(|| format!("No {event} handler configured"))?;
+
+    let ctx = HandlerContext::new(event, None, base, mode, reason, timeout, true);
+
+    let mut cmd = exec.command(&ctx, &sandbox);
+    cmd.current_dir(&dir)
+        .kill_on_drop(true);
+
+    if handler == TestHandler::Detach {
+        cmd.env("EXIT_DETACH_COMMENCE", ExitStatus::Commence.as_str())
+            .env("EXIT_DETACH_ABORT", ExitStatus::Abort.as_str())
+            .env("EXIT_DETACH_DEFER", ExitStatus::Defer.as_str());
+    }
+
+    debug!(target: "sdtxd::proc", ?exec, ?dir, "running {} handler test", event);
+
+    let messages = std::sync::Mutex::new(Vec::new());
+    let on_status = |status| {
+        let message = match status {
+            HandlerStatus::Status(msg)         => format!("status: {msg}"),
+            HandlerStatus::Progress(value)     => format!("progress: {value}"),
+            HandlerStatus::Error(msg)          => format!("error: {msg}"),
+            HandlerStatus::Crash(name, signal) => format!("crash: {name} (signal {signal})"),
+        };
+        messages.lock().unwrap().push(message);
+    };
+
+    let start = Instant::now();
+    let output = run_handler(&mut cmd, &ctx, on_status, ignore_extend);
+    let output = tokio::time::timeout(Duration::from_secs_f32(timeout.max(0.0)), output).await;
+    let duration = start.elapsed();
+
+    let (result, stdout, stderr) = match output {
+        Ok(output) => {
+            let result = HandlerResult::from(&output);
+            let output = output.with_context(|| format!("Failed to run {event} handler"))?;
+            (result, output.stdout, output.stderr)
+        },
+        Err(_) => (HandlerResult::Timeout, Vec::new(), Vec::new()),
+    };
+
+    Ok(TestOutput {
+        result,
+        duration,
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        messages: messages.into_inner().unwrap(),
+    })
+}
+
+
+/// Pre- or post-execution hook of a handler.
+struct Hook {
+    path: Option<PathBuf>,
+    dir: PathBuf,
+    ctx: HandlerContext,
+    timeout: Duration,
+    log: OutputLog,
+    sandbox: Sandbox,
+}
+
+impl Hook {
+    /// Run this hook, if specified. Failures are logged but otherwise
+    /// ignored.
+    async fn run(&self, session: Option<SessionId>, name: &'static str) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+
+        let sid = session.map(SessionId::value);
+        debug!(target: "sdtxd::proc", session=sid, ?path, dir=?self.dir, "running {}", name);
+
+        let mut cmd = Command::new(path);
+        sandbox::apply(&mut cmd, &self.sandbox);
+        cmd.current_dir(&self.dir)
+            .kill_on_drop(true);
+
+        let output = run_handler(&mut cmd, &self.ctx, |_| {}, ignore_extend);
+        match tokio::time::timeout(self.timeout, output).await {
+            Ok(Ok(output)) => output.log(session, name, self.log),
+            Ok(Err(err)) => warn!(target: "sdtxd::proc", session=sid, error=%err, "failed to run {}", name),
+            Err(_) => warn!(target: "sdtxd::proc", session=sid, "{} timed out", name),
+        }
+    }
+}
+
+
+/// Wait until udev has processed all queued events, or the timeout expires.
+async fn udev_settle(timeout: Duration) -> Result<()> {
+    let status = Command::new("udevadm")
+        .arg("settle")
+        .arg(format!("--timeout={}", timeout.as_secs_f64().ceil().max(1.0) as u64))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status().await
+        .context("Failed to run udevadm")?;
+
+    if !status.success() {
+        bail!("udevadm settle failed ({status})");
+    }
+
+    Ok(())
+}
+
+
+/// Run the given attachment steps, concurrently where their dependencies
+/// allow it.
+///
+/// Steps that depend on a failed step are skipped. A step that cannot be run
+/// at all, e.g. due to a wrong path, counts as failed. Dependencies are
+/// validated when loading the config, so all steps either run or are skipped.
+async fn run_attach_steps<F>(session: SessionId, mut pending: BTreeMap<String, AttachStep>,
+                             dir: &Path, ctx: &HandlerContext, on_status: F,
+                             log: OutputLog, sandbox: Sandbox)
+where
+    F: Fn(HandlerStatus),
+{
+    let on_status = &on_status;
+    let mut done = BTreeSet::new();
+    let mut failed = BTreeSet::new();
+    let mut running = FuturesUnordered::new();
+
+    loop {
+        // skip all steps that depend on a failed step
+        let skipped: Vec<String> = pending.iter()
+            .filter(|(_, step)| step.after.iter().any(|dep| failed.contains(dep)))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in skipped {
+            warn!(target: "sdtxd::proc", %session, step=%name, "skipping attachment step due to failed dependency");
+
+            pending.remove(&name);
+            failed.insert(name);
+        }
+
+        // start all steps whose dependencies have completed
+        let ready: Vec<String> = pending.iter()
+            .filter(|(_, step)| step.after.iter().all(|dep| done.contains(dep)))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in ready {
+            let step = pending.remove(&name).unwrap();
+
+            debug!(target: "sdtxd::proc", %session, step=%name, path=?step.exec, ?dir,
+                   "running attachment step");
+
+            running.push(async move {
+                let mut cmd = Command::new(dir.join(&step.exec));
+                sandbox::apply(&mut cmd, &sandbox);
+                cmd.current_dir(dir)
+                    .kill_on_drop(true);
+
+                let output = match run_handler(&mut cmd, ctx, on_status, ignore_extend).await {
+                    Ok(output) => output,
+                    Err(err) => {
+                        error!(target: "sdtxd::proc", %session, step=%name, path=?step.exec,
+                               "failed to run attachment step: {}", err);
+                        let msg = format!("Failed to run attachment step '{name}': {err}");
+                        on_status(HandlerStatus::Error(msg));
+
+                        return (name, false);
+                    },
+                };
+
+                output.log(Some(session), format!("attachment step '{name}'"), log);
+
+                (name, output.status.success())
+            });
+        }
+
+        // wait for next step to complete
+        match running.next().await {
+            Some((name, success)) => {
+                if success {
+                    done.insert(name);
+                } else {
+                    failed.insert(name);
+                }
+            },
+            None => break,
+        }
+    }
+}
+
+/// Lock the latch while a handler is running, unless it is already locked.
+/// Returns whether the lock has been taken. Failing to lock the latch is not
+/// fatal, the handler is run regardless.
+fn lock_latch(lock: &LatchLock, session: SessionId, command: impl FnOnce() -> Result<()>) -> bool {
+    match lock.acquire(command) {
+        Ok(locked) => locked,
+        Err(err) => {
+            warn!(target: "sdtxd::proc", %session, "failed to lock latch: {:#}", err);
+            false
+        },
+    }
+}
+
+/// Release the lock taken via `lock_latch()`, keeping any explicit lock.
+fn unlock_latch(lock: &LatchLock, session: SessionId, command: impl FnOnce() -> Result<()>) {
+    if let Err(err) = lock.release(command) {
+        warn!(target: "sdtxd::proc", %session, "failed to unlock latch: {:#}", err);
+    }
+}
+
+/// Run the given handler command, passing the context as JSON document via
+/// stdin and as environment variables, and collect its output.
+///
+/// Status reports and timeout extension requests printed by the handler on
+/// stdout are parsed and forwarded via the provided callbacks as they arrive.
+/// Such lines are not included in the returned output.
+async fn run_handler<F, E>(cmd: &mut Command, ctx: &HandlerContext, on_status: F, on_extend: E)
+    -> std::io::Result<std::process::Output>
+where
+    F: Fn(HandlerStatus),
+    E: Fn(Duration),
+{
+    let mut child = cmd
+        .envs(ctx.env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(sandbox::map_error)?;
+
+    // Write context and close stdin to signal EOF. Handlers are not required
+    // to read their input, so ignore errors due to them having closed stdin
+    // or having exited already.
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(err) = stdin.write_all(&ctx.to_json()).await {
+            trace!(target: "sdtxd::proc", error=%err, "failed to write handler context");
+        }
+    }
+
+    // stdout and stderr have been set up as pipes above
+    let stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+
+    let stdout = async move {
+        let mut reader = BufReader::new(stdout);
+        let mut output = Vec::new();
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).await? == 0 {
+                break;
+            }
+
+            let message = std::str::from_utf8(&line).ok()
+                .and_then(|l| parse_message_line(l.trim_end()));
+
+            match message {
+                Some(HandlerMessage::Status(status)) => on_status(status),
+                Some(HandlerMessage::Extend(time))   => on_extend(time),
+                None                                 => output.extend_from_slice(&line),
+            }
+        }
+
+        std::io::Result::Ok(output)
+    };
+
+    let stderr = async move {
+        let mut output = Vec::new();
+        stderr.read_to_end(&mut output).await?;
+        std::io::Result::Ok(output)
+    };
+
+    let (stdout, stderr) = tokio::try_join!(stdout, stderr)?;
+    let status = child.wait().await?;
+
+    Ok(std::process::Output { status, stdout, stderr })
+}
+
+/// Callback for handlers that do not support timeout extensions.
+fn ignore_extend(time: Duration) {
+    debug!(target: "sdtxd::proc", ?time, "timeout extension not supported for this handler, ignoring");
+}
+
+
+/// Control message sent by a handler via its standard output.
+enum HandlerMessage {
+    Status(HandlerStatus),
+    Extend(Duration),
+}
+
+/// Parse a single line of handler output according to the status protocol.
+///
+/// Returns `None` if the line is not a valid protocol message, in which case
+/// it should be treated as plain log output.
+fn parse_message_line(line: &str) -> Option<HandlerMessage> {
+    if let Some(msg) = line.strip_prefix("STATUS:") {
+        Some(HandlerMessage::Status(HandlerStatus::Status(msg.trim().to_owned())))
+    } else if let Some(value) = line.strip_prefix("PROGRESS:") {
+        value.trim().parse::<u8>().ok()
+            .map(|v| HandlerMessage::Status(HandlerStatus::Progress(v.min(100))))
+    } else if let Some(msg) = line.strip_prefix("ERROR:") {
+        Some(HandlerMessage::Status(HandlerStatus::Error(msg.trim().to_owned())))
+    } else if let Some(value) = line.strip_prefix("EXTEND:") {
+        value.trim().parse::<f32>().ok()
+            .filter(|v| v.is_finite() && *v > 0.0)
+            .map(|v| HandlerMessage::Extend(Duration::from_secs_f32(v)))
+    } else {
+        None
+    }
+}
+
+
+/// Options for logging the output of handler processes.
+#[derive(Debug, Clone, Copy)]
+struct OutputLog {
+    /// Level override, chosen based on the output if unspecified.
+    level: Option<Level>,
+
+    /// Syslog identifier used when forwarding output to the journal. Output
+    /// is written to the daemon log if unspecified.
+    journal: Option<&'static str>,
+}
+
 trait ProcessOutputExt {
-    fn log<S: AsRef<str>>(&self, procname: S);
+    fn log<S: AsRef<str>>(&self, session: Option<SessionId>, procname: S, opts: OutputLog);
 }
 
 impl ProcessOutputExt for std::process::Output {
-    fn log<S: AsRef<str>>(&self, procname: S) {
+    fn log<S: AsRef<str>>(&self, session: Option<SessionId>, procname: S, opts: OutputLog) {
 
         fn log_stream(level: Level, name: &'static str, data: &[u8]) {
             if !data.is_empty() {
@@ -295,16 +1250,166 @@ impl ProcessOutputExt for std::process::Output {
             }
         }
 
-        let level = if !self.stderr.is_empty() {
+        fn journal_stream(ident: &str, session: Option<SessionId>, procname: &str,
+                          name: &'static str, data: &[u8])
+            -> std::io::Result<()>
+        {
+            use tracing_journald::{Priority, PriorityMappings};
+            use tracing_subscriber::layer::SubscriberExt;
+
+            if data.is_empty() {
+                return Ok(());
+            }
+
+            // Send the output via its own journal layer, so that it shows up
+            // under the syslog identifier of the handler instead of ours.
+            let layer = tracing_journald::layer()?
+                .with_field_prefix(None)
+                .with_syslog_identifier(ident.to_owned())
+                .with_priority_mappings(PriorityMappings {
+                    info: Priority::Informational,
+                    ..PriorityMappings::new()
+                });
+
+            let level = match name {
+                "stderr" => Level::WARN,
+                _        => Level::INFO,
+            };
+
+            // the session is left unset for processes run outside of sessions
+            let session = session.map(|s| s.value());
+
+            let subscriber = tracing_subscriber::registry().with(layer);
+            tracing::subscriber::with_default(subscriber, || {
+                for line in String::from_utf8_lossy(data).lines() {
+                    event!(target: "sdtxd::proc", level, sdtx_session_id=session, sdtx_process=procname,
+                           sdtx_stream=name, "{}", line);
+                }
+            });
+
+            Ok(())
+        }
+
+        let level = opts.level.unwrap_or(if self.status.signal().is_some() || !self.stderr.is_empty() {
             tracing::Level::WARN
         } else if !self.stdout.is_empty() {
             tracing::Level::INFO
         } else {
             tracing::Level::DEBUG
-        };
+        });
+
+        let sid = session.map(|s| s.value());
+        if let Some(signal) = self.status.signal() {
+            event!(target: "sdtxd::proc", level, session=sid, signal, "{} crashed ({})", procname.as_ref(), self.status);
+        } else {
+            event!(target: "sdtxd::proc", level, session=sid, "{} exited with {}", procname.as_ref(), self.status);
+        }
+
+        // forward output to the journal if requested, fall back to our own
+        // log for any stream for which that fails
+        for (name, data) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if let Some(ident) = opts.journal {
+                match journal_stream(ident, session, procname.as_ref(), name, data) {
+                    Ok(()) => continue,
+                    Err(err) => {
+                        warn!(target: "sdtxd::proc", session=sid, stream=name, error=%err,
+                              "failed to forward output to journal");
+                    },
+                }
+            }
+
+            log_stream(level, name, data);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::logic::context::BaseContext;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(dir: &Path, name: &str, body: &str) -> AttachStep {
+        let path = dir.join(format!("{}.sh", name));
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        AttachStep { exec: path, after: Vec::new() }
+    }
+
+    fn context() -> HandlerContext {
+        HandlerContext {
+            event: "attach",
+            session: Some(0),
+            base: BaseContext { state: "attached", device_type: "hid".into(), id: 0 },
+            device_mode: "laptop",
+            cancel_reason: None,
+            timeout: 5.0,
+            dry_run: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn attach_steps_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let slow = |name| format!("echo start {0} >> log; sleep 0.5; echo end {0} >> log", name);
+
+        let mut steps = BTreeMap::new();
+        steps.insert("a".to_owned(), script(dir, "a", &slow("a")));
+        steps.insert("b".to_owned(), script(dir, "b", &slow("b")));
+        steps.insert("c".to_owned(), AttachStep {
+            after: vec!["a".into(), "b".into()],
+            ..script(dir, "c", "echo start c >> log")
+        });
+        steps.insert("d".to_owned(), script(dir, "d", "exit 1"));
+        steps.insert("e".to_owned(), AttachStep {
+            after: vec!["d".into()],
+            ..script(dir, "e", "echo start e >> log")
+        });
+
+        let log = OutputLog { level: None, journal: None };
+        run_attach_steps(SessionId::default(), steps, dir, &context(), |_| {}, log,
+                         Sandbox::default()).await;
+
+        let lines = std::fs::read_to_string(dir.join("log")).unwrap();
+        let lines: Vec<_> = lines.lines().collect();
+        let pos = |line| lines.iter().position(|l| *l == line);
+
+        // independent steps run concurrently
+        assert!(pos("start a").unwrap() < pos("end b").unwrap());
+        assert!(pos("start b").unwrap() < pos("end a").unwrap());
+
+        // dependent steps wait for all their dependencies
+        assert!(pos("start c").unwrap() > pos("end a").unwrap());
+        assert!(pos("start c").unwrap() > pos("end b").unwrap());
+
+        // steps depending on a failed step are skipped
+        assert_eq!(pos("start e"), None);
+    }
+
+    #[tokio::test]
+    async fn clean_env() {
+        let sandbox = Sandbox { clean_env: true, ..Sandbox::default() };
+
+        let mut cmd = Command::new("env");
+        sandbox::apply(&mut cmd, &sandbox);
+
+        let output = run_handler(&mut cmd, &context(), |_| {}, ignore_extend).await.unwrap();
+        assert!(output.status.success());
+
+        let output = String::from_utf8(output.stdout).unwrap();
+        let mut vars: Vec<_> = output.lines()
+            .map(|line| line.split('=').next().unwrap())
+            .filter(|name| !name.starts_with("SDTX_"))
+            .collect();
 
-        event!(target: "sdtxd::proc", level, "{} exited with {}", procname.as_ref(), self.status);
-        log_stream(level, "stdout", &self.stdout);
-        log_stream(level, "stderr", &self.stderr);
+        vars.sort_unstable();
+        assert_eq!(vars, ["LANG", "PATH"]);
+        assert!(output.lines().any(|line| line == "SDTX_EVENT=attach"));
     }
 }
@@ -0,0 +1,181 @@
+use crate::config::Sandbox;
+
+use std::io::{Error, Result};
+
+use tokio::process::Command;
+
+
+const SANDBOX_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Bit offset of the setup step in the error code reported by `setup()`.
+const STEP_SHIFT: i32 = 16;
+
+
+/// Steps of the sandbox setup that can fail, see `check()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    TmpNamespace = 1,
+    TmpPrivate,
+    TmpMount,
+    RootNamespace,
+    RootPrivate,
+    RootRemount,
+    NoNewPrivileges,
+}
+
+impl Step {
+    const ALL: [Step; 7] = [
+        Step::TmpNamespace,
+        Step::TmpPrivate,
+        Step::TmpMount,
+        Step::RootNamespace,
+        Step::RootPrivate,
+        Step::RootRemount,
+        Step::NoNewPrivileges,
+    ];
+
+    /// The config option requiring this step.
+    fn option(self) -> &'static str {
+        match self {
+            Step::TmpNamespace | Step::TmpPrivate | Step::TmpMount => "private_tmp",
+            Step::RootNamespace | Step::RootPrivate | Step::RootRemount => "read_only_root",
+            Step::NoNewPrivileges => "no_new_privileges",
+        }
+    }
+
+    fn action(self) -> &'static str {
+        match self {
+            Step::TmpNamespace | Step::RootNamespace => "failed to create mount namespace",
+            Step::TmpPrivate | Step::RootPrivate => "failed to make mounts private",
+            Step::TmpMount => "failed to mount tmpfs on /tmp",
+            Step::RootRemount => "failed to remount / read-only",
+            Step::NoNewPrivileges => "failed to set no_new_privs",
+        }
+    }
+}
+
+
+/// Apply the given sandbox options to a handler command.
+///
+/// This must be called directly after creating the command, as clearing the
+/// environment would otherwise remove any variables set before.
+pub fn apply(cmd: &mut Command, config: &Sandbox) {
+    if config.clean_env {
+        cmd.env_clear()
+            .env("PATH", SANDBOX_PATH)
+            .env("LANG", "C.UTF-8");
+    }
+
+    if !config.private_tmp && !config.read_only_root && !config.no_new_privileges {
+        return;
+    }
+
+    let config = *config;
+
+    // SAFETY: The closure is run in the forked child process before exec and
+    // only performs async-signal-safe system calls without allocating.
+    unsafe {
+        cmd.pre_exec(move || setup(&config));
+    }
+}
+
+fn setup(config: &Sandbox) -> Result<()> {
+    if config.private_tmp || config.read_only_root {
+        let (ns, private) = if config.private_tmp {
+            (Step::TmpNamespace, Step::TmpPrivate)
+        } else {
+            (Step::RootNamespace, Step::RootPrivate)
+        };
+
+        // create a new mount namespace, mounts below are private to the handler
+        check(unsafe { libc::unshare(libc::CLONE_NEWNS) }, ns)?;
+
+        // don't propagate any mount changes back to the host
+        check(unsafe {
+            libc::mount(std::ptr::null(), c_str(b"/\0"), std::ptr::null(),
+                        libc::MS_REC | libc::MS_PRIVATE, std::ptr::null())
+        }, private)?;
+    }
+
+    if config.private_tmp {
+        check(unsafe {
+            libc::mount(c_str(b"tmpfs\0"), c_str(b"/tmp\0"), c_str(b"tmpfs\0"),
+                        libc::MS_NOSUID | libc::MS_NODEV, c_str(b"mode=1777\0").cast())
+        }, Step::TmpMount)?;
+    }
+
+    if config.read_only_root {
+        // only changes the flags of the root mount in our namespace, other
+        // mounts (including /tmp above) remain writable
+        check(unsafe {
+            libc::mount(std::ptr::null(), c_str(b"/\0"), std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY, std::ptr::null())
+        }, Step::RootRemount)?;
+    }
+
+    if config.no_new_privileges {
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }, Step::NoNewPrivileges)?;
+    }
+
+    Ok(())
+}
+
+/// Translate an error returned when spawning a sandboxed command into one
+/// naming the sandbox option that failed. Other errors are returned as is.
+pub fn map_error(err: Error) -> Error {
+    let code = match err.raw_os_error() {
+        Some(code) => code,
+        None => return err,
+    };
+
+    let step = Step::ALL.iter().copied()
+        .find(|step| *step as i32 == code >> STEP_SHIFT);
+
+    let step = match step {
+        Some(step) => step,
+        None => return err,
+    };
+
+    let cause = Error::from_raw_os_error(code & ((1 << STEP_SHIFT) - 1));
+    Error::new(cause.kind(), format!("sandbox option '{}': {}: {}", step.option(), step.action(), cause))
+}
+
+fn c_str(s: &'static [u8]) -> *const libc::c_char {
+    s.as_ptr().cast()
+}
+
+/// Check the return value of a system call made during setup.
+///
+/// Errors of pre-exec closures are passed to the parent as plain error code,
+/// so encode the failed step in the bits above the errno. This can later be
+/// decoded by `map_error()`.
+fn check(ret: libc::c_int, step: Step) -> Result<()> {
+    if ret < 0 {
+        let errno = Error::last_os_error().raw_os_error().unwrap_or(libc::EINVAL);
+        Err(Error::from_raw_os_error((step as i32) << STEP_SHIFT | errno))
+    } else {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn map_errors() {
+        let err = Error::from_raw_os_error((Step::TmpMount as i32) << STEP_SHIFT | libc::ENOENT);
+        let err = map_error(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), format!("sandbox option 'private_tmp': failed to mount tmpfs on /tmp: {}",
+                                            Error::from_raw_os_error(libc::ENOENT)));
+
+        let err = Error::from_raw_os_error((Step::RootNamespace as i32) << STEP_SHIFT | libc::EPERM);
+        assert!(map_error(err).to_string().starts_with("sandbox option 'read_only_root': failed to create"));
+
+        // plain errors, e.g. from exec, are passed through
+        let err = map_error(Error::from_raw_os_error(libc::ENOENT));
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::device::{BaseFirmware, BatteryInfo};
 use crate::logic::{
     Adapter,
     AtHandle,
@@ -6,21 +7,70 @@ use crate::logic::{
     DeviceMode,
     DtHandle,
     DtcHandle,
+    HandlerStatus,
     LatchState,
     LatchStatus,
+    SessionId,
 };
 use crate::service::{ServiceHandle, Event};
 
+use std::time::Duration;
+
 use anyhow::Result;
 
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use tracing::Instrument;
+
 
 pub struct ServiceAdapter {
     service: ServiceHandle,
+    latch_timeout: Duration,
+    countdown: Option<JoinHandle<()>>,
 }
 
 impl ServiceAdapter {
-    pub fn new(service: ServiceHandle) -> Self {
-        Self { service }
+    pub fn new(service: ServiceHandle, latch_timeout: Duration) -> Self {
+        Self { service, latch_timeout, countdown: None }
+    }
+
+    /// Emit the remaining time until the EC closes the latch again once per
+    /// second, until the countdown is stopped or has run out.
+    fn start_countdown(&mut self, session: SessionId) {
+        self.stop_countdown();
+
+        if self.latch_timeout.is_zero() {
+            return;
+        }
+
+        let service = self.service.clone();
+        let deadline = Instant::now() + self.latch_timeout;
+
+        let countdown = async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+            loop {
+                interval.tick().await;
+
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let remaining = remaining.as_secs_f32().round() as u32;
+
+                service.emit_event(session, Event::DetachmentCountdown { remaining });
+
+                if remaining == 0 {
+                    break;
+                }
+            }
+        };
+
+        self.countdown = Some(tokio::spawn(countdown.in_current_span()));
+    }
+
+    fn stop_countdown(&mut self) {
+        if let Some(task) = self.countdown.take() {
+            task.abort();
+        }
     }
 }
 
@@ -32,11 +82,26 @@ impl Adapter for ServiceAdapter {
     }
 
     fn on_base_state(&mut self, info: BaseInfo) -> Result<()> {
+        self.stop_countdown();
         self.service.set_base_info(info);
         Ok(())
     }
 
+    fn on_base_battery(&mut self, battery: Option<BatteryInfo>) -> Result<()> {
+        self.service.set_base_battery(battery);
+        Ok(())
+    }
+
+    fn on_base_firmware(&mut self, firmware: Option<BaseFirmware>) -> Result<()> {
+        self.service.set_base_firmware(firmware);
+        Ok(())
+    }
+
     fn on_latch_status(&mut self, status: LatchStatus) -> Result<()> {
+        if status != LatchStatus::Opened {
+            self.stop_countdown();
+        }
+
         self.service.set_latch_status(status);
         Ok(())
     }
@@ -46,63 +111,88 @@ impl Adapter for ServiceAdapter {
         Ok(())
     }
 
-    fn request_inhibited(&mut self, reason: CancelReason) -> Result<()> {
-        self.service.emit_event(Event::DetachmentInhibited { reason });
+    fn request_inhibited(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentInhibited { reason });
+        Ok(())
+    }
+
+    fn detachment_start(&mut self, session: SessionId, _handle: DtHandle) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentStart);
         Ok(())
     }
 
-    fn detachment_start(&mut self, _handle: DtHandle) -> Result<()> {
-        self.service.emit_event(Event::DetachmentStart);
+    fn detachment_ready(&mut self, session: SessionId) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentReady);
+        self.start_countdown(session);
         Ok(())
     }
 
-    fn detachment_ready(&mut self) -> Result<()> {
-        self.service.emit_event(Event::DetachmentReady);
+    fn detachment_complete(&mut self, session: SessionId) -> Result<()> {
+        self.stop_countdown();
+        self.service.emit_event(session, Event::DetachmentComplete);
         Ok(())
     }
 
-    fn detachment_complete(&mut self) -> Result<()> {
-        self.service.emit_event(Event::DetachmentComplete);
+    fn detachment_cancel(&mut self, session: SessionId, reason: CancelReason) -> Result<()> {
+        self.stop_countdown();
+        self.service.emit_event(session, Event::DetachmentCancel { reason });
         Ok(())
     }
 
-    fn detachment_cancel(&mut self, reason: CancelReason) -> Result<()> {
-        self.service.emit_event(Event::DetachmentCancel { reason });
+    fn detachment_cancel_start(&mut self, session: SessionId, _handle: DtcHandle) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentCancelStart);
         Ok(())
     }
 
-    fn detachment_cancel_start(&mut self, _handle: DtcHandle) -> Result<()> {
-        self.service.emit_event(Event::DetachmentCancelStart);
+    fn detachment_cancel_complete(&mut self, session: SessionId) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentCancelComplete);
         Ok(())
     }
 
-    fn detachment_cancel_complete(&mut self) -> Result<()> {
-        self.service.emit_event(Event::DetachmentCancelComplete);
+    fn detachment_cancel_timeout(&mut self, session: SessionId) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentCancelTimeout);
         Ok(())
     }
 
-    fn detachment_cancel_timeout(&mut self) -> Result<()> {
-        self.service.emit_event(Event::DetachmentCancelTimeout);
+    fn detachment_unexpected(&mut self, session: SessionId) -> Result<()> {
+        self.stop_countdown();
+        self.service.emit_event(session, Event::DetachmentUnexpected);
         Ok(())
     }
 
-    fn detachment_unexpected(&mut self) -> Result<()> {
-        self.service.emit_event(Event::DetachmentUnexpected);
+    fn detachment_slow(&mut self, session: SessionId) -> Result<()> {
+        self.service.emit_event(session, Event::DetachmentHandlerSlow);
         Ok(())
     }
 
-    fn attachment_start(&mut self, _handle: AtHandle) -> Result<()> {
-        self.service.emit_event(Event::AttachmentStart);
+    fn attachment_start(&mut self, session: SessionId, _handle: AtHandle) -> Result<()> {
+        self.service.emit_event(session, Event::AttachmentStart);
         Ok(())
     }
 
-    fn attachment_complete(&mut self) -> Result<()> {
-        self.service.emit_event(Event::AttachmentComplete);
+    fn attachment_complete(&mut self, session: SessionId) -> Result<()> {
+        self.service.emit_event(session, Event::AttachmentComplete);
+        Ok(())
+    }
+
+    fn attachment_timeout(&mut self, session: SessionId) -> Result<()> {
+        self.service.emit_event(session, Event::AttachmentTimeout);
+        Ok(())
+    }
+
+    fn handler_status(&mut self, session: Option<SessionId>, status: HandlerStatus) -> Result<()> {
+        let event = Event::HandlerStatus { status };
+
+        match session {
+            Some(session) => self.service.emit_event(session, event),
+            None          => self.service.emit_global_event(event),
+        }
+
         Ok(())
     }
 
-    fn attachment_timeout(&mut self) -> Result<()> {
-        self.service.emit_event(Event::AttachmentTimeout);
+    fn base_feasible(&mut self) -> Result<()> {
+        self.service.emit_global_event(Event::BaseFeasible);
         Ok(())
     }
 }
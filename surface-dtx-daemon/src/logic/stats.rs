@@ -0,0 +1,263 @@
+use crate::logic::SessionId;
+use crate::metrics::{Histogram, Metrics};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::os::unix::process::ExitStatusExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::time::Instant;
+
+use tracing::info;
+
+
+const MAX_RECORDS: usize = 64;
+
+
+/// Runtime information about a single handler invocation.
+#[derive(Debug, Clone)]
+pub struct HandlerRecord {
+    pub handler: &'static str,
+    pub session: Option<SessionId>,
+    pub time: SystemTime,
+    pub duration: Duration,
+    pub result: HandlerResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerResult {
+    Exited(i32),
+    Signaled(i32),
+    Timeout,
+    Error,
+}
+
+impl From<&std::io::Result<std::process::Output>> for HandlerResult {
+    fn from(output: &std::io::Result<std::process::Output>) -> Self {
+        let status = match output {
+            Ok(output) => output.status,
+            Err(_) => return Self::Error,
+        };
+
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Self::Exited(code),
+            (_, Some(signal)) => Self::Signaled(signal),
+            _ => Self::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for HandlerResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Exited(code)     => write!(f, "exit:{code}"),
+            Self::Signaled(signal) => write!(f, "signal:{signal}"),
+            Self::Timeout          => write!(f, "timeout"),
+            Self::Error            => write!(f, "error"),
+        }
+    }
+}
+
+
+/// Accumulated statistics of all invocations of a single handler.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandlerTotals {
+    pub count: u64,
+    pub failures: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl HandlerTotals {
+    fn add(&mut self, record: &HandlerRecord) {
+        self.count += 1;
+        self.total += record.duration;
+        self.max = self.max.max(record.duration);
+
+        if record.result != HandlerResult::Exited(0) {
+            self.failures += 1;
+        }
+    }
+}
+
+
+/// Stage of a handler task, for timing purposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Time from scheduling the task until the handler has been started,
+    /// including queueing, delays, pre-exec hooks, and deferrals.
+    Delay,
+
+    /// Time spent running the handler, summed over all runs.
+    Exec,
+
+    /// Time from scheduling the task until it has been completed.
+    Total,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Delay => "delay",
+            Self::Exec  => "exec",
+            Self::Total => "total",
+        }
+    }
+}
+
+
+/// The last handler invocations, shared between the process adapter and the
+/// D-Bus service.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerRecords {
+    inner: Arc<Mutex<Records>>,
+    metrics: Metrics,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    recent: VecDeque<HandlerRecord>,
+    totals: BTreeMap<&'static str, HandlerTotals>,
+    stages: BTreeMap<(&'static str, Stage), Histogram>,
+}
+
+impl HandlerRecords {
+    /// Create new records, also reporting the duration of each invocation to
+    /// the given metrics.
+    pub fn new(metrics: Metrics) -> Self {
+        Self { inner: Arc::default(), metrics }
+    }
+
+    /// Record a handler invocation that has been started at the given time.
+    pub fn push(&self, handler: &'static str, session: Option<SessionId>, start: Instant,
+                result: HandlerResult)
+    {
+        let duration = start.elapsed();
+        self.metrics.handler(handler, duration);
+
+        let record = HandlerRecord {
+            handler,
+            session,
+            time: SystemTime::now() - duration,
+            duration,
+            result,
+        };
+
+        let mut records = self.inner.lock().unwrap();
+        records.totals.entry(handler).or_default().add(&record);
+
+        if records.recent.len() >= MAX_RECORDS {
+            records.recent.pop_front();
+        }
+        records.recent.push_back(record);
+    }
+
+    /// Get all recorded invocations, oldest first.
+    pub fn get(&self) -> Vec<HandlerRecord> {
+        self.inner.lock().unwrap().recent.iter().cloned().collect()
+    }
+
+    /// Get the accumulated statistics of each handler since startup.
+    pub fn totals(&self) -> Vec<(&'static str, HandlerTotals)> {
+        self.inner.lock().unwrap().totals.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    /// Get the duration histograms of each handler stage since startup.
+    pub fn histograms(&self) -> Vec<(&'static str, Stage, Histogram)> {
+        self.inner.lock().unwrap().stages.iter().map(|((h, s), v)| (*h, *s, *v)).collect()
+    }
+
+    /// Start timing the stages of a handler task, which is being scheduled
+    /// now.
+    pub fn timer(&self, handler: &'static str, session: Option<SessionId>) -> StageTimer {
+        let timings = Timings { scheduled: Instant::now(), delay: None, exec: Duration::ZERO, running: None };
+
+        StageTimer {
+            records: self.clone(),
+            handler,
+            session,
+            timings: Arc::new(Mutex::new(timings)),
+        }
+    }
+
+    fn observe(&self, handler: &'static str, stage: Stage, duration: Duration) {
+        let mut records = self.inner.lock().unwrap();
+        records.stages.entry((handler, stage)).or_default().observe(duration);
+    }
+}
+
+
+/// Wall-clock timings of the stages of a single handler task, shared between
+/// the process task running the handler and the task driving it.
+#[derive(Debug, Clone)]
+pub struct StageTimer {
+    records: HandlerRecords,
+    handler: &'static str,
+    session: Option<SessionId>,
+    timings: Arc<Mutex<Timings>>,
+}
+
+#[derive(Debug)]
+struct Timings {
+    scheduled: Instant,
+    delay: Option<Duration>,
+    exec: Duration,
+    running: Option<Instant>,
+}
+
+impl StageTimer {
+    /// Mark the start of a handler run.
+    pub fn begin(&self) {
+        let mut timings = self.timings.lock().unwrap();
+        let now = Instant::now();
+
+        let delay = now - timings.scheduled;
+        timings.delay.get_or_insert(delay);
+        timings.running = Some(now);
+    }
+
+    /// Mark the end of a handler run and record its invocation.
+    pub fn end(&self, result: HandlerResult) {
+        let start = {
+            let mut timings = self.timings.lock().unwrap();
+            let start = timings.running.take().unwrap_or(timings.scheduled);
+
+            timings.exec += start.elapsed();
+            start
+        };
+
+        self.records.push(self.handler, self.session, start, result);
+    }
+
+    /// Log the timings of all stages and add them to the histograms. Stages
+    /// that have not been reached, e.g. because no handler has been run, are
+    /// omitted.
+    pub fn finish(self) {
+        let (delay, exec, total) = {
+            let timings = self.timings.lock().unwrap();
+
+            // a handler may still be running if the task has timed out
+            let running = timings.running.map(|start| start.elapsed()).unwrap_or_default();
+            let exec = timings.delay.map(|_| timings.exec + running);
+
+            (timings.delay, exec, timings.scheduled.elapsed())
+        };
+
+        let handler = self.handler;
+        match self.session {
+            Some(session) => {
+                info!(target: "sdtxd::proc", %session, handler, ?delay, ?exec, ?total, "handler task timings");
+            },
+            None => {
+                info!(target: "sdtxd::proc", handler, ?delay, ?exec, ?total, "handler task timings");
+            },
+        }
+
+        let stages = [(Stage::Delay, delay), (Stage::Exec, exec), (Stage::Total, Some(total))];
+        for (stage, duration) in stages {
+            if let Some(duration) = duration {
+                self.records.observe(handler, stage, duration);
+            }
+        }
+    }
+}
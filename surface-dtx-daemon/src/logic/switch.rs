@@ -0,0 +1,189 @@
+use crate::config::TabletSwitch;
+use crate::logic::{Adapter, BaseInfo, DeviceMode, LatchState};
+use crate::service::{Event, ServiceHandle};
+use crate::utils::task::{JoinGuard, JoinHandleExt};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use evdev::{AttributeSet, Device, EventType, InputEvent, SwitchCode};
+use evdev::uinput::VirtualDevice;
+
+use tracing::{debug, info, warn};
+
+
+const DEVICE_NAME: &str = "Surface DTX Tablet Mode Switch";
+
+/// Delay after a device mode change before checking the platform switch.
+const CHECK_DELAY: Duration = Duration::from_secs(2);
+
+
+/// Adapter reporting the device mode via the SW_TABLET_MODE switch of a
+/// virtual input device. Does nothing if the switch is disabled.
+pub struct TabletSwitchAdapter {
+    device: Option<VirtualDevice>,
+    studio: bool,
+    state: Option<bool>,
+}
+
+impl TabletSwitchAdapter {
+    pub fn new(config: &TabletSwitch) -> Result<Self> {
+        let device = if config.enabled {
+            Some(create()?)
+        } else {
+            None
+        };
+
+        Ok(Self { device, studio: config.studio, state: None })
+    }
+
+    fn update(&mut self, mode: DeviceMode) {
+        let device = match &mut self.device {
+            Some(device) => device,
+            None => return,
+        };
+
+        let tablet = match mode {
+            DeviceMode::Laptop => false,
+            DeviceMode::Tablet => true,
+            DeviceMode::Studio => self.studio,
+        };
+
+        if self.state == Some(tablet) {
+            return;
+        }
+
+        debug!(target: "sdtxd::core", tablet, "updating tablet-mode switch");
+
+        let event = InputEvent::new(EventType::SWITCH.0, SwitchCode::SW_TABLET_MODE.0, tablet as i32);
+
+        // failing to update the switch should not affect the core, so only warn
+        match device.emit(&[event]) {
+            Ok(()) => self.state = Some(tablet),
+            Err(err) => warn!(target: "sdtxd::core", error=%err, "failed to update tablet-mode switch"),
+        }
+    }
+}
+
+impl Adapter for TabletSwitchAdapter {
+    fn set_state(&mut self, mode: DeviceMode, _base: BaseInfo, _latch: LatchState) {
+        self.update(mode);
+    }
+
+    fn on_device_mode(&mut self, mode: DeviceMode) -> Result<()> {
+        self.update(mode);
+        Ok(())
+    }
+}
+
+
+fn create() -> Result<VirtualDevice> {
+    let mut switches = AttributeSet::<SwitchCode>::new();
+    switches.insert(SwitchCode::SW_TABLET_MODE);
+
+    let device = VirtualDevice::builder()
+        .and_then(|b| b.name(DEVICE_NAME).with_switches(&switches))
+        .and_then(|b| b.build())
+        .context("Failed to create virtual tablet-mode switch")?;
+
+    Ok(device)
+}
+
+
+/// Adapter comparing the device mode reported by the DTX device with the
+/// SW_TABLET_MODE switch of the platform (e.g. provided by the Surface
+/// Aggregator Module or intel-vbtn). Does nothing if the check is disabled or
+/// no such switch is present.
+///
+/// The switch is checked some time after each device mode change, as both
+/// are reported independently and may not be updated at the same time. A
+/// mismatch is logged and emitted as diagnostic event.
+pub struct TabletSwitchCheckAdapter {
+    switch: Option<Arc<Mutex<Device>>>,
+    service: ServiceHandle,
+    studio: bool,
+    check: Option<JoinGuard<()>>,
+}
+
+impl TabletSwitchCheckAdapter {
+    pub fn new(config: &TabletSwitch, service: ServiceHandle) -> Self {
+        let switch = if config.verify { find_platform_switch() } else { None };
+        let switch = switch.map(|device| Arc::new(Mutex::new(device)));
+
+        Self { switch, service, studio: config.studio, check: None }
+    }
+
+    fn schedule(&mut self, mode: DeviceMode) {
+        let switch = match &self.switch {
+            Some(switch) => switch.clone(),
+            None => return,
+        };
+
+        let expected = match mode {
+            DeviceMode::Laptop => false,
+            DeviceMode::Tablet => true,
+            DeviceMode::Studio => self.studio,
+        };
+
+        let service = self.service.clone();
+
+        // replaces and thereby cancels any pending check
+        self.check = Some(tokio::spawn(async move {
+            tokio::time::sleep(CHECK_DELAY).await;
+
+            let tablet = match switch.lock().unwrap().get_switch_state() {
+                Ok(state) => state.contains(SwitchCode::SW_TABLET_MODE),
+                Err(err) => {
+                    warn!(target: "sdtxd::core", error=%err, "failed to read platform tablet-mode switch");
+                    return;
+                },
+            };
+
+            if tablet == expected {
+                debug!(target: "sdtxd::core", ?mode, tablet, "platform tablet-mode switch matches device mode");
+                return;
+            }
+
+            warn!(target: "sdtxd::core", ?mode, tablet,
+                  "device mode reported by DTX does not match platform tablet-mode switch");
+
+            service.emit_global_event(Event::DeviceModeMismatch { mode, tablet_switch: tablet });
+        }).guard());
+    }
+}
+
+impl Adapter for TabletSwitchCheckAdapter {
+    fn set_state(&mut self, mode: DeviceMode, _base: BaseInfo, _latch: LatchState) {
+        self.schedule(mode);
+    }
+
+    fn on_device_mode(&mut self, mode: DeviceMode) -> Result<()> {
+        self.schedule(mode);
+        Ok(())
+    }
+}
+
+
+/// Find the input device providing the SW_TABLET_MODE switch of the
+/// platform, ignoring our own virtual switch.
+fn find_platform_switch() -> Option<Device> {
+    let found = evdev::enumerate().find(|(_, device)| {
+        let has_switch = device.supported_switches()
+            .is_some_and(|s| s.contains(SwitchCode::SW_TABLET_MODE));
+
+        has_switch && device.name() != Some(DEVICE_NAME)
+    });
+
+    match found {
+        Some((path, device)) => {
+            info!(target: "sdtxd::core", ?path, name=?device.name(), "checking device mode against platform tablet-mode switch");
+            Some(device)
+        },
+        None => {
+            warn!(target: "sdtxd::core", "no platform tablet-mode switch found, not checking device mode");
+            None
+        },
+    }
+}
@@ -1,21 +1,33 @@
 #[macro_use]
 mod utils;
+use utils::logfile::RotatingFile;
+use utils::loglevel::LogControl;
 use utils::task::JoinHandleExt;
 
 mod cli;
 
 mod config;
-use config::Config;
+use config::{Config, Diagnostics, LogBackend};
+
+mod device;
+use device::{Device, Hotplug, MockDevice, Record, Selection};
+
+mod manager;
+use manager::{DeviceHandles, DeviceManager};
 
 mod logic;
+use logic::{DeviceContext, DryRun};
+
+mod metrics;
+use metrics::Metrics;
 
 mod service;
 use service::Service;
 
 
-use std::{sync::{Arc, Mutex}, path::PathBuf, io::IsTerminal};
+use std::{sync::{Arc, Mutex}, path::PathBuf, io::IsTerminal, time::Duration};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use dbus::channel::MatchingReceiver;
 use dbus::message::MatchRule;
@@ -24,12 +36,22 @@ use dbus_crossroads::Crossroads;
 
 use futures::prelude::*;
 
-use tokio::signal::unix::{signal, SignalKind};
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
-use tracing::{error, info, trace, warn};
+use tracing::{debug, error, info, trace, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::prelude::*;
 
 
-fn bootstrap() -> Result<Config> {
+/// Recorded events to replay instead of using the actual devices.
+struct Replay {
+    path: PathBuf,
+    speed: f64,
+}
+
+fn bootstrap(log_guard: &mut Option<WorkerGuard>)
+    -> Result<(Config, Diagnostics, DryRun, LogControl, Option<Replay>)>
+{
     // handle command line input
     let matches = cli::app().get_matches();
 
@@ -40,51 +62,130 @@ fn bootstrap() -> Result<Config> {
     };
 
     // set up logger
-    let filter = tracing_subscriber::EnvFilter::from_env("SDTXD_LOG")
-        .add_directive(tracing::Level::from(config.log.level).into());
+    let (log, filter) = LogControl::new(config.log.level);
+
+    // records are also written to the log file, if configured
+    let file = match config.log.file {
+        Some(ref path) => {
+            let max_size = config.log.file_max_size.saturating_mul(1024);
+            let file = RotatingFile::open(path, max_size, config.log.file_rotations)
+                .with_context(|| format!("Failed to open log file '{}'", path.display()))?;
+
+            // write from a separate thread to not block on file I/O, the
+            // guard flushes pending records when dropped
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            *log_guard = Some(guard);
+
+            let layer = tracing_subscriber::fmt::layer()
+                .fmt_fields(tracing_subscriber::fmt::format::PrettyFields::new())
+                .with_ansi(false)
+                .with_writer(writer);
+
+            Some(layer)
+        },
+        None => None,
+    };
+
+    let registry = tracing_subscriber::registry().with(filter).with(file);
 
-    let fmt = tracing_subscriber::fmt::format::PrettyFields::new();
+    // Fields of records and their spans (e.g. the session of a detachment
+    // process) are sent as journal fields, without prefix, so that they can
+    // be queried via e.g. "journalctl SESSION=<id>".
+    let (journal, journal_err) = match config.log.backend {
+        LogBackend::Journal => match tracing_journald::layer() {
+            Ok(layer) => {
+                let layer = layer.with_field_prefix(None)
+                    .with_syslog_identifier("surface-dtx-daemon".into());
 
-    let subscriber = tracing_subscriber::fmt()
-        .fmt_fields(fmt)
-        .with_env_filter(filter)
-        .with_ansi(std::io::stdout().is_terminal());
+                (Some(layer), None)
+            },
+            Err(err) => (None, Some(err)),
+        },
+        LogBackend::Stdout => (None, None),
+    };
 
-    if matches.get_flag("no-log-time") {
-        subscriber.without_time().init();
+    if let Some(journal) = journal {
+        registry.with(journal).init();
     } else {
-        subscriber.init();
+        let fmt = tracing_subscriber::fmt::format::PrettyFields::new();
+
+        let layer = tracing_subscriber::fmt::layer()
+            .fmt_fields(fmt)
+            .with_ansi(std::io::stdout().is_terminal());
+
+        if matches.get_flag("no-log-time") {
+            registry.with(layer.without_time()).init();
+        } else {
+            registry.with(layer).init();
+        }
+    }
+
+    if let Some(err) = journal_err {
+        warn!(target: "sdtxd", error=%err, "systemd journal not available, logging to stdout instead");
     }
 
     // warn about unknown config items
     diag.log();
 
-    Ok(config)
+    let dry_run = DryRun::new(matches.get_flag("dry-run"));
+    if dry_run.get() {
+        warn!(target: "sdtxd", "running in dry-run mode, the latch will not be opened");
+    }
+
+    let replay = matches.get_one::<PathBuf>("replay").map(|path| Replay {
+        path: path.clone(),
+        speed: *matches.get_one::<f64>("replay-speed").unwrap(),
+    });
+
+    if let Some(ref replay) = replay {
+        if !(replay.speed.is_finite() && replay.speed > 0.0) {
+            bail!("Invalid replay speed: {}", replay.speed);
+        }
+    }
+
+    Ok((config, diag, dry_run, log, replay))
 }
 
-async fn run() -> Result<()> {
-    let config = bootstrap()?;
+async fn run(log_guard: &mut Option<WorkerGuard>) -> Result<()> {
+    let (config, diag, dry_run, log, replay) = bootstrap(log_guard)?;
 
     // set up signal handling
     trace!(target: "sdtxd", "setting up signal handling");
 
     let mut sigint = signal(SignalKind::interrupt()).context("Failed to set up signal handling")?;
     let mut sigterm = signal(SignalKind::terminate()).context("Failed to set up signal handling")?;
+    let sigusr2 = signal(SignalKind::user_defined2()).context("Failed to set up signal handling")?;
+
+    // prepare devices, waiting for them if necessary
+    trace!(target: "sdtxd", "preparing devices");
+
+    // devices are static when replaying, otherwise follow hotplug events
+    let follow_hotplug = replay.is_none();
+    let selection = device_selection(&config);
+
+    let devices = async {
+        match replay {
+            Some(replay) => replay_device(&replay),
+            None => open_devices(&config, &selection).await,
+        }
+    };
+
+    let devices = tokio::select! {
+        devices = devices => devices?,
+        signame = async { tokio::select! {
+            _ = sigint.recv()  => "SIGINT",
+            _ = sigterm.recv() => "SIGTERM",
+        }} => {
+            info!(target: "sdtxd", "received {} while waiting for DTX device, shutting down...", signame);
+            return Ok(());
+        },
+    };
 
     let sig = async { tokio::select! {
         _ = sigint.recv()  => "SIGINT",
         _ = sigterm.recv() => "SIGTERM",
     }};
 
-    // prepare devices
-    trace!(target: "sdtxd", "preparing devices");
-
-    let event_device = sdtx_tokio::connect().await
-        .context("Failed to access DTX device")?;
-
-    let control_device = sdtx_tokio::connect().await
-        .context("Failed to access DTX device")?;
-
     // set up D-Bus connection
     trace!(target: "sdtxd", "connecting to D-Bus");
 
@@ -99,9 +200,34 @@ async fn run() -> Result<()> {
 
     let dbus_cr = Arc::new(Mutex::new(Crossroads::new()));
 
-    let serv = Service::new(dbus_conn.clone(), control_device);
-    serv.request_name().await?;
-    serv.register(&mut dbus_cr.lock().unwrap())?;
+    // some methods (e.g. handler tests) run asynchronously on the runtime
+    let spawn = Box::new(|task| { tokio::spawn(task); });
+    dbus_cr.lock().unwrap().set_async_support(Some((dbus_conn.clone(), spawn)));
+
+    // set up task-queue, shared by all devices
+    trace!(target: "sdtxd", "setting up task queue");
+
+    let (mut queue, queue_tx) = utils::taskq::new();
+    let mut queue_task = tokio::spawn(async move { queue.run().await }).guard();
+
+    // set up per-device services and event handlers
+    trace!(target: "sdtxd", "setting up DTX event handling");
+
+    // set up metrics exporter, if enabled
+    let metrics = Metrics::default();
+
+    if let Some(ref address) = config.metrics.listen {
+        trace!(target: "sdtxd", "setting up metrics exporter");
+        metrics::serve(address, metrics.clone()).await?;
+    }
+
+    let ctx = DeviceContext::new(dry_run, log.clone(), metrics);
+    let mut manager = DeviceManager::new(config, diag, ctx, dbus_conn.clone(), dbus_cr.clone(),
+                                         queue_tx);
+
+    for device in devices {
+        manager.add(device)?;
+    }
 
     let cr = dbus_cr.clone();
     let token = dbus_conn.start_receive(MatchRule::new_method_call(), Box::new(move |msg, conn| {
@@ -111,46 +237,66 @@ async fn run() -> Result<()> {
     }));
 
     let recv_guard = utils::scope::guard(|| { let _ = dbus_conn.stop_receive(token).unwrap(); });
-    let serv_guard = utils::scope::guard(|| { serv.unregister(&mut dbus_cr.lock().unwrap()); });
 
-    // set up task-queue
-    trace!(target: "sdtxd", "setting up task queue");
+    Service::request_name(&dbus_conn).await?;
 
-    let (mut queue, queue_tx) = utils::taskq::new();
-    let mut queue_task = tokio::spawn(async move { queue.run().await }).guard();
+    // set up suspend/resume monitoring
+    trace!(target: "sdtxd", "setting up suspend monitoring");
 
-    // set up event handler
-    trace!(target: "sdtxd", "setting up DTX event handling");
+    let mr = MatchRule::new_signal("org.freedesktop.login1.Manager", "PrepareForSleep");
+    let (_sleep_msgs, mut sleep_stream) = dbus_conn
+        .add_match(mr).await
+        .context("Failed to set up D-Bus connection")?
+        .msg_stream();
+
+    let (sleep_tx, sleep_rx) = tokio::sync::mpsc::unbounded_channel();
 
-    let proc_adp = logic::ProcessAdapter::new(config, queue_tx);
-    let srvc_adp = logic::ServiceAdapter::new(serv.handle());
+    let mut sleep_task = tokio::spawn(async move {
+        while let Some(msg) = sleep_stream.next().await {
+            let active: bool = msg.read1().context("Protocol error")?;
+            let _ = sleep_tx.send(active);
+        }
+
+        Ok(())
+    }).guard();
+
+    // set up device hotplug monitoring
+    let hotplug = if follow_hotplug {
+        trace!(target: "sdtxd", "setting up hotplug monitoring");
+        Some(Hotplug::new(selection))
+    } else {
+        None
+    };
 
-    let mut core = logic::Core::new(event_device, (proc_adp, srvc_adp));
-    let mut event_task = tokio::spawn(async move { core.run().await }).guard();
+    let mut manager_task = tokio::spawn(manager.run(sleep_rx, hotplug)).guard();
 
     // collect main driver tasks
     let tasks = async { tokio::select! {
         result = &mut dbus_task  => result,
-        result = &mut event_task => result,
+        result = &mut manager_task => result,
         result = &mut queue_task => result,
+        result = &mut sleep_task => result,
+        result = watchdog() => result,
+        result = log_toggle(sigusr2, log) => result,
     }};
 
     // run until whatever comes first: error, panic, or shutdown signal
     info!(target: "sdtxd", "running...");
 
+    // devices are open and the D-Bus name has been acquired
+    utils::notify::ready();
+
     tokio::select! {
         signame = sig => {
             // first shutdown signal: try to do a clean shutdown and complete
             // the task queue
             info!(target: "sdtxd", "received {}, shutting down...", signame);
+            utils::notify::stopping();
 
-            // stop event task: don't handle any new DTX events and drop task
-            // queue transmitter to eventually cause the task queue task to
-            // complete
-            event_task.abort();
-
-            // unregister service
-            drop(serv_guard);
+            // stop device manager: don't handle any new DTX events, unregister
+            // services, and drop task queue transmitter to eventually cause the
+            // task queue task to complete
+            manager_task.abort();
 
             // stop D-Bus message handling
             drop(recv_guard);
@@ -183,14 +329,116 @@ async fn run() -> Result<()> {
     }
 }
 
+/// Open all DTX devices found in the system, as well as the mock device if
+/// configured. Unless a mock device is used, waits for the hardware to appear
+/// first.
+async fn open_devices(config: &Config, selection: &Selection) -> Result<Vec<DeviceHandles>> {
+    // with a mock device, the hardware is optional, so don't wait for it
+    let paths = if config.debug.mock_device.is_some() {
+        device::enumerate(selection)?
+    } else {
+        let timeout = config.device.wait_timeout.map(|t| Duration::from_secs_f32(t.max(0.0)));
+        device::wait(timeout, selection).await?
+    };
+
+    let mut devices = paths.iter()
+        .map(|path| DeviceHandles::open(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(ref path) = config.debug.mock_device {
+        warn!(target: "sdtxd", socket=?path, "using mock DTX device");
+
+        let device = MockDevice::bind(path)?;
+        devices.push(DeviceHandles {
+            name: "mock".to_owned(),
+            devnode: None,
+            event: Device::Mock(device.clone()),
+            control: Device::Mock(device),
+        });
+    }
+
+    Ok(devices)
+}
+
+/// Criteria for selecting the DTX devices to use, as configured.
+fn device_selection(config: &Config) -> Selection {
+    let selection = Selection {
+        syspath: config.device.syspath.clone(),
+        instance: config.device.instance.clone(),
+        serial: config.device.serial.clone(),
+    };
+
+    if !selection.is_empty() {
+        info!(target: "sdtxd", ?selection, "only using selected DTX devices");
+    }
+
+    selection
+}
+
+/// Set up a simulated device replaying the given recording, to be used instead
+/// of all other devices.
+fn replay_device(replay: &Replay) -> Result<Vec<DeviceHandles>> {
+    let records = Record::load(&replay.path)?;
+
+    warn!(target: "sdtxd", file=?replay.path, speed=replay.speed, "replaying recorded DTX events");
+
+    let device = MockDevice::replay(records, replay.speed);
+    Ok(vec![DeviceHandles {
+        name: "replay".to_owned(),
+        devnode: None,
+        event: Device::Mock(device.clone()),
+        control: Device::Mock(device),
+    }])
+}
+
+/// Ping the service manager watchdog, if enabled, at half of its timeout.
+/// Runs alongside the main driver tasks, so that pings stop if the runtime
+/// hangs. Never completes.
+async fn watchdog() -> std::result::Result<Result<()>, tokio::task::JoinError> {
+    let timeout = match utils::notify::watchdog_timeout() {
+        Some(timeout) => timeout,
+        None => return future::pending().await,
+    };
+
+    debug!(target: "sdtxd", ?timeout, "service manager watchdog enabled");
+
+    let mut interval = tokio::time::interval(timeout / 2);
+    loop {
+        interval.tick().await;
+        utils::notify::watchdog();
+    }
+}
+
+/// Switch between the configured log level and trace whenever SIGUSR2 is
+/// received. Never completes.
+async fn log_toggle(mut sig: Signal, log: LogControl)
+    -> std::result::Result<Result<()>, tokio::task::JoinError>
+{
+    while sig.recv().await.is_some() {
+        info!(target: "sdtxd", "received SIGUSR2, toggling trace logging");
+
+        if let Err(err) = log.toggle() {
+            warn!(target: "sdtxd", "{:#}", err);
+        }
+    }
+
+    future::pending().await
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     // run main function and log critical errors
-    let result = run().await;
+    let mut log_guard = None;
+
+    let result = run(&mut log_guard).await;
     if let Err(ref err) = result {
         error!(target: "sdtxd", "critical error: {}\n", err);
     }
 
+    // write out any pending records to the log file, exiting below skips
+    // destructors
+    drop(log_guard);
+
     // for some reason tokio won't properly shut down, even though every task
     // we spawned should be either canceled or completed by now...
     if let Err(err) = result {
@@ -0,0 +1,376 @@
+use crate::config::{Config, Diagnostics};
+use crate::device::{self, BaseBattery, Device, EventRecorder, HardwareDevice, Hotplug, HotplugEvent, LegacyDevice};
+use crate::logic::{self, DeviceContext, SleepHandle};
+use crate::service::{DebugService, Service};
+use crate::utils::task::{JoinGuard, JoinHandleExt};
+use crate::utils::taskq::TaskSender;
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Error, Result};
+
+use dbus::nonblock::SyncConnection;
+use dbus_crossroads::Crossroads;
+
+use futures::prelude::*;
+
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinError;
+
+use tracing::{error, info, warn};
+
+
+/// A DTX device to be managed, with separate handles for event handling and
+/// control via the D-Bus service.
+pub struct DeviceHandles {
+    pub name: String,
+
+    /// Device node, if backed by the kernel driver.
+    pub devnode: Option<PathBuf>,
+
+    pub event: Device,
+    pub control: Device,
+}
+
+impl DeviceHandles {
+    /// Open the DTX device at the given device node, using the kernel
+    /// interface determined by it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if device::is_legacy(path) {
+            warn!(target: "sdtxd", devnode=?path, "using legacy DTX kernel interface, functionality is limited");
+
+            let device = LegacyDevice::open(path)?;
+            return Ok(Self {
+                name,
+                devnode: Some(path.to_owned()),
+                event: Device::Legacy(device.clone()),
+                control: Device::Legacy(device),
+            });
+        }
+
+        let event = HardwareDevice::open(path)?;
+        let control = HardwareDevice::open(path)?;
+
+        if event.is_read_only() {
+            warn!(target: "sdtxd", devnode=?path, "DTX device is read-only, monitoring only without latch control");
+        }
+
+        Ok(Self {
+            name,
+            devnode: Some(path.to_owned()),
+            event: Device::Hardware(event),
+            control: Device::Hardware(control),
+        })
+    }
+}
+
+
+/// Sets up and tears down the D-Bus services and event handling of DTX
+/// devices, both at startup and when devices are added or removed at runtime.
+pub struct DeviceManager {
+    config: Config,
+    diag: Diagnostics,
+    ctx: DeviceContext,
+    conn: Arc<SyncConnection>,
+    cr: Arc<Mutex<Crossroads>>,
+    queue: TaskSender<Error>,
+    recorded: bool,
+    devices: Vec<Managed>,
+
+    /// Device owning the primary object path, identified by its device node
+    /// or by its name if it has none.
+    primary: Option<PathBuf>,
+}
+
+struct Managed {
+    name: String,
+    devnode: Option<PathBuf>,
+    path: dbus::Path<'static>,
+    service: Service,
+    debug: Option<DebugService>,
+    sleep: SleepHandle,
+    task: JoinGuard<Result<()>>,
+    _raw_task: Option<JoinGuard<()>>,
+}
+
+impl DeviceManager {
+    /// Create a new device manager. Daemon-wide state in the given context
+    /// is shared with all devices.
+    pub fn new(config: Config, diag: Diagnostics, ctx: DeviceContext, conn: Arc<SyncConnection>,
+               cr: Arc<Mutex<Crossroads>>, queue: TaskSender<Error>)
+        -> Self
+    {
+        if config.debug.inject {
+            warn!(target: "sdtxd", "debug event injection enabled");
+        }
+
+        if config.debug.raw_events {
+            info!(target: "sdtxd", "raw event signals enabled");
+        }
+
+        Self { config, diag, ctx, conn, cr, queue, recorded: false, devices: Vec::new(),
+               primary: None }
+    }
+
+    /// Set up the D-Bus service and event handling for the given device.
+    pub fn add(&mut self, device: DeviceHandles) -> Result<()> {
+        let DeviceHandles { name, devnode, event: event_device, control: control_device } = device;
+
+        let path = self.service_path(&name, devnode.as_deref());
+        info!(target: "sdtxd", device=%name, object=%path, "managing DTX device");
+
+        let ctx = self.ctx.for_device();
+
+        let service = Service::new(self.conn.clone(), path.clone(), control_device, ctx.clone(),
+                                   &self.config, &self.diag);
+
+        // only sessions of actual hardware are audited
+        let hardware = matches!(event_device, Device::Hardware(_) | Device::Legacy(_));
+        let audit_adp = logic::AuditAdapter::new(hardware, ctx.records.clone(), ctx.dry_run.clone());
+
+        let proc_adp = logic::ProcessAdapter::new(self.config.clone(), self.queue.clone(),
+                                                  ctx.retry.clone(), ctx.records.clone(),
+                                                  ctx.dry_run.clone(), ctx.lock.clone());
+        let srvc_adp = logic::ServiceAdapter::new(service.handle(), self.latch_timeout());
+
+        // the base battery is only tracked for actual hardware
+        let battery = hardware.then(|| BaseBattery::new(self.config.base.battery.clone()));
+
+        let switch_adp = logic::TabletSwitchAdapter::new(&self.config.tablet_switch)?;
+        let check_adp = logic::TabletSwitchCheckAdapter::new(&self.config.tablet_switch,
+                                                             service.handle());
+
+        let raw_events = event_device.raw_events().filter(|_| self.config.debug.raw_events);
+
+        // only the first device is recorded
+        let recorder = match &self.config.debug.record {
+            Some(path) if !self.recorded => Some(EventRecorder::create(path)?),
+            _ => None,
+        };
+        self.recorded |= recorder.is_some();
+
+        let metrics_adp = logic::MetricsAdapter::new(ctx.metrics.clone());
+
+        let adapter = (proc_adp, srvc_adp, switch_adp, check_adp, metrics_adp, audit_adp);
+        let mut core = logic::Core::new(event_device, battery, recorder, adapter, ctx.dry_run,
+                                        ctx.last_event, ctx.force);
+
+        // set up debug service for event injection and raw events, if enabled
+        let debug = (self.config.debug.inject || self.config.debug.raw_events).then(|| {
+            DebugService::new(format!("{path}/debug").into(), core.inject_handle(),
+                              self.config.debug.inject)
+        });
+
+        let raw_task = match (&debug, raw_events) {
+            (Some(debug), Some(events)) => {
+                let task = debug.forward_raw_events(self.conn.clone(), events);
+                Some(tokio::spawn(task).guard())
+            },
+            _ => None,
+        };
+
+        // register services only once everything else has been set up
+        {
+            let mut cr = self.cr.lock().unwrap();
+
+            service.register(&mut cr)?;
+
+            if let Some(ref debug) = debug {
+                if let Err(err) = debug.register(&mut cr) {
+                    service.unregister(&mut cr);
+                    return Err(err);
+                }
+            }
+        }
+
+        let sleep = core.sleep_handle();
+        let task = tokio::spawn(async move { core.run().await }).guard();
+
+        self.devices.push(Managed {
+            name, devnode, path, service, debug, sleep, task, _raw_task: raw_task,
+        });
+
+        Ok(())
+    }
+
+    /// Stop event handling and remove the D-Bus service of the device with
+    /// the given device node. Does nothing if the device is not managed.
+    pub fn remove(&mut self, devnode: &Path) {
+        let index = self.devices.iter()
+            .position(|d| d.devnode.as_deref() == Some(devnode));
+
+        if let Some(index) = index {
+            let device = self.devices.remove(index);
+            info!(target: "sdtxd", device=%device.name, object=%device.path, "DTX device removed");
+
+            self.release(device);
+        }
+    }
+
+    /// Handle events of all managed devices, forward suspend/resume
+    /// notifications, and follow devices being added or removed. Devices
+    /// failing with an error are released, the remaining ones are kept.
+    pub async fn run(mut self, mut sleep: UnboundedReceiver<bool>, mut hotplug: Option<Hotplug>)
+        -> Result<()>
+    {
+        loop {
+            tokio::select! {
+                (result, index) = exited(&mut self.devices) => {
+                    let device = self.devices.remove(index);
+
+                    match result {
+                        Ok(Ok(())) => {
+                            info!(target: "sdtxd", device=%device.name, "DTX event stream closed");
+                            self.release(device);
+                        },
+                        Ok(Err(err)) => {
+                            // only give up on the failing device, keep the others running
+                            error!(target: "sdtxd", device=%device.name, "DTX event handling failed, \
+                                   releasing device: {:#}", err);
+                            self.release(device);
+                        },
+                        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                        Err(_) => unreachable!("Task unexpectedly canceled"),
+                    }
+                },
+                Some(active) = sleep.recv() => {
+                    for device in &self.devices {
+                        device.sleep.prepare_for_sleep(active);
+                    }
+                },
+                event = next_hotplug(&mut hotplug) => match event {
+                    HotplugEvent::Added(path) => self.on_added(&path),
+                    HotplugEvent::Removed(path) => self.remove(&path),
+                },
+            }
+        }
+    }
+
+    fn on_added(&mut self, path: &Path) {
+        // the device may have been picked up at startup already
+        if self.devices.iter().any(|d| d.devnode.as_deref() == Some(path)) {
+            return;
+        }
+
+        info!(target: "sdtxd", devnode=?path, "DTX device added");
+
+        // a failing device should not take down the others, so only warn
+        if let Err(err) = DeviceHandles::open(path).and_then(|device| self.add(device)) {
+            warn!(target: "sdtxd", devnode=?path, "failed to set up DTX device: {:#}", err);
+        }
+    }
+
+    fn release(&self, device: Managed) {
+        let mut cr = self.cr.lock().unwrap();
+
+        if let Some(ref debug) = device.debug {
+            debug.unregister(&mut cr);
+        }
+
+        device.service.unregister(&mut cr);
+    }
+
+    fn latch_timeout(&self) -> Duration {
+        Duration::from_secs_f32(self.config.latch.open_timeout.max(0.0))
+    }
+
+    /// D-Bus object path for a new device with the given name and device
+    /// node. The primary path is assigned to the first device, so that
+    /// existing clients continue to work unchanged with a single device. It
+    /// stays bound to that device, i.e. is re-used when it is added again
+    /// after having been removed, but never handed to a different device.
+    fn service_path(&mut self, name: &str, devnode: Option<&Path>) -> dbus::Path<'static> {
+        let owner = devnode.map(Path::to_owned).unwrap_or_else(|| name.into());
+        let primary = *self.primary.get_or_insert_with(|| owner.clone()) == owner;
+
+        // object paths in use, including those of the debug services
+        let taken: BTreeSet<String> = self.devices.iter()
+            .flat_map(|d| [d.path.to_string(), format!("{}/debug", d.path)])
+            .collect();
+
+        service_path(name, primary, &taken)
+    }
+}
+
+impl Drop for DeviceManager {
+    fn drop(&mut self) {
+        for device in std::mem::take(&mut self.devices) {
+            self.release(device);
+        }
+    }
+}
+
+
+/// Wait for the event task of any managed device to complete. Never
+/// completes if there are no devices.
+async fn exited(devices: &mut [Managed]) -> (Result<Result<()>, JoinError>, usize) {
+    if devices.is_empty() {
+        return future::pending().await;
+    }
+
+    let (result, index, _) = future::select_all(devices.iter_mut().map(|d| &mut d.task)).await;
+    (result, index)
+}
+
+/// D-Bus object path for a device with the given name, not colliding with any
+/// of the given paths. Names are sanitized, so different names may map to the
+/// same path, in which case an index is appended.
+fn service_path(name: &str, primary: bool, taken: &BTreeSet<String>) -> dbus::Path<'static> {
+    if primary && !taken.contains(Service::PATH) {
+        return Service::PATH.into();
+    }
+
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    let base = format!("{}/{}", Service::PATH, name);
+
+    let mut path = base.clone();
+    let mut index = 1;
+    while taken.contains(&path) {
+        path = format!("{base}_{index}");
+        index += 1;
+    }
+
+    path.into()
+}
+
+async fn next_hotplug(hotplug: &mut Option<Hotplug>) -> HotplugEvent {
+    match hotplug {
+        Some(hotplug) => hotplug.next().await,
+        None => future::pending().await,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn taken(paths: &[&str]) -> BTreeSet<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(&*service_path("surface_dtx", true, &taken(&[])), "/org/surface/dtx");
+
+        // the primary path is only used for its owner
+        assert_eq!(&*service_path("surface_dtx", false, &taken(&[])), "/org/surface/dtx/surface_dtx");
+
+        // sanitized names colliding with each other or with debug services
+        let used = taken(&["/org/surface/dtx", "/org/surface/dtx/debug", "/org/surface/dtx/dtx_1"]);
+        assert_eq!(&*service_path("dtx-1", false, &used), "/org/surface/dtx/dtx_1_1");
+        assert_eq!(&*service_path("debug", false, &used), "/org/surface/dtx/debug_1");
+
+        let used = taken(&["/org/surface/dtx/dtx_1", "/org/surface/dtx/dtx_1_1"]);
+        assert_eq!(&*service_path("dtx.1", true, &used), "/org/surface/dtx");
+        assert_eq!(&*service_path("dtx.1", false, &used), "/org/surface/dtx/dtx_1_2");
+    }
+}
@@ -0,0 +1,300 @@
+use crate::device::DeviceError;
+use crate::logic::{CancelReason, RuntimeError};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+
+use tracing::{debug, info, trace};
+
+
+/// Upper bounds of the duration histogram buckets, in seconds.
+pub const DURATION_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Maximum size of a request accepted by the exporter.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Time after which a client that has not sent a complete request is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+
+/// Simple event counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Counter {
+    DetachmentAttempts,
+    DetachmentInhibited,
+    DetachmentComplete,
+    DetachmentUnexpected,
+    AttachmentComplete,
+}
+
+impl Counter {
+    const ALL: [Counter; 5] = [
+        Counter::DetachmentAttempts,
+        Counter::DetachmentInhibited,
+        Counter::DetachmentComplete,
+        Counter::DetachmentUnexpected,
+        Counter::AttachmentComplete,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::DetachmentAttempts   => "sdtxd_detachment_attempts",
+            Self::DetachmentInhibited  => "sdtxd_detachment_inhibited",
+            Self::DetachmentComplete   => "sdtxd_detachment_complete",
+            Self::DetachmentUnexpected => "sdtxd_detachment_unexpected",
+            Self::AttachmentComplete   => "sdtxd_attachment_complete",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Self::DetachmentAttempts   => "Detachment processes started.",
+            Self::DetachmentInhibited  => "Detachment requests rejected before starting.",
+            Self::DetachmentComplete   => "Detachment processes completed by removing the base.",
+            Self::DetachmentUnexpected => "Bases removed without a detachment process.",
+            Self::AttachmentComplete   => "Attachment processes completed.",
+        }
+    }
+}
+
+
+/// Metrics of all managed devices, shared between the metrics adapters, the
+/// D-Bus services, and the exporter.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Registry>>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<Counter, u64>,
+    cancels: BTreeMap<&'static str, u64>,
+    handlers: BTreeMap<&'static str, Histogram>,
+    device_errors: BTreeMap<&'static str, u64>,
+}
+
+/// Histogram of durations, using the bounds in `DURATION_BUCKETS`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Histogram {
+    /// Number of observations per bucket, i.e. between the previous and the
+    /// given upper bound, with an additional last bucket for everything
+    /// above the largest bound.
+    pub buckets: [u64; DURATION_BUCKETS.len() + 1],
+
+    pub count: u64,
+
+    /// Sum of all observations, in seconds.
+    pub sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: Duration) {
+        let value = value.as_secs_f64();
+        let index = DURATION_BUCKETS.iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    pub fn inc(&self, counter: Counter) {
+        *self.inner.lock().unwrap().counters.entry(counter).or_default() += 1;
+    }
+
+    pub fn cancel(&self, reason: CancelReason) {
+        *self.inner.lock().unwrap().cancels.entry(cancel_label(reason)).or_default() += 1;
+    }
+
+    pub fn handler(&self, handler: &'static str, duration: Duration) {
+        let mut registry = self.inner.lock().unwrap();
+        registry.handlers.entry(handler).or_default().observe(duration);
+    }
+
+    pub fn device_error(&self, cause: Option<DeviceError>) {
+        let label = match cause {
+            Some(DeviceError::PermissionDenied) => "permission-denied",
+            Some(DeviceError::Disconnected)     => "disconnected",
+            Some(DeviceError::Busy)             => "busy",
+            Some(DeviceError::Unsupported)      => "unsupported",
+            Some(DeviceError::ReadOnly)         => "read-only",
+            None                                => "other",
+        };
+
+        *self.inner.lock().unwrap().device_errors.entry(label).or_default() += 1;
+    }
+
+    /// Render all metrics in the OpenMetrics text format.
+    fn render(&self) -> String {
+        let registry = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        // writing to a string can not fail
+        for counter in Counter::ALL {
+            let name = counter.name();
+            let value = registry.counters.get(&counter).copied().unwrap_or_default();
+
+            writeln!(out, "# TYPE {name} counter").unwrap();
+            writeln!(out, "# HELP {name} {}", counter.help()).unwrap();
+            writeln!(out, "{name}_total {value}").unwrap();
+        }
+
+        writeln!(out, "# TYPE sdtxd_detachment_cancels counter").unwrap();
+        writeln!(out, "# HELP sdtxd_detachment_cancels Detachment processes canceled, by reason.").unwrap();
+        for (reason, value) in &registry.cancels {
+            writeln!(out, "sdtxd_detachment_cancels_total{{reason=\"{reason}\"}} {value}").unwrap();
+        }
+
+        writeln!(out, "# TYPE sdtxd_handler_duration_seconds histogram").unwrap();
+        writeln!(out, "# HELP sdtxd_handler_duration_seconds Run time of handler invocations.").unwrap();
+        for (handler, hist) in &registry.handlers {
+            let name = "sdtxd_handler_duration_seconds";
+
+            // buckets are cumulative in the exposition format
+            let mut value = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(hist.buckets) {
+                value += count;
+                writeln!(out, "{name}_bucket{{handler=\"{handler}\",le=\"{bound:?}\"}} {value}").unwrap();
+            }
+
+            writeln!(out, "{name}_bucket{{handler=\"{handler}\",le=\"+Inf\"}} {}", hist.count).unwrap();
+            writeln!(out, "{name}_sum{{handler=\"{handler}\"}} {:?}", hist.sum).unwrap();
+            writeln!(out, "{name}_count{{handler=\"{handler}\"}} {}", hist.count).unwrap();
+        }
+
+        writeln!(out, "# TYPE sdtxd_device_errors counter").unwrap();
+        writeln!(out, "# HELP sdtxd_device_errors Failed DTX device requests, by cause.").unwrap();
+        for (cause, value) in &registry.device_errors {
+            writeln!(out, "sdtxd_device_errors_total{{cause=\"{cause}\"}} {value}").unwrap();
+        }
+
+        writeln!(out, "# EOF").unwrap();
+        out
+    }
+}
+
+/// Coarse label of a cancellation reason, without the error codes to keep the
+/// number of distinct labels small.
+fn cancel_label(reason: CancelReason) -> &'static str {
+    match reason {
+        CancelReason::UserRequest        => "request",
+        CancelReason::HandlerTimeout     => "timeout:handler",
+        CancelReason::HandlerSpawnFailed => "error:handler:spawn",
+        CancelReason::DisconnectTimeout  => "timeout:disconnect",
+        CancelReason::Runtime(err) => match err {
+            RuntimeError::NotAttached    => "error:runtime:not-attached",
+            RuntimeError::NotFeasible    => "error:runtime:not-feasible",
+            RuntimeError::Timeout        => "error:runtime:timeout",
+            RuntimeError::Unknown(_)     => "error:runtime:unknown",
+        },
+        CancelReason::Hardware(_)        => "error:hardware",
+        CancelReason::Unknown(_)         => "unknown",
+    }
+}
+
+
+/// Serve the given metrics via HTTP on the given address, which is either a
+/// TCP address (e.g. "127.0.0.1:9617") or the path of a unix socket.
+pub async fn serve(address: &str, metrics: Metrics) -> Result<()> {
+    if address.starts_with('/') {
+        let path = Path::new(address);
+
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).context("Failed to remove stale metrics socket");
+            },
+            _ => {},
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind metrics socket '{address}'"))?;
+
+        info!(target: "sdtxd", socket=?path, "serving metrics");
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => { tokio::spawn(respond(stream, metrics.clone())); },
+                    Err(err) => debug!(target: "sdtxd", "failed to accept metrics client: {}", err),
+                }
+            }
+        });
+    } else {
+        let listener = TcpListener::bind(address).await
+            .with_context(|| format!("Failed to bind metrics address '{address}'"))?;
+
+        info!(target: "sdtxd", %address, "serving metrics");
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => { tokio::spawn(respond(stream, metrics.clone())); },
+                    Err(err) => debug!(target: "sdtxd", "failed to accept metrics client: {}", err),
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Answer a single HTTP request and close the connection.
+async fn respond<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, metrics: Metrics) {
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Some(request)) => request,
+        _ => return,
+    };
+
+    let line = request.lines().next().unwrap_or_default();
+    trace!(target: "sdtxd", request=line, "metrics request");
+
+    let mut parts = line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render();
+
+            format!("HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}", body.len())
+        },
+        (Some("GET"), _) => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        },
+        _ => {
+            "HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        },
+    };
+
+    // the client may already be gone, nothing to do about that
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Read the request head, i.e. everything up to the first empty line.
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Option<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 512];
+
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_SIZE {
+            return None;
+        }
+
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    String::from_utf8(buf).ok()
+}
@@ -1,3 +1,4 @@
+use crate::device::{BaseFirmware, BatteryInfo, BatteryStatus};
 use crate::logic::{
     BaseInfo,
     BaseState,
@@ -7,9 +8,10 @@ use crate::logic::{
     HardwareError,
     LatchStatus,
     RuntimeError,
+    SessionId,
 };
 
-use dbus::arg::Variant;
+use dbus::arg::{PropMap, Variant};
 
 
 pub trait DbusArg {
@@ -22,6 +24,46 @@ pub trait DbusArg {
     }
 }
 
+impl DbusArg for String {
+    type Arg = String;
+
+    fn as_arg(&self) -> String {
+        self.clone()
+    }
+}
+
+impl DbusArg for bool {
+    type Arg = bool;
+
+    fn as_arg(&self) -> bool {
+        *self
+    }
+}
+
+impl DbusArg for u8 {
+    type Arg = u8;
+
+    fn as_arg(&self) -> u8 {
+        *self
+    }
+}
+
+impl DbusArg for u32 {
+    type Arg = u32;
+
+    fn as_arg(&self) -> u32 {
+        *self
+    }
+}
+
+impl DbusArg for i32 {
+    type Arg = i32;
+
+    fn as_arg(&self) -> i32 {
+        *self
+    }
+}
+
 impl DbusArg for DeviceMode {
     type Arg = String;
 
@@ -34,6 +76,14 @@ impl DbusArg for DeviceMode {
     }
 }
 
+impl DbusArg for SessionId {
+    type Arg = u64;
+
+    fn as_arg(&self) -> u64 {
+        self.value()
+    }
+}
+
 impl DbusArg for LatchStatus {
     type Arg = String;
 
@@ -59,6 +109,60 @@ impl DbusArg for BaseInfo {
     }
 }
 
+/// Battery of the base as dictionary, empty if there is no battery.
+impl DbusArg for Option<BatteryInfo> {
+    type Arg = PropMap;
+
+    fn as_arg(&self) -> Self::Arg {
+        let mut map = PropMap::new();
+
+        if let Some(battery) = self {
+            map.insert("status".into(), battery.status.as_variant());
+
+            if let Some(capacity) = battery.capacity {
+                map.insert("capacity".into(), capacity.as_variant());
+            }
+        }
+
+        map
+    }
+}
+
+impl DbusArg for BatteryStatus {
+    type Arg = String;
+
+    fn as_arg(&self) -> Self::Arg {
+        match self {
+            BatteryStatus::Unknown     => "unknown",
+            BatteryStatus::Charging    => "charging",
+            BatteryStatus::Discharging => "discharging",
+            BatteryStatus::NotCharging => "not-charging",
+            BatteryStatus::Full        => "full",
+        }.into()
+    }
+}
+
+/// Firmware of the base as dictionary, containing only the known values.
+impl DbusArg for Option<BaseFirmware> {
+    type Arg = PropMap;
+
+    fn as_arg(&self) -> Self::Arg {
+        let mut map = PropMap::new();
+
+        if let Some(firmware) = self {
+            if let Some(version) = &firmware.version {
+                map.insert("version".into(), version.as_variant());
+            }
+
+            if let Some(serial) = &firmware.serial {
+                map.insert("serial".into(), serial.as_variant());
+            }
+        }
+
+        map
+    }
+}
+
 impl DbusArg for BaseState {
     type Arg = String;
 
@@ -90,6 +194,7 @@ impl DbusArg for CancelReason {
         match self {
             CancelReason::UserRequest             => "request".into(),
             CancelReason::HandlerTimeout          => "timeout:handler".into(),
+            CancelReason::HandlerSpawnFailed      => "error:handler:spawn".into(),
             CancelReason::DisconnectTimeout       => "timeout:disconnect".into(),
             CancelReason::Runtime(rt) => match rt {
                 RuntimeError::NotAttached         => "error:runtime:not-attached".into(),
@@ -0,0 +1,104 @@
+use crate::device::{parse_event, RawEvent};
+use crate::logic::InjectHandle;
+
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use dbus::Message;
+use dbus::channel::Sender;
+use dbus::nonblock::SyncConnection;
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use tracing::{info, trace, warn};
+
+
+/// Debug service, allowing simulated device events to be injected into the
+/// daemon, e.g. for testing handlers and notifications without touching the
+/// actual hardware, and providing raw device events for diagnostics.
+pub struct DebugService {
+    path: dbus::Path<'static>,
+    data: Data,
+}
+
+#[derive(Clone)]
+struct Data {
+    inject: InjectHandle,
+    inject_enabled: bool,
+}
+
+impl DebugService {
+    const INTERFACE: &'static str = "org.surface.dtx.Debug";
+
+    /// Create a new debug service at the given object path, typically the
+    /// path of the device service with "/debug" appended. Event injection is
+    /// rejected unless enabled.
+    pub fn new(path: dbus::Path<'static>, inject: InjectHandle, inject_enabled: bool) -> Self {
+        Self { path, data: Data { inject, inject_enabled } }
+    }
+
+    pub fn register(&self, cr: &mut Crossroads) -> Result<()> {
+        let iface_token = cr.register(Self::INTERFACE, |b: &mut IfaceBuilder<Data>| {
+            // inject method, handles the given event as if emitted by the device
+            b.method("Inject", ("event",), (), move |_ctx, data, (name,): (String,)| {
+                if !data.inject_enabled {
+                    return Err(MethodErr::failed("Event injection is disabled"));
+                }
+
+                let event = parse_event(&name)
+                    .map_err(|e| MethodErr::invalid_arg(&e))?;
+
+                info!(target: "sdtxd::srvc", event=%name, "injecting simulated event");
+
+                data.inject.inject(event);
+                Ok(())
+            });
+
+            // raw event signal, emitted for each event received from the
+            // device before translation
+            b.signal::<(u16, Vec<u8>), _>("RawEvent", ("code", "data"));
+        });
+
+        cr.insert(self.path.clone(), &[iface_token], self.data.clone());
+        Ok(())
+    }
+
+    pub fn unregister(&self, cr: &mut Crossroads) {
+        let _ : Option<Data> = cr.remove(&self.path);
+    }
+
+    /// Emit the given raw events as signals until the device is dropped.
+    pub fn forward_raw_events(&self, conn: Arc<SyncConnection>,
+                              mut events: broadcast::Receiver<RawEvent>)
+        -> impl Future<Output=()> + Send + 'static
+    {
+        let path = self.path.clone();
+        let interface = Self::INTERFACE.into();
+
+        async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(target: "sdtxd::srvc", skipped=n, "dropped raw events, receiver lagging behind");
+                        continue;
+                    },
+                    Err(RecvError::Closed) => break,
+                };
+
+                trace!(target: "sdtxd::srvc", object=%path, code=event.code, data=?event.data,
+                       "emitting raw event");
+
+                let signal = Message::signal(&path, &interface, &"RawEvent".into())
+                    .append2(event.code, event.data);
+
+                // only fails when memory runs out
+                conn.send(signal).unwrap();
+            }
+        }
+    }
+}
+
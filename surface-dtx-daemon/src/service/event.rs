@@ -1,10 +1,10 @@
-use crate::logic::CancelReason;
+use crate::logic::{CancelReason, DeviceMode, HandlerStatus, SessionId};
 use crate::service::arg::DbusArg;
 
-use dbus::arg::{Append, Dict, RefArg, Variant};
+use dbus::arg::Append;
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Event {
     DetachmentInhibited { reason: CancelReason },
     DetachmentStart,
@@ -15,47 +15,109 @@ pub enum Event {
     DetachmentCancelComplete,
     DetachmentCancelTimeout,
     DetachmentUnexpected,
+    DetachmentHandlerSlow,
+    DetachmentCountdown { remaining: u32 },
     AttachmentStart,
     AttachmentComplete,
     AttachmentTimeout,
+    BaseFeasible,
+    DeviceModeMismatch { mode: DeviceMode, tablet_switch: bool },
+    HandlerStatus { status: HandlerStatus },
 }
 
-impl dbus::arg::AppendAll for Event {
-    fn append(&self, ia: &mut dbus::arg::IterAppend) {
+impl Event {
+    fn append(&self, ia: &mut dbus::arg::IterAppend, session: Option<SessionId>) {
         match self {
-            Self::DetachmentInhibited { reason }   => append1(ia, "detachment:inhibited", "reason", reason),
-            Self::DetachmentStart                  => append0(ia, "detachment:start"),
-            Self::DetachmentReady                  => append0(ia, "detachment:ready"),
-            Self::DetachmentComplete               => append0(ia, "detachment:complete"),
-            Self::DetachmentCancel { reason }      => append1(ia, "detachment:cancel", "reason", reason),
-            Self::DetachmentCancelStart            => append0(ia, "detachment:cancel:start"),
-            Self::DetachmentCancelComplete         => append0(ia, "detachment:cancel:complete"),
-            Self::DetachmentCancelTimeout          => append0(ia, "detachment:cancel:timeout"),
-            Self::DetachmentUnexpected             => append0(ia, "detachment:unexpected"),
-            Self::AttachmentStart                  => append0(ia, "attachment:start"),
-            Self::AttachmentComplete               => append0(ia, "attachment:complete"),
-            Self::AttachmentTimeout                => append0(ia, "attachment:timeout"),
+            Self::DetachmentInhibited { reason }   => append1(ia, session, "detachment:inhibited", "reason", reason),
+            Self::DetachmentStart                  => append0(ia, session, "detachment:start"),
+            Self::DetachmentReady                  => append0(ia, session, "detachment:ready"),
+            Self::DetachmentComplete               => append0(ia, session, "detachment:complete"),
+            Self::DetachmentCancel { reason }      => append1(ia, session, "detachment:cancel", "reason", reason),
+            Self::DetachmentCancelStart            => append0(ia, session, "detachment:cancel:start"),
+            Self::DetachmentCancelComplete         => append0(ia, session, "detachment:cancel:complete"),
+            Self::DetachmentCancelTimeout          => append0(ia, session, "detachment:cancel:timeout"),
+            Self::DetachmentUnexpected             => append0(ia, session, "detachment:unexpected"),
+            Self::DetachmentHandlerSlow            => append0(ia, session, "detachment:handler:slow"),
+            Self::DetachmentCountdown { remaining } => append1(ia, session, "detachment:countdown", "remaining", remaining),
+            Self::AttachmentStart                  => append0(ia, session, "attachment:start"),
+            Self::AttachmentComplete               => append0(ia, session, "attachment:complete"),
+            Self::AttachmentTimeout                => append0(ia, session, "attachment:timeout"),
+            Self::BaseFeasible                     => append0(ia, session, "base:feasible"),
+            Self::DeviceModeMismatch { mode, tablet_switch }
+                => append2(ia, session, "device-mode:mismatch", "mode", mode, "tablet-switch", tablet_switch),
+            Self::HandlerStatus { status } => match status {
+                HandlerStatus::Status(msg)         => append1(ia, session, "handler:status", "message", msg),
+                HandlerStatus::Progress(value)     => append1(ia, session, "handler:progress", "progress", value),
+                HandlerStatus::Error(msg)          => append1(ia, session, "handler:error", "message", msg),
+                HandlerStatus::Crash(name, signal) => append2(ia, session, "handler:crash", "handler", name, "signal", signal),
+            },
         }
     }
 }
 
-fn append0(ia: &mut dbus::arg::IterAppend, ty: &'static str) {
-    let values: Dict<String, Variant<Box<dyn RefArg>>, _> = Dict::new(std::iter::empty());
 
+/// An event tagged with the session it belongs to (if any), as emitted via
+/// D-Bus.
+#[derive(Debug, Clone)]
+pub struct SessionEvent {
+    pub session: Option<SessionId>,
+    pub event: Event,
+}
+
+impl dbus::arg::AppendAll for SessionEvent {
+    fn append(&self, ia: &mut dbus::arg::IterAppend) {
+        self.event.append(ia, self.session)
+    }
+}
+
+fn append0(ia: &mut dbus::arg::IterAppend, session: Option<SessionId>, ty: &'static str) {
     ty.append(ia);
-    values.append(ia);
+
+    ia.append_dict(&"s".into(), &"v".into(), |ia| {
+        append_session(ia, session);
+    });
 }
 
-fn append1<T>(ia: &mut dbus::arg::IterAppend, ty: &'static str, name: &'static str, value: &T)
+fn append1<T>(ia: &mut dbus::arg::IterAppend, session: Option<SessionId>, ty: &'static str,
+              name: &'static str, value: &T)
 where
     T: DbusArg,
 {
     ty.append(ia);
 
     ia.append_dict(&"s".into(), &"v".into(), |ia| {
-        ia.append_dict_entry(|ia| {
-            ia.append(name.to_owned());
-            ia.append(value.as_variant());
-        })
+        append_session(ia, session);
+        append_entry(ia, name, value);
     });
 }
+
+fn append2<T, U>(ia: &mut dbus::arg::IterAppend, session: Option<SessionId>, ty: &'static str,
+                 name1: &'static str, value1: &T, name2: &'static str, value2: &U)
+where
+    T: DbusArg,
+    U: DbusArg,
+{
+    ty.append(ia);
+
+    ia.append_dict(&"s".into(), &"v".into(), |ia| {
+        append_session(ia, session);
+        append_entry(ia, name1, value1);
+        append_entry(ia, name2, value2);
+    });
+}
+
+fn append_session(ia: &mut dbus::arg::IterAppend, session: Option<SessionId>) {
+    if let Some(session) = session {
+        append_entry(ia, "session", &session);
+    }
+}
+
+fn append_entry<T>(ia: &mut dbus::arg::IterAppend, name: &'static str, value: &T)
+where
+    T: DbusArg,
+{
+    ia.append_dict_entry(|ia| {
+        ia.append(name.to_owned());
+        ia.append(value.as_variant());
+    })
+}
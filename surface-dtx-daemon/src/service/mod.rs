@@ -1,33 +1,54 @@
 mod arg;
 use arg::DbusArg;
 
+mod debug;
+pub use debug::DebugService;
+
 mod event;
 pub use event::Event;
+use event::SessionEvent;
 
 mod prop;
 use prop::Property;
 
+mod stats;
+use stats::Statistics;
+
 
+use crate::config::{Config, Diagnostics};
+use crate::device::{BaseFirmware, BatteryInfo, DeviceError, DtxDevice};
+use crate::logic;
+use crate::metrics::{Metrics, DURATION_BUCKETS};
+use crate::utils::loglevel::LogControl;
 use crate::logic::{
     BaseInfo,
     BaseState,
+    DeviceContext,
     DeviceMode,
     DeviceType,
+    DryRun,
+    ForceRequest,
+    HandlerRecord,
+    HandlerRecords,
+    LastEvent,
+    LatchLock,
     LatchStatus,
+    SessionId,
 };
 
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 
-use dbus::{Message, arg::{RefArg, Variant}};
+use dbus::{Message, arg::{PropMap, RefArg, Variant}};
 use dbus::nonblock::SyncConnection;
-use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+use dbus_crossroads::{Crossroads, IfaceBuilder, IfaceToken, MethodErr};
 
-use sdtx_tokio::Device;
+use tokio::sync::Notify;
 
-use tracing::trace;
+use tracing::{debug, info, trace, warn};
 
 
 pub struct Service {
@@ -36,21 +57,43 @@ pub struct Service {
 }
 
 impl Service {
-    const PATH: &'static str = "/org/surface/dtx";
+    pub const PATH: &'static str = "/org/surface/dtx";
     const INTERFACE: &'static str = "org.surface.dtx";
 
-    pub fn new(conn: Arc<SyncConnection>, device: Device) -> Self {
-        Self { conn, inner: Arc::new(Shared::new(device)) }
+    pub fn new<D: DtxDevice + 'static>(conn: Arc<SyncConnection>, path: dbus::Path<'static>,
+                                       device: D, ctx: DeviceContext, config: &Config,
+                                       diag: &Diagnostics)
+        -> Self
+    {
+        let mut shared = Shared::new(Box::new(device), ctx.retry, ctx.records, ctx.dry_run);
+        shared.path = path;
+        shared.force = ctx.force;
+        shared.lock = ctx.lock;
+        shared.log = Some(ctx.log);
+        shared.metrics = ctx.metrics;
+        shared.last_event = ctx.last_event;
+        shared.started = ctx.started;
+        shared.report = ConfigReport::new(config, diag);
+        shared.config = config.clone();
+
+        Self { conn, inner: Arc::new(shared) }
     }
 
-    pub async fn request_name(&self) -> Result<()> {
-        self.conn.request_name(Self::INTERFACE, false, true, false).await
+    pub async fn request_name(conn: &SyncConnection) -> Result<()> {
+        conn.request_name(Self::INTERFACE, false, true, false).await
             .context("Failed to set up D-Bus service")
             .map(|_| ())
     }
 
     pub fn register(&self, cr: &mut Crossroads) -> Result<()> {
-        let iface_token = cr.register(Self::INTERFACE, |b: &mut IfaceBuilder<Arc<Shared>>| {
+        let iface_token = Self::register_interface(cr);
+
+        cr.insert(self.inner.path.clone(), &[iface_token], self.inner.clone());
+        Ok(())
+    }
+
+    fn register_interface(cr: &mut Crossroads) -> IfaceToken<Arc<Shared>> {
+        cr.register(Self::INTERFACE, |b: &mut IfaceBuilder<Arc<Shared>>| {
             // device-mode property
             b.property("DeviceMode")
                 .emits_changed_true()
@@ -66,25 +109,189 @@ impl Service {
                 .emits_changed_true()
                 .get(|_, service| Ok(service.base_info.as_arg()));
 
+            // base battery, empty if not present
+            b.property("BaseBattery")
+                .emits_changed_true()
+                .get(|_, service| Ok(service.base_battery.as_arg()));
+
+            // base firmware, empty if not known
+            b.property("BaseFirmware")
+                .emits_changed_true()
+                .get(|_, service| Ok(service.base_firmware.as_arg()));
+
+            // explicit latch lock, not including temporary locks by handlers
+            b.property("LatchLocked")
+                .emits_changed_true()
+                .get(|_, service| Ok(service.lock.is_explicit()));
+
+            // dry-run mode
+            b.property("DryRun")
+                .emits_changed_true()
+                .get(|_, service| Ok(service.dry_run.get()))
+                .set(|_, service, value: bool| {
+                    info!(target: "sdtxd::srvc", enabled=value, "dry-run mode changed");
+                    service.dry_run.set(value);
+                    Ok(Some(value))
+                });
+
             // request method
             b.method("Request", (), (), move |_ctx, service, _args: ()| {
                 match service.device.latch_request() {
                     Ok(()) => { Ok(()) },
-                    Err(e) => { Err(MethodErr::failed(&e)) },
+                    Err(e) => { Err(device_error(service, e)) },
+                }
+            });
+
+            // forced request method, skips the detachment handler
+            b.method("RequestForce", (), (), move |_ctx, service, _args: ()| {
+                warn!(target: "sdtxd::srvc", "forced detachment requested");
+
+                service.force.set(true);
+                match service.device.latch_request() {
+                    Ok(()) => { Ok(()) },
+                    Err(e) => {
+                        service.force.set(false);
+                        Err(device_error(service, e))
+                    },
+                }
+            });
+
+            // lock method, prevents the latch from being opened
+            b.method("Lock", (), (), move |ctx, service, _args: ()| {
+                info!(target: "sdtxd::srvc", "locking latch on request");
+
+                let locked = service.lock.is_explicit();
+
+                match service.lock.lock(|| service.device.latch_lock()) {
+                    Ok(()) => {
+                        if !locked {
+                            ctx.push_msg(latch_locked_changed(&service.path, true));
+                        }
+                        Ok(())
+                    },
+                    Err(e) => { Err(device_error(service, e)) },
+                }
+            });
+
+            // unlock method, allows the latch to be opened again
+            b.method("Unlock", (), (), move |ctx, service, _args: ()| {
+                info!(target: "sdtxd::srvc", "unlocking latch on request");
+
+                let locked = service.lock.is_explicit();
+
+                match service.lock.unlock(|| service.device.latch_unlock()) {
+                    Ok(()) => {
+                        if locked {
+                            ctx.push_msg(latch_locked_changed(&service.path, false));
+                        }
+                        Ok(())
+                    },
+                    Err(e) => { Err(device_error(service, e)) },
+                }
+            });
+
+            // retry method, re-runs deferred detachment handlers
+            b.method("Retry", (), (), move |_ctx, service, _args: ()| {
+                service.retry.notify_waiters();
+                Ok(())
+            });
+
+            // handler records method, returns the last handler invocations
+            b.method("GetHandlerRecords", (), ("records",), move |_ctx, service, _args: ()| {
+                let records = service.records.get().iter()
+                    .map(record_to_propmap)
+                    .collect::<Vec<_>>();
+
+                Ok((records,))
+            });
+
+            // statistics method, returns event counts, handler totals, and
+            // stage duration histograms since startup
+            b.method("GetStatistics", (), ("counters", "cancels", "handlers", "buckets", "histograms"),
+                     move |_ctx, service, _args: ()| {
+                let (counters, cancels) = {
+                    let stats = service.stats.lock().unwrap();
+                    (stats.counters(), stats.cancels())
+                };
+
+                let handlers = service.records.totals().into_iter()
+                    .map(|(handler, t)| {
+                        (handler.to_owned(), t.count, t.failures, t.total.as_secs_f64(),
+                         t.max.as_secs_f64())
+                    })
+                    .collect::<Vec<_>>();
+
+                let histograms = service.records.histograms().into_iter()
+                    .map(|(handler, stage, h)| {
+                        (handler.to_owned(), stage.as_str().to_owned(), h.buckets.to_vec())
+                    })
+                    .collect::<Vec<_>>();
+
+                Ok((counters, cancels, handlers, DURATION_BUCKETS.to_vec(), histograms))
+            });
+
+            // config method, returns the effective configuration and its problems
+            b.method("GetConfig", (), ("path", "config", "unknowns", "problems"),
+                     move |_ctx, service, _args: ()| {
+                let report = &service.report;
+                Ok((report.path.clone(), report.config.clone(), report.unknowns.clone(),
+                    report.problems.clone()))
+            });
+
+            // log level method, changes the log level at runtime
+            b.method("SetLogLevel", ("level",), (), move |_ctx, service, (level,): (String,)| {
+                let level = match level.as_str() {
+                    "default" => None,
+                    level => Some(level.parse().map_err(|e| MethodErr::invalid_arg(&format!("{e}")))?),
+                };
+
+                let log = service.log.as_ref()
+                    .ok_or_else(|| MethodErr::failed("Log level control not available"))?;
+
+                log.set(level).map_err(|e| MethodErr::failed(&format!("{e:#}")))
+            });
+
+            // health method, checks that the DTX device is still responding
+            b.method("GetHealth", (), ("uptime", "available", "last_event"),
+                     move |_ctx, service, _args: ()| {
+                let uptime = service.started.elapsed().as_secs_f64();
+
+                let available = match service.device.get_device_mode() {
+                    Ok(_) => true,
+                    Err(err) => {
+                        debug!(target: "sdtxd::srvc", "health check: DTX device unavailable: {:#}", err);
+                        false
+                    },
+                };
+
+                Ok((uptime, available, service.last_event.get()))
+            });
+
+            // handler test method, runs a handler in dry-run mode and reports its output
+            b.method_with_cr_async("TestHandler", ("handler",),
+                                   ("result", "duration", "stdout", "stderr", "messages"),
+                                   |mut ctx, cr, (handler,): (String,)| {
+                let service = cr.data_mut::<Arc<Shared>>(ctx.path()).cloned();
+
+                async move {
+                    // the object is registered with the interface, so its data always exists
+                    let service = service.unwrap();
+
+                    let result = test_handler(&service, &handler).await
+                        .map_err(|e| MethodErr::failed(&format!("{e:#}")));
+
+                    ctx.reply(result)
                 }
             });
 
             // event signal
             b.signal::<(String, HashMap<String, Variant<Box<dyn RefArg>>>), _>
                 ("Event", ("type", "values"));
-        });
-
-        cr.insert(Self::PATH, &[iface_token], self.inner.clone());
-        Ok(())
+        })
     }
 
     pub fn unregister(&self, cr: &mut Crossroads) {
-        let _ : Option<Arc<Shared>> = cr.remove(&Self::PATH.into());
+        let _ : Option<Arc<Shared>> = cr.remove(&self.inner.path);
     }
 
     pub fn handle(&self) -> ServiceHandle {
@@ -101,29 +308,55 @@ pub struct ServiceHandle {
 
 impl ServiceHandle {
     pub fn set_device_mode(&self, value: DeviceMode) {
-        self.inner.device_mode.set(self.conn.as_ref(), value);
+        self.inner.device_mode.set(self.conn.as_ref(), &self.inner.path, value);
     }
 
     pub fn set_latch_status(&self, value: LatchStatus) {
-        self.inner.latch_status.set(self.conn.as_ref(), value);
+        self.inner.latch_status.set(self.conn.as_ref(), &self.inner.path, value);
     }
 
     pub fn set_base_info(&self, value: BaseInfo) {
-        self.inner.base_info.set(self.conn.as_ref(), value);
+        let BaseInfo { state, device_type, id, battery, firmware } = value;
+
+        self.set_base_battery(battery);
+        self.set_base_firmware(firmware);
+
+        // battery and firmware have their own properties, keep them out of
+        // the base info to not signal changes of the latter for them
+        let info = BaseInfo { state, device_type, id, battery: None, firmware: None };
+        self.inner.base_info.set(self.conn.as_ref(), &self.inner.path, info);
+    }
+
+    pub fn set_base_battery(&self, value: Option<BatteryInfo>) {
+        self.inner.base_battery.set(self.conn.as_ref(), &self.inner.path, value);
+    }
+
+    pub fn set_base_firmware(&self, value: Option<BaseFirmware>) {
+        self.inner.base_firmware.set(self.conn.as_ref(), &self.inner.path, value);
+    }
+
+    pub fn emit_event(&self, session: SessionId, event: Event) {
+        self.emit(Some(session), event)
     }
 
-    pub fn emit_event(&self, event: Event) {
+    pub fn emit_global_event(&self, event: Event) {
+        self.emit(None, event)
+    }
+
+    fn emit(&self, session: Option<SessionId>, event: Event) {
         use dbus::channel::Sender;
 
-        let path = Service::PATH.into();
+        let path = &self.inner.path;
         let interface = Service::INTERFACE.into();
 
-        // build signal message
-        let mut signal = Message::signal(&path, &interface, &"Event".into());
-        signal.append_all(event);
+        trace!(target: "sdtxd::srvc", object=%path, interface=Service::INTERFACE,
+               session=?session.map(|s| s.value()), value=?event, "emmiting event");
 
-        trace!(target: "sdtxd::srvc", object=Service::PATH, interface=Service::INTERFACE,
-               value=?event, "emmiting event");
+        self.inner.stats.lock().unwrap().record(&event);
+
+        // build signal message
+        let mut signal = Message::signal(path, &interface, &"Event".into());
+        signal.append_all(SessionEvent { session, event });
 
         // only fails when memory runs out
         self.conn.send(signal).unwrap();
@@ -132,25 +365,249 @@ impl ServiceHandle {
 
 
 struct Shared {
-    device: Device,
+    path: dbus::Path<'static>,
+    device: Box<dyn DtxDevice>,
     device_mode: Property<DeviceMode>,
     latch_status: Property<LatchStatus>,
     base_info: Property<BaseInfo>,
+    base_battery: Property<Option<BatteryInfo>>,
+    base_firmware: Property<Option<BaseFirmware>>,
+    retry: Arc<Notify>,
+    records: HandlerRecords,
+    stats: Mutex<Statistics>,
+    dry_run: DryRun,
+    force: ForceRequest,
+    lock: LatchLock,
+    log: Option<LogControl>,
+    metrics: Metrics,
+    last_event: LastEvent,
+    started: Instant,
+    config: Config,
+    report: ConfigReport,
 }
 
 impl Shared {
-    fn new(device: Device) -> Self {
+    fn new(device: Box<dyn DtxDevice>, retry: Arc<Notify>, records: HandlerRecords, dry_run: DryRun)
+        -> Self
+    {
         let base = BaseInfo {
             state: BaseState::Attached,
             device_type: DeviceType::Ssh,
             id: 0,
+            battery: None,
+            firmware: None,
         };
 
         Self {
+            path: Service::PATH.into(),
             device,
             device_mode: Property::new("DeviceMode", DeviceMode::Laptop),
             latch_status: Property::new("LatchStatus", LatchStatus::Closed),
             base_info: Property::new("Base", base),
+            base_battery: Property::new("BaseBattery", None),
+            base_firmware: Property::new("BaseFirmware", None),
+            retry,
+            records,
+            stats: Mutex::new(Statistics::default()),
+            dry_run,
+            force: ForceRequest::default(),
+            lock: LatchLock::default(),
+            log: None,
+            metrics: Metrics::default(),
+            last_event: LastEvent::default(),
+            started: Instant::now(),
+            config: Config::default(),
+            report: ConfigReport::default(),
+        }
+    }
+}
+
+
+/// Effective configuration of the daemon, as reported via D-Bus.
+#[derive(Default)]
+struct ConfigReport {
+    path: String,
+    config: String,
+    unknowns: Vec<String>,
+    problems: Vec<String>,
+}
+
+impl ConfigReport {
+    fn new(config: &Config, diag: &Diagnostics) -> Self {
+        Self {
+            path: diag.path.display().to_string(),
+            // serialization of the config can not fail
+            config: serde_json::to_string(config).unwrap(),
+            unknowns: diag.unknowns.iter().cloned().collect(),
+            problems: diag.problems.clone(),
         }
     }
 }
+
+
+/// Signal for a change of the LatchLocked property. Unlike other properties,
+/// it is only changed in response to method calls, so the signal is sent
+/// along with the reply.
+fn latch_locked_changed(path: &dbus::Path<'static>, locked: bool) -> Message {
+    use dbus::message::SignalArgs;
+    use dbus::ffidisp::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged as PropertiesChanged;
+
+    let mut changed: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+    changed.insert("LatchLocked".into(), Variant(Box::new(locked)));
+
+    let changed = PropertiesChanged {
+        interface_name: Service::INTERFACE.into(),
+        changed_properties: changed,
+        invalidated_properties: Vec::new(),
+    };
+
+    changed.to_emit_message(path)
+}
+
+/// Convert an error of a latch command to a D-Bus error, named after its
+/// cause where possible so that clients can handle it accordingly.
+fn device_error(service: &Shared, err: anyhow::Error) -> MethodErr {
+    warn!(target: "sdtxd::srvc", "DTX device request failed: {:#}", err);
+
+    let cause = DeviceError::of(&err);
+    service.metrics.device_error(cause);
+
+    let cause = match cause {
+        Some(cause) => cause,
+        None => return MethodErr::failed(&err),
+    };
+
+    let name = match cause {
+        DeviceError::PermissionDenied => "org.freedesktop.DBus.Error.AccessDenied",
+        DeviceError::Disconnected     => "org.surface.dtx.Error.Disconnected",
+        DeviceError::Busy             => "org.surface.dtx.Error.Busy",
+        DeviceError::Unsupported      => "org.freedesktop.DBus.Error.NotSupported",
+        DeviceError::ReadOnly         => "org.freedesktop.DBus.Error.NotSupported",
+    };
+
+    // avoid repeating the cause if it is the error itself
+    let msg = if err.is::<DeviceError>() {
+        cause.to_string()
+    } else {
+        format!("{cause}: {err:#}")
+    };
+
+    (name, msg).into()
+}
+
+async fn test_handler(service: &Shared, handler: &str)
+    -> Result<(String, f64, String, String, Vec<String>)>
+{
+    let handler = handler.parse()?;
+    let mut base = service.base_info.lock().unwrap().clone();
+    base.battery = *service.base_battery.lock().unwrap();
+    base.firmware = service.base_firmware.lock().unwrap().clone();
+    let mode = *service.device_mode.lock().unwrap();
+
+    info!(target: "sdtxd::srvc", ?handler, "running handler test on request");
+
+    let out = logic::test_handler(&service.config, handler, base, mode).await?;
+    Ok((out.result.to_string(), out.duration.as_secs_f64(), out.stdout, out.stderr, out.messages))
+}
+
+fn record_to_propmap(record: &HandlerRecord) -> PropMap {
+    let time = record.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let mut map = PropMap::new();
+    map.insert("handler".into(), Variant(Box::new(record.handler.to_owned())));
+    map.insert("time".into(), Variant(Box::new(time.as_secs())));
+    map.insert("duration".into(), Variant(Box::new(record.duration.as_secs_f64())));
+    map.insert("result".into(), Variant(Box::new(record.result.to_string())));
+
+    if let Some(session) = record.session {
+        map.insert("session".into(), session.as_variant());
+    }
+
+    map
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use sdtx::BaseInfo;
+
+    use std::cell::RefCell;
+    use std::path::Path;
+
+    use futures::prelude::*;
+
+    /// Interface description shipped for external bindings, relative to the
+    /// crate root. Run the tests with `SDTX_BLESS=1` to update it.
+    const INTROSPECTION_FILE: &str = "../etc/dbus/org.surface.dtx.xml";
+
+    struct NullDevice;
+
+    impl DtxDevice for NullDevice {
+        async fn try_clone(&self) -> Result<Self> {
+            Ok(NullDevice)
+        }
+
+        fn reopen(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn events(&mut self) -> Result<impl Stream<Item=Result<sdtx::Event>> + Unpin + '_> {
+            Ok(stream::empty())
+        }
+
+        fn latch_lock(&self) -> Result<()> { Ok(()) }
+        fn latch_unlock(&self) -> Result<()> { Ok(()) }
+        fn latch_request(&self) -> Result<()> { Ok(()) }
+        fn latch_confirm(&self) -> Result<()> { Ok(()) }
+        fn latch_heartbeat(&self) -> Result<()> { Ok(()) }
+        fn latch_cancel(&self) -> Result<()> { Ok(()) }
+
+        fn get_base_info(&self) -> Result<BaseInfo> {
+            Ok(BaseInfo { state: BaseState::Attached, device_type: DeviceType::Ssh, id: 0 })
+        }
+
+        fn get_latch_status(&self) -> Result<LatchStatus> {
+            Ok(LatchStatus::Closed)
+        }
+
+        fn get_device_mode(&self) -> Result<DeviceMode> {
+            Ok(DeviceMode::Laptop)
+        }
+    }
+
+    fn introspect() -> String {
+        let shared = Shared::new(Box::new(NullDevice), Arc::new(Notify::new()),
+                                 HandlerRecords::default(), DryRun::new(false));
+
+        let mut cr = Crossroads::new();
+        let iface_token = Service::register_interface(&mut cr);
+        cr.insert(Service::PATH, &[iface_token], Arc::new(shared));
+
+        let mut msg = Message::new_method_call(Service::INTERFACE, Service::PATH,
+                                               "org.freedesktop.DBus.Introspectable", "Introspect")
+            .unwrap();
+        msg.set_serial(1);
+
+        let replies = RefCell::new(Vec::new());
+        cr.handle_message(msg, &replies).unwrap();
+
+        let replies = replies.into_inner();
+        replies[0].read1::<&str>().unwrap().to_owned()
+    }
+
+    #[test]
+    fn introspection_up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(INTROSPECTION_FILE);
+        let xml = introspect() + "\n";
+
+        if std::env::var_os("SDTX_BLESS").is_some() {
+            std::fs::write(&path, &xml).unwrap();
+            return;
+        }
+
+        let shipped = std::fs::read_to_string(&path).unwrap();
+        assert!(shipped == xml, "{} is out of date, re-run tests with SDTX_BLESS=1", INTROSPECTION_FILE);
+    }
+}
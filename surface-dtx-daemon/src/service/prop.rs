@@ -20,7 +20,7 @@ impl<T> Property<T> {
         Self { name, value: Mutex::new(value) }
     }
 
-    pub fn set<C>(&self, conn: &C, value: T)
+    pub fn set<C>(&self, conn: &C, path: &dbus::Path<'static>, value: T)
     where
         C: dbus::channel::Sender,
         T: DbusArg + PartialEq + std::fmt::Debug,
@@ -34,7 +34,7 @@ impl<T> Property<T> {
                 return;
             }
 
-            trace!(target: "sdtxd::srvc", object=Service::PATH, interface=Service::INTERFACE,
+            trace!(target: "sdtxd::srvc", object=%path, interface=Service::INTERFACE,
                    name=self.name, old=?*stored, new=?value, "changing property");
 
             *stored = value;
@@ -56,7 +56,7 @@ impl<T> Property<T> {
             invalidated_properties: Vec::new(),
         };
 
-        let msg = changed.to_emit_message(&Service::PATH.into());
+        let msg = changed.to_emit_message(path);
 
         // send will only fail due to lack of memory
         conn.send(msg).unwrap();
@@ -0,0 +1,44 @@
+use crate::service::arg::DbusArg;
+use crate::service::event::Event;
+
+use std::collections::{BTreeMap, HashMap};
+
+
+/// Event counts since startup, collected from the events emitted by the
+/// service.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    counters: BTreeMap<&'static str, u64>,
+    cancels: BTreeMap<String, u64>,
+}
+
+impl Statistics {
+    pub fn record(&mut self, event: &Event) {
+        let counter = match event {
+            Event::DetachmentInhibited { .. } => "detachment:inhibited",
+            Event::DetachmentStart            => "detachment:start",
+            Event::DetachmentComplete         => "detachment:complete",
+            Event::DetachmentCancel { .. }    => "detachment:cancel",
+            Event::DetachmentCancelTimeout    => "detachment:cancel:timeout",
+            Event::DetachmentUnexpected       => "detachment:unexpected",
+            Event::DetachmentHandlerSlow      => "detachment:handler:slow",
+            Event::AttachmentComplete         => "attachment:complete",
+            Event::AttachmentTimeout          => "attachment:timeout",
+            _                                 => return,
+        };
+
+        *self.counters.entry(counter).or_default() += 1;
+
+        if let Event::DetachmentCancel { reason } = event {
+            *self.cancels.entry(reason.as_arg()).or_default() += 1;
+        }
+    }
+
+    pub fn counters(&self) -> HashMap<String, u64> {
+        self.counters.iter().map(|(k, v)| ((*k).to_owned(), *v)).collect()
+    }
+
+    pub fn cancels(&self) -> HashMap<String, u64> {
+        self.cancels.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+
+/// Log file, rotated once it exceeds a given size.
+///
+/// On rotation, the current file is renamed to `<path>.1`, `<path>.1` to
+/// `<path>.2`, and so on, dropping the oldest one once the given number of
+/// rotated files is exceeded. With zero rotations, the file is simply
+/// truncated.
+///
+/// The rolling appender of tracing-appender only supports rotating by time,
+/// so this is used as the writer behind its non-blocking writer instead.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    rotations: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, rotations: u32) -> Result<Self> {
+        let file = open(path, false)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self { path: path.to_owned(), max_size, rotations, file, size })
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        for i in (1..self.rotations).rev() {
+            let from = self.rotated(i);
+
+            if from.exists() {
+                std::fs::rename(from, self.rotated(i + 1))?;
+            }
+        }
+
+        if self.rotations > 0 {
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = open(&self.path, true)?;
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // records are written as a whole, so only rotate between them
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            // keep writing to the current file if rotation fails, there is
+            // no better place to report this to
+            let _ = self.rotate();
+        }
+
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+
+fn open(path: &Path, truncate: bool) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(!truncate)
+        .write(true)
+        .truncate(truncate)
+        .mode(0o640)
+        .open(path)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for record in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n"] {
+            file.write_all(record.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read(&path), "cccccccc\n");
+        assert_eq!(read(&file.rotated(1)), "bbbbbbbb\n");
+        assert_eq!(read(&file.rotated(2)), "aaaaaaaa\n");
+
+        // the oldest file is dropped once the limit is exceeded
+        file.write_all(b"dddddddd\n").unwrap();
+
+        assert_eq!(read(&path), "dddddddd\n");
+        assert_eq!(read(&file.rotated(1)), "cccccccc\n");
+        assert_eq!(read(&file.rotated(2)), "bbbbbbbb\n");
+        assert!(!file.rotated(3).exists());
+    }
+
+    #[test]
+    fn rotate_appends_to_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+
+        std::fs::write(&path, "aaaaaaaa\n").unwrap();
+
+        let mut file = RotatingFile::open(&path, 10, 1).unwrap();
+        file.write_all(b"bbbbbbbb\n").unwrap();
+
+        assert_eq!(read(&path), "bbbbbbbb\n");
+        assert_eq!(read(&file.rotated(1)), "aaaaaaaa\n");
+    }
+
+    #[test]
+    fn truncate_without_rotations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+
+        let mut file = RotatingFile::open(&path, 10, 0).unwrap();
+        file.write_all(b"aaaaaaaa\n").unwrap();
+        file.write_all(b"bbbbbbbb\n").unwrap();
+
+        assert_eq!(read(&path), "bbbbbbbb\n");
+        assert!(!file.rotated(1).exists());
+    }
+
+    #[test]
+    fn rotate_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+
+        let mut file = RotatingFile::open(&path, 10, 1).unwrap();
+
+        // a directory in place of the rotated file lets the rename fail
+        std::fs::create_dir(file.rotated(1)).unwrap();
+        std::fs::write(file.rotated(1).join("blocker"), "").unwrap();
+
+        file.write_all(b"aaaaaaaa\n").unwrap();
+        file.write_all(b"bbbbbbbb\n").unwrap();
+
+        // nothing is lost, records keep going to the current file
+        assert_eq!(read(&path), "aaaaaaaa\nbbbbbbbb\n");
+
+        // rotation is retried with the next record
+        std::fs::remove_dir_all(file.rotated(1)).unwrap();
+        file.write_all(b"cccccccc\n").unwrap();
+
+        assert_eq!(read(&path), "cccccccc\n");
+        assert_eq!(read(&file.rotated(1)), "aaaaaaaa\nbbbbbbbb\n");
+    }
+}
@@ -0,0 +1,65 @@
+use crate::config::LogLevel;
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+
+use tracing::info;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+
+/// Handle for changing the log level at runtime, e.g. to capture detailed
+/// traces of a failing detachment without restarting the daemon.
+#[derive(Clone)]
+pub struct LogControl {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    configured: LogLevel,
+    current: Mutex<LogLevel>,
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogControl {
+    /// Set up a reloadable filter for the given (configured) log level.
+    pub fn new(configured: LogLevel) -> (Self, reload::Layer<EnvFilter, Registry>) {
+        let (layer, handle) = reload::Layer::new(filter(configured));
+
+        let inner = Inner { configured, current: Mutex::new(configured), handle };
+        (Self { inner: Arc::new(inner) }, layer)
+    }
+
+    /// Change the log level, or restore the configured one if `None`.
+    pub fn set(&self, level: Option<LogLevel>) -> Result<()> {
+        let level = level.unwrap_or(self.inner.configured);
+        let mut current = self.inner.current.lock().unwrap();
+
+        self.inner.handle.reload(filter(level))
+            .context("Failed to change log level")?;
+
+        *current = level;
+
+        info!(target: "sdtxd", ?level, "log level changed");
+        Ok(())
+    }
+
+    /// Switch between the configured log level and trace.
+    pub fn toggle(&self) -> Result<()> {
+        let current = *self.inner.current.lock().unwrap();
+
+        if current == LogLevel::Trace {
+            self.set(None)
+        } else {
+            self.set(Some(LogLevel::Trace))
+        }
+    }
+}
+
+
+/// Filter for the given level. Directives from the environment are always
+/// applied on top of it.
+fn filter(level: LogLevel) -> EnvFilter {
+    EnvFilter::from_env("SDTXD_LOG")
+        .add_directive(tracing::Level::from(level).into())
+}
@@ -1,6 +1,10 @@
 #[macro_use]
 mod tracing;
 
+pub mod logfile;
+pub mod loglevel;
+pub mod notify;
+pub mod ratelimit;
 pub mod scope;
 pub mod task;
 pub mod taskq;
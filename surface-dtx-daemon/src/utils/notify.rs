@@ -0,0 +1,69 @@
+use std::io::Result;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use tracing::warn;
+
+
+/// Notify the service manager that startup has completed.
+pub fn ready() {
+    notify("READY=1")
+}
+
+/// Notify the service manager that we are shutting down.
+pub fn stopping() {
+    notify("STOPPING=1")
+}
+
+/// Keep-alive ping for the service manager watchdog.
+pub fn watchdog() {
+    notify("WATCHDOG=1")
+}
+
+/// Interval in which the watchdog needs to be pinged, if it is enabled for
+/// this process. Pings are recommended at half of this interval.
+pub fn watchdog_timeout() -> Option<Duration> {
+    // the watchdog may be meant for a different process, e.g. our parent
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec))
+}
+
+/// Send the given state to the service manager, as sd_notify(3) does. Does
+/// nothing if we have not been started by a service manager supporting this.
+fn notify(state: &str) {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+
+    // failing to notify should not affect anything else, so only warn
+    if let Err(err) = send(&path, state) {
+        warn!(target: "sdtxd", socket=?path, state, error=%err, "failed to notify service manager");
+    }
+}
+
+fn send(path: &std::ffi::OsStr, state: &str) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // socket paths starting with '@' refer to the abstract namespace
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+
+/// Suppresses repetitions of log messages, e.g. for hardware errors that may
+/// recur every second on a flaky EC.
+///
+/// Messages are identified by a key. The first occurrence of a message is
+/// always logged, repetitions within the interval are counted and reported
+/// together with the next occurrence after it. Use via the `event_limited!`
+/// macro.
+#[derive(Debug)]
+pub struct RateLimit {
+    interval: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    logged: Instant,
+    suppressed: u32,
+}
+
+impl RateLimit {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, entries: HashMap::new() }
+    }
+
+    /// Check whether the message with the given key should be logged now.
+    /// Returns the number of repetitions suppressed since it has last been
+    /// logged, or `None` if it should be suppressed.
+    pub fn check(&mut self, key: &str) -> Option<u32> {
+        let now = Instant::now();
+
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => {
+                self.entries.insert(key.to_owned(), Entry { logged: now, suppressed: 0 });
+                return Some(0);
+            },
+        };
+
+        if now.duration_since(entry.logged) < self.interval {
+            entry.suppressed = entry.suppressed.saturating_add(1);
+            return None;
+        }
+
+        let suppressed = entry.suppressed;
+        *entry = Entry { logged: now, suppressed: 0 };
+
+        Some(suppressed)
+    }
+}
@@ -4,24 +4,37 @@ use std::pin::Pin;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::mpsc::error::SendError;
 
-use tracing::trace;
+use tracing::{error, trace, Instrument};
 
 
 pub type Task<E> = Pin<Box<dyn Future<Output=Result<(), E>> + Send>>;
 
 
+/// Queue running submitted tasks one after another, in order of submission.
+///
+/// Tasks are never run concurrently: a task is only started once the
+/// previous one has completed. Handlers rely on this to not interleave their
+/// side effects, e.g. an attachment handler submitted while the
+/// detachment-abort handler is still running will only be started after the
+/// latter (including its hooks) has completed.
+///
+/// Tasks of all devices share the queue, so a failing task is only logged
+/// and does not stop the queue.
 #[derive(Debug)]
 pub struct TaskQueue<E> {
     rx: UnboundedReceiver<Task<E>>,
 }
 
-impl<E> TaskQueue<E> {
+impl<E: std::fmt::Display> TaskQueue<E> {
     pub async fn run(&mut self) -> Result<(), E> {
         while let Some(task) = self.rx.recv().await {
             trace!(target: "sdtxd::tq", "running next task");
             let result = task.await;
             trace!(target: "sdtxd::tq", "task completed");
-            result?;
+
+            if let Err(err) = result {
+                error!(target: "sdtxd::tq", "task failed: {:#}", err);
+            }
         }
 
         Ok(())
@@ -29,18 +42,28 @@ impl<E> TaskQueue<E> {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct TaskSender<E> {
     tx: UnboundedSender<Task<E>>,
 }
 
+// derive would require E: Clone, which is not needed for the sender
+impl<E> Clone for TaskSender<E> {
+    fn clone(&self) -> Self {
+        Self { tx: self.tx.clone() }
+    }
+}
+
 impl<E> TaskSender<E> {
+    /// Submit a task, to be run after all previously submitted tasks have
+    /// completed. The task runs in the current span, i.e. the span of the
+    /// session it has been submitted for.
     pub fn submit<T>(&self, task: T) -> Result<(), SendError<Task<E>>>
     where
         T: Future<Output=Result<(), E>> + Send + 'static
     {
         trace!(target: "sdtxd::tq", "submitting new task");
-        self.tx.send(Box::pin(task))
+        self.tx.send(Box::pin(task.in_current_span()))
     }
 }
 
@@ -50,3 +73,70 @@ pub fn new<E>() -> (TaskQueue<E>, TaskSender<E>) {
 
     (TaskQueue { rx }, TaskSender { tx })
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    fn task(log: &Log, name: &'static str, duration: u64, fail: bool) -> impl Future<Output=anyhow::Result<()>> {
+        let log = log.clone();
+
+        async move {
+            log.lock().unwrap().push(format!("{} start", name));
+            tokio::time::sleep(Duration::from_millis(duration)).await;
+            log.lock().unwrap().push(format!("{} end", name));
+
+            if fail {
+                anyhow::bail!("{} failed", name);
+            }
+
+            Ok(())
+        }
+    }
+
+    async fn run(tasks: &[(&'static str, u64, bool)]) -> Vec<String> {
+        let log = Log::default();
+        let (mut queue, tx) = new();
+
+        // later tasks complete faster, so running any of them concurrently
+        // would change the order
+        for (name, duration, fail) in tasks {
+            tx.submit(task(&log, name, *duration, *fail)).unwrap();
+        }
+        drop(tx);
+
+        queue.run().await.unwrap();
+
+        let log = log.lock().unwrap();
+        log.clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn order() {
+        let log = run(&[("detach", 300, false), ("abort", 200, false), ("attach", 100, false)]).await;
+
+        assert_eq!(log, [
+            "detach start", "detach end",
+            "abort start", "abort end",
+            "attach start", "attach end",
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn order_with_failure() {
+        let log = run(&[("detach", 300, false), ("abort", 200, true), ("attach", 100, false)]).await;
+
+        // the failed task is logged and the queue continues with the next one
+        assert_eq!(log, [
+            "detach start", "detach end",
+            "abort start", "abort end",
+            "attach start", "attach end",
+        ]);
+    }
+}
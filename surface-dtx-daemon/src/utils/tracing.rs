@@ -41,3 +41,18 @@ macro_rules! event {
         }
     };
 }
+
+// for logging with rate limit, see utils::ratelimit::RateLimit
+macro_rules! event_limited {
+    ($limit:expr, $key:expr, target: $target:expr, $lvl:expr, $($arg:tt)+ ) => {
+        let key: &str = &$key;
+
+        if let Some(repeated) = $limit.check(key) {
+            if repeated > 0 {
+                event!(target: $target, $lvl, "{}: message repeated {} times", key, repeated);
+            }
+
+            event!(target: $target, $lvl, $($arg)+);
+        }
+    };
+}
@@ -15,4 +15,8 @@ pub fn app() -> Command {
             .long("no-log-time")
             .help("Do not emit timestamps in log")
             .action(ArgAction::SetTrue))
+        .arg(Arg::new("history")
+            .long("history")
+            .help("Print the notification history and exit")
+            .action(ArgAction::SetTrue))
 }
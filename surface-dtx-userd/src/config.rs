@@ -1,4 +1,5 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
@@ -17,6 +18,18 @@ pub struct Config {
 
     #[serde(default)]
     pub log: Log,
+
+    #[serde(default)]
+    pub notify: Notify,
+
+    #[serde(default)]
+    pub tray: Tray,
+
+    #[serde(default)]
+    pub battery: Battery,
+
+    #[serde(default)]
+    pub hooks: BTreeMap<String, Hook>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -36,6 +49,174 @@ pub enum LogLevel {
     Trace,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notify {
+    #[serde(default)]
+    pub sound: BTreeMap<String, Sound>,
+
+    #[serde(default)]
+    pub style: BTreeMap<String, Style>,
+
+    #[serde(default)]
+    pub quiet: Quiet,
+
+    #[serde(default)]
+    pub lock: Lock,
+
+    #[serde(default="defaults::notify_fallback")]
+    pub fallback: bool,
+
+    #[serde(default)]
+    pub attach_summary: bool,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify {
+            sound: BTreeMap::new(),
+            style: BTreeMap::new(),
+            quiet: Quiet::default(),
+            lock: Lock::default(),
+            fallback: defaults::notify_fallback(),
+            attach_summary: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Sound {
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Style {
+    #[serde(default)]
+    pub icon: Option<String>,
+
+    #[serde(default)]
+    pub category: Option<String>,
+
+    #[serde(default)]
+    pub urgency: Option<Urgency>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all="lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hook {
+    pub exec: PathBuf,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Tray {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Battery {
+    #[serde(default="defaults::battery_enabled")]
+    pub enabled: bool,
+
+    #[serde(default="defaults::battery_device")]
+    pub device: String,
+
+    #[serde(default="defaults::battery_warning")]
+    pub warning: f64,
+
+    #[serde(default="defaults::battery_critical")]
+    pub critical: f64,
+
+    #[serde(default)]
+    pub detach_threshold: Option<f64>,
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Battery {
+            enabled: defaults::battery_enabled(),
+            device: defaults::battery_device(),
+            warning: defaults::battery_warning(),
+            critical: defaults::battery_critical(),
+            detach_threshold: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Quiet {
+    #[serde(default)]
+    pub desktop: bool,
+
+    #[serde(default)]
+    pub start: Option<TimeOfDay>,
+
+    #[serde(default)]
+    pub end: Option<TimeOfDay>,
+
+    #[serde(default="defaults::quiet_presentation")]
+    pub presentation: bool,
+}
+
+impl Default for Quiet {
+    fn default() -> Self {
+        Quiet {
+            desktop: false,
+            start: None,
+            end: None,
+            presentation: defaults::quiet_presentation(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Lock {
+    #[serde(default)]
+    pub hold: bool,
+}
+
+/// Time of day in minutes after midnight, specified as "HH:MM".
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(pub u16);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid time of day '{value}', expected HH:MM");
+
+        let (h, m) = value.split_once(':').ok_or_else(invalid)?;
+        let h: u16 = h.parse().map_err(|_| invalid())?;
+        let m: u16 = m.parse().map_err(|_| invalid())?;
+
+        if h >= 24 || m >= 60 {
+            return Err(invalid());
+        }
+
+        Ok(TimeOfDay(h * 60 + m))
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
+
 
 impl Config {
     pub fn load() -> Result<(Config, Diagnostics)> {
@@ -111,6 +292,43 @@ impl Diagnostics {
 }
 
 
+mod defaults {
+    pub fn notify_fallback() -> bool {
+        true
+    }
+
+    pub fn quiet_presentation() -> bool {
+        true
+    }
+
+    pub fn battery_enabled() -> bool {
+        true
+    }
+
+    pub fn battery_device() -> String {
+        "BAT1".into()
+    }
+
+    pub fn battery_warning() -> f64 {
+        10.0
+    }
+
+    pub fn battery_critical() -> f64 {
+        5.0
+    }
+}
+
+
+impl From<Urgency> for i32 {
+    fn from(urgency: Urgency) -> Self {
+        match urgency {
+            Urgency::Low      => 0,
+            Urgency::Normal   => 1,
+            Urgency::Critical => 2,
+        }
+    }
+}
+
 impl From<LogLevel> for tracing::Level {
     fn from(level: LogLevel) -> Self {
         match level {
@@ -122,3 +340,73 @@ impl From<LogLevel> for tracing::Level {
         }
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let config: Config = toml::from_str("").unwrap();
+
+        assert!(config.notify.fallback);
+        assert!(config.notify.quiet.presentation);
+        assert!(!config.tray.enabled);
+        assert!(config.battery.enabled);
+        assert_eq!(config.battery.device, "BAT1");
+        assert_eq!(config.battery.warning, 10.0);
+        assert_eq!(config.battery.critical, 5.0);
+        assert_eq!(config.battery.detach_threshold, None);
+        assert!(config.hooks.is_empty());
+    }
+
+    #[test]
+    fn parse() {
+        let data = r#"
+            [notify]
+            quiet = { start = "22:30", end = "07:00" }
+
+            [notify.style."detachment:ready"]
+            icon = "input-tablet"
+            urgency = "critical"
+
+            [battery]
+            device = "BAT2"
+            detach_threshold = 15
+
+            [hooks]
+            "detachment:complete" = { exec = "~/on-detach.sh", args = ["a", "b"] }
+        "#;
+
+        let config: Config = toml::from_str(data).unwrap();
+
+        assert_eq!(config.notify.quiet.start, Some(TimeOfDay(22 * 60 + 30)));
+        assert_eq!(config.notify.quiet.end, Some(TimeOfDay(7 * 60)));
+
+        let style = &config.notify.style["detachment:ready"];
+        assert_eq!(style.icon.as_deref(), Some("input-tablet"));
+        assert_eq!(style.urgency, Some(Urgency::Critical));
+        assert_eq!(i32::from(Urgency::Critical), 2);
+
+        assert_eq!(config.battery.device, "BAT2");
+        assert_eq!(config.battery.detach_threshold, Some(15.0));
+
+        let hook = &config.hooks["detachment:complete"];
+        assert_eq!(hook.exec, Path::new("~/on-detach.sh"));
+        assert_eq!(hook.args, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn time_of_day() {
+        assert_eq!(TimeOfDay::try_from("00:00".to_string()), Ok(TimeOfDay(0)));
+        assert_eq!(TimeOfDay::try_from("23:59".to_string()), Ok(TimeOfDay(23 * 60 + 59)));
+        assert_eq!(String::from(TimeOfDay(7 * 60 + 5)), "07:05");
+
+        for value in ["24:00", "12:60", "12", "ab:cd", ""] {
+            assert!(TimeOfDay::try_from(value.to_string()).is_err(), "{}", value);
+        }
+
+        assert!(toml::from_str::<Config>("[notify]\nquiet = { start = \"25:00\" }").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::config::Battery;
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::Message;
+use dbus::arg::RefArg;
+use dbus::message::SignalArgs;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+
+use tracing::{debug, trace};
+
+
+const UPOWER_NAME: &str = "org.freedesktop.UPower";
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+const DEVICE_INTERFACE: &str = "org.freedesktop.UPower.Device";
+
+/// UPower state of a battery that is discharging.
+const STATE_DISCHARGING: u32 = 2;
+
+/// Margin (in percent) by which the battery needs to recover above the
+/// warning threshold before warnings are shown again.
+const HYSTERESIS: f64 = 2.0;
+
+
+/// Battery level of the base, relative to the configured thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Normal,
+    Warning,
+    Critical,
+}
+
+/// Monitors the base battery via UPower, warning about low levels at which
+/// the EC may force-disconnect the base.
+pub struct BatteryMonitor {
+    config: Battery,
+    path: dbus::Path<'static>,
+    percentage: f64,
+    state: u32,
+    notified: Level,
+}
+
+impl BatteryMonitor {
+    /// Look up the configured battery device via UPower.
+    pub async fn find(config: Battery, system: &SyncConnection) -> Result<Option<Self>> {
+        let upower = Proxy::new(UPOWER_NAME, UPOWER_PATH, Duration::from_secs(5), system);
+
+        let (devices,): (Vec<dbus::Path<'static>>,) = upower
+            .method_call(UPOWER_NAME, "EnumerateDevices", ()).await
+            .context("Failed to enumerate UPower devices")?;
+
+        for path in devices {
+            let device = Proxy::new(UPOWER_NAME, path.clone(), Duration::from_secs(5), system);
+
+            let native: String = device.get(DEVICE_INTERFACE, "NativePath").await
+                .context("Failed to query UPower device")?;
+
+            if native != config.device {
+                continue;
+            }
+
+            let percentage: f64 = device.get(DEVICE_INTERFACE, "Percentage").await
+                .context("Failed to query battery percentage")?;
+
+            let state: u32 = device.get(DEVICE_INTERFACE, "State").await
+                .context("Failed to query battery state")?;
+
+            debug!(target: "sdtxu::battery", %path, percentage, state, "monitoring base battery");
+
+            return Ok(Some(BatteryMonitor { config, path, percentage, state, notified: Level::Normal }));
+        }
+
+        Ok(None)
+    }
+
+    pub fn path(&self) -> &dbus::Path<'static> {
+        &self.path
+    }
+
+    /// Current battery percentage.
+    pub fn percentage(&self) -> f64 {
+        self.percentage
+    }
+
+    /// Battery percentage required for detachment, if configured.
+    pub fn detach_threshold(&self) -> Option<f64> {
+        self.config.detach_threshold
+    }
+
+    /// Update the battery state from a PropertiesChanged signal of the
+    /// device, returning the level to warn about, if any.
+    pub fn on_properties_changed(&mut self, msg: &Message) -> Option<Level> {
+        let changed = PropertiesPropertiesChanged::from_message(msg)?;
+        if changed.interface_name != DEVICE_INTERFACE {
+            return None;
+        }
+
+        let props = changed.changed_properties;
+
+        if let Some(percentage) = props.get("Percentage").and_then(|v| v.as_f64()) {
+            self.percentage = percentage;
+        }
+
+        if let Some(state) = props.get("State").and_then(|v| v.as_u64()) {
+            self.state = state as u32;
+        }
+
+        trace!(target: "sdtxu::battery", percentage=self.percentage, state=self.state,
+               "base battery changed");
+
+        self.check()
+    }
+
+    /// Check the current level, returning it if the user should be warned.
+    ///
+    /// Each level is only reported once until the battery has recovered
+    /// above the warning threshold.
+    pub fn check(&mut self) -> Option<Level> {
+        if self.percentage > self.config.warning + HYSTERESIS {
+            self.notified = Level::Normal;
+            return None;
+        }
+
+        if self.state != STATE_DISCHARGING {
+            return None;
+        }
+
+        let level = if self.percentage <= self.config.critical {
+            Level::Critical
+        } else if self.percentage <= self.config.warning {
+            Level::Warning
+        } else {
+            Level::Normal
+        };
+
+        if level > self.notified {
+            self.notified = level;
+            Some(level)
+        } else {
+            None
+        }
+    }
+}
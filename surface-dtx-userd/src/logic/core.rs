@@ -1,34 +1,107 @@
-use crate::logic::{CancelReason, Event};
-use crate::utils::notify::{Notification, NotificationHandle, Timeout};
+use crate::config::Notify;
+use crate::logic::{BatteryLevel, BatteryMonitor, QuietFilter, Tray};
+use crate::logic::history::{History, Outcome};
+use crate::logic::devices::BaseDevices;
+use crate::logic::lock;
+use crate::logic::session;
+use crate::utils::dialog;
+use crate::utils::notify::{Capabilities, Notification, NotificationHandle, Timeout};
 
 use std::borrow::Cow;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 
+use dbus::Message;
 use dbus::nonblock::SyncConnection;
 
-use tracing::{debug, trace};
+use surface_dtx_client::{BaseState, CancelReason, Client, Event, HardwareError, LatchStatus, RuntimeError};
+
+use tracing::{debug, trace, warn};
 
 
 pub struct Core {
-    session:  Arc<SyncConnection>,
-    canceled: bool,
-    notif:    Option<NotificationHandle>,
+    config:     Notify,
+    quiet:      QuietFilter,
+    session:    Arc<SyncConnection>,
+    system:     Arc<SyncConnection>,
+    tray:       Option<Tray>,
+    battery:    Option<BatteryMonitor>,
+    canceled:   bool,
+    infeasible: bool,
+    notif:      Option<NotificationHandle>,     // notification of the current detachment
+    unexpected: Option<NotificationHandle>,
+    missing:    Option<NotificationHandle>,
+    progress:   Progress,
+    caps:       Option<Capabilities>,
+    locked:     bool,
+    held:       Vec<String>,
+    history:    History,
+    inhibit:    Option<u32>,
+}
+
+#[derive(Default)]
+struct Progress {
+    notif:  Option<NotificationHandle>,
+    status: Option<String>,
+    value:  Option<u8>,
+    detach: bool,
 }
 
 impl Core {
-    pub fn new(session: Arc<SyncConnection>) -> Self {
+    pub fn new(config: Notify, session: Arc<SyncConnection>, system: Arc<SyncConnection>,
+               tray: Option<Tray>) -> Self
+    {
         Core {
+            quiet:      QuietFilter::new(config.quiet.clone()),
+            config,
             session,
-            canceled: false,
-            notif:    None,
+            system,
+            tray,
+            battery:    None,
+            canceled:   false,
+            infeasible: false,
+            notif:      None,
+            unexpected: None,
+            missing:    None,
+            progress:   Progress::default(),
+            caps:       None,
+            locked:     false,
+            held:       Vec::new(),
+            history:    History::new(),
+            inhibit:    None,
         }
     }
 
     pub async fn handle(&mut self, event: Event) -> Result<()> {
         debug!(target: "sdtxu::core", ?event, "event received");
 
+        // close progress notification once the handler is done
+        match event {
+            Event::HandlerStatus { .. } | Event::HandlerProgress { .. } | Event::HandlerError { .. } => {},
+            Event::DetachmentStart | Event::AttachmentStart | Event::DetachmentHandlerSlow => {},
+            Event::DetachmentCountdown { .. } => {},
+            _ => self.close_progress_notification().await?,
+        }
+
+        // track ongoing detachments for the tray icon
+        if let Some(ref tray) = self.tray {
+            match event {
+                Event::DetachmentStart => tray.set_detaching(true),
+                Event::DetachmentComplete | Event::DetachmentCancel { .. }
+                    | Event::DetachmentUnexpected => tray.set_detaching(false),
+                _ => {},
+            }
+        }
+
+        // keep the screen from locking while the user follows the detachment
+        match event {
+            Event::DetachmentStart => self.inhibit_idle().await,
+            Event::DetachmentComplete | Event::DetachmentCancel { .. }
+                | Event::DetachmentUnexpected => self.uninhibit_idle().await,
+            _ => {},
+        }
+
         match event {
             Event::DetachmentInhibited { reason } => self.on_detachment_inhibited(reason).await,
             Event::DetachmentStart                => self.on_detachment_start().await,
@@ -37,77 +110,218 @@ impl Core {
             Event::DetachmentCancel { reason }    => self.on_detachment_cancel(reason).await,
             Event::DetachmentCancelTimeout        => self.on_detachment_cancel_timeout().await,
             Event::DetachmentUnexpected           => self.on_detachment_unexpected().await,
+            Event::DetachmentHandlerSlow          => self.on_detachment_handler_slow().await,
+            Event::DetachmentCountdown { remaining } => self.on_detachment_countdown(remaining).await,
             Event::AttachmentComplete             => self.on_attachment_complete().await,
             Event::AttachmentTimeout              => self.on_attachment_timeout().await,
+            Event::BaseFeasible                   => self.on_base_feasible().await,
+            Event::HandlerStatus { message }      => self.on_handler_status(message).await,
+            Event::HandlerProgress { progress }   => self.on_handler_progress(progress).await,
+            Event::HandlerError { message }       => self.on_handler_error(message).await,
+            Event::HandlerCrash { handler, signal } => self.on_handler_crash(handler, signal).await,
             _ => Ok(()),
         }
     }
 
-    async fn on_detachment_inhibited(&mut self, reason: CancelReason) -> Result<()> {
-        let (category, summary, body): (_, _, Cow<'static, str>) = match reason {
-            CancelReason::Runtime(err) => match err {
-                super::types::RuntimeError::NotFeasible => (
-                    "device",
-                    "Surface DTX: Cannot detach",
-                    "Detachment inhibited by the controller. \
-                     Please make sure that the tablet battery is sufficently charged."
-                        .into()
-                ),
-                super::types::RuntimeError::Unknown(x) => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    format!("Detachment inhibited due to unknown runtime error ({x}).")
-                        .into()
-                ),
-                _ => { return Ok(()); },
+    /// Reconstruct the notifications of an ongoing detachment from the
+    /// current state of the daemon, e.g. when we have been (re-)started while
+    /// the latch is already open.
+    ///
+    /// The daemon does not expose its runtime state, so only the
+    /// detachment-ready notification can be restored.
+    pub async fn catch_up(&mut self, system: &SyncConnection) -> Result<()> {
+        let client = Client::new(system);
+
+        let latch = client.latch_status().await?;
+        let base = client.base().await?;
+
+        debug!(target: "sdtxu::core", %latch, base=%base.state, "catching up with daemon state");
+
+        if latch == LatchStatus::Opened && base.state == BaseState::Attached {
+            self.inhibit_idle().await;
+            self.canceled = false;
+            self.on_detachment_ready().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn inhibit_idle(&mut self) {
+        if self.inhibit.is_some() {
+            return;
+        }
+
+        match lock::inhibit(&self.session, "Clipboard detachment in progress").await {
+            Ok(cookie) => {
+                debug!(target: "sdtxu::core", cookie, "inhibiting screen saver");
+                self.inhibit = Some(cookie);
             },
-            CancelReason::Hardware(err) => match err {
-                super::types::HardwareError::FailedToOpen => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    "Hardware error: The controller failed to open the latch."
-                        .into()
-                ),
-                super::types::HardwareError::FailedToRemainOpen => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    "Hardware error: The controller failed to keep the latch open."
-                        .into()
-                ),
-                super::types::HardwareError::FailedToClose => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    "Hardware error: The controller failed to close the latch."
-                        .into()
-                ),
-                super::types::HardwareError::Unknown(x) => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    format!("Detachment inhibited due to unknown hardware error ({x}).")
-                        .into()
-                ),
+            Err(err) => {
+                warn!(target: "sdtxu::core", "{:#}", err);
             },
-            CancelReason::Unknown(x) => (
-                "device.error",
-                "Surface DTX: Error",
-                format!("Detachment inhibited due to unknown error ({x}).")
-                    .into()
-            ),
-            _ => { return Ok(()); },
+        }
+    }
+
+    async fn uninhibit_idle(&mut self) {
+        if let Some(cookie) = self.inhibit.take() {
+            debug!(target: "sdtxu::core", cookie, "releasing screen saver inhibition");
+
+            if let Err(err) = lock::uninhibit(&self.session, cookie).await {
+                warn!(target: "sdtxu::core", "{:#}", err);
+            }
+        }
+    }
+
+    pub fn set_battery(&mut self, battery: BatteryMonitor) {
+        self.battery = Some(battery);
+    }
+
+    /// Check the current battery level and warn if it is low.
+    pub async fn check_battery(&mut self) -> Result<()> {
+        let (level, percentage) = match self.battery {
+            Some(ref mut battery) => (battery.check(), battery.percentage()),
+            None => return Ok(()),
+        };
+
+        match level {
+            Some(level) => self.on_battery_low(level, percentage).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Handle a PropertiesChanged signal of a UPower device.
+    pub async fn on_battery_changed(&mut self, msg: Message) -> Result<()> {
+        let (level, percentage) = match self.battery {
+            Some(ref mut battery) if msg.path().as_ref() == Some(battery.path()) => {
+                (battery.on_properties_changed(&msg), battery.percentage())
+            },
+            _ => return Ok(()),
         };
 
-        let handle = Notification::create("Surface DTX")
+        match level {
+            Some(level) => self.on_battery_low(level, percentage).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Warn about a low base battery.
+    async fn on_battery_low(&mut self, level: BatteryLevel, percentage: f64) -> Result<()> {
+        let (ty, summary, urgency) = match level {
+            BatteryLevel::Normal   => return Ok(()),
+            BatteryLevel::Warning  => ("battery-low", "Surface DTX: Base battery low", 1),
+            BatteryLevel::Critical => ("battery-critical", "Surface DTX: Base battery critical", 2),
+        };
+
+        let notif = Notification::create("Surface DTX")
+            .summary(summary)
+            .body(format!("The base battery is at {percentage:.0}%. \
+                           The base may be disconnected soon. \
+                           Please connect the device to a charger."))
+            .hint_s("image-path", "battery-caution")
+            .hint_s("category", "device")
+            .hint("urgency", urgency)
+            .build();
+
+        self.show(ty, notif).await?;
+        Ok(())
+    }
+
+    /// Update the screen lock state, showing a summary of the notifications
+    /// held back while the screen was locked once it is unlocked.
+    pub async fn on_lock_changed(&mut self, locked: bool) -> Result<()> {
+        debug!(target: "sdtxu::notify", locked, "screen lock state changed");
+
+        self.locked = locked;
+        if locked || self.held.is_empty() {
+            return Ok(());
+        }
+
+        let held = std::mem::take(&mut self.held);
+        let body = held.iter()
+            .map(|summary| summary.strip_prefix("Surface DTX: ").unwrap_or(summary))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let notif = Notification::create("Surface DTX")
+            .summary("Surface DTX: While the screen was locked")
+            .body(body)
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device")
+            .build();
+
+        self.show("lock-summary", notif).await?;
+        Ok(())
+    }
+
+    /// Warn about the system daemon not running, e.g. because it has crashed.
+    pub async fn on_daemon_lost(&mut self) -> Result<()> {
+        // any detachment in progress is gone with the daemon
+        self.uninhibit_idle().await;
+
+        if self.missing.is_some() {
+            return Ok(());
+        }
+
+        let notif = Notification::create("Surface DTX")
+            .summary("Surface DTX: Daemon not running")
+            .body("The Surface DTX daemon is not running. \
+                   Clipboard detachment will not be handled properly and you will \
+                   not be notified when it is safe to detach. \
+                   Please check the status of surface-dtx-daemon.service.")
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device.error")
+            .hint("urgency", 2)
+            .hint("resident", true)
+            .expires(Timeout::Never)
+            .build();
+
+        self.missing = self.show("daemon-missing", notif).await?;
+        Ok(())
+    }
+
+    /// Close the daemon warning once the system daemon is back and restore
+    /// the notifications of a detachment in progress.
+    pub async fn on_daemon_available(&mut self, system: &SyncConnection) -> Result<()> {
+        if let Some(handle) = self.missing.take() {
+            trace!(target: "sdtxu::notify", id = handle.id, "closing notification");
+
+            handle.close(&self.session).await
+                .context("Failed to close notification")?;
+        }
+
+        self.catch_up(system).await
+    }
+
+    /// Forget about all currently displayed notifications, e.g. after the
+    /// notification server has been restarted.
+    pub fn reset_notifications(&mut self) {
+        self.notif = None;
+        self.unexpected = None;
+        self.missing = None;
+        self.progress.notif = None;
+        self.caps = None;
+    }
+
+    async fn on_detachment_inhibited(&mut self, reason: CancelReason) -> Result<()> {
+        // remember if we told the user that detachment is not feasible
+        if reason == CancelReason::Runtime(RuntimeError::NotFeasible) {
+            self.infeasible = true;
+        }
+
+        let (category, summary, body) = match inhibited_text(reason, self.battery_state()) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+
+        let notif = Notification::create("Surface DTX")
             .summary(summary)
             .body(body)
             .hint_s("image-path", "input-tablet")
             .hint_s("category", category)
             .hint("urgency", 2)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+            .build();
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "detach-inhibited",
-               "displaying notification");
+        self.show("detach-inhibited", notif).await?;
 
         Ok(())
     }
@@ -117,7 +331,10 @@ impl Core {
         self.close_current_notification().await?;
         self.canceled = false;
 
-        Ok(())
+        // show progress notification until the base is ready to be detached,
+        // this notification is updated in place for the rest of the detachment
+        self.progress.detach = true;
+        self.show_progress_notification().await
     }
 
     async fn on_detachment_ready(&mut self) -> Result<()> {
@@ -125,23 +342,28 @@ impl Core {
             return Ok(());
         }
 
-        // display detachment-ready notification
-        let handle = Notification::create("Surface DTX")
-            .summary("Surface DTX: Clipboard can be detached")
-            .body("You can disconnect the clipboard now.")
-            .hint_s("image-path", "input-tablet")
-            .hint_s("category", "device.removed")
-            .hint("urgency", 2)
-            .hint("resident", true)
-            .expires(Timeout::Never)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+        // display detachment-ready notification, replacing the progress
+        // notification of this detachment
+        let mut notif = detach_ready_notification(None);
+        if let Some(handle) = self.notif {
+            notif.set_replaces(handle.id);
+        }
+
+        self.notif = self.show("detach-ready", notif).await?;
+        Ok(())
+    }
+
+    async fn on_detachment_countdown(&mut self, remaining: u32) -> Result<()> {
+        // update the detachment-ready notification, if it is still shown
+        let handle = match self.notif {
+            Some(handle) if !self.canceled => handle,
+            _ => return Ok(()),
+        };
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "detach-ready",
-               "displaying notification");
+        let mut notif = detach_ready_notification(Some(remaining));
+        notif.set_replaces(handle.id);
 
-        self.notif = Some(handle);
+        self.notif = self.show("detach-countdown", notif).await?;
         Ok(())
     }
 
@@ -151,97 +373,43 @@ impl Core {
     }
 
     async fn on_detachment_cancel(&mut self, reason: CancelReason) -> Result<()> {
-        // close detachment-ready notification
-        self.close_current_notification().await?;
+        // remember if we told the user that detachment is not feasible
+        if reason == CancelReason::Runtime(RuntimeError::NotFeasible) {
+            self.infeasible = true;
+        }
 
         // mark ourselves as canceled and prevent new detachment-ready notifications
         self.canceled = true;
 
-        let (category, summary, body): (_, _, Cow<'static, str>) = match reason {
-            CancelReason::HandlerTimeout => (
-                "device.error",
-                "Surface DTX: Error",
-                "Detachment canceled due to handler timeout. \
-                 This may lead to data loss! \
-                 Please consult the logs for mode details."
-                    .into()
-            ),
-            CancelReason::Runtime(err) => match err {
-                super::types::RuntimeError::NotFeasible => (
-                    "device",
-                    "Surface DTX: Detachment canceled",
-                    "Detachment canceled by the controller. \
-                     Please make sure that the tablet battery is sufficently charged."
-                        .into()
-                ),
-                super::types::RuntimeError::Timeout => (
-                    "device.error",
-                    "Surface DTX: Detachment canceled",
-                    "The detachment process has timed out while the base was locked. \
-                     Please ensure that the detachment handler is set up correctly."
-                        .into()
-                ),
-                super::types::RuntimeError::Unknown(x) => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    format!("Detachment canceled due to unknown runtime error ({x}).")
-                        .into()
-                ),
-                _ => { return Ok(()); },
-            },
-            CancelReason::Hardware(err) => match err {
-                super::types::HardwareError::FailedToOpen => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    "Hardware error: The controller failed to open the latch."
-                        .into()
-                ),
-                super::types::HardwareError::FailedToRemainOpen => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    "Hardware error: The controller failed to keep the latch open."
-                        .into()
-                ),
-                super::types::HardwareError::FailedToClose => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    "Hardware error: The controller failed to close the latch."
-                        .into()
-                ),
-                super::types::HardwareError::Unknown(x) => (
-                    "device.error",
-                    "Surface DTX: Error",
-                    format!("Detachment canceled due to unknown hardware error ({x}).")
-                        .into()
-                ),
-            },
-            CancelReason::Unknown(x) => (
-                "device.error",
-                "Surface DTX: Error",
-                format!("Detachment canceled due to unknown error ({x}).")
-                    .into()
-            ),
-            _ => { return Ok(()); },
+        let (category, summary, body) = match cancel_text(reason, self.battery_state()) {
+            Some(text) => text,
+            None => return self.close_current_notification().await,
         };
 
-        let handle = Notification::create("Surface DTX")
+        // replace the notification of this detachment with the cancellation
+        // notice, the detachment is over so forget about it afterwards
+        let notif = Notification::create("Surface DTX")
             .summary(summary)
             .body(body)
             .hint_s("image-path", "input-tablet")
             .hint_s("category", category)
             .hint("urgency", 2)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+            .replaces(self.notif.take().map(|h| h.id).unwrap_or(0))
+            .build();
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "detach-cancel",
-               "displaying notification");
+        self.show("detach-cancel", notif).await?;
 
         Ok(())
     }
 
+    /// Current percentage and detachment threshold of the tablet battery, if
+    /// monitored.
+    fn battery_state(&self) -> Option<(f64, Option<f64>)> {
+        self.battery.as_ref().map(|b| (b.percentage(), b.detach_threshold()))
+    }
+
     async fn on_detachment_cancel_timeout(&mut self) -> Result<()> {
-        let handle = Notification::create("Surface DTX")
+        let notif = Notification::create("Surface DTX")
             .summary("Surface DTX: Error")
             .body("The detachment cancellation handler has timed out. \
                    This may lead to data loss! \
@@ -249,72 +417,377 @@ impl Core {
             .hint_s("image-path", "input-tablet")
             .hint_s("category", "device.error")
             .hint("urgency", 2)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+            .build();
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "detach-cancel-timeout",
-               "displaying notification");
+        self.show("detach-cancel-timeout", notif).await?;
 
         Ok(())
     }
 
     async fn on_detachment_unexpected(&mut self) -> Result<()> {
-        let handle = Notification::create("Surface DTX")
+        let notif = Notification::create("Surface DTX")
             .summary("Surface DTX: Error")
             .body("Base disconnected unexpectedly. \
                    This may lead to data loss! \
-                   Please consult the logs for more details.")
+                   Please save your work, reattach the base, and check that \
+                   devices connected to it are working. \
+                   Consult the logs for more details.")
             .hint_s("image-path", "input-tablet")
             .hint_s("category", "device.error")
             .hint("urgency", 2)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+            .hint("resident", true)
+            .expires(Timeout::Never)
+            .build();
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "detach-unexpected",
-               "displaying notification");
+        // keep the notification around until the base has been reattached
+        if let Some(handle) = self.show("detach-unexpected", notif).await? {
+            self.unexpected = Some(handle);
+        }
+
+        Ok(())
+    }
+
+    async fn on_detachment_handler_slow(&mut self) -> Result<()> {
+        let notif = Notification::create("Surface DTX")
+            .summary("Surface DTX: Detachment taking long")
+            .body("The detachment handler is taking longer than expected. \
+                   Detachment may be canceled soon.")
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device")
+            .hint("transient", true)
+            .build();
+
+        self.show("detach-slow", notif).await?;
 
         Ok(())
     }
 
     async fn on_attachment_complete(&mut self) -> Result<()> {
-        let handle = Notification::create("Surface DTX")
+        // the base is back, the unexpected-disconnect error no longer applies
+        self.close_unexpected_notification().await?;
+
+        let body = if self.config.attach_summary {
+            self.attach_summary().await.into()
+        } else {
+            Cow::from("The base has been successfully attached and is ready.")
+        };
+
+        let notif = Notification::create("Surface DTX")
             .summary("Surface DTX: Base attached")
-            .body("The base has been successfully attached and is ready.")
+            .body(body)
             .hint_s("image-path", "input-tablet")
             .hint_s("category", "device.added")
             .hint("transient", true)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+            .build();
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "attach-complete",
-               "displaying notification");
+        self.show("attach-complete", notif).await?;
 
         Ok(())
     }
 
+    /// Short summary of the devices that are available after attaching the
+    /// base.
+    async fn attach_summary(&self) -> String {
+        let base = Client::new(self.system.clone()).base().await;
+        let devices = BaseDevices::probe();
+
+        debug!(target: "sdtxu::core", ?base, ?devices, "base devices after attachment");
+
+        let status = |present| if present { "available" } else { "missing" };
+
+        let mut summary = match base {
+            Ok(base) => format!("The base ({}, id {:#04x}) has been attached.\n", base.device_type, base.id),
+            Err(_) => "The base has been attached.\n".to_owned(),
+        };
+
+        summary.push_str(&format!("Keyboard: {}\nTouchpad: {}\nDiscrete GPU: {}",
+                                  status(devices.keyboard), status(devices.touchpad),
+                                  status(devices.dgpu)));
+        summary
+    }
+
     async fn on_attachment_timeout(&mut self) -> Result<()> {
-        let handle = Notification::create("Surface DTX")
+        let notif = Notification::create("Surface DTX")
             .summary("Surface DTX: Error")
             .body("The attachment handler has timed out. \
                    Please consult the logs for more details.")
             .hint_s("image-path", "input-tablet")
             .hint_s("category", "device.error")
             .hint("urgency", 2)
-            .build()
-            .show(&self.session).await
-            .context("Failed to display notification")?;
+            .build();
+
+        self.show("attach-timeout", notif).await?;
+
+        Ok(())
+    }
+
+    async fn on_base_feasible(&mut self) -> Result<()> {
+        // only notify if the user has previously been told that detachment
+        // is not feasible
+        if !self.infeasible {
+            return Ok(());
+        }
+        self.infeasible = false;
+
+        let notif = Notification::create("Surface DTX")
+            .summary("Surface DTX: Clipboard can be detached")
+            .body("The tablet battery is sufficiently charged. \
+                   You can detach the clipboard now.")
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device")
+            .hint("transient", true)
+            .build();
+
+        self.show("base-feasible", notif).await?;
+
+        Ok(())
+    }
+
+    async fn on_handler_status(&mut self, message: String) -> Result<()> {
+        self.progress.status = Some(message);
+        self.show_progress_notification().await
+    }
+
+    async fn on_handler_progress(&mut self, progress: u8) -> Result<()> {
+        self.progress.value = Some(progress);
+        self.show_progress_notification().await
+    }
+
+    async fn on_handler_error(&mut self, message: String) -> Result<()> {
+        let notif = Notification::create("Surface DTX")
+            .summary("Surface DTX: Handler error")
+            .body(message)
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device.error")
+            .hint("urgency", 2)
+            .build();
+
+        self.show("handler-error", notif).await?;
+
+        Ok(())
+    }
+
+    async fn on_handler_crash(&mut self, handler: String, signal: i32) -> Result<()> {
+        let handler = match handler.as_str() {
+            "detach"       => "detachment handler",
+            "detach-abort" => "detachment-abort handler",
+            "attach"       => "attachment handler",
+            "posture"      => "posture-change handler",
+            _              => "handler",
+        };
+
+        let notif = Notification::create("Surface DTX")
+            .summary("Surface DTX: Handler crashed")
+            .body(format!("The {handler} script crashed (signal {signal})."))
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device.error")
+            .hint("urgency", 2)
+            .build();
 
-        trace!(target: "sdtxu::notify", id = handle.id, ty = "attach-timeout",
-               "displaying notification");
+        self.show("handler-crash", notif).await?;
 
         Ok(())
     }
 
+    async fn show_progress_notification(&mut self) -> Result<()> {
+        let (summary, default_body) = if self.progress.detach {
+            ("Surface DTX: Preparing detachment", "Preparing the clipboard for detachment...")
+        } else {
+            ("Surface DTX", "Running handler...")
+        };
+
+        let body = self.progress.status.clone()
+            .unwrap_or_else(|| default_body.into());
+
+        // detachment progress is shown as part of the detachment notification
+        let handle = if self.progress.detach { self.notif } else { self.progress.notif };
+
+        let mut notif = Notification::create("Surface DTX")
+            .summary(summary)
+            .body(body)
+            .hint_s("image-path", "input-tablet")
+            .hint_s("category", "device")
+            .replaces(handle.map(|h| h.id).unwrap_or(0))
+            .build();
+
+        if let Some(value) = self.progress.value {
+            notif.add_hint("value", value as i32);
+        }
+
+        // keep the detachment progress visible until the base is ready, it is
+        // closed explicitly once the handler is done
+        if self.progress.detach {
+            notif.add_hint("resident", true);
+            notif.set_expires(Timeout::Never);
+        } else {
+            notif.add_hint("transient", true);
+        }
+
+        let handle = self.show("handler-progress", notif).await?;
+
+        if self.progress.detach {
+            self.notif = handle;
+        } else {
+            self.progress.notif = handle;
+        }
+
+        Ok(())
+    }
+
+    /// Display the given notification, unless it is suppressed due to quiet
+    /// hours or an inactive session, or held back while the screen is locked. Style and sound hints
+    /// configured for the notification type override the defaults.
+    ///
+    /// The outcome is recorded in the notification history.
+    async fn show(&mut self, ty: &'static str, notif: Notification<'_>)
+        -> Result<Option<NotificationHandle>>
+    {
+        let summary = notif.summary().to_owned();
+        let result = self.display(ty, notif).await;
+
+        // updates of existing notifications would only clutter the history
+        if !matches!(ty, "detach-countdown" | "handler-progress") {
+            let outcome = match result {
+                Ok((_, outcome)) => outcome,
+                Err(_) => Outcome::Failed,
+            };
+
+            if let Err(err) = self.history.record(ty, outcome, &summary) {
+                debug!(target: "sdtxu::notify", "failed to record notification history: {:#}", err);
+            }
+        }
+
+        result.map(|(handle, _)| handle)
+    }
+
+    async fn display(&mut self, ty: &'static str, mut notif: Notification<'_>)
+        -> Result<(Option<NotificationHandle>, Outcome)>
+    {
+        // apply style first, so that the configured urgency is respected by
+        // the quiet filter
+        if let Some(style) = self.config.style.get(ty) {
+            if let Some(ref icon) = style.icon {
+                notif.add_hint_s("image-path", icon.clone());
+            }
+            if let Some(ref category) = style.category {
+                notif.add_hint_s("category", category.clone());
+            }
+            if let Some(urgency) = style.urgency {
+                notif.add_hint("urgency", i32::from(urgency));
+            }
+        }
+
+        // with multiple users logged in, only notify the one at the device
+        match session::is_active(&self.system).await {
+            Ok(true) => {},
+            Ok(false) => {
+                debug!(target: "sdtxu::notify", ty, "suppressing notification in inactive session");
+                return Ok((None, Outcome::Suppressed));
+            },
+            Err(err) => {
+                debug!(target: "sdtxu::notify", "failed to query session state: {:#}", err);
+            },
+        }
+
+        if self.quiet.suppress(&self.session, &notif).await {
+            debug!(target: "sdtxu::notify", ty, "suppressing notification due to quiet hours");
+            return Ok((None, Outcome::Suppressed));
+        }
+
+        // hold back non-critical notifications until the screen is unlocked
+        if self.config.lock.hold && self.locked && !notif.is_critical() {
+            debug!(target: "sdtxu::notify", ty, "holding notification while screen is locked");
+
+            if !self.held.iter().any(|s| s == notif.summary()) {
+                self.held.push(notif.summary().to_owned());
+            }
+            return Ok((None, Outcome::Held));
+        }
+
+        // don't disturb presentations, e.g. while sharing the screen
+        if self.quiet.downgrade(&self.session, &notif).await {
+            debug!(target: "sdtxu::notify", ty, "showing notification silently due to presentation mode");
+
+            notif.add_hint("transient", true);
+            notif.add_hint("suppress-sound", true);
+        } else if let Some(sound) = self.config.sound.get(ty) {
+            if let Some(ref name) = sound.name {
+                notif.add_hint_s("sound-name", name.clone());
+            }
+            if let Some(ref file) = sound.file {
+                notif.add_hint_s("sound-file", file.to_string_lossy().into_owned());
+            }
+        }
+
+        // the server may not support all features, query them once per server
+        if self.caps.is_none() {
+            match Capabilities::query(&self.session).await {
+                Ok(caps) => {
+                    debug!(target: "sdtxu::notify", ?caps, "notification server capabilities");
+                    self.caps = Some(caps);
+                },
+                Err(err) => {
+                    debug!(target: "sdtxu::notify", error=%err, "failed to query notification server capabilities");
+                },
+            }
+        }
+
+        if let Some(ref caps) = self.caps {
+            notif.adapt(caps);
+        }
+
+        let critical = notif.is_critical();
+        let summary = notif.summary().to_owned();
+        let body = notif.body().to_owned();
+
+        let handle = match notif.show(&self.session).await {
+            Ok(handle) => handle,
+            Err(err) if self.config.fallback && critical && is_missing_server(&err) => {
+                // without notification server, the user would not be told
+                // about the detachment or errors at all, so show a dialog
+                debug!(target: "sdtxu::notify", ty, "no notification server, falling back to dialog");
+
+                dialog::show(&summary, &body, ty != "detach-ready")
+                    .context("Failed to display fallback dialog")?;
+
+                return Ok((None, Outcome::Dialog));
+            },
+            Err(err) => return Err(err).context("Failed to display notification"),
+        };
+
+        trace!(target: "sdtxu::notify", id = handle.id, ty, "displaying notification");
+
+        Ok((Some(handle), Outcome::Shown))
+    }
+
+    async fn close_progress_notification(&mut self) -> Result<()> {
+        let progress = std::mem::take(&mut self.progress);
+
+        match progress.notif {
+            Some(handle) => {
+                trace!(target: "sdtxu::notify", id = handle.id, "closing notification");
+
+                handle.close(&self.session).await
+                    .context("Failed to close notification")
+            },
+            None => Ok(()),
+        }
+    }
+
+    async fn close_unexpected_notification(&mut self) -> Result<()> {
+        match self.unexpected.take() {
+            Some(handle) => {
+                trace!(target: "sdtxu::notify", id = handle.id, "closing notification");
+
+                handle.close(&self.session).await
+                    .context("Failed to close notification")
+            },
+            None => Ok(()),
+        }
+    }
+
     async fn close_current_notification(&mut self) -> Result<()> {
-        match self.notif {
+        match self.notif.take() {
             Some(handle) => {
                 trace!(target: "sdtxu::notify", id = handle.id, "closing notification");
 
@@ -325,3 +798,245 @@ impl Core {
         }
     }
 }
+
+
+/// Category, summary, and body of a notification.
+type Text = (&'static str, &'static str, Cow<'static, str>);
+
+/// Notification text shown when detachment is inhibited, as category,
+/// summary, and body, or `None` if no notification should be shown.
+fn inhibited_text(reason: CancelReason, battery: Option<(f64, Option<f64>)>) -> Option<Text> {
+    let text = match reason {
+        CancelReason::Runtime(err) => match err {
+            RuntimeError::NotAttached => (
+                "device",
+                "Surface DTX: Cannot detach",
+                "No base is attached, there is nothing to detach."
+                    .into()
+            ),
+            RuntimeError::NotFeasible => (
+                "device",
+                "Surface DTX: Cannot detach",
+                infeasible_body("Detachment inhibited by the controller.", battery),
+            ),
+            RuntimeError::Timeout => (
+                "device.error",
+                "Surface DTX: Cannot detach",
+                "Detachment inhibited as the controller did not respond in time."
+                    .into()
+            ),
+            RuntimeError::Unknown(x) => (
+                "device.error",
+                "Surface DTX: Error",
+                format!("Detachment inhibited due to unknown runtime error ({x}).")
+                    .into()
+            ),
+        },
+        CancelReason::Hardware(err) => match err {
+            HardwareError::FailedToOpen => (
+                "device.error",
+                "Surface DTX: Error",
+                "Hardware error: The controller failed to open the latch."
+                    .into()
+            ),
+            HardwareError::FailedToRemainOpen => (
+                "device.error",
+                "Surface DTX: Error",
+                "Hardware error: The controller failed to keep the latch open."
+                    .into()
+            ),
+            HardwareError::FailedToClose => (
+                "device.error",
+                "Surface DTX: Error",
+                "Hardware error: The controller failed to close the latch."
+                    .into()
+            ),
+            HardwareError::Unknown(x) => (
+                "device.error",
+                "Surface DTX: Error",
+                format!("Detachment inhibited due to unknown hardware error ({x}).")
+                    .into()
+            ),
+        },
+        CancelReason::Unknown(x) => (
+            "device.error",
+            "Surface DTX: Error",
+            format!("Detachment inhibited due to unknown error ({x}).")
+                .into()
+        ),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+/// Notification text shown when detachment is canceled, as category,
+/// summary, and body, or `None` if no notification should be shown.
+fn cancel_text(reason: CancelReason, battery: Option<(f64, Option<f64>)>) -> Option<Text> {
+    let text = match reason {
+        CancelReason::HandlerTimeout => (
+            "device.error",
+            "Surface DTX: Error",
+            "Detachment canceled due to handler timeout. \
+             This may lead to data loss! \
+             Please consult the logs for mode details."
+                .into()
+        ),
+        CancelReason::HandlerSpawnFailed => (
+            "device.error",
+            "Surface DTX: Error",
+            "Detachment canceled as the detachment handler could not be started. \
+             Please ensure that the detachment handler is set up correctly."
+                .into()
+        ),
+        CancelReason::Runtime(err) => match err {
+            RuntimeError::NotFeasible => (
+                "device",
+                "Surface DTX: Detachment canceled",
+                infeasible_body("Detachment canceled by the controller.", battery),
+            ),
+            RuntimeError::Timeout => (
+                "device.error",
+                "Surface DTX: Detachment canceled",
+                "The detachment process has timed out while the base was locked. \
+                 Please ensure that the detachment handler is set up correctly."
+                    .into()
+            ),
+            RuntimeError::Unknown(x) => (
+                "device.error",
+                "Surface DTX: Error",
+                format!("Detachment canceled due to unknown runtime error ({x}).")
+                    .into()
+            ),
+            _ => return None,
+        },
+        CancelReason::Hardware(err) => match err {
+            HardwareError::FailedToOpen => (
+                "device.error",
+                "Surface DTX: Error",
+                "Hardware error: The controller failed to open the latch."
+                    .into()
+            ),
+            HardwareError::FailedToRemainOpen => (
+                "device.error",
+                "Surface DTX: Error",
+                "Hardware error: The controller failed to keep the latch open."
+                    .into()
+            ),
+            HardwareError::FailedToClose => (
+                "device.error",
+                "Surface DTX: Error",
+                "Hardware error: The controller failed to close the latch."
+                    .into()
+            ),
+            HardwareError::Unknown(x) => (
+                "device.error",
+                "Surface DTX: Error",
+                format!("Detachment canceled due to unknown hardware error ({x}).")
+                    .into()
+            ),
+        },
+        CancelReason::Unknown(x) => (
+            "device.error",
+            "Surface DTX: Error",
+            format!("Detachment canceled due to unknown error ({x}).")
+                .into()
+        ),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+/// Notification body explaining why detachment is not feasible, including
+/// the current battery percentage and detachment threshold, if known.
+fn infeasible_body(prefix: &str, battery: Option<(f64, Option<f64>)>) -> Cow<'static, str> {
+    match battery {
+        Some((percentage, Some(threshold))) => format!(
+            "{prefix} The battery is at {percentage:.0}%, at least {threshold:.0}% are required for detachment."
+        ).into(),
+        Some((percentage, None)) => format!(
+            "{prefix} The battery is at {percentage:.0}%, please make sure that it is sufficently charged."
+        ).into(),
+        None => format!("{prefix} Please make sure that the battery is sufficently charged.").into(),
+    }
+}
+
+fn is_missing_server(err: &dbus::Error) -> bool {
+    matches!(err.name(), Some("org.freedesktop.DBus.Error.ServiceUnknown")
+                       | Some("org.freedesktop.DBus.Error.NameHasNoOwner"))
+}
+
+fn detach_ready_notification(remaining: Option<u32>) -> Notification<'static> {
+    let body = match remaining {
+        Some(0) => "You can disconnect the clipboard now. The latch is about to close.".into(),
+        Some(secs) => format!("You can disconnect the clipboard now. \
+                               The latch closes in {secs} seconds."),
+        None => "You can disconnect the clipboard now.".into(),
+    };
+
+    Notification::create("Surface DTX")
+        .summary("Surface DTX: Clipboard can be detached")
+        .body(body)
+        .hint_s("image-path", "input-tablet")
+        .hint_s("category", "device.removed")
+        .hint("urgency", 2)
+        .hint("resident", true)
+        .expires(Timeout::Never)
+        .build()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inhibited() {
+        let (category, summary, body) = inhibited_text(CancelReason::Runtime(RuntimeError::NotAttached), None)
+            .unwrap();
+
+        assert_eq!(category, "device");
+        assert_eq!(summary, "Surface DTX: Cannot detach");
+        assert_eq!(body, "No base is attached, there is nothing to detach.");
+
+        let (category, summary, body) = inhibited_text(CancelReason::Hardware(HardwareError::Unknown(3)), None)
+            .unwrap();
+
+        assert_eq!(category, "device.error");
+        assert_eq!(summary, "Surface DTX: Error");
+        assert_eq!(body, "Detachment inhibited due to unknown hardware error (3).");
+
+        assert!(inhibited_text(CancelReason::UserRequest, None).is_none());
+        assert!(inhibited_text(CancelReason::HandlerTimeout, None).is_none());
+    }
+
+    #[test]
+    fn cancel() {
+        let (category, summary, _) = cancel_text(CancelReason::HandlerTimeout, None).unwrap();
+
+        assert_eq!(category, "device.error");
+        assert_eq!(summary, "Surface DTX: Error");
+
+        let (category, summary, body) = cancel_text(CancelReason::Runtime(RuntimeError::NotFeasible), Some((42.0, Some(50.0))))
+            .unwrap();
+
+        assert_eq!(category, "device");
+        assert_eq!(summary, "Surface DTX: Detachment canceled");
+        assert_eq!(body, "Detachment canceled by the controller. \
+                          The battery is at 42%, at least 50% are required for detachment.");
+
+        assert!(cancel_text(CancelReason::UserRequest, None).is_none());
+        assert!(cancel_text(CancelReason::Runtime(RuntimeError::NotAttached), None).is_none());
+    }
+
+    #[test]
+    fn infeasible() {
+        assert_eq!(infeasible_body("Inhibited.", Some((12.4, Some(15.0)))),
+                   "Inhibited. The battery is at 12%, at least 15% are required for detachment.");
+        assert_eq!(infeasible_body("Inhibited.", Some((12.6, None))),
+                   "Inhibited. The battery is at 13%, please make sure that it is sufficently charged.");
+        assert_eq!(infeasible_body("Inhibited.", None),
+                   "Inhibited. Please make sure that the battery is sufficently charged.");
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+
+const INPUT_DEVICE_DIR: &str = "/sys/class/input";
+const PCI_DEVICE_DIR: &str = "/sys/bus/pci/devices";
+
+const PCI_VENDOR_NVIDIA: &str = "0x10de";
+const PCI_CLASS_DISPLAY: &str = "0x03";
+
+
+/// Devices provided by the base, as far as they can be found in sysfs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BaseDevices {
+    pub keyboard: bool,
+    pub touchpad: bool,
+    pub dgpu: bool,
+}
+
+impl BaseDevices {
+    /// Look up the devices provided by the base.
+    ///
+    /// This is a best-effort check: keyboard and touchpad are identified by
+    /// the name of their input devices, the dGPU by its PCI vendor and class.
+    pub fn probe() -> Self {
+        let mut devices = BaseDevices::default();
+
+        for name in input_device_names() {
+            if !name.contains("Surface") {
+                continue;
+            }
+
+            devices.keyboard |= name.contains("Keyboard");
+            devices.touchpad |= name.contains("Touchpad");
+        }
+
+        devices.dgpu = has_dgpu();
+        devices
+    }
+}
+
+fn input_device_names() -> Vec<String> {
+    let entries = match std::fs::read_dir(INPUT_DEVICE_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries.flatten()
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("name")).ok())
+        .map(|name| name.trim().to_owned())
+        .collect()
+}
+
+fn has_dgpu() -> bool {
+    let entries = match std::fs::read_dir(PCI_DEVICE_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        read_attr(&path, "vendor") == PCI_VENDOR_NVIDIA
+            && read_attr(&path, "class").starts_with(PCI_CLASS_DISPLAY)
+    })
+}
+
+fn read_attr(path: &Path, attr: &str) -> String {
+    std::fs::read_to_string(path.join(attr))
+        .map(|value| value.trim().to_owned())
+        .unwrap_or_default()
+}
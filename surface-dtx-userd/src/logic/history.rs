@@ -0,0 +1,146 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+
+const HISTORY_LOCAL_PATH: &str = "surface-dtx/notifications.log";
+
+/// Maximum number of entries kept in the history file.
+const MAX_ENTRIES: usize = 500;
+
+
+/// What happened to a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Shown,
+    Suppressed,
+    Held,
+    Dialog,
+    Failed,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Shown      => "shown",
+            Self::Suppressed => "suppressed",
+            Self::Held       => "held",
+            Self::Dialog     => "dialog",
+            Self::Failed     => "failed",
+        }
+    }
+}
+
+/// Persistent history of the notifications handled by this daemon, stored in
+/// the state directory of the user.
+///
+/// Each line of the history file holds the UNIX timestamp, the notification
+/// type, the outcome, and the summary of one notification, separated by tabs.
+pub struct History {
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History { path: history_path() }
+    }
+
+    /// Append an entry to the history, dropping the oldest entries if the
+    /// history has grown too large.
+    pub fn record(&self, ty: &str, outcome: Outcome, summary: &str) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create state directory (path: {dir:?})"))?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open history file (path: {path:?})"))?;
+
+        let summary = summary.replace(['\t', '\n'], " ");
+        writeln!(file, "{}\t{}\t{}\t{}", now(), ty, outcome.as_str(), summary)
+            .with_context(|| format!("Failed to write history file (path: {path:?})"))?;
+
+        // trim history, keeping only the most recent entries
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read history file (path: {path:?})"))?;
+
+        let lines: Vec<_> = data.lines().collect();
+        if lines.len() > MAX_ENTRIES {
+            let mut data = lines[lines.len() - MAX_ENTRIES..].join("\n");
+            data.push('\n');
+
+            std::fs::write(path, data)
+                .with_context(|| format!("Failed to write history file (path: {path:?})"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Print the history to stdout in human-readable form.
+    pub fn dump() -> Result<()> {
+        let path = history_path()
+            .ok_or_else(|| anyhow::anyhow!("Failed to determine state directory"))?;
+
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read history file (path: {path:?})"));
+            },
+        };
+
+        for line in data.lines() {
+            let mut fields = line.splitn(4, '\t');
+
+            let time = fields.next().and_then(|t| t.parse().ok()).map(format_time);
+            let ty = fields.next().unwrap_or_default();
+            let outcome = fields.next().unwrap_or_default();
+            let summary = fields.next().unwrap_or_default();
+
+            println!("{}  {:<22} {:<10} {}", time.unwrap_or_else(|| "?".into()), ty, outcome, summary);
+        }
+
+        Ok(())
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+
+    Some(dir.join(HISTORY_LOCAL_PATH))
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn format_time(time: i64) -> String {
+    // SAFETY: localtime_r() only accesses the provided pointers, both of
+    // which are valid for the duration of the call.
+    let tm = unsafe {
+        let time = time as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        tm
+    };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
@@ -0,0 +1,101 @@
+use crate::config::Hook;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use dbus::Message;
+use dbus::arg::{RefArg, Variant};
+
+use tokio::process::Command;
+
+use tracing::{debug, warn};
+
+
+/// User-configured scripts run in the user session when the daemon emits an
+/// event.
+pub struct Hooks {
+    hooks: BTreeMap<String, Hook>,
+    dir: PathBuf,
+}
+
+impl Hooks {
+    pub fn new(hooks: BTreeMap<String, Hook>, dir: PathBuf) -> Self {
+        Hooks { hooks, dir }
+    }
+
+    /// Run the hook configured for the given event message, if any.
+    ///
+    /// The hook is run in the background. Its output is discarded and errors
+    /// are only logged, as hooks should not be able to affect the daemon.
+    #[allow(clippy::type_complexity)]
+    pub fn run(&self, msg: &Message) {
+        let (ty, args): (&str, HashMap<&str, Variant<Box<dyn RefArg>>>) = match msg.read2() {
+            Ok(values) => values,
+            Err(_) => return,
+        };
+
+        let hook = match self.hooks.get(ty) {
+            Some(hook) => hook,
+            None => return,
+        };
+
+        let path = resolve(&self.dir, &hook.exec);
+
+        let mut cmd = Command::new(&path);
+        cmd.args(&hook.args)
+            .env("SDTX_EVENT", ty)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        for (key, value) in &args {
+            let value = if let Some(s) = value.as_str() {
+                s.to_owned()
+            } else if let Some(n) = value.as_i64() {
+                n.to_string()
+            } else if let Some(n) = value.as_u64() {
+                n.to_string()
+            } else {
+                continue;
+            };
+
+            cmd.env(format!("SDTX_{}", key.to_uppercase()), value);
+        }
+
+        debug!(target: "sdtxu::hooks", event=ty, ?path, "running hook");
+
+        let ty = ty.to_owned();
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                warn!(target: "sdtxu::hooks", event=%ty, ?path, error=%err, "failed to run hook");
+                return;
+            },
+        };
+
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) if status.success() => {},
+                Ok(status) => {
+                    warn!(target: "sdtxu::hooks", event=%ty, ?path, %status, "hook failed");
+                },
+                Err(err) => {
+                    warn!(target: "sdtxu::hooks", event=%ty, ?path, error=%err, "failed to run hook");
+                },
+            }
+        });
+    }
+}
+
+/// Resolve a hook path, expanding `~` to the home directory and interpreting
+/// relative paths relative to the config directory.
+fn resolve(dir: &Path, exec: &Path) -> PathBuf {
+    if let Ok(rest) = exec.strip_prefix("~") {
+        if let Some(home) = std::env::var_os("HOME") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+
+    dir.join(exec)
+}
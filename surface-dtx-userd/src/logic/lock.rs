@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::Message;
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+
+
+const SCREENSAVER_NAME: &str = "org.freedesktop.ScreenSaver";
+const SCREENSAVER_PATH: &str = "/org/freedesktop/ScreenSaver";
+const SCREENSAVER_INTERFACE: &str = "org.freedesktop.ScreenSaver";
+
+
+/// Query whether the screen is currently locked.
+pub async fn is_locked(session: &SyncConnection) -> Result<bool> {
+    let proxy = Proxy::new(SCREENSAVER_NAME, SCREENSAVER_PATH, Duration::from_secs(5), session);
+
+    let (active,): (bool,) = proxy.method_call(SCREENSAVER_INTERFACE, "GetActive", ()).await
+        .context("Failed to query screen lock state")?;
+
+    Ok(active)
+}
+
+/// Inhibit the screen saver and idle actions (such as locking the screen)
+/// until released via [`uninhibit`], returning the inhibition cookie.
+pub async fn inhibit(session: &SyncConnection, reason: &str) -> Result<u32> {
+    let proxy = Proxy::new(SCREENSAVER_NAME, SCREENSAVER_PATH, Duration::from_secs(5), session);
+
+    let (cookie,): (u32,) = proxy.method_call(SCREENSAVER_INTERFACE, "Inhibit", ("Surface DTX", reason)).await
+        .context("Failed to inhibit screen saver")?;
+
+    Ok(cookie)
+}
+
+/// Release an inhibition previously taken via [`inhibit`].
+pub async fn uninhibit(session: &SyncConnection, cookie: u32) -> Result<()> {
+    let proxy = Proxy::new(SCREENSAVER_NAME, SCREENSAVER_PATH, Duration::from_secs(5), session);
+
+    proxy.method_call(SCREENSAVER_INTERFACE, "UnInhibit", (cookie,)).await
+        .context("Failed to release screen saver inhibition")
+}
+
+/// Match rule for changes of the screen lock state.
+pub fn match_rule() -> MatchRule<'static> {
+    MatchRule::new_signal(SCREENSAVER_INTERFACE, "ActiveChanged")
+        .with_path(SCREENSAVER_PATH)
+}
+
+/// Parse the new lock state from an ActiveChanged signal.
+pub fn parse_changed(msg: &Message) -> Option<bool> {
+    msg.read1().ok()
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::Message;
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::Sender;
+use dbus::message::SignalArgs;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+
+use tracing::{debug, trace};
+
+
+const PATH: &str = "/org/surface/dtx";
+const INTERFACE: &str = "org.surface.dtx.Userd";
+
+const DAEMON_NAME: &str = surface_dtx_client::NAME;
+const DAEMON_INTERFACE: &str = surface_dtx_client::INTERFACE;
+
+
+/// Session-bus service mirroring the properties and events of the system
+/// daemon, so that desktop widgets can consume them without system-bus
+/// access.
+#[derive(Clone)]
+pub struct Mirror {
+    session: Arc<SyncConnection>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    pub device_mode: String,
+    pub latch_status: String,
+    pub base: (String, String, u8),
+}
+
+impl Mirror {
+    pub fn new(session: Arc<SyncConnection>) -> Self {
+        Mirror { session, state: Arc::new(Mutex::new(State::default())) }
+    }
+
+    pub fn register(&self, cr: &mut Crossroads) {
+        let token = cr.register(INTERFACE, |b: &mut IfaceBuilder<Arc<Mutex<State>>>| {
+            b.property("DeviceMode")
+                .emits_changed_true()
+                .get(|_, state| Ok(state.lock().unwrap().device_mode.clone()));
+
+            b.property("LatchStatus")
+                .emits_changed_true()
+                .get(|_, state| Ok(state.lock().unwrap().latch_status.clone()));
+
+            b.property("Base")
+                .emits_changed_true()
+                .get(|_, state| Ok(state.lock().unwrap().base.clone()));
+
+            b.signal::<(String, HashMap<String, Variant<Box<dyn RefArg>>>), _>
+                ("Event", ("type", "values"));
+        });
+
+        cr.insert(PATH, &[token], self.state.clone());
+    }
+
+    pub async fn request_name(&self) -> Result<()> {
+        self.session.request_name(INTERFACE, false, true, false).await
+            .context("Failed to set up D-Bus service (session)")
+            .map(|_| ())
+    }
+
+    /// Query the current properties of the daemon.
+    pub async fn refresh(&self, system: &SyncConnection) -> Result<()> {
+        let proxy = Proxy::new(DAEMON_NAME, PATH, Duration::from_secs(5), system);
+
+        let device_mode: String = proxy.get(DAEMON_INTERFACE, "DeviceMode").await
+            .context("Failed to query device mode")?;
+
+        let latch_status: String = proxy.get(DAEMON_INTERFACE, "LatchStatus").await
+            .context("Failed to query latch status")?;
+
+        let base: (String, String, u8) = proxy.get(DAEMON_INTERFACE, "Base").await
+            .context("Failed to query base info")?;
+
+        self.update(State { device_mode, latch_status, base });
+        Ok(())
+    }
+
+    /// Current state of the daemon, as far as known.
+    pub fn state(&self) -> State {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Update the stored state from a PropertiesChanged signal of the daemon.
+    pub fn on_properties_changed(&self, msg: &Message) {
+        let changed = match PropertiesPropertiesChanged::from_message(msg) {
+            Some(changed) if changed.interface_name == DAEMON_INTERFACE => changed.changed_properties,
+            _ => return,
+        };
+
+        let mut state = self.state();
+
+        if let Some(mode) = changed.get("DeviceMode").and_then(|v| v.as_str()) {
+            state.device_mode = mode.into();
+        }
+
+        if let Some(latch) = changed.get("LatchStatus").and_then(|v| v.as_str()) {
+            state.latch_status = latch.into();
+        }
+
+        if let Some(base) = changed.get("Base").and_then(|v| parse_base(&v.0)) {
+            state.base = base;
+        }
+
+        self.update(state);
+    }
+
+    /// Re-emit an event signal of the daemon on the session bus.
+    pub fn forward_event(&self, msg: &Message) {
+        let mut signal = Message::signal(&PATH.into(), &INTERFACE.into(), &"Event".into());
+        signal.append_items(&msg.get_items());
+
+        trace!(target: "sdtxu::mirror", "forwarding event");
+
+        // only fails when memory runs out
+        self.session.send(signal).unwrap();
+    }
+
+    fn update(&self, new: State) {
+        let mut changed: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            if state.device_mode != new.device_mode {
+                changed.insert("DeviceMode".into(), Variant(Box::new(new.device_mode.clone())));
+            }
+
+            if state.latch_status != new.latch_status {
+                changed.insert("LatchStatus".into(), Variant(Box::new(new.latch_status.clone())));
+            }
+
+            if state.base != new.base {
+                changed.insert("Base".into(), Variant(Box::new(new.base.clone())));
+            }
+
+            *state = new;
+        }
+
+        if changed.is_empty() {
+            return;
+        }
+
+        debug!(target: "sdtxu::mirror", properties=?changed.keys().collect::<Vec<_>>(),
+               "daemon properties changed");
+
+        let signal = PropertiesPropertiesChanged {
+            interface_name: INTERFACE.into(),
+            changed_properties: changed,
+            invalidated_properties: Vec::new(),
+        };
+
+        self.session.send(signal.to_emit_message(&PATH.into())).unwrap();
+    }
+}
+
+fn parse_base(value: &dyn RefArg) -> Option<(String, String, u8)> {
+    let mut fields = value.as_iter()?;
+
+    let state = fields.next()?.as_str()?.to_owned();
+    let ty = fields.next()?.as_str()?.to_owned();
+    let id = fields.next()?.as_u64()? as u8;
+
+    Some((state, ty, id))
+}
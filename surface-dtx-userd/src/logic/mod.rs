@@ -1,23 +1,86 @@
+mod battery;
+use self::battery::{BatteryMonitor, Level as BatteryLevel};
+
 mod core;
 use self::core::Core;
 
-mod types;
-pub use self::types::{CancelReason, Event};
+mod devices;
+
+mod history;
+pub use self::history::History;
+
+mod hooks;
+use self::hooks::Hooks;
+
+mod lock;
+
+mod session;
+
+mod quiet;
+use self::quiet::QuietFilter;
 
+mod mirror;
+use self::mirror::Mirror;
 
+mod tray;
+use self::tray::Tray;
+
+
+use crate::config::Config;
 use crate::utils::task::JoinHandleExt;
 
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use anyhow::{Context, Result};
 
+use dbus::channel::MatchingReceiver;
 use dbus::message::MatchRule;
+use dbus::nonblock::Proxy;
 use dbus_tokio::connection;
+use dbus_crossroads::Crossroads;
 
 use futures::prelude::*;
 
-use tracing::trace;
+use surface_dtx_client::Event;
+
+use tokio::time::Instant;
+
+use tracing::{debug, trace, warn};
 
 
-pub async fn run() -> Result<()> {
+const NOTIFICATION_SERVICE: &str = "org.freedesktop.Notifications";
+const DAEMON_SERVICE: &str = surface_dtx_client::NAME;
+
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(60);
+
+
+pub async fn run(config: Config) -> Result<()> {
+    let mut delay = RECONNECT_DELAY_MIN;
+
+    // reconnect with exponential backoff whenever one of the D-Bus
+    // connections is lost, e.g. when the session bus restarts
+    loop {
+        let start = Instant::now();
+        let err = match run_connected(&config).await {
+            Ok(()) => anyhow::anyhow!("D-Bus connection closed"),
+            Err(err) => err,
+        };
+
+        // reset backoff if the previous connection has been up for a while
+        if start.elapsed() >= RECONNECT_DELAY_MAX {
+            delay = RECONNECT_DELAY_MIN;
+        }
+
+        warn!(target: "sdtxu", "{:#}, reconnecting in {:?}", err, delay);
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+    }
+}
+
+async fn run_connected(config: &Config) -> Result<()> {
     // set up and start D-Bus connections (system and user-session)
     let (sys_rsrc, sys_conn) = connection::new_system_sync()
         .context("Failed to connect to D-Bus (system)")?;
@@ -32,23 +95,262 @@ pub async fn run() -> Result<()> {
     let mut dses_task = tokio::spawn(ses_rsrc).guard();
 
     // set up D-Bus message listener task
+    let notify = config.notify.clone();
+    let notify_lock = config.notify.lock.hold;
+    let tray_enabled = config.tray.enabled;
+    let battery = config.battery.clone();
+    let hooks = Hooks::new(config.hooks.clone(), config.dir.clone());
     let mut main_task = tokio::spawn(async move {
-        let mut core = Core::new(ses_conn);
+        // set up session-bus objects
+        let mirror = Mirror::new(ses_conn.clone());
+        let tray = tray_enabled.then(|| Tray::new(ses_conn.clone(), sys_conn.clone()));
+
+        let mut cr = Crossroads::new();
+        mirror.register(&mut cr);
+        if let Some(ref tray) = tray {
+            tray.register(&mut cr);
+        }
+
+        let cr = Arc::new(Mutex::new(cr));
+        ses_conn.start_receive(MatchRule::new_method_call(), Box::new(move |msg, conn| {
+            // Crossroads::handle_message() only fails if message is not a method call
+            cr.lock().unwrap().handle_message(msg, conn).unwrap();
+            true
+        }));
+
+        // another instance may already provide the service, this is not fatal
+        if let Err(err) = mirror.request_name().await {
+            warn!(target: "sdtxu::mirror", "{:#}", err);
+        }
+
+        // the tray icon is optional, don't fail if the desktop lacks support
+        let tray = match tray {
+            Some(tray) => match tray.announce().await {
+                Ok(()) => Some(tray),
+                Err(err) => {
+                    warn!(target: "sdtxu::tray", "failed to set up tray icon: {:#}", err);
+                    None
+                },
+            },
+            None => None,
+        };
+
+        let mut core = Core::new(notify, ses_conn.clone(), sys_conn.clone(), tray.clone());
+
+        let mr = MatchRule::new_signal(surface_dtx_client::INTERFACE, "Event");
+        let (_msgs, mut events) = sys_conn
+            .add_match(mr).await
+            .context("Failed to set up D-Bus connection")?
+            .msg_stream();
 
-        let mr = MatchRule::new_signal("org.surface.dtx", "Event");
-        let (_msgs, mut stream) = sys_conn
+        // track daemon properties for the session service and tray icon
+        let mr = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+            .with_sender(surface_dtx_client::NAME)
+            .with_path(surface_dtx_client::PATH);
+        let (_props, mut props) = sys_conn
             .add_match(mr).await
             .context("Failed to set up D-Bus connection")?
             .msg_stream();
 
-        while let Some(mut msg) = stream.next().await {
-            trace!(target: "sdtxu::core", message = ?msg, "message received");
+        // restore notifications if a detachment is already in progress
+        if let Err(err) = core.catch_up(&sys_conn).await {
+            warn!(target: "sdtxu::core", "failed to catch up with daemon state: {:#}", err);
+        }
+
+        if let Err(err) = mirror.refresh(&sys_conn).await {
+            warn!(target: "sdtxu::mirror", "failed to query daemon state: {:#}", err);
+        }
+
+        if let Some(ref tray) = tray {
+            let state = mirror.state();
+            tray.set_device_mode(&state.device_mode);
+            tray.set_base_state(&state.base.0);
+            tray.set_detaching(state.latch_status == "opened");
+        }
+
+        // monitor the base battery via UPower
+        let mr = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+            .with_sender("org.freedesktop.UPower");
+        let (_upower, mut upower) = sys_conn
+            .add_match(mr).await
+            .context("Failed to set up D-Bus connection")?
+            .msg_stream();
+
+        let battery = if battery.enabled {
+            match BatteryMonitor::find(battery, &sys_conn).await {
+                Ok(Some(battery)) => Some(battery),
+                Ok(None) => {
+                    debug!(target: "sdtxu::battery", "base battery not found");
+                    None
+                },
+                Err(err) => {
+                    warn!(target: "sdtxu::battery", "failed to set up battery monitoring: {:#}", err);
+                    None
+                },
+            }
+        } else {
+            None
+        };
+
+        if let Some(battery) = battery {
+            core.set_battery(battery);
+
+            if let Err(err) = core.check_battery().await {
+                warn!(target: "sdtxu::core", "failed to handle battery change: {:#}", err);
+            }
+        }
+
+        // track the system daemon, warn if it is not running
+        let mr = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+            .with_sender("org.freedesktop.DBus");
+        let (_daemon, mut daemon) = sys_conn
+            .add_match(mr).await
+            .context("Failed to set up D-Bus connection")?
+            .msg_stream();
+
+        let proxy = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus",
+                               Duration::from_secs(5), sys_conn.clone());
+        let (running,): (bool,) = proxy.method_call("org.freedesktop.DBus", "NameHasOwner", (DAEMON_SERVICE,)).await
+            .context("Failed to query system daemon")?;
+
+        if !running {
+            warn!(target: "sdtxu::core", "system daemon is not running");
+
+            if let Err(err) = core.on_daemon_lost().await {
+                warn!(target: "sdtxu::core", "failed to handle daemon loss: {:#}", err);
+            }
+        }
+
+        // track the screen lock state to hold back notifications while locked
+        let (_lock, mut lock) = ses_conn
+            .add_match(lock::match_rule()).await
+            .context("Failed to set up D-Bus connection")?
+            .msg_stream();
+
+        if notify_lock {
+            match lock::is_locked(&ses_conn).await {
+                Ok(locked) => core.on_lock_changed(locked).await?,
+                Err(err) => debug!(target: "sdtxu::notify", "{:#}", err),
+            }
+        }
+
+        // track the notification server, notifications displayed by a
+        // previous instance are gone once it has been restarted
+        let mr = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+            .with_sender("org.freedesktop.DBus");
+        let (_owners, mut owners) = ses_conn
+            .add_match(mr).await
+            .context("Failed to set up D-Bus connection")?
+            .msg_stream();
+
+        loop {
+            tokio::select! {
+                msg = events.next() => {
+                    let mut msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+
+                    trace!(target: "sdtxu::core", message = ?msg, "message received");
+
+                    let msg = msg.as_result().context("D-Bus remote error")?;
+                    mirror.forward_event(msg);
+                    hooks.run(msg);
+
+                    let evt = Event::try_from_message(msg)?;
+
+                    if let Some(evt) = evt {
+                        // failing to display a notification should not take
+                        // down the daemon, e.g. if the server is restarting
+                        if let Err(err) = core.handle(evt).await {
+                            warn!(target: "sdtxu::core", "failed to handle event: {:#}", err);
+                        }
+                    }
+                },
+                msg = props.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+
+                    mirror.on_properties_changed(&msg);
+
+                    if let Some(ref tray) = tray {
+                        let state = mirror.state();
+                        tray.set_device_mode(&state.device_mode);
+                        tray.set_base_state(&state.base.0);
+                    }
+                },
+                msg = upower.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+
+                    if let Err(err) = core.on_battery_changed(msg).await {
+                        warn!(target: "sdtxu::core", "failed to handle battery change: {:#}", err);
+                    }
+                },
+                msg = daemon.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
+
+                    let owner = match msg.read3::<&str, &str, &str>() {
+                        Ok((DAEMON_SERVICE, _, owner)) => owner,
+                        _ => continue,
+                    };
+
+                    // signal subscriptions are based on the well-known name,
+                    // so we only need to refresh our state once it returns
+                    if owner.is_empty() {
+                        warn!(target: "sdtxu::core", "system daemon has stopped");
+
+                        if let Err(err) = core.on_daemon_lost().await {
+                            warn!(target: "sdtxu::core", "failed to handle daemon loss: {:#}", err);
+                        }
+                    } else {
+                        debug!(target: "sdtxu::core", "system daemon is available");
+
+                        if let Err(err) = core.on_daemon_available(&sys_conn).await {
+                            warn!(target: "sdtxu::core", "failed to handle daemon restart: {:#}", err);
+                        }
+
+                        if let Err(err) = mirror.refresh(&sys_conn).await {
+                            warn!(target: "sdtxu::mirror", "failed to query daemon state: {:#}", err);
+                        }
+
+                        if let Some(ref tray) = tray {
+                            let state = mirror.state();
+                            tray.set_device_mode(&state.device_mode);
+                            tray.set_base_state(&state.base.0);
+                        }
+                    }
+                },
+                msg = lock.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
 
-            let msg = msg.as_result().context("D-Bus remote error")?;
-            let evt = Event::try_from_message(msg)?;
+                    if let Some(locked) = lock::parse_changed(&msg) {
+                        if let Err(err) = core.on_lock_changed(locked).await {
+                            warn!(target: "sdtxu::core", "failed to handle screen lock change: {:#}", err);
+                        }
+                    }
+                },
+                msg = owners.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    };
 
-            if let Some(evt) = evt {
-                core.handle(evt).await?;
+                    if let Ok((NOTIFICATION_SERVICE, _, _)) = msg.read3::<&str, &str, &str>() {
+                        debug!(target: "sdtxu::notify", "notification server changed");
+                        core.reset_notifications();
+                    }
+                },
             }
         }
 
@@ -0,0 +1,156 @@
+use crate::config::{Quiet, TimeOfDay};
+use crate::utils::notify::Notification;
+
+use std::time::Duration;
+
+use dbus::arg::{RefArg, Variant};
+use dbus::nonblock::{Proxy, SyncConnection};
+
+use tracing::debug;
+
+
+/// Filter suppressing non-critical notifications during quiet hours, i.e.
+/// while do-not-disturb is enabled on the desktop or during the configured
+/// time window.
+///
+/// Notifications with critical urgency (errors that may lead to data loss and
+/// the detachment-ready notification) are never suppressed.
+///
+/// While the desktop is in presentation mode, non-critical notifications are
+/// not suppressed but downgraded to silent, transient ones.
+pub struct QuietFilter {
+    config: Quiet,
+}
+
+impl QuietFilter {
+    pub fn new(config: Quiet) -> Self {
+        QuietFilter { config }
+    }
+
+    pub async fn suppress(&self, conn: &SyncConnection, notif: &Notification<'_>) -> bool {
+        if notif.is_critical() {
+            return false;
+        }
+
+        if let (Some(start), Some(end)) = (self.config.start, self.config.end) {
+            if in_window(local_time(), start, end) {
+                return true;
+            }
+        }
+
+        self.config.desktop && desktop_dnd(conn).await
+    }
+
+    /// Whether the given notification should be shown silently and
+    /// transiently, i.e. because the desktop is in presentation mode.
+    pub async fn downgrade(&self, conn: &SyncConnection, notif: &Notification<'_>) -> bool {
+        if notif.is_critical() || !self.config.presentation {
+            return false;
+        }
+
+        presentation_mode(conn).await
+    }
+}
+
+fn in_window(now: TimeOfDay, start: TimeOfDay, end: TimeOfDay) -> bool {
+    if start <= end {
+        start <= now && now < end
+    } else {
+        // window spans midnight
+        now >= start || now < end
+    }
+}
+
+fn local_time() -> TimeOfDay {
+    // SAFETY: time() and localtime_r() only access the provided pointers,
+    // both of which are valid for the duration of the calls.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+
+        TimeOfDay((tm.tm_hour * 60 + tm.tm_min) as u16)
+    }
+}
+
+/// Query the do-not-disturb state of the desktop via the settings portal.
+///
+/// This uses the GNOME "show-banners" setting, which is disabled while
+/// do-not-disturb is active. Any failure is treated as do-not-disturb being
+/// inactive.
+async fn desktop_dnd(conn: &SyncConnection) -> bool {
+    let proxy = Proxy::new(
+        "org.freedesktop.portal.Desktop",
+        "/org/freedesktop/portal/desktop",
+        Duration::from_secs(1),
+        conn,
+    );
+
+    let result: Result<(Variant<Box<dyn RefArg>>,), _> = proxy
+        .method_call(
+            "org.freedesktop.portal.Settings",
+            "Read",
+            ("org.gnome.desktop.notifications", "show-banners"),
+        )
+        .await;
+
+    let value = match result {
+        Ok((value,)) => value,
+        Err(err) => {
+            debug!(target: "sdtxu::notify", error=%err, "failed to query do-not-disturb state");
+            return false;
+        },
+    };
+
+    // the deprecated Read method wraps the value in an additional variant
+    let mut value: &dyn RefArg = &value.0;
+    while let Some(inner) = value.as_iter().and_then(|mut i| i.next()) {
+        value = inner;
+    }
+
+    value.as_i64() == Some(0)
+}
+
+/// Query whether the desktop is in presentation mode, i.e. whether some
+/// application inhibits the session from going idle.
+///
+/// This checks the GNOME session manager first and falls back to the KDE
+/// power-management inhibition interface. Any failure is treated as
+/// presentation mode being inactive.
+async fn presentation_mode(conn: &SyncConnection) -> bool {
+    // GNOME: IsInhibited() with the "idle" flag
+    let proxy = Proxy::new(
+        "org.gnome.SessionManager",
+        "/org/gnome/SessionManager",
+        Duration::from_secs(1),
+        conn,
+    );
+
+    let result: Result<(bool,), _> = proxy
+        .method_call("org.gnome.SessionManager", "IsInhibited", (8u32,))
+        .await;
+
+    if let Ok((inhibited,)) = result {
+        return inhibited;
+    }
+
+    // KDE: HasInhibit() on the power-management interface
+    let proxy = Proxy::new(
+        "org.freedesktop.PowerManagement",
+        "/org/freedesktop/PowerManagement/Inhibit",
+        Duration::from_secs(1),
+        conn,
+    );
+
+    let result: Result<(bool,), _> = proxy
+        .method_call("org.freedesktop.PowerManagement.Inhibit", "HasInhibit", ())
+        .await;
+
+    match result {
+        Ok((inhibited,)) => inhibited,
+        Err(err) => {
+            debug!(target: "sdtxu::notify", error=%err, "failed to query presentation mode");
+            false
+        },
+    }
+}
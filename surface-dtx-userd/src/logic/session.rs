@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+
+
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+
+/// Seat owning the built-in hardware, including the DTX device.
+const HARDWARE_SEAT: &str = "seat0";
+
+
+/// Query whether the graphical session of the current user is active on the
+/// seat owning the hardware.
+///
+/// Returns `true` if the user has no graphical session known to logind, as
+/// there is nothing to compare against in that case.
+pub async fn is_active(system: &SyncConnection) -> Result<bool> {
+    let manager = Proxy::new(LOGIND_NAME, LOGIND_PATH, Duration::from_secs(5), system);
+
+    // SAFETY: getuid() is always successful
+    let uid = unsafe { libc::getuid() };
+
+    let (user,): (dbus::Path<'static>,) = manager
+        .method_call("org.freedesktop.login1.Manager", "GetUser", (uid,)).await
+        .context("Failed to look up logind user")?;
+
+    let user = Proxy::new(LOGIND_NAME, user, Duration::from_secs(5), system);
+
+    let (_, session): (String, dbus::Path<'static>) = user.get("org.freedesktop.login1.User", "Display").await
+        .context("Failed to query graphical session")?;
+
+    if &*session == "/" {
+        return Ok(true);
+    }
+
+    let session = Proxy::new(LOGIND_NAME, session, Duration::from_secs(5), system);
+
+    let active: bool = session.get("org.freedesktop.login1.Session", "Active").await
+        .context("Failed to query session state")?;
+
+    let remote: bool = session.get("org.freedesktop.login1.Session", "Remote").await
+        .context("Failed to query session state")?;
+
+    let (seat, _): (String, dbus::Path<'static>) = session.get("org.freedesktop.login1.Session", "Seat").await
+        .context("Failed to query session seat")?;
+
+    Ok(active && !remote && seat == HARDWARE_SEAT)
+}
@@ -0,0 +1,302 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use dbus::Message;
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::channel::Sender;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+
+use surface_dtx_client::Client;
+
+use tracing::{debug, trace, warn};
+
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+
+const MENU_PATH: &str = "/MenuBar";
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+const MENU_REQUEST: i32 = 1;
+const MENU_CANCEL: i32 = 2;
+const MENU_LOCK: i32 = 3;
+const MENU_UNLOCK: i32 = 4;
+
+type Layout = (i32, PropMap, Vec<Variant<Box<dyn RefArg>>>);
+type MenuEvent = (i32, String, Variant<Box<dyn RefArg>>, u32);
+
+
+/// Tray icon (StatusNotifierItem) reflecting the current device mode and base
+/// state, with a context menu for controlling the latch.
+#[derive(Clone)]
+pub struct Tray {
+    session: Arc<SyncConnection>,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    system: Arc<SyncConnection>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    mode: String,
+    base: String,
+    detaching: bool,
+    revision: u32,
+}
+
+impl Tray {
+    pub fn new(session: Arc<SyncConnection>, system: Arc<SyncConnection>) -> Self {
+        let shared = Arc::new(Shared {
+            system,
+            state: Mutex::new(State {
+                mode: "laptop".into(),
+                base: "attached".into(),
+                detaching: false,
+                revision: 1,
+            }),
+        });
+
+        Tray { session, shared }
+    }
+
+    pub fn register(&self, cr: &mut Crossroads) {
+        let item = cr.register(ITEM_INTERFACE, register_item);
+        let menu = cr.register(MENU_INTERFACE, register_menu);
+        cr.insert(ITEM_PATH, &[item], self.shared.clone());
+        cr.insert(MENU_PATH, &[menu], self.shared.clone());
+    }
+
+    /// Announce the tray icon to the status-notifier watcher of the desktop.
+    pub async fn announce(&self) -> Result<()> {
+        let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+        self.session.request_name(name.clone(), false, true, false).await
+            .context("Failed to set up tray icon")?;
+
+        let watcher = Proxy::new("org.kde.StatusNotifierWatcher", "/StatusNotifierWatcher",
+                                 Duration::from_secs(5), self.session.clone());
+
+        watcher.method_call::<(), _, _, _>("org.kde.StatusNotifierWatcher",
+                                           "RegisterStatusNotifierItem", (name,)).await
+            .context("Failed to register tray icon")?;
+
+        debug!(target: "sdtxu::tray", "tray icon registered");
+        Ok(())
+    }
+
+    pub fn set_device_mode(&self, mode: &str) {
+        self.update(|state| state.mode = mode.into());
+    }
+
+    pub fn set_base_state(&self, base: &str) {
+        self.update(|state| state.base = base.into());
+    }
+
+    pub fn set_detaching(&self, detaching: bool) {
+        self.update(|state| state.detaching = detaching);
+    }
+
+    fn update<F: FnOnce(&mut State)>(&self, f: F) {
+        let revision = {
+            let mut state = self.shared.state.lock().unwrap();
+
+            let old = state.clone();
+            f(&mut state);
+
+            if old.mode == state.mode && old.base == state.base && old.detaching == state.detaching {
+                return;
+            }
+
+            trace!(target: "sdtxu::tray", ?old, new=?*state, "tray state changed");
+
+            state.revision += 1;
+            state.revision
+        };
+
+        for member in ["NewIcon", "NewToolTip", "NewStatus"] {
+            let mut msg = Message::signal(&ITEM_PATH.into(), &ITEM_INTERFACE.into(), &member.into());
+            if member == "NewStatus" {
+                msg = msg.append1(self.shared.state.lock().unwrap().status());
+            }
+
+            // only fails when memory runs out
+            self.session.send(msg).unwrap();
+        }
+
+        let msg = Message::signal(&MENU_PATH.into(), &MENU_INTERFACE.into(), &"LayoutUpdated".into())
+            .append2(revision, 0i32);
+
+        self.session.send(msg).unwrap();
+    }
+}
+
+impl State {
+    fn icon(&self) -> &'static str {
+        match (self.base.as_str(), self.mode.as_str()) {
+            ("detached", _) => "input-tablet",
+            (_, "tablet")   => "input-tablet",
+            _               => "computer-laptop",
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        if self.detaching { "NeedsAttention" } else { "Active" }
+    }
+
+    fn tooltip(&self) -> String {
+        if self.detaching {
+            format!("Detaching clipboard (mode: {})", self.mode)
+        } else {
+            format!("Base {} (mode: {})", self.base, self.mode)
+        }
+    }
+
+    /// Menu items and their properties.
+    fn items(&self) -> Vec<(i32, PropMap)> {
+        let attached = self.base == "attached";
+
+        let items = [
+            (MENU_REQUEST, "Request detach", attached && !self.detaching),
+            (MENU_CANCEL, "Cancel", self.detaching),
+            (MENU_LOCK, "Lock latch", attached),
+            (MENU_UNLOCK, "Unlock latch", attached),
+        ];
+
+        items.iter()
+            .map(|(id, label, enabled)| {
+                let mut props = PropMap::new();
+                props.insert("label".into(), Variant(Box::new(label.to_string())));
+                props.insert("enabled".into(), Variant(Box::new(*enabled)));
+                (*id, props)
+            })
+            .collect()
+    }
+
+    fn layout(&self) -> Layout {
+        let children = self.items().into_iter()
+            .map(|(id, props)| {
+                let item: Layout = (id, props, Vec::new());
+                Variant(Box::new(item) as Box<dyn RefArg>)
+            })
+            .collect();
+
+        let mut props = PropMap::new();
+        props.insert("children-display".into(), Variant(Box::new("submenu".to_string())));
+
+        (0, props, children)
+    }
+}
+
+fn register_item(b: &mut IfaceBuilder<Arc<Shared>>) {
+    b.property("Category").get(|_, _| Ok("Hardware".to_string()));
+    b.property("Id").get(|_, _| Ok("surface-dtx".to_string()));
+    b.property("Title").get(|_, _| Ok("Surface DTX".to_string()));
+    b.property("ItemIsMenu").get(|_, _| Ok(true));
+    b.property("Menu").get(|_, _| Ok(dbus::Path::from(MENU_PATH)));
+
+    b.property("Status")
+        .get(|_, shared| Ok(shared.state.lock().unwrap().status().to_string()));
+
+    b.property("IconName")
+        .get(|_, shared| Ok(shared.state.lock().unwrap().icon().to_string()));
+
+    b.property("ToolTip").get(|_, shared| {
+        let state = shared.state.lock().unwrap();
+        let pixmaps: Vec<(i32, i32, Vec<u8>)> = Vec::new();
+
+        Ok((state.icon().to_string(), pixmaps, "Surface DTX".to_string(), state.tooltip()))
+    });
+
+    // the item is a menu, activation is handled by the host via the menu
+    b.method("Activate", ("x", "y"), (), |_, _, _: (i32, i32)| Ok(()));
+    b.method("SecondaryActivate", ("x", "y"), (), |_, _, _: (i32, i32)| Ok(()));
+    b.method("ContextMenu", ("x", "y"), (), |_, _, _: (i32, i32)| Ok(()));
+    b.method("Scroll", ("delta", "orientation"), (), |_, _, _: (i32, String)| Ok(()));
+
+    b.signal::<(), _>("NewIcon", ());
+    b.signal::<(), _>("NewToolTip", ());
+    b.signal::<(String,), _>("NewStatus", ("status",));
+}
+
+fn register_menu(b: &mut IfaceBuilder<Arc<Shared>>) {
+    b.property("Version").get(|_, _| Ok(3u32));
+    b.property("TextDirection").get(|_, _| Ok("ltr".to_string()));
+    b.property("Status").get(|_, _| Ok("normal".to_string()));
+    b.property("IconThemePath").get(|_, _| Ok(Vec::<String>::new()));
+
+    b.method("GetLayout", ("parentId", "recursionDepth", "propertyNames"), ("revision", "layout"),
+             |_, shared, (_parent, _depth, _names): (i32, i32, Vec<String>)| {
+        let state = shared.state.lock().unwrap();
+        Ok((state.revision, state.layout()))
+    });
+
+    b.method("GetGroupProperties", ("ids", "propertyNames"), ("properties",),
+             |_, shared, (ids, _names): (Vec<i32>, Vec<String>)| {
+        let props = shared.state.lock().unwrap().items().into_iter()
+            .filter(|(id, _)| ids.is_empty() || ids.contains(id))
+            .collect::<Vec<_>>();
+
+        Ok((props,))
+    });
+
+    b.method("GetProperty", ("id", "name"), ("value",), |_, _, (_id, _name): (i32, String)| {
+        Err::<(Variant<bool>,), _>(MethodErr::failed("Unsupported property"))
+    });
+
+    b.method("Event", ("id", "eventId", "data", "timestamp"), (),
+             |_, shared, (id, event, _, _): MenuEvent| {
+        if event == "clicked" {
+            shared.activate(id);
+        }
+        Ok(())
+    });
+
+    b.method("EventGroup", ("events",), ("idErrors",),
+             |_, shared, (events,): (Vec<MenuEvent>,)| {
+        for (id, event, _, _) in events {
+            if event == "clicked" {
+                shared.activate(id);
+            }
+        }
+        Ok((Vec::<i32>::new(),))
+    });
+
+    b.method("AboutToShow", ("id",), ("needUpdate",), |_, _, _: (i32,)| Ok((false,)));
+
+    b.method("AboutToShowGroup", ("ids",), ("updatesNeeded", "idErrors"),
+             |_, _, _: (Vec<i32>,)| Ok((Vec::<i32>::new(), Vec::<i32>::new())));
+
+    b.signal::<(u32, i32), _>("LayoutUpdated", ("revision", "parent"));
+}
+
+impl Shared {
+    fn activate(&self, id: i32) {
+        let method = match id {
+            // requesting again while detaching cancels the detachment
+            MENU_REQUEST | MENU_CANCEL => "Request",
+            MENU_LOCK                  => "Lock",
+            MENU_UNLOCK                => "Unlock",
+            _ => return,
+        };
+
+        debug!(target: "sdtxu::tray", method, "menu item activated");
+
+        let client = Client::new(self.system.clone());
+        tokio::spawn(async move {
+            let result = match id {
+                MENU_LOCK   => client.lock().await,
+                MENU_UNLOCK => client.unlock().await,
+                _           => client.request().await,
+            };
+
+            if let Err(err) = result {
+                warn!(target: "sdtxu::tray", method, "failed to call daemon: {:#}", err);
+            }
+        });
+    }
+}
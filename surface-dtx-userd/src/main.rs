@@ -8,15 +8,13 @@ use std::{path::PathBuf, io::IsTerminal};
 use crate::config::Config;
 
 use anyhow::{Context, Result};
+use clap::ArgMatches;
 use tokio::signal::unix::{SignalKind, signal};
 
 use tracing::{error, info};
 
 
-fn bootstrap() -> Result<Config> {
-    // handle command line input
-    let matches = cli::app().get_matches();
-
+fn bootstrap(matches: &ArgMatches) -> Result<Config> {
     // set up config
     let (config, diag) = match matches.get_one::<PathBuf>("config") {
         Some(path) => Config::load_file(path)?,
@@ -47,7 +45,14 @@ fn bootstrap() -> Result<Config> {
 }
 
 async fn run() -> Result<()> {
-    let _config = bootstrap()?;
+    // handle command line input
+    let matches = cli::app().get_matches();
+
+    if matches.get_flag("history") {
+        return logic::History::dump();
+    }
+
+    let config = bootstrap(&matches)?;
 
     // set up signal handling for shutdown
     let mut sigint = signal(SignalKind::interrupt()).context("Failed to set up signal handling")?;
@@ -63,7 +68,7 @@ async fn run() -> Result<()> {
     };
 
     // set up main logic task
-    let main = logic::run();
+    let main = logic::run(config);
 
     // wait for error or shutdown signal
     info!(target: "sdtxu", "running...");
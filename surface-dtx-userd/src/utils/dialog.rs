@@ -0,0 +1,61 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+
+use tokio::process::Command;
+
+
+/// Dialog programs used when no notification server is available, in order
+/// of preference.
+const PROGRAMS: &[&str] = &["zenity", "kdialog", "xmessage"];
+
+
+/// Show a simple message dialog using the first available dialog program.
+///
+/// The dialog is shown in the background, this does not wait for the user to
+/// close it.
+pub fn show(title: &str, text: &str, error: bool) -> Result<()> {
+    let program = PROGRAMS.iter()
+        .copied()
+        .find(|p| in_path(p))
+        .ok_or_else(|| anyhow::anyhow!("No dialog program found"))?;
+
+    let mut cmd = Command::new(program);
+    match program {
+        "zenity" => {
+            cmd.arg(if error { "--error" } else { "--info" })
+                .arg("--no-markup")
+                .arg(format!("--title={title}"))
+                .arg(format!("--text={text}"));
+        },
+        "kdialog" => {
+            cmd.arg("--title").arg(title)
+                .arg(if error { "--error" } else { "--msgbox" })
+                .arg(text);
+        },
+        _ => {
+            cmd.arg("-center").arg(format!("{title}\n\n{text}"));
+        },
+    }
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run dialog program '{program}'"))?;
+
+    // reap the process once the dialog has been closed
+    tokio::spawn(async move {
+        let _ = child.wait().await;
+    });
+
+    Ok(())
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file()))
+        .unwrap_or(false)
+}
@@ -28,6 +28,14 @@ pub struct NotificationHandle {
     pub id: u32,
 }
 
+/// Optional features supported by the notification server.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Capabilities {
+    pub actions:     bool,
+    pub body:        bool,
+    pub body_markup: bool,
+}
+
 
 #[allow(unused)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -90,6 +98,45 @@ impl<'a> Notification<'a> {
         self.hints.insert(key.into(), Variant(Box::new(value) as Box<dyn RefArg>));
     }
 
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Whether this notification has critical urgency.
+    pub fn is_critical(&self) -> bool {
+        self.hints.get("urgency").and_then(|v| v.as_i64()) == Some(2)
+    }
+
+    /// Adapt this notification to the features supported by the server.
+    ///
+    /// Actions are dropped if unsupported and markup is stripped from the
+    /// body if the server would otherwise display it verbatim. Servers that
+    /// do not show a body at all get the first sentence of it appended to the
+    /// summary instead.
+    pub fn adapt(&mut self, caps: &Capabilities) {
+        if !caps.actions {
+            self.actions.clear();
+        }
+
+        if !caps.body_markup && self.body.contains(['<', '&']) {
+            self.body = strip_markup(&self.body).into();
+        }
+
+        if !caps.body && !self.body.is_empty() {
+            let body = std::mem::take(&mut self.body);
+            let sentence = match body.find(". ") {
+                Some(pos) => &body[..=pos],
+                None => &body[..],
+            };
+
+            self.summary = format!("{}: {}", self.summary, sentence).into();
+        }
+    }
+
     pub fn set_expires(&mut self, timeout: Timeout) {
         self.expires = match timeout {
             Timeout::Unspecified => -1,
@@ -179,6 +226,34 @@ impl<'a> NotificationBuilder<'a> {
 }
 
 
+impl Capabilities {
+    pub async fn query(conn: &SyncConnection) -> Result<Self, dbus::Error> {
+        let proxy = Proxy::new(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            Duration::from_secs(5),
+            conn,
+        );
+
+        let (caps,): (Vec<String>,) = proxy
+            .method_call(
+                "org.freedesktop.Notifications",
+                "GetCapabilities",
+                (),
+            )
+            .await?;
+
+        let has = |cap: &str| caps.iter().any(|c| c == cap);
+
+        Ok(Capabilities {
+            actions:     has("actions"),
+            body:        has("body"),
+            body_markup: has("body-markup"),
+        })
+    }
+}
+
+
 impl NotificationHandle {
     pub async fn close(self, conn: &SyncConnection) -> Result<(), dbus::Error> {
         let proxy = Proxy::new(
@@ -197,3 +272,25 @@ impl NotificationHandle {
             .await
     }
 }
+
+
+/// Remove markup tags from the given text and resolve the basic XML entities.
+fn strip_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {},
+        }
+    }
+
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}